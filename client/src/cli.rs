@@ -17,16 +17,34 @@ pub enum Command {
     Health,
 
     /// Register a new user account
-    Register { username: String, password: String },
+    Register {
+        username: String,
+        password: String,
+
+        /// Email address for verification and password resets
+        #[arg(long)]
+        email: Option<String>,
+    },
 
     /// Log in and save tokens locally
-    Login { username: String, password: String },
+    Login {
+        username: String,
+        password: String,
+
+        /// Limit the session to this scope (repeatable; default: all you may hold)
+        #[arg(long = "scope")]
+        scopes: Vec<String>,
+    },
 
     /// Show the current authenticated user
     Me,
 
     /// Refresh the access token using the refresh token
-    Refresh,
+    Refresh {
+        /// Narrow the new access token to this scope (repeatable)
+        #[arg(long = "scope")]
+        scopes: Vec<String>,
+    },
 
     /// Upload a file
     Upload {
@@ -36,6 +54,10 @@ pub enum Command {
         /// Make the uploaded file public
         #[arg(long, default_value_t = false)]
         public: bool,
+
+        /// Description, searchable with `sfs search --text`
+        #[arg(long)]
+        description: Option<String>,
     },
 
     /// Download a file you have access to
@@ -48,6 +70,12 @@ pub enum Command {
         out: String,
     },
 
+    /// Delete a file you own
+    Delete {
+        /// File id on the server
+        file_id: u32,
+    },
+
     /// Share a file with another user id
     Share {
         /// File id on the server
@@ -78,8 +106,379 @@ pub enum Command {
 
     /// List files visible to the logged-in user
     #[command(alias = "files")]
-    List,
+    List {
+        /// Page to show, following cursors from the first page
+        #[arg(long, default_value_t = 1)]
+        page: u32,
+
+        /// Files per page (server default 100)
+        #[arg(long)]
+        page_size: Option<u32>,
+
+        /// Print every page
+        #[arg(long, default_value_t = false, conflicts_with = "page")]
+        all: bool,
+
+        /// name, size or date
+        #[arg(long, default_value = "date")]
+        sort: String,
+
+        /// asc or desc (default: asc for name, desc otherwise)
+        #[arg(long)]
+        order: Option<String>,
+
+        /// Only files shared with you
+        #[arg(long, default_value_t = false)]
+        shared_only: bool,
+
+        /// Only files you own
+        #[arg(long, default_value_t = false, conflicts_with = "shared_only")]
+        owned_only: bool,
+
+        /// Only public (true) or private (false) files
+        #[arg(long)]
+        public: Option<bool>,
+
+        /// Only files with this tag (repeatable; all must match)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+
+    /// Search files visible to the logged-in user; all given filters must match
+    Search {
+        /// Filename substring, or a glob over the whole name with * and ?
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Words in the description (prefix match)
+        #[arg(long)]
+        text: Option<String>,
+
+        /// Smallest size in bytes
+        #[arg(long)]
+        min_size: Option<u64>,
+
+        /// Largest size in bytes
+        #[arg(long)]
+        max_size: Option<u64>,
+
+        /// Uploaded at or after this unix time
+        #[arg(long)]
+        since: Option<i64>,
+
+        /// Uploaded before this unix time
+        #[arg(long)]
+        until: Option<i64>,
+
+        /// Owner's username
+        #[arg(long)]
+        owner: Option<String>,
+
+        /// Only files you own (owner) or that are shared with you (shared)
+        #[arg(long)]
+        access: Option<String>,
+
+        /// Only public (true) or private (false) files
+        #[arg(long)]
+        public: Option<bool>,
+
+        /// Only files with this tag (repeatable; all must match)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+
+        /// Only files with this metadata key, or key=value
+        #[arg(long)]
+        meta: Option<String>,
+
+        /// Results per page (server default 100)
+        #[arg(long)]
+        limit: Option<u32>,
+
+        /// Page to show, following cursors from the first page
+        #[arg(long, default_value_t = 1)]
+        page: u32,
+
+        /// Print every page
+        #[arg(long, default_value_t = false, conflicts_with = "page")]
+        all: bool,
+    },
+
+    /// Print changes to your files as they happen (uploads, shares, revocations, updates)
+    Watch,
+
+    /// Show download counts and downloaders of a file you own
+    Stats {
+        /// File id on the server
+        file_id: u32,
+    },
+
+    /// Tag files you own; tags are shown in `sfs list` and filter it with --tag
+    #[command(subcommand)]
+    Tag(TagCommand),
+
+    /// Key/value metadata on files you own
+    #[command(subcommand)]
+    Meta(MetaCommand),
+
+    /// Manage webhooks for uploads, deletes, shares, revocations and downloads of your files
+    #[command(subcommand)]
+    Webhook(WebhookCommand),
+
+    /// Manage two-factor authentication
+    #[command(name = "2fa", subcommand)]
+    TwoFactor(TwoFactorCommand),
+
+    /// Change or reset your password
+    #[command(subcommand)]
+    Password(PasswordCommand),
+
+    /// Export or delete your account
+    #[command(subcommand)]
+    Account(AccountCommand),
+
+    /// Set and verify your email address
+    #[command(subcommand)]
+    Email(EmailCommand),
+
+    /// Log in through the identity provider (single sign-on)
+    #[command(subcommand)]
+    Sso(SsoCommand),
+
+    /// Manage personal API keys (use one by setting SFS_API_KEY)
+    #[command(subcommand)]
+    Apikey(ApiKeyCommand),
+
+    /// Show the audit log: your own events, or everyone's for admins
+    Audit {
+        /// Only this action, e.g. auth.login or file.download
+        #[arg(long)]
+        action: Option<String>,
+
+        /// Only success or failure
+        #[arg(long)]
+        outcome: Option<String>,
+
+        /// Only events of this user id (admins only)
+        #[arg(long)]
+        actor: Option<u32>,
+
+        /// Only events about this file id
+        #[arg(long)]
+        file: Option<u32>,
+
+        /// Only events at or after this unix time
+        #[arg(long)]
+        since: Option<i64>,
+
+        /// Maximum number of events (server default 100)
+        #[arg(long)]
+        limit: Option<u32>,
+    },
 
-    /// Remove saved tokens (log out)
+    /// Admin: check the audit log's hash chain and signed checkpoints
+    AuditVerify {
+        /// Sign the current head of the chain first
+        #[arg(long, default_value_t = false)]
+        checkpoint: bool,
+    },
+
+    /// End the session on the server and remove saved tokens (log out)
     Logout,
-}
\ No newline at end of file
+}
+
+#[derive(Subcommand)]
+pub enum TwoFactorCommand {
+    /// Enroll an authenticator app and enable 2FA
+    Enable,
+
+    /// Disable 2FA (asks for a current code or recovery code)
+    Disable {
+        /// Account password
+        password: String,
+    },
+}
+#[derive(Subcommand)]
+pub enum PasswordCommand {
+    /// Change the password of the logged-in user (logs out other sessions)
+    Change {
+        /// Current password
+        current: String,
+
+        /// New password
+        new: String,
+    },
+
+    /// Request a password reset token by mail
+    Forgot { username: String },
+
+    /// Set a new password using a reset token
+    Reset {
+        /// Token from the reset mail or from an admin
+        token: String,
+
+        /// New password
+        new: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ApiKeyCommand {
+    /// Create a key; it is printed once and cannot be shown again
+    Create {
+        /// Label to recognize the key by
+        name: String,
+
+        /// Scope to grant: files:read, files:write or shares:manage (repeatable)
+        #[arg(long = "scope", required = true)]
+        scopes: Vec<String>,
+
+        /// Expire the key after this many days (default: never)
+        #[arg(long)]
+        expires_days: Option<u32>,
+
+        /// Limit the key to files with this tag, like a folder; its uploads get the tag
+        #[arg(long)]
+        tag: Option<String>,
+    },
+
+    /// List your keys with their scopes and last use
+    List,
+
+    /// Revoke a key by id
+    Revoke { id: u32 },
+}
+
+#[derive(Subcommand)]
+pub enum TagCommand {
+    /// Add tags to a file
+    Add {
+        file_id: u32,
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+
+    /// Remove a tag from a file
+    Remove { file_id: u32, tag: String },
+
+    /// Show the tags of a file
+    Show { file_id: u32 },
+
+    /// List the tags on files you can see, with how many files have each
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum MetaCommand {
+    /// Show the metadata of a file
+    Show { file_id: u32 },
+
+    /// Set metadata keys, e.g. `sfs meta set 3 project=apollo status=final`
+    Set {
+        file_id: u32,
+        #[arg(required = true)]
+        pairs: Vec<String>,
+    },
+
+    /// Remove metadata keys
+    Unset {
+        file_id: u32,
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum WebhookCommand {
+    /// Register a webhook; its signing secret is printed once
+    Add {
+        url: String,
+
+        /// Only deliver this event (repeatable; default: all). One of file.uploaded,
+        /// file.deleted, file.downloaded, share.created, share.revoked
+        #[arg(long = "event")]
+        events: Vec<String>,
+
+        /// Signing secret (default: generated by the server)
+        #[arg(long)]
+        secret: Option<String>,
+    },
+
+    /// List your webhooks with their queued and failed deliveries
+    List,
+
+    /// Remove a webhook and drop its queued deliveries
+    Remove { id: u32 },
+
+    /// Send a signed test delivery
+    Ping { id: u32 },
+
+    /// Show the delivery log of a webhook, newest first
+    Deliveries {
+        id: u32,
+
+        /// Maximum number of deliveries (server default 50)
+        #[arg(long)]
+        limit: Option<u32>,
+    },
+
+    /// Run a local receiver that prints deliveries and checks their signatures
+    Listen {
+        #[arg(long, default_value_t = 9000)]
+        port: u16,
+
+        /// Secret to check signatures with
+        #[arg(long)]
+        secret: Option<String>,
+
+        /// HTTP status to answer with; use 500 to watch retries
+        #[arg(long, default_value_t = 204)]
+        status: u16,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SsoCommand {
+    /// Log in via the identity provider and save tokens locally
+    Login,
+
+    /// Link an identity at the provider to the logged-in account
+    Link,
+}
+
+#[derive(Subcommand)]
+pub enum EmailCommand {
+    /// Set or change your email address and mail a verification token to it
+    Set { email: String },
+
+    /// Remove your email address
+    Remove,
+
+    /// Mail a new verification token
+    Resend,
+
+    /// Confirm your email address with the token from the verification mail
+    Verify { token: String },
+}
+
+#[derive(Subcommand)]
+pub enum AccountCommand {
+    /// Download a tar archive of your files and account data
+    Export {
+        /// Output path to save the archive
+        #[arg(long)]
+        out: String,
+    },
+
+    /// Permanently delete your account and all your files
+    ///
+    /// Give the password. Accounts without one (single sign-on) give a 2FA
+    /// code instead, or nothing within 5 minutes of logging in.
+    Delete {
+        /// Account password
+        password: Option<String>,
+
+        /// 2FA code or recovery code, for accounts without a password
+        #[arg(long)]
+        code: Option<String>,
+    },
+}
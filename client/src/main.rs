@@ -1,10 +1,14 @@
 mod cli;
 mod token_store;
 mod types;
+mod webhook_listener;
 
 use clap::Parser;
 
-use cli::{Cli, Command};
+use cli::{
+    AccountCommand, ApiKeyCommand, Cli, Command, EmailCommand, MetaCommand, PasswordCommand,
+    SsoCommand, TagCommand, TwoFactorCommand, WebhookCommand,
+};
 use token_store::*;
 use types::*;
 
@@ -21,7 +25,11 @@ fn print_body_pretty_if_json(body: &str) {
     if b.is_empty() {
         return;
     }
-    if let Ok(json) = serde_json::from_str::<serde_json::Value>(b) {
+    if let Ok(err) = serde_json::from_str::<ApiErrorResp>(b) {
+        for line in err.lines() {
+            println!("{line}");
+        }
+    } else if let Ok(json) = serde_json::from_str::<serde_json::Value>(b) {
         println!("{}", serde_json::to_string_pretty(&json).unwrap());
     } else {
         println!("{b}");
@@ -33,13 +41,208 @@ fn eprint_body_pretty_if_json(body: &str) {
     if b.is_empty() {
         return;
     }
-    if let Ok(json) = serde_json::from_str::<serde_json::Value>(b) {
+    if let Ok(err) = serde_json::from_str::<ApiErrorResp>(b) {
+        for line in err.lines() {
+            eprintln!("{line}");
+        }
+    } else if let Ok(json) = serde_json::from_str::<serde_json::Value>(b) {
         eprintln!("{}", serde_json::to_string_pretty(&json).unwrap());
     } else {
         eprintln!("{b}");
     }
 }
 
+/// Print page `page` (or every page with `all`) of `GET /files` or `GET /files/search`.
+/// Cursors only go forward, so page N takes N requests.
+async fn print_file_pages(
+    url: &str,
+    query: &[(&str, String)],
+    tok: &str,
+    page: u32,
+    all: bool,
+    what: &str,
+    command: &str,
+) {
+    if page == 0 {
+        eprintln!("--page starts at 1");
+        return;
+    }
+
+    let client = reqwest::Client::new();
+    let mut items: Vec<crate::types::FileListItem> = Vec::new();
+    let mut cursor: Option<String> = None;
+    let mut current = 1;
+    let page_resp = loop {
+        let mut req = client.get(url).query(query).bearer_auth(tok);
+        if let Some(cursor) = &cursor {
+            req = req.query(&[("cursor", cursor)]);
+        }
+
+        let resp = match req.send().await {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("{what} request failed: {e}");
+                return;
+            }
+        };
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            eprint_http(&format!("{what} failed"), status);
+            eprint_body_pretty_if_json(&body);
+            return;
+        }
+
+        let mut resp: crate::types::FileListPageResp = match resp.json().await {
+            Ok(j) => j,
+            Err(e) => {
+                eprintln!("Failed to parse JSON: {e}");
+                return;
+            }
+        };
+
+        if all || current == page {
+            items.append(&mut resp.items);
+        }
+        if (!all && current == page) || resp.next_cursor.is_none() {
+            break resp;
+        }
+        cursor = resp.next_cursor.take();
+        current += 1;
+    };
+
+    if current < page {
+        println!("Page {page} is past the last page ({current}).");
+        return;
+    }
+
+    print_file_table(&items);
+    if all {
+        println!("\n{} files", page_resp.total);
+    } else if !items.is_empty() {
+        println!(
+            "\nPage {page}: {} of {} files",
+            items.len(),
+            page_resp.total
+        );
+    }
+    if !all && page_resp.next_cursor.is_some() {
+        println!("More: {command} --page {}", page + 1);
+    }
+}
+
+/// Print files from `GET /files` or `GET /files/search` as a table
+fn print_file_table(items: &[crate::types::FileListItem]) {
+    if items.is_empty() {
+        println!("No files found.");
+        return;
+    }
+
+    println!(
+        "{:<6} {:<7} {:<10} {:<8} {:<12} {:<10} NAME",
+        "ID", "PUBLIC", "SIZE", "ACCESS", "UPLOADED_AT", "DOWNLOADS"
+    );
+    for f in items {
+        println!(
+            "{:<6} {:<7} {:<10} {:<8} {:<12} {:<10} {}",
+            f.file_id,
+            if f.is_public { "yes" } else { "no" },
+            f.size,
+            f.access,
+            f.uploaded_at,
+            f.download_count.map_or("-".to_string(), |n| n.to_string()),
+            f.filename
+        );
+        if !f.tags.is_empty() {
+            println!("{:<6} tags: {}", "", f.tags.join(", "));
+        }
+        if let Some(description) = &f.description {
+            println!("{:<6} {description}", "");
+        }
+    }
+}
+
+/// Print one Server-Sent Event block from `GET /events`
+fn print_live_event(block: &str) {
+    let mut event = "message";
+    let mut data = String::new();
+    for line in block.lines() {
+        if let Some(v) = line.strip_prefix("event:") {
+            event = v.trim();
+        } else if let Some(v) = line.strip_prefix("data:") {
+            data.push_str(v.trim());
+        }
+    }
+
+    match event {
+        // Keep-alive comments have no fields
+        "message" if data.is_empty() => {}
+        "ready" => println!("Watching for changes. Press Ctrl-C to stop."),
+        "lagged" => println!("Some events were missed; run `sfs list` to catch up."),
+        _ => println!("{event:<14} {data}"),
+    }
+}
+
+/// Ask for a line of input on stdin; `None` if empty or stdin is closed
+fn prompt(label: &str) -> Option<String> {
+    use std::io::Write;
+
+    print!("{label}");
+    std::io::stdout().flush().ok()?;
+
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line).ok()?;
+    let line = line.trim();
+    if line.is_empty() {
+        None
+    } else {
+        Some(line.to_string())
+    }
+}
+
+/// Ask for a 2FA code and finish a login at `POST /login/2fa`
+async fn complete_two_factor(base: &str, challenge: &TwoFactorChallengeResp) -> Option<AuthResp> {
+    let Some(code) = prompt("Two-factor code (or recovery code): ") else {
+        eprintln!("No code entered.");
+        return None;
+    };
+
+    let url = format!("{base}/login/2fa");
+    let resp = reqwest::Client::new()
+        .post(url)
+        .json(&TwoFactorLoginReq {
+            challenge_token: &challenge.challenge_token,
+            code: &code,
+        })
+        .send()
+        .await;
+
+    let resp = match resp {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Login request failed: {e}");
+            return None;
+        }
+    };
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        eprint_http("Login failed", status);
+        eprint_body_pretty_if_json(&body);
+        return None;
+    }
+
+    match resp.json().await {
+        Ok(j) => Some(j),
+        Err(e) => {
+            eprintln!("Failed to parse JSON: {e}");
+            None
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
@@ -69,14 +272,19 @@ async fn main() {
             println!("Health OK");
         }
 
-        Command::Register { username, password } => {
+        Command::Register {
+            username,
+            password,
+            email,
+        } => {
             let url = format!("{}/register", cli.base);
 
             let resp = reqwest::Client::new()
                 .post(url)
-                .json(&AuthReq {
+                .json(&RegisterReq {
                     username: &username,
                     password: &password,
+                    email: email.as_deref(),
                 })
                 .send()
                 .await;
@@ -115,17 +323,27 @@ async fn main() {
                 return;
             }
 
-            println!("Registered.");
+            if email.is_some() {
+                println!("Registered. Check your mail, then run: sfs email verify <token>");
+            } else {
+                println!("Registered.");
+            }
         }
 
-        Command::Login { username, password } => {
+        Command::Login {
+            username,
+            password,
+            scopes,
+        } => {
             let url = format!("{}/login", cli.base);
+            let scope = scopes.join(" ");
 
             let resp = reqwest::Client::new()
                 .post(url)
                 .json(&AuthReq {
                     username: &username,
                     password: &password,
+                    scope: (!scope.is_empty()).then_some(scope.as_str()),
                 })
                 .send()
                 .await;
@@ -146,7 +364,7 @@ async fn main() {
                 return;
             }
 
-            let auth: AuthResp = match resp.json().await {
+            let login: LoginResp = match resp.json().await {
                 Ok(j) => j,
                 Err(e) => {
                     eprintln!("Failed to parse JSON: {e}");
@@ -154,6 +372,17 @@ async fn main() {
                 }
             };
 
+            let auth = match login {
+                LoginResp::Tokens(auth) => auth,
+                LoginResp::TwoFactor(challenge) => {
+                    match complete_two_factor(&cli.base, &challenge).await {
+                        Some(auth) => auth,
+                        None => return,
+                    }
+                }
+            };
+
+            let scope = auth.scope;
             let store = TokenStore {
                 access_token: auth.access_token,
                 refresh_token: auth.refresh_token,
@@ -164,7 +393,7 @@ async fn main() {
                 return;
             }
 
-            println!("Logged in.");
+            println!("Logged in. Scopes: {scope}");
         }
 
         Command::Me => {
@@ -206,7 +435,7 @@ async fn main() {
             print_body_pretty_if_json(&body);
         }
 
-        Command::Refresh => {
+        Command::Refresh { scopes } => {
             let mut store = match load_tokens() {
                 Ok(s) => s,
                 Err(_) => {
@@ -224,12 +453,12 @@ async fn main() {
             };
 
             let url = format!("{}/token/refresh", cli.base);
+            let mut req = reqwest::Client::new().get(url).bearer_auth(refresh_token);
+            if !scopes.is_empty() {
+                req = req.query(&[("scope", scopes.join(" "))]);
+            }
 
-            let resp = reqwest::Client::new()
-                .get(url)
-                .bearer_auth(refresh_token)
-                .send()
-                .await;
+            let resp = req.send().await;
 
             let resp = match resp {
                 Ok(r) => r,
@@ -244,6 +473,12 @@ async fn main() {
                 let body = resp.text().await.unwrap_or_default();
                 eprint_http("Refresh failed", status);
                 eprint_body_pretty_if_json(&body);
+
+                // The refresh token is dead (expired, revoked or replayed): force a new login
+                if status == reqwest::StatusCode::UNAUTHORIZED {
+                    let _ = logout_local();
+                    eprintln!("Saved tokens cleared. Run: sfs login <user> <pass>");
+                }
                 return;
             }
 
@@ -264,9 +499,14 @@ async fn main() {
             }
 
             println!("Refreshed. Tokens updated at {:?}", token_path());
+            println!("Scopes: {}", auth.scope);
         }
 
-        Command::Upload { path, public } => {
+        Command::Upload {
+            path,
+            public,
+            description,
+        } => {
             // Load saved tokens
             let store = match load_tokens() {
                 Ok(s) => s,
@@ -301,9 +541,12 @@ async fn main() {
 
             let part = reqwest::multipart::Part::bytes(bytes).file_name(file_name);
 
-            let form = reqwest::multipart::Form::new()
+            let mut form = reqwest::multipart::Form::new()
                 .part("file", part)
                 .text("is_public", public.to_string());
+            if let Some(description) = description {
+                form = form.text("description", description);
+            }
 
             // Send
             let url = format!("{}/file/upload", cli.base);
@@ -339,8 +582,8 @@ async fn main() {
             };
 
             println!(
-                "Uploaded file_id={} filename={} size={} public={}",
-                out.file_id, out.filename, out.size, out.is_public
+                "Uploaded file_id={} filename={} size={} public={} type={}",
+                out.file_id, out.filename, out.size, out.is_public, out.content_type
             );
         }
 
@@ -385,6 +628,13 @@ async fn main() {
                 return;
             }
 
+            let content_type = resp
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("unknown type")
+                .to_string();
+
             let bytes = match resp.bytes().await {
                 Ok(b) => b,
                 Err(e) => {
@@ -398,7 +648,49 @@ async fn main() {
                 return;
             }
 
-            println!("Saved to {out}");
+            println!("Saved to {out} ({content_type})");
+        }
+
+        Command::Delete { file_id } => {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let url = format!("{}/file/{}", cli.base, file_id);
+            let resp = match reqwest::Client::new()
+                .delete(url)
+                .bearer_auth(tok)
+                .send()
+                .await
+            {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Delete request failed: {e}");
+                    return;
+                }
+            };
+
+            if resp.status() == reqwest::StatusCode::NO_CONTENT {
+                println!("File {file_id} deleted.");
+                return;
+            }
+
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            eprint_http("Delete failed", status);
+            eprint_body_pretty_if_json(&body);
         }
 
         Command::Share { file_id, user_id } => {
@@ -522,6 +814,13 @@ async fn main() {
                 return;
             }
 
+            let content_type = resp
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("unknown type")
+                .to_string();
+
             let bytes = match resp.bytes().await {
                 Ok(b) => b,
                 Err(e) => {
@@ -535,10 +834,20 @@ async fn main() {
                 return;
             }
 
-            println!("Saved to {out}");
+            println!("Saved to {out} ({content_type})");
         }
 
-        Command::List => {
+        Command::List {
+            page,
+            page_size,
+            all,
+            sort,
+            order,
+            shared_only,
+            owned_only,
+            public,
+            tags,
+        } => {
             let store = match load_tokens() {
                 Ok(s) => s,
                 Err(_) => {
@@ -555,8 +864,192 @@ async fn main() {
                 }
             };
 
+            let mut query: Vec<(&str, String)> = vec![("sort", sort)];
+            if let Some(order) = order {
+                query.push(("order", order));
+            }
+            if let Some(page_size) = page_size {
+                query.push(("limit", page_size.to_string()));
+            }
+            if shared_only {
+                query.push(("access", "shared".to_string()));
+            } else if owned_only {
+                query.push(("access", "owner".to_string()));
+            }
+            if let Some(public) = public {
+                query.push(("is_public", public.to_string()));
+            }
+            if !tags.is_empty() {
+                query.push(("tag", tags.join(",")));
+            }
+
             let url = format!("{}/files", cli.base);
+            print_file_pages(&url, &query, tok, page, all, "List", "sfs list").await;
+        }
+
+        Command::Search {
+            name,
+            text,
+            min_size,
+            max_size,
+            since,
+            until,
+            owner,
+            access,
+            public,
+            tags,
+            meta,
+            limit,
+            page,
+            all,
+        } => {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let mut query: Vec<(&str, String)> = Vec::new();
+            if let Some(name) = name {
+                query.push(("name", name));
+            }
+            if let Some(text) = text {
+                query.push(("q", text));
+            }
+            if let Some(min_size) = min_size {
+                query.push(("min_size", min_size.to_string()));
+            }
+            if let Some(max_size) = max_size {
+                query.push(("max_size", max_size.to_string()));
+            }
+            if let Some(since) = since {
+                query.push(("since", since.to_string()));
+            }
+            if let Some(until) = until {
+                query.push(("until", until.to_string()));
+            }
+            if let Some(owner) = owner {
+                query.push(("owner", owner));
+            }
+            if let Some(access) = access {
+                query.push(("access", access));
+            }
+            if let Some(public) = public {
+                query.push(("is_public", public.to_string()));
+            }
+            if !tags.is_empty() {
+                query.push(("tag", tags.join(",")));
+            }
+            if let Some(meta) = meta {
+                match meta.split_once('=') {
+                    Some((key, value)) => {
+                        query.push(("meta_key", key.to_string()));
+                        query.push(("meta_value", value.to_string()));
+                    }
+                    None => query.push(("meta_key", meta)),
+                }
+            }
+            if let Some(limit) = limit {
+                query.push(("limit", limit.to_string()));
+            }
+
+            let url = format!("{}/files/search", cli.base);
+            print_file_pages(&url, &query, tok, page, all, "Search", "sfs search ...").await;
+        }
+
+        Command::Watch => loop {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let url = format!("{}/events", cli.base);
+            let mut resp = match reqwest::Client::new()
+                .get(url)
+                .bearer_auth(tok)
+                .send()
+                .await
+            {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Watch request failed: {e}; retrying in 5s");
+                    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                eprint_http("Watch failed", status);
+                eprint_body_pretty_if_json(&body);
+                if status == reqwest::StatusCode::UNAUTHORIZED {
+                    eprintln!("Run: sfs refresh");
+                }
+                return;
+            }
+
+            // Events are separated by a blank line
+            let mut buf: Vec<u8> = Vec::new();
+            loop {
+                match resp.chunk().await {
+                    Ok(Some(chunk)) => buf.extend_from_slice(&chunk),
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("Event stream failed: {e}");
+                        break;
+                    }
+                }
+                while let Some(pos) = buf.windows(2).position(|w| w == b"\n\n") {
+                    let block: Vec<u8> = buf.drain(..pos + 2).collect();
+                    print_live_event(&String::from_utf8_lossy(&block));
+                }
+            }
+
+            // The server ends streams when the access token would have expired
+            eprintln!("Event stream closed; reconnecting");
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        },
+
+        Command::Stats { file_id } => {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
 
+            let url = format!("{}/file/{}/stats", cli.base, file_id);
             let resp = match reqwest::Client::new()
                 .get(url)
                 .bearer_auth(tok)
@@ -565,7 +1058,7 @@ async fn main() {
             {
                 Ok(r) => r,
                 Err(e) => {
-                    eprintln!("List request failed: {e}");
+                    eprintln!("Stats request failed: {e}");
                     return;
                 }
             };
@@ -573,12 +1066,12 @@ async fn main() {
             if !resp.status().is_success() {
                 let status = resp.status();
                 let body = resp.text().await.unwrap_or_default();
-                eprint_http("List failed", status);
+                eprint_http("Stats failed", status);
                 eprint_body_pretty_if_json(&body);
                 return;
             }
 
-            let items: Vec<crate::types::FileListItem> = match resp.json().await {
+            let stats: FileStatsResp = match resp.json().await {
                 Ok(j) => j,
                 Err(e) => {
                     eprintln!("Failed to parse JSON: {e}");
@@ -586,28 +1079,1306 @@ async fn main() {
                 }
             };
 
-            if items.is_empty() {
-                println!("No files found.");
-                return;
-            }
+            println!("File {} ({})", stats.file_id, stats.filename);
+            println!("Downloads:        {}", stats.download_count);
+            println!(
+                "Last downloaded:  {}",
+                stats
+                    .last_downloaded_at
+                    .map_or("never".to_string(), |t| t.to_string())
+            );
+            println!("Public downloads: {}", stats.public_downloads);
 
-            println!("{:<6} {:<7} {:<10} {:<8} {:<12} {}", "ID", "PUBLIC", "SIZE", "ACCESS", "UPLOADED_AT", "NAME");
-            for f in items {
+            if !stats.downloaders.is_empty() {
                 println!(
-                    "{:<6} {:<7} {:<10} {:<8} {:<12} {}",
-                    f.file_id,
-                    if f.is_public { "yes" } else { "no" },
-                    f.size,
-                    f.access,
-                    f.uploaded_at,
-                    f.filename
+                    "\n{:<8} {:<20} {:<10} LAST_DOWNLOADED_AT",
+                    "USER_ID", "USERNAME", "DOWNLOADS"
                 );
+                for d in stats.downloaders {
+                    println!(
+                        "{:<8} {:<20} {:<10} {}",
+                        d.user_id,
+                        d.username.as_deref().unwrap_or("(deleted)"),
+                        d.downloads,
+                        d.last_downloaded_at
+                    );
+                }
             }
         }
 
-        Command::Logout => match logout_local() {
-            Ok(()) => println!("Logged out."),
-            Err(e) => eprintln!("Failed to remove: {e}"),
-        },
+        Command::Tag(cmd) => {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let client = reqwest::Client::new();
+            let req = match &cmd {
+                TagCommand::Add { file_id, tags } => client
+                    .post(format!("{}/file/{file_id}/tags", cli.base))
+                    .json(&serde_json::json!({ "tags": tags })),
+                TagCommand::Remove { file_id, tag } => {
+                    let mut url = reqwest::Url::parse(&format!("{}/file/{file_id}/tags", cli.base))
+                        .expect("valid base URL");
+                    url.path_segments_mut()
+                        .expect("base URL can have a path")
+                        .push(tag);
+                    client.delete(url)
+                }
+                TagCommand::Show { file_id } => {
+                    client.get(format!("{}/file/{file_id}/tags", cli.base))
+                }
+                TagCommand::List => client.get(format!("{}/tags", cli.base)),
+            };
+
+            let resp = match req.bearer_auth(tok).send().await {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Tag request failed: {e}");
+                    return;
+                }
+            };
+
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                eprint_http("Tag request failed", status);
+                eprint_body_pretty_if_json(&body);
+                return;
+            }
+
+            match cmd {
+                TagCommand::Remove { file_id, tag } => {
+                    println!("Removed tag {tag} from file {file_id}")
+                }
+                TagCommand::List => {
+                    let tags: Vec<TagCountResp> = match resp.json().await {
+                        Ok(j) => j,
+                        Err(e) => {
+                            eprintln!("Failed to parse JSON: {e}");
+                            return;
+                        }
+                    };
+                    if tags.is_empty() {
+                        println!("No tags.");
+                        return;
+                    }
+                    println!("{:<32} FILES", "TAG");
+                    for t in tags {
+                        println!("{:<32} {}", t.tag, t.files);
+                    }
+                }
+                TagCommand::Add { .. } | TagCommand::Show { .. } => {
+                    let out: FileTagsResp = match resp.json().await {
+                        Ok(j) => j,
+                        Err(e) => {
+                            eprintln!("Failed to parse JSON: {e}");
+                            return;
+                        }
+                    };
+                    if out.tags.is_empty() {
+                        println!("File {} has no tags.", out.file_id);
+                    } else {
+                        println!("File {} tags: {}", out.file_id, out.tags.join(", "));
+                    }
+                }
+            }
+        }
+
+        Command::Meta(cmd) => {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let client = reqwest::Client::new();
+            let req = match cmd {
+                MetaCommand::Show { file_id } => {
+                    client.get(format!("{}/file/{file_id}/metadata", cli.base))
+                }
+                MetaCommand::Set { file_id, pairs } => {
+                    let mut changes = serde_json::Map::new();
+                    for pair in pairs {
+                        let Some((key, value)) = pair.split_once('=') else {
+                            eprintln!("Expected key=value, got {pair:?}");
+                            return;
+                        };
+                        changes.insert(key.to_string(), value.into());
+                    }
+                    client
+                        .patch(format!("{}/file/{file_id}/metadata", cli.base))
+                        .json(&changes)
+                }
+                MetaCommand::Unset { file_id, keys } => {
+                    let changes: serde_json::Map<String, serde_json::Value> = keys
+                        .into_iter()
+                        .map(|k| (k, serde_json::Value::Null))
+                        .collect();
+                    client
+                        .patch(format!("{}/file/{file_id}/metadata", cli.base))
+                        .json(&changes)
+                }
+            };
+
+            let resp = match req.bearer_auth(tok).send().await {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Metadata request failed: {e}");
+                    return;
+                }
+            };
+
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                eprint_http("Metadata request failed", status);
+                eprint_body_pretty_if_json(&body);
+                return;
+            }
+
+            let out: FileMetadataResp = match resp.json().await {
+                Ok(j) => j,
+                Err(e) => {
+                    eprintln!("Failed to parse JSON: {e}");
+                    return;
+                }
+            };
+
+            if out.metadata.is_empty() {
+                println!("File {} has no metadata.", out.file_id);
+                return;
+            }
+            for (key, value) in out.metadata {
+                println!("{key}={value}");
+            }
+        }
+
+        Command::TwoFactor(cmd) => {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            match cmd {
+                TwoFactorCommand::Enable => {
+                    let url = format!("{}/me/2fa/enroll", cli.base);
+                    let resp = match reqwest::Client::new()
+                        .post(url)
+                        .bearer_auth(tok)
+                        .send()
+                        .await
+                    {
+                        Ok(r) => r,
+                        Err(e) => {
+                            eprintln!("Enroll request failed: {e}");
+                            return;
+                        }
+                    };
+
+                    if !resp.status().is_success() {
+                        let status = resp.status();
+                        let body = resp.text().await.unwrap_or_default();
+                        eprint_http("Enroll failed", status);
+                        eprint_body_pretty_if_json(&body);
+                        return;
+                    }
+
+                    let enroll: TotpEnrollResp = match resp.json().await {
+                        Ok(j) => j,
+                        Err(e) => {
+                            eprintln!("Failed to parse JSON: {e}");
+                            return;
+                        }
+                    };
+
+                    println!("Add this account to your authenticator app:");
+                    println!("  {}", enroll.otpauth_uri);
+                    println!("Or enter the secret manually: {}", enroll.secret);
+
+                    let Some(code) = prompt("Code from the app: ") else {
+                        eprintln!("No code entered. 2FA not enabled.");
+                        return;
+                    };
+
+                    let url = format!("{}/me/2fa/confirm", cli.base);
+                    let resp = match reqwest::Client::new()
+                        .post(url)
+                        .bearer_auth(tok)
+                        .json(&serde_json::json!({ "code": code }))
+                        .send()
+                        .await
+                    {
+                        Ok(r) => r,
+                        Err(e) => {
+                            eprintln!("Confirm request failed: {e}");
+                            return;
+                        }
+                    };
+
+                    if !resp.status().is_success() {
+                        let status = resp.status();
+                        let body = resp.text().await.unwrap_or_default();
+                        eprint_http("Confirm failed", status);
+                        eprint_body_pretty_if_json(&body);
+                        return;
+                    }
+
+                    let confirmed: TotpConfirmResp = match resp.json().await {
+                        Ok(j) => j,
+                        Err(e) => {
+                            eprintln!("Failed to parse JSON: {e}");
+                            return;
+                        }
+                    };
+
+                    println!("2FA enabled. Save these recovery codes; each works once:");
+                    for code in confirmed.recovery_codes {
+                        println!("  {code}");
+                    }
+                }
+
+                TwoFactorCommand::Disable { password } => {
+                    let Some(code) = prompt("Two-factor code (or recovery code): ") else {
+                        eprintln!("No code entered.");
+                        return;
+                    };
+
+                    let url = format!("{}/me/2fa/disable", cli.base);
+                    let resp = match reqwest::Client::new()
+                        .post(url)
+                        .bearer_auth(tok)
+                        .json(&serde_json::json!({ "password": password, "code": code }))
+                        .send()
+                        .await
+                    {
+                        Ok(r) => r,
+                        Err(e) => {
+                            eprintln!("Disable request failed: {e}");
+                            return;
+                        }
+                    };
+
+                    if resp.status() == reqwest::StatusCode::NO_CONTENT {
+                        println!("2FA disabled.");
+                        return;
+                    }
+
+                    let status = resp.status();
+                    let body = resp.text().await.unwrap_or_default();
+                    eprint_http("Disable failed", status);
+                    eprint_body_pretty_if_json(&body);
+                }
+            }
+        }
+
+        Command::Password(PasswordCommand::Change { current, new }) => {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let url = format!("{}/me/password", cli.base);
+            let resp = match reqwest::Client::new()
+                .post(url)
+                .bearer_auth(tok)
+                .json(&serde_json::json!({ "current_password": current, "new_password": new }))
+                .send()
+                .await
+            {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Password change request failed: {e}");
+                    return;
+                }
+            };
+
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                eprint_http("Password change failed", status);
+                eprint_body_pretty_if_json(&body);
+                return;
+            }
+
+            let auth: AuthResp = match resp.json().await {
+                Ok(j) => j,
+                Err(e) => {
+                    eprintln!("Failed to parse JSON: {e}");
+                    return;
+                }
+            };
+
+            let store = TokenStore {
+                access_token: auth.access_token,
+                refresh_token: auth.refresh_token,
+            };
+
+            if let Err(e) = save_tokens(&store) {
+                eprintln!("Failed to save tokens: {e}");
+                return;
+            }
+
+            println!("Password changed. Other sessions have been logged out.");
+        }
+
+        Command::Password(PasswordCommand::Forgot { username }) => {
+            let url = format!("{}/password/forgot", cli.base);
+            let resp = match reqwest::Client::new()
+                .post(url)
+                .json(&serde_json::json!({ "username": username }))
+                .send()
+                .await
+            {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Reset request failed: {e}");
+                    return;
+                }
+            };
+
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                eprint_http("Reset request failed", status);
+                eprint_body_pretty_if_json(&body);
+                return;
+            }
+
+            println!(
+                "If the account exists and has a verified email address, a reset token has been mailed to it."
+            );
+        }
+
+        Command::Password(PasswordCommand::Reset { token, new }) => {
+            let url = format!("{}/password/reset", cli.base);
+            let resp = match reqwest::Client::new()
+                .post(url)
+                .json(&serde_json::json!({ "token": token, "new_password": new }))
+                .send()
+                .await
+            {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Reset request failed: {e}");
+                    return;
+                }
+            };
+
+            if resp.status() == reqwest::StatusCode::NO_CONTENT {
+                let _ = logout_local();
+                println!("Password reset. Run: sfs login <user> <pass>");
+                return;
+            }
+
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            eprint_http("Reset failed", status);
+            eprint_body_pretty_if_json(&body);
+        }
+
+        Command::Account(cmd) => {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            match cmd {
+                AccountCommand::Export { out } => {
+                    let url = format!("{}/me/export", cli.base);
+                    let resp = match reqwest::Client::new()
+                        .get(url)
+                        .bearer_auth(tok)
+                        .send()
+                        .await
+                    {
+                        Ok(r) => r,
+                        Err(e) => {
+                            eprintln!("Export request failed: {e}");
+                            return;
+                        }
+                    };
+
+                    if !resp.status().is_success() {
+                        let status = resp.status();
+                        let body = resp.text().await.unwrap_or_default();
+                        eprint_http("Export failed", status);
+                        eprint_body_pretty_if_json(&body);
+                        return;
+                    }
+
+                    let bytes = match resp.bytes().await {
+                        Ok(b) => b,
+                        Err(e) => {
+                            eprintln!("Failed reading response bytes: {e}");
+                            return;
+                        }
+                    };
+
+                    if let Err(e) = tokio::fs::write(&out, &bytes).await {
+                        eprintln!("Failed writing to {out}: {e}");
+                        return;
+                    }
+
+                    println!("Saved export to {out} (see manifest.json inside)");
+                }
+
+                AccountCommand::Delete { password, code } => {
+                    let Some(answer) = prompt(
+                        "This deletes your account and all your files. Type 'delete' to confirm: ",
+                    ) else {
+                        eprintln!("Aborted.");
+                        return;
+                    };
+                    if answer != "delete" {
+                        eprintln!("Aborted.");
+                        return;
+                    }
+
+                    let url = format!("{}/me", cli.base);
+                    let resp = match reqwest::Client::new()
+                        .delete(url)
+                        .bearer_auth(tok)
+                        .json(&serde_json::json!({ "password": password, "code": code }))
+                        .send()
+                        .await
+                    {
+                        Ok(r) => r,
+                        Err(e) => {
+                            eprintln!("Delete request failed: {e}");
+                            return;
+                        }
+                    };
+
+                    if resp.status() == reqwest::StatusCode::NO_CONTENT {
+                        let _ = logout_local();
+                        println!("Account deleted. Saved tokens removed.");
+                        return;
+                    }
+
+                    let status = resp.status();
+                    let body = resp.text().await.unwrap_or_default();
+                    eprint_http("Delete failed", status);
+                    eprint_body_pretty_if_json(&body);
+                }
+            }
+        }
+
+        Command::Email(EmailCommand::Verify { token }) => {
+            let url = format!("{}/email/verify", cli.base);
+            let resp = match reqwest::Client::new()
+                .post(url)
+                .json(&serde_json::json!({ "token": token }))
+                .send()
+                .await
+            {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Verify request failed: {e}");
+                    return;
+                }
+            };
+
+            if resp.status() == reqwest::StatusCode::NO_CONTENT {
+                println!("Email address verified.");
+                return;
+            }
+
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            eprint_http("Verify failed", status);
+            eprint_body_pretty_if_json(&body);
+        }
+
+        Command::Email(cmd) => {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let client = reqwest::Client::new();
+            let (req, done) = match cmd {
+                EmailCommand::Set { email } => (
+                    client
+                        .put(format!("{}/me/email", cli.base))
+                        .json(&serde_json::json!({ "email": email })),
+                    "Email address saved. Check your mail, then run: sfs email verify <token>",
+                ),
+                EmailCommand::Remove => (
+                    client
+                        .put(format!("{}/me/email", cli.base))
+                        .json(&serde_json::json!({ "email": null })),
+                    "Email address removed.",
+                ),
+                EmailCommand::Resend => (
+                    client.post(format!("{}/me/email/resend", cli.base)),
+                    "Verification token sent.",
+                ),
+                EmailCommand::Verify { .. } => unreachable!(),
+            };
+
+            let resp = match req.bearer_auth(tok).send().await {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Email request failed: {e}");
+                    return;
+                }
+            };
+
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                eprint_http("Email request failed", status);
+                eprint_body_pretty_if_json(&body);
+                return;
+            }
+
+            println!("{done}");
+        }
+
+        Command::Sso(SsoCommand::Login) => {
+            let url = format!("{}/oidc/login", cli.base);
+            let client = match reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+            {
+                Ok(c) => c,
+                Err(e) => {
+                    eprintln!("Failed to build HTTP client: {e}");
+                    return;
+                }
+            };
+
+            let resp = match client.get(url).send().await {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("SSO request failed: {e}");
+                    return;
+                }
+            };
+
+            let location = resp
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string);
+            let Some(location) = location else {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                eprint_http("SSO login failed", status);
+                eprint_body_pretty_if_json(&body);
+                return;
+            };
+
+            println!("Open this URL in a browser and sign in:\n\n{location}\n");
+            let Some(pasted) = prompt("Paste the JSON shown after signing in: ") else {
+                eprintln!("Nothing pasted.");
+                return;
+            };
+
+            let login: LoginResp = match serde_json::from_str(&pasted) {
+                Ok(j) => j,
+                Err(e) => {
+                    eprintln!("Failed to parse JSON: {e}");
+                    return;
+                }
+            };
+
+            let auth = match login {
+                LoginResp::Tokens(auth) => auth,
+                LoginResp::TwoFactor(challenge) => {
+                    match complete_two_factor(&cli.base, &challenge).await {
+                        Some(auth) => auth,
+                        None => return,
+                    }
+                }
+            };
+
+            let scope = auth.scope;
+            let store = TokenStore {
+                access_token: auth.access_token,
+                refresh_token: auth.refresh_token,
+            };
+
+            if let Err(e) = save_tokens(&store) {
+                eprintln!("Failed to save tokens: {e}");
+                return;
+            }
+
+            println!("Logged in. Scopes: {scope}");
+        }
+
+        Command::Sso(SsoCommand::Link) => {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let url = format!("{}/me/oidc/link", cli.base);
+            let resp = match reqwest::Client::new()
+                .post(url)
+                .bearer_auth(tok)
+                .send()
+                .await
+            {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Link request failed: {e}");
+                    return;
+                }
+            };
+
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                eprint_http("Link request failed", status);
+                eprint_body_pretty_if_json(&body);
+                return;
+            }
+
+            let link: OidcLinkResp = match resp.json().await {
+                Ok(j) => j,
+                Err(e) => {
+                    eprintln!("Failed to parse JSON: {e}");
+                    return;
+                }
+            };
+
+            println!(
+                "Open this URL in a browser and sign in:\n\n{}\n",
+                link.authorization_url
+            );
+            let Some(pasted) = prompt("Paste the JSON shown after signing in: ") else {
+                eprintln!("Nothing pasted.");
+                return;
+            };
+
+            let pending: OidcLinkPendingResp = match serde_json::from_str(&pasted) {
+                Ok(j) => j,
+                Err(e) => {
+                    eprintln!("Failed to parse JSON: {e}");
+                    return;
+                }
+            };
+
+            let url = format!("{}/me/oidc/link/confirm", cli.base);
+            let resp = match reqwest::Client::new()
+                .post(url)
+                .bearer_auth(tok)
+                .json(&serde_json::json!({ "link_code": pending.link_code }))
+                .send()
+                .await
+            {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Link request failed: {e}");
+                    return;
+                }
+            };
+
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                eprint_http("Link failed", status);
+                eprint_body_pretty_if_json(&body);
+                return;
+            }
+
+            match resp.json::<OidcLinkedResp>().await {
+                Ok(linked) => println!("Linked {} ({}).", linked.subject, linked.issuer),
+                Err(e) => eprintln!("Failed to parse JSON: {e}"),
+            }
+        }
+
+        Command::Webhook(WebhookCommand::Listen {
+            port,
+            secret,
+            status,
+        }) => {
+            if let Err(e) = webhook_listener::listen(port, secret, status).await {
+                eprintln!("Listener failed: {e}");
+            }
+        }
+
+        Command::Webhook(cmd) => {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let client = reqwest::Client::new();
+            let (req, what) = match &cmd {
+                WebhookCommand::Add {
+                    url,
+                    events,
+                    secret,
+                } => (
+                    client
+                        .post(format!("{}/me/webhooks", cli.base))
+                        .json(&serde_json::json!({
+                            "url": url,
+                            "events": events,
+                            "secret": secret,
+                        })),
+                    "Webhook creation",
+                ),
+                WebhookCommand::List => (
+                    client.get(format!("{}/me/webhooks", cli.base)),
+                    "Webhook list",
+                ),
+                WebhookCommand::Remove { id } => (
+                    client.delete(format!("{}/me/webhooks/{id}", cli.base)),
+                    "Webhook removal",
+                ),
+                WebhookCommand::Ping { id } => (
+                    client.post(format!("{}/me/webhooks/{id}/ping", cli.base)),
+                    "Webhook ping",
+                ),
+                WebhookCommand::Deliveries { id, limit } => {
+                    let mut req = client.get(format!("{}/me/webhooks/{id}/deliveries", cli.base));
+                    if let Some(limit) = limit {
+                        req = req.query(&[("limit", limit)]);
+                    }
+                    (req, "Delivery log")
+                }
+                WebhookCommand::Listen { .. } => unreachable!(),
+            };
+
+            let resp = match req.bearer_auth(tok).send().await {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Webhook request failed: {e}");
+                    return;
+                }
+            };
+
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                eprint_http(&format!("{what} failed"), status);
+                eprint_body_pretty_if_json(&body);
+                return;
+            }
+
+            match cmd {
+                WebhookCommand::Add { .. } => {
+                    let created: WebhookCreatedResp = match resp.json().await {
+                        Ok(j) => j,
+                        Err(e) => {
+                            eprintln!("Failed to parse JSON: {e}");
+                            return;
+                        }
+                    };
+                    println!(
+                        "Created webhook id={} url={} events={}",
+                        created.id,
+                        created.url,
+                        created.events.join(",")
+                    );
+                    println!("\nSigning secret: {}\n", created.secret);
+                    println!(
+                        "Store it now; it will not be shown again. Test with: sfs webhook ping {}",
+                        created.id
+                    );
+                }
+
+                WebhookCommand::List => {
+                    let hooks: Vec<WebhookResp> = match resp.json().await {
+                        Ok(j) => j,
+                        Err(e) => {
+                            eprintln!("Failed to parse JSON: {e}");
+                            return;
+                        }
+                    };
+                    if hooks.is_empty() {
+                        println!("No webhooks.");
+                        return;
+                    }
+                    println!(
+                        "{:<5} {:<8} {:<7} {:<40} EVENTS",
+                        "ID", "PENDING", "FAILED", "URL"
+                    );
+                    for h in hooks {
+                        println!(
+                            "{:<5} {:<8} {:<7} {:<40} {}",
+                            h.id,
+                            h.pending_deliveries,
+                            h.failed_deliveries,
+                            h.url,
+                            h.events.join(",")
+                        );
+                    }
+                }
+
+                WebhookCommand::Remove { id } => println!("Webhook {id} removed."),
+
+                WebhookCommand::Ping { id } => {
+                    println!("Ping queued. Check: sfs webhook deliveries {id}")
+                }
+
+                WebhookCommand::Deliveries { .. } => {
+                    let deliveries: Vec<WebhookDeliveryResp> = match resp.json().await {
+                        Ok(j) => j,
+                        Err(e) => {
+                            eprintln!("Failed to parse JSON: {e}");
+                            return;
+                        }
+                    };
+                    if deliveries.is_empty() {
+                        println!("No deliveries.");
+                        return;
+                    }
+                    println!(
+                        "{:<6} {:<16} {:<10} {:<8} {:<11} {:<11} {:<6} ERROR",
+                        "ID", "EVENT", "STATUS", "ATTEMPTS", "CREATED_AT", "NEXT_TRY", "HTTP"
+                    );
+                    for d in deliveries {
+                        println!(
+                            "{:<6} {:<16} {:<10} {:<8} {:<11} {:<11} {:<6} {}",
+                            d.id,
+                            d.event,
+                            d.status,
+                            d.attempts,
+                            d.created_at,
+                            d.next_attempt_at.map_or("-".to_string(), |t| t.to_string()),
+                            d.last_status_code
+                                .map_or("-".to_string(), |c| c.to_string()),
+                            d.last_error.as_deref().unwrap_or("")
+                        );
+                    }
+                }
+
+                WebhookCommand::Listen { .. } => unreachable!(),
+            }
+        }
+
+        Command::Apikey(cmd) => {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let client = reqwest::Client::new();
+            match cmd {
+                ApiKeyCommand::Create {
+                    name,
+                    scopes,
+                    expires_days,
+                    tag,
+                } => {
+                    let url = format!("{}/me/api-keys", cli.base);
+                    let resp = match client
+                        .post(url)
+                        .bearer_auth(tok)
+                        .json(&serde_json::json!({
+                            "name": name,
+                            "scopes": scopes,
+                            "expires_in_days": expires_days,
+                            "tag": tag,
+                        }))
+                        .send()
+                        .await
+                    {
+                        Ok(r) => r,
+                        Err(e) => {
+                            eprintln!("API key request failed: {e}");
+                            return;
+                        }
+                    };
+
+                    if !resp.status().is_success() {
+                        let status = resp.status();
+                        let body = resp.text().await.unwrap_or_default();
+                        eprint_http("API key creation failed", status);
+                        eprint_body_pretty_if_json(&body);
+                        return;
+                    }
+
+                    let created: ApiKeyCreatedResp = match resp.json().await {
+                        Ok(j) => j,
+                        Err(e) => {
+                            eprintln!("Failed to parse JSON: {e}");
+                            return;
+                        }
+                    };
+
+                    println!(
+                        "Created API key id={} scopes={}{}",
+                        created.id,
+                        created.scopes.join(","),
+                        created.tag.map(|t| format!(" tag={t}")).unwrap_or_default()
+                    );
+                    println!("\n{}\n", created.key);
+                    println!(
+                        "Store it now; it will not be shown again. Use it with: SFS_API_KEY=<key> sfs ..."
+                    );
+                }
+
+                ApiKeyCommand::List => {
+                    let url = format!("{}/me/api-keys", cli.base);
+                    let resp = match client.get(url).bearer_auth(tok).send().await {
+                        Ok(r) => r,
+                        Err(e) => {
+                            eprintln!("API key request failed: {e}");
+                            return;
+                        }
+                    };
+
+                    let status = resp.status();
+                    let body = resp.text().await.unwrap_or_default();
+                    if !status.is_success() {
+                        eprint_http("API key list failed", status);
+                        eprint_body_pretty_if_json(&body);
+                        return;
+                    }
+                    print_body_pretty_if_json(&body);
+                }
+
+                ApiKeyCommand::Revoke { id } => {
+                    let url = format!("{}/me/api-keys/{id}", cli.base);
+                    let resp = match client.delete(url).bearer_auth(tok).send().await {
+                        Ok(r) => r,
+                        Err(e) => {
+                            eprintln!("API key request failed: {e}");
+                            return;
+                        }
+                    };
+
+                    if resp.status() == reqwest::StatusCode::NO_CONTENT {
+                        println!("API key {id} revoked.");
+                        return;
+                    }
+
+                    let status = resp.status();
+                    let body = resp.text().await.unwrap_or_default();
+                    eprint_http("Revoke failed", status);
+                    eprint_body_pretty_if_json(&body);
+                }
+            }
+        }
+
+        Command::Audit {
+            action,
+            outcome,
+            actor,
+            file,
+            since,
+            limit,
+        } => {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let mut query: Vec<(&str, String)> = Vec::new();
+            if let Some(action) = action {
+                query.push(("action", action));
+            }
+            if let Some(outcome) = outcome {
+                query.push(("outcome", outcome));
+            }
+            if let Some(actor) = actor {
+                query.push(("actor_id", actor.to_string()));
+            }
+            if let Some(file) = file {
+                query.push(("target_type", "file".to_string()));
+                query.push(("target_id", file.to_string()));
+            }
+            if let Some(since) = since {
+                query.push(("since", since.to_string()));
+            }
+            if let Some(limit) = limit {
+                query.push(("limit", limit.to_string()));
+            }
+
+            let url = format!("{}/audit", cli.base);
+            let resp = match reqwest::Client::new()
+                .get(url)
+                .query(&query)
+                .bearer_auth(tok)
+                .send()
+                .await
+            {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Audit request failed: {e}");
+                    return;
+                }
+            };
+
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                eprint_http("Audit failed", status);
+                eprint_body_pretty_if_json(&body);
+                return;
+            }
+
+            let events: Vec<AuditEventResp> = match resp.json().await {
+                Ok(j) => j,
+                Err(e) => {
+                    eprintln!("Failed to parse JSON: {e}");
+                    return;
+                }
+            };
+
+            if events.is_empty() {
+                println!("No events found.");
+                return;
+            }
+
+            println!(
+                "{:<6} {:<11} {:<22} {:<6} {:<8} {:<16} {:<16} DETAIL",
+                "ID", "AT", "ACTION", "ACTOR", "OUTCOME", "TARGET", "IP"
+            );
+            for e in events {
+                let target = match (&e.target_type, &e.target_id) {
+                    (Some(t), Some(id)) => format!("{t}:{id}"),
+                    _ => "-".to_string(),
+                };
+                println!(
+                    "{:<6} {:<11} {:<22} {:<6} {:<8} {:<16} {:<16} {}",
+                    e.id,
+                    e.at,
+                    e.action,
+                    e.actor_id.map_or("-".to_string(), |id| id.to_string()),
+                    e.outcome,
+                    target,
+                    e.ip.as_deref().unwrap_or("-"),
+                    e.detail.as_deref().unwrap_or("")
+                );
+            }
+        }
+
+        Command::AuditVerify { checkpoint } => {
+            let store = match load_tokens() {
+                Ok(s) => s,
+                Err(_) => {
+                    eprintln!("No saved tokens. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let tok = match require_access(&store) {
+                Some(t) => t,
+                None => {
+                    eprintln!("No access token saved. Run: sfs login <user> <pass>");
+                    return;
+                }
+            };
+
+            let client = reqwest::Client::new();
+
+            if checkpoint {
+                let url = format!("{}/admin/audit/checkpoint", cli.base);
+                let resp = match client.post(url).bearer_auth(tok).send().await {
+                    Ok(r) => r,
+                    Err(e) => {
+                        eprintln!("Checkpoint request failed: {e}");
+                        return;
+                    }
+                };
+
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                if status == reqwest::StatusCode::NO_CONTENT {
+                    println!("Latest checkpoint already covers the head of the chain.");
+                } else if status.is_success() {
+                    match serde_json::from_str::<AuditCheckpointResp>(&body) {
+                        Ok(cp) => println!("Signed checkpoint {} at event {}.", cp.id, cp.event_id),
+                        Err(_) => print_body_pretty_if_json(&body),
+                    }
+                } else {
+                    eprint_http("Checkpoint failed", status);
+                    eprint_body_pretty_if_json(&body);
+                    return;
+                }
+            }
+
+            let url = format!("{}/admin/audit/verify", cli.base);
+            let resp = match client.get(url).bearer_auth(tok).send().await {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Verify request failed: {e}");
+                    return;
+                }
+            };
+
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                eprint_http("Verify failed", status);
+                eprint_body_pretty_if_json(&body);
+                return;
+            }
+
+            let report: AuditVerifyResp = match resp.json().await {
+                Ok(j) => j,
+                Err(e) => {
+                    eprintln!("Failed to parse JSON: {e}");
+                    return;
+                }
+            };
+
+            println!(
+                "Checked {} events and {} checkpoints.",
+                report.events_checked, report.checkpoints_checked
+            );
+            if let (Some(id), Some(hash)) = (report.head_id, &report.head_hash) {
+                println!("Head: event {id} {hash}");
+            }
+            match &report.latest_checkpoint {
+                Some(cp) => println!(
+                    "Latest checkpoint: {} at event {} (created {}, key {}); {} events since",
+                    cp.id, cp.event_id, cp.created_at, cp.key_id, report.unsigned_events
+                ),
+                None => println!(
+                    "No checkpoints yet; {} events unsigned",
+                    report.unsigned_events
+                ),
+            }
+
+            if report.valid {
+                println!("Audit log OK.");
+            } else if let Some(broken) = report.first_broken {
+                let at = match (broken.event_id, broken.checkpoint_id) {
+                    (_, Some(cp)) => format!("checkpoint {cp}"),
+                    (Some(id), None) => format!("event {id}"),
+                    (None, None) => "unknown position".to_string(),
+                };
+                eprintln!("Audit log BROKEN at {at}: {}", broken.reason);
+            }
+        }
+
+        Command::Logout => {
+            // Best effort: the local tokens go even if the server cannot be told
+            if let Some(tok) = load_tokens().ok().as_ref().and_then(require_access) {
+                let url = format!("{}/logout", cli.base);
+                match reqwest::Client::new()
+                    .post(url)
+                    .bearer_auth(tok)
+                    .send()
+                    .await
+                {
+                    Ok(resp) if resp.status().is_success() => {}
+                    Ok(resp) => eprint_http("Server logout failed", resp.status()),
+                    Err(e) => eprintln!("Server logout failed: {e}"),
+                }
+            }
+
+            match logout_local() {
+                Ok(()) => println!("Logged out."),
+                Err(e) => eprintln!("Failed to remove: {e}"),
+            }
+        }
     }
 }
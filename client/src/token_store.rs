@@ -17,7 +17,20 @@ pub fn save_tokens(store: &TokenStore) -> std::io::Result<()> {
     fs::write(p, s)
 }
 
+/// Environment variable holding a personal API key. When set, it is used as
+/// the bearer token instead of the saved tokens (handy for CI).
+pub const API_KEY_ENV: &str = "SFS_API_KEY";
+
 pub fn load_tokens() -> std::io::Result<TokenStore> {
+    if let Ok(key) = std::env::var(API_KEY_ENV)
+        && !key.trim().is_empty()
+    {
+        return Ok(TokenStore {
+            access_token: key.trim().to_string(),
+            refresh_token: String::new(),
+        });
+    }
+
     let p = token_path();
     let s = fs::read_to_string(p)?;
     Ok(serde_json::from_str(&s).unwrap_or_default())
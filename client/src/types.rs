@@ -4,12 +4,54 @@ use serde::{Deserialize, Serialize};
 pub struct AuthReq<'a> {
     pub username: &'a str,
     pub password: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+pub struct RegisterReq<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<&'a str>,
 }
 
 #[derive(Deserialize)]
 pub struct AuthResp {
     pub access_token: String,
     pub refresh_token: String,
+    #[serde(default)]
+    pub scope: String,
+}
+
+/// `POST /login` returns either tokens or a 2FA challenge
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum LoginResp {
+    TwoFactor(TwoFactorChallengeResp),
+    Tokens(AuthResp),
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorChallengeResp {
+    pub challenge_token: String,
+}
+
+#[derive(Serialize)]
+pub struct TwoFactorLoginReq<'a> {
+    pub challenge_token: &'a str,
+    pub code: &'a str,
+}
+
+#[derive(Deserialize)]
+pub struct TotpEnrollResp {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Deserialize)]
+pub struct TotpConfirmResp {
+    pub recovery_codes: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -31,6 +73,16 @@ pub struct UploadResp {
     pub filename: String,
     pub size: u64,
     pub is_public: bool,
+    #[serde(default)]
+    pub content_type: String,
+}
+
+/// `GET /files` and `GET /files/search` response
+#[derive(Debug, Deserialize)]
+pub struct FileListPageResp {
+    pub items: Vec<FileListItem>,
+    pub total: u64,
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -41,4 +93,181 @@ pub struct FileListItem {
     pub is_public: bool,
     pub uploaded_at: i64,
     pub access: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Only set on files you own
+    #[serde(default)]
+    pub download_count: Option<u64>,
+}
+
+/// `GET`/`POST /file/:id/tags` response
+#[derive(Deserialize)]
+pub struct FileTagsResp {
+    pub file_id: u32,
+    pub tags: Vec<String>,
+}
+
+/// One entry of `GET /tags`
+#[derive(Deserialize)]
+pub struct TagCountResp {
+    pub tag: String,
+    pub files: u64,
+}
+
+/// `GET`/`PATCH /file/:id/metadata` response
+#[derive(Deserialize)]
+pub struct FileMetadataResp {
+    pub file_id: u32,
+    pub metadata: std::collections::BTreeMap<String, String>,
+}
+
+/// `GET /file/:id/stats` response
+#[derive(Deserialize)]
+pub struct FileStatsResp {
+    pub file_id: u32,
+    pub filename: String,
+    pub download_count: u64,
+    pub last_downloaded_at: Option<i64>,
+    pub public_downloads: u64,
+    pub downloaders: Vec<DownloaderStatsResp>,
+}
+
+#[derive(Deserialize)]
+pub struct DownloaderStatsResp {
+    pub user_id: u32,
+    pub username: Option<String>,
+    pub downloads: u64,
+    pub last_downloaded_at: i64,
+}
+
+/// `POST /me/webhooks` response
+#[derive(Deserialize)]
+pub struct WebhookCreatedResp {
+    pub id: u32,
+    pub url: String,
+    pub events: Vec<String>,
+    pub secret: String,
+}
+
+/// One webhook from `GET /me/webhooks`
+#[derive(Deserialize)]
+pub struct WebhookResp {
+    pub id: u32,
+    pub url: String,
+    pub events: Vec<String>,
+    pub pending_deliveries: u32,
+    pub failed_deliveries: u32,
+}
+
+/// One delivery from `GET /me/webhooks/:id/deliveries`
+#[derive(Deserialize)]
+pub struct WebhookDeliveryResp {
+    pub id: i64,
+    pub event: String,
+    pub status: String,
+    pub attempts: u32,
+    pub created_at: i64,
+    pub next_attempt_at: Option<i64>,
+    pub last_status_code: Option<u16>,
+    pub last_error: Option<String>,
+}
+
+/// `POST /me/oidc/link` response
+#[derive(Deserialize)]
+pub struct OidcLinkResp {
+    pub authorization_url: String,
+}
+
+/// What `GET /oidc/callback` shows at the end of a link flow
+#[derive(Deserialize)]
+pub struct OidcLinkPendingResp {
+    pub link_code: String,
+}
+
+/// `POST /me/oidc/link/confirm` response
+#[derive(Deserialize)]
+pub struct OidcLinkedResp {
+    pub issuer: String,
+    pub subject: String,
+}
+
+/// `POST /me/api-keys` response
+#[derive(Deserialize)]
+pub struct ApiKeyCreatedResp {
+    pub id: u32,
+    pub key: String,
+    pub scopes: Vec<String>,
+    pub tag: Option<String>,
+}
+
+/// One event from `GET /audit`
+#[derive(Deserialize)]
+pub struct AuditEventResp {
+    pub id: i64,
+    pub at: i64,
+    pub action: String,
+    pub actor_id: Option<u32>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub ip: Option<String>,
+    pub outcome: String,
+    pub detail: Option<String>,
+}
+
+/// `GET /admin/audit/verify` response
+#[derive(Deserialize)]
+pub struct AuditVerifyResp {
+    pub valid: bool,
+    pub events_checked: u64,
+    pub checkpoints_checked: u64,
+    pub head_id: Option<i64>,
+    pub head_hash: Option<String>,
+    pub latest_checkpoint: Option<AuditCheckpointResp>,
+    pub unsigned_events: u64,
+    pub first_broken: Option<AuditBrokenLinkResp>,
+}
+
+#[derive(Deserialize)]
+pub struct AuditCheckpointResp {
+    pub id: i64,
+    pub event_id: i64,
+    pub created_at: i64,
+    pub key_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct AuditBrokenLinkResp {
+    pub event_id: Option<i64>,
+    pub checkpoint_id: Option<i64>,
+    pub reason: String,
+}
+
+/// Body of every server error response
+#[derive(Deserialize)]
+pub struct ApiErrorResp {
+    pub code: String,
+    pub message: String,
+    pub request_id: Option<String>,
+    pub details: Option<serde_json::Value>,
+}
+
+impl ApiErrorResp {
+    /// Human-readable lines: code and message, then details and request id if present
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("Error [{}]: {}", self.code, self.message)];
+        if let Some(serde_json::Value::Object(details)) = &self.details {
+            for (key, value) in details {
+                match value {
+                    serde_json::Value::String(s) => lines.push(format!("  {key}: {s}")),
+                    other => lines.push(format!("  {key}: {other}")),
+                }
+            }
+        }
+        if let Some(id) = &self.request_id {
+            lines.push(format!("Request id: {id}"));
+        }
+        lines
+    }
 }
@@ -0,0 +1,144 @@
+//! A tiny HTTP receiver for testing webhooks locally: prints each delivery and
+//! checks its signature.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Largest request head (request line and headers) accepted
+const MAX_HEAD_LEN: usize = 64 * 1024;
+
+/// Largest body accepted
+const MAX_BODY_LEN: usize = 1024 * 1024;
+
+/// Deliveries signed further from the local clock than this are reported as stale
+const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// Listen on `port` until interrupted, answering every delivery with `status`
+pub async fn listen(port: u16, secret: Option<String>, status: u16) -> std::io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    println!("Listening for webhooks on http://127.0.0.1:{port}/ (Ctrl-C to stop)");
+    if secret.is_none() {
+        println!("No --secret given; signatures are not checked.");
+    }
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let secret = secret.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, secret.as_deref(), status).await {
+                eprintln!("Bad request: {e}");
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, secret: Option<&str>, status: u16) -> std::io::Result<()> {
+    let mut buf = Vec::new();
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        if buf.len() > MAX_HEAD_LEN {
+            return Err(std::io::Error::other("request head too large"));
+        }
+        let mut chunk = [0u8; 4096];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(std::io::Error::other("connection closed mid-request"));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+        .collect();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    };
+
+    let len: usize = header("content-length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    if len > MAX_BODY_LEN {
+        return Err(std::io::Error::other("body too large"));
+    }
+    let mut body = buf[head_end + 4..].to_vec();
+    while body.len() < len {
+        let mut chunk = vec![0u8; len - body.len()];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(len);
+
+    let event = header("x-sfs-event").unwrap_or("-");
+    let delivery = header("x-sfs-delivery").unwrap_or("-");
+    let check = match secret {
+        Some(secret) => check_signature(
+            secret,
+            header("x-sfs-timestamp"),
+            header("x-sfs-signature"),
+            &body,
+        ),
+        None => "unchecked".to_string(),
+    };
+
+    println!("\n{request_line}  event={event} delivery={delivery} signature={check}");
+    match serde_json::from_slice::<serde_json::Value>(&body) {
+        Ok(json) => println!("{}", serde_json::to_string_pretty(&json).unwrap()),
+        Err(_) => println!("{}", String::from_utf8_lossy(&body)),
+    }
+
+    let status = if check == "invalid" { 401 } else { status };
+    let response =
+        format!("HTTP/1.1 {status} Webhook\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// `ok`, `stale` (valid but signed too long ago) or `invalid`
+fn check_signature(
+    secret: &str,
+    timestamp: Option<&str>,
+    signature: Option<&str>,
+    body: &[u8],
+) -> String {
+    let (Some(timestamp), Some(signature)) = (timestamp, signature) else {
+        return "invalid".to_string();
+    };
+    let Some(expected) = signature
+        .strip_prefix("sha256=")
+        .and_then(|s| hex::decode(s).ok())
+    else {
+        return "invalid".to_string();
+    };
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    if mac.verify_slice(&expected).is_err() {
+        return "invalid".to_string();
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    match timestamp.parse::<i64>() {
+        Ok(t) if (now - t).abs() <= MAX_CLOCK_SKEW_SECS => "ok".to_string(),
+        _ => "stale".to_string(),
+    }
+}
@@ -0,0 +1,220 @@
+//! Minimal OpenID Connect provider for trying out single sign-on locally.
+//!
+//! Every authorization request is approved at once for a single configured user,
+//! so the whole login flow runs without a real identity provider:
+//!
+//! ```bash
+//! cargo run --example mock_idp
+//! OIDC_ISSUER=http://localhost:9000 OIDC_CLIENT_ID=sfs cargo run
+//! # then open http://localhost:8080/oidc/login in a browser (or run `sfs sso login`)
+//! ```
+//!
+//! Settings: `MOCK_IDP_PORT` (9000), `MOCK_IDP_SUB` (mock-user-1),
+//! `MOCK_IDP_USERNAME` (mockuser), `MOCK_IDP_EMAIL` (mockuser@example.com).
+//! A `login_hint` query parameter on `/authorize` overrides the subject.
+//! Not for production use: there is no authentication at all.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{
+    Form, Json, Router,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use parking_lot::Mutex;
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+const KID: &str = "mock-idp";
+
+#[derive(Clone)]
+struct Idp {
+    issuer: String,
+    encoding: EncodingKey,
+    public_x: String,
+    username: String,
+    email: String,
+    default_sub: String,
+    codes: Arc<Mutex<HashMap<String, PendingCode>>>,
+}
+
+/// An issued authorization code, redeemable once at `/token`
+struct PendingCode {
+    client_id: String,
+    redirect_uri: String,
+    code_challenge: String,
+    nonce: Option<String>,
+    sub: String,
+}
+
+#[derive(Deserialize)]
+struct AuthorizeQuery {
+    client_id: String,
+    redirect_uri: String,
+    state: Option<String>,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+    login_hint: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenForm {
+    grant_type: String,
+    code: String,
+    redirect_uri: String,
+    client_id: String,
+    code_verifier: String,
+}
+
+#[tokio::main]
+async fn main() {
+    let port: u16 = std::env::var("MOCK_IDP_PORT")
+        .ok()
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(9000);
+    let env = |name: &str, default: &str| std::env::var(name).unwrap_or_else(|_| default.into());
+
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).expect("key generation");
+    let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).expect("key parsing");
+
+    let idp = Idp {
+        issuer: format!("http://localhost:{port}"),
+        encoding: EncodingKey::from_ed_der(pkcs8.as_ref()),
+        public_x: URL_SAFE_NO_PAD.encode(pair.public_key().as_ref()),
+        username: env("MOCK_IDP_USERNAME", "mockuser"),
+        email: env("MOCK_IDP_EMAIL", "mockuser@example.com"),
+        default_sub: env("MOCK_IDP_SUB", "mock-user-1"),
+        codes: Arc::new(Mutex::new(HashMap::new())),
+    };
+
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/jwks", get(jwks))
+        .route("/authorize", get(authorize))
+        .route("/token", post(token))
+        .with_state(idp.clone());
+
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+        .await
+        .expect("bind");
+    println!("Mock OIDC provider running at {}", idp.issuer);
+    axum::serve(listener, app).await.expect("serve");
+}
+
+async fn discovery(State(idp): State<Idp>) -> Json<Value> {
+    Json(json!({
+        "issuer": idp.issuer,
+        "authorization_endpoint": format!("{}/authorize", idp.issuer),
+        "token_endpoint": format!("{}/token", idp.issuer),
+        "jwks_uri": format!("{}/jwks", idp.issuer),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["EdDSA"],
+        "code_challenge_methods_supported": ["S256"],
+    }))
+}
+
+async fn jwks(State(idp): State<Idp>) -> Json<Value> {
+    Json(json!({
+        "keys": [{
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "alg": "EdDSA",
+            "use": "sig",
+            "kid": KID,
+            "x": idp.public_x,
+        }]
+    }))
+}
+
+/// Approve immediately and send the browser back with a code
+async fn authorize(State(idp): State<Idp>, Query(q): Query<AuthorizeQuery>) -> Response {
+    let (Some(challenge), Some("S256")) = (q.code_challenge, q.code_challenge_method.as_deref())
+    else {
+        return (StatusCode::BAD_REQUEST, "PKCE with S256 is required").into_response();
+    };
+
+    let code = Uuid::new_v4().to_string();
+    let sub = q.login_hint.unwrap_or(idp.default_sub.clone());
+    println!("Approving login for sub={sub}");
+
+    idp.codes.lock().insert(
+        code.clone(),
+        PendingCode {
+            client_id: q.client_id,
+            redirect_uri: q.redirect_uri.clone(),
+            code_challenge: challenge,
+            nonce: q.nonce,
+            sub,
+        },
+    );
+
+    let mut target = reqwest::Url::parse(&q.redirect_uri).expect("redirect_uri");
+    target.query_pairs_mut().append_pair("code", &code);
+    if let Some(state) = &q.state {
+        target.query_pairs_mut().append_pair("state", state);
+    }
+
+    Redirect::to(target.as_str()).into_response()
+}
+
+async fn token(State(idp): State<Idp>, Form(form): Form<TokenForm>) -> Response {
+    let invalid =
+        |error: &str| (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+
+    if form.grant_type != "authorization_code" {
+        return invalid("unsupported_grant_type");
+    }
+    let Some(pending) = idp.codes.lock().remove(&form.code) else {
+        return invalid("invalid_grant");
+    };
+    if pending.client_id != form.client_id || pending.redirect_uri != form.redirect_uri {
+        return invalid("invalid_grant");
+    }
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(form.code_verifier.as_bytes()));
+    if challenge != pending.code_challenge {
+        return invalid("invalid_grant");
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let claims = json!({
+        "iss": idp.issuer,
+        "sub": pending.sub,
+        "aud": pending.client_id,
+        "iat": now,
+        "exp": now + 300,
+        "nonce": pending.nonce,
+        "preferred_username": idp.username,
+        "email": idp.email,
+        "email_verified": true,
+    });
+
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(KID.into());
+    let id_token = match jsonwebtoken::encode(&header, &claims, &idp.encoding) {
+        Ok(t) => t,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    Json(json!({
+        "access_token": Uuid::new_v4().to_string(),
+        "token_type": "Bearer",
+        "expires_in": 300,
+        "id_token": id_token,
+    }))
+    .into_response()
+}
@@ -0,0 +1,527 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{Extension, State},
+    http::{HeaderValue, StatusCode, header},
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use tokio::fs::File as TokioFile;
+use tokio_util::io::ReaderStream;
+
+use crate::api::AppState;
+use crate::api::audit::{RequestInfo, with_outcome};
+use crate::api::auth::check_lockout;
+use crate::api::error::ApiError;
+use crate::api::file::require_no_limit;
+use crate::audit::AuditAction;
+use crate::auth::error::AuthError;
+use crate::auth::scope::TagLimit;
+use crate::auth::service::AuthService;
+use crate::auth::throttle::user_key;
+use crate::auth::types::Credential;
+use crate::storage::disk::{ensure_upload_dir, final_upload_path, temp_upload_path};
+
+/// Proof that the caller is the account holder: the password, or for accounts
+/// without one a 2FA code or neither right after logging in
+#[derive(Deserialize)]
+pub struct DeleteAccountRequest {
+    pub password: Option<String>,
+    /// TOTP or recovery code
+    pub code: Option<String>,
+}
+
+/// `manifest.json` at the root of an export archive
+#[derive(Serialize)]
+pub struct ExportManifest {
+    pub exported_at: i64,
+    pub user: ExportUser,
+    pub files: Vec<ExportFile>,
+    pub shared_with_me: Vec<ExportSharedFile>,
+}
+
+#[derive(Serialize)]
+pub struct ExportUser {
+    pub user_id: u32,
+    pub username: String,
+    pub email: Option<String>,
+    pub created_at: i64,
+    pub two_factor_enabled: bool,
+    /// Single sign-on identities linked to the account
+    pub identities: Vec<ExportIdentity>,
+}
+
+#[derive(Serialize)]
+pub struct ExportIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: i64,
+}
+
+/// A file owned by the user; its contents are in the archive at `archive_path`
+#[derive(Serialize)]
+pub struct ExportFile {
+    pub file_id: u32,
+    pub filename: String,
+    pub size: u64,
+    pub is_public: bool,
+    pub uploaded_at: i64,
+    pub description: Option<String>,
+    pub content_type: Option<String>,
+    pub download_count: u64,
+    pub last_downloaded_at: Option<i64>,
+    pub tags: Vec<String>,
+    pub metadata: BTreeMap<String, String>,
+    /// `None` if the stored blob is missing
+    pub archive_path: Option<String>,
+    pub shared_with: Vec<ExportShare>,
+}
+
+#[derive(Serialize)]
+pub struct ExportShare {
+    pub user_id: u32,
+    pub username: String,
+}
+
+/// A file another user shared with this user (metadata only)
+#[derive(Serialize)]
+pub struct ExportSharedFile {
+    pub file_id: u32,
+    pub filename: String,
+    pub owner_id: u32,
+    pub owner_username: String,
+}
+
+/// DELETE /me
+///
+/// Permanently delete the account: owned files and their blobs, shares in both
+/// directions, tokens and 2FA state. The caller confirms with the password;
+/// accounts without one, created by single sign-on, give a 2FA code or log in
+/// again and delete within five minutes.
+/// Wrong passwords and codes count towards the login lockout for the account.
+pub async fn delete_account_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Extension(credential): Extension<Credential>,
+    info: RequestInfo,
+    Json(req): Json<DeleteAccountRequest>,
+) -> Result<StatusCode, ApiError> {
+    let result = delete_account(&state, user_id, &credential, &req).await;
+
+    let event = info
+        .event(AuditAction::AccountDelete)
+        .target("user", user_id);
+    state.audit.record(with_outcome(event, &result)).await;
+
+    result
+}
+
+async fn delete_account(
+    state: &AppState,
+    user_id: u32,
+    credential: &Credential,
+    req: &DeleteAccountRequest,
+) -> Result<StatusCode, ApiError> {
+    let username = state
+        .auth
+        .repo
+        .get_username_by_id(user_id)
+        .await?
+        .ok_or(AuthError::UserNotFound)?;
+    let keys = [user_key(&username)];
+
+    check_lockout(state, &keys).await?;
+
+    let confirmed = state
+        .auth
+        .confirm_account_holder(
+            user_id,
+            req.password.as_deref(),
+            req.code.as_deref(),
+            credential,
+        )
+        .await;
+    match confirmed {
+        Ok(()) => {}
+        Err(e @ (AuthError::InvalidCredentials | AuthError::InvalidTwoFactorCode)) => {
+            let _ = state.throttle.record_failure(&keys).await;
+            return Err(e.into());
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let file_ids = delete_account_rows(state, user_id).await?;
+
+    // Blobs go only after the rows are gone, so a failed transaction loses nothing
+    for file_id in &file_ids {
+        let path = final_upload_path(*file_id as u64);
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            eprintln!("Failed to remove {}: {e}", path.display());
+        }
+    }
+
+    let _ = state.throttle.clear(&keys[0]).await;
+
+    println!(
+        "[security] account deleted: user_id={user_id} username={username} ({} files)",
+        file_ids.len()
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete all rows belonging to a user in one transaction; returns the ids of their files
+async fn delete_account_rows(state: &AppState, user_id: u32) -> Result<Vec<i64>, sqlx::Error> {
+    let mut tx = state.db.begin().await?;
+
+    let file_ids: Vec<i64> = sqlx::query("SELECT id FROM files WHERE owner_id = ?1")
+        .bind(user_id as i64)
+        .fetch_all(&mut *tx)
+        .await?
+        .iter()
+        .map(|r| r.get("id"))
+        .collect();
+
+    // Shares of the user's files, and shares granted to the user
+    sqlx::query(
+        r#"
+        DELETE FROM permissions
+        WHERE user_id = ?1
+            OR file_id IN (SELECT id FROM files WHERE owner_id = ?1)
+        "#,
+    )
+    .bind(user_id as i64)
+    .execute(&mut *tx)
+    .await?;
+
+    for table in ["file_downloads", "file_tags", "file_metadata"] {
+        sqlx::query(&format!(
+            "DELETE FROM {table} WHERE file_id IN (SELECT id FROM files WHERE owner_id = ?1)"
+        ))
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    sqlx::query("DELETE FROM files WHERE owner_id = ?1")
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        "DELETE FROM webhook_deliveries WHERE webhook_id IN (SELECT id FROM webhooks WHERE user_id = ?1)",
+    )
+    .bind(user_id as i64)
+    .execute(&mut *tx)
+    .await?;
+
+    for table in [
+        "refresh_tokens",
+        "password_reset_tokens",
+        "email_verification_tokens",
+        "user_identities",
+        "api_keys",
+        "recovery_codes",
+        "user_totp",
+        "webhooks",
+    ] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE user_id = ?1"))
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    sqlx::query("DELETE FROM oidc_login_states WHERE link_user_id = ?1")
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM oidc_pending_links WHERE user_id = ?1")
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM users WHERE id = ?1")
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(file_ids)
+}
+
+/// GET /me/export
+///
+/// Download a tar archive with `manifest.json` (account, file metadata and shares)
+/// and the contents of every file the user owns under `files/`.
+/// Keys limited to a tag cannot export.
+pub async fn export_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Extension(limit): Extension<TagLimit>,
+) -> Result<Response, ApiError> {
+    require_no_limit(&limit)?;
+    let manifest = build_manifest(&state, user_id).await?;
+    let username = manifest.user.username.clone();
+
+    ensure_upload_dir().await.map_err(|_| ApiError::Internal)?;
+    let archive_path = temp_upload_path();
+
+    let path = archive_path.clone();
+    let written = tokio::task::spawn_blocking(move || write_archive(&path, manifest))
+        .await
+        .map_err(|_| ApiError::Internal)?;
+
+    if let Err(e) = written {
+        eprintln!("Export for user_id={user_id} failed: {e}");
+        let _ = tokio::fs::remove_file(&archive_path).await;
+        return Err(ApiError::Internal);
+    }
+
+    let file = TokioFile::open(&archive_path)
+        .await
+        .map_err(|_| ApiError::Internal)?;
+    let len = file.metadata().await.map_err(|_| ApiError::Internal)?.len();
+
+    // The open handle keeps the data readable after the name is removed
+    let _ = tokio::fs::remove_file(&archive_path).await;
+
+    let mut resp = Response::new(Body::from_stream(ReaderStream::new(file)));
+    let headers = resp.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-tar"),
+    );
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+    let disposition = format!(
+        "attachment; filename=\"sfs-export-{}.tar\"",
+        safe_name(&username)
+    );
+    if let Ok(v) = HeaderValue::from_str(&disposition) {
+        headers.insert(header::CONTENT_DISPOSITION, v);
+    }
+
+    Ok(resp)
+}
+
+/// Collect everything the export contains, except file contents
+async fn build_manifest(state: &AppState, user_id: u32) -> Result<ExportManifest, ApiError> {
+    let user = sqlx::query(
+        r#"
+        SELECT u.username, u.email, u.created_at,
+            EXISTS (
+                SELECT 1 FROM user_totp t
+                WHERE t.user_id = u.id AND t.enabled_at IS NOT NULL
+            ) AS two_factor_enabled
+        FROM users u
+        WHERE u.id = ?1
+        "#,
+    )
+    .bind(user_id as i64)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(AuthError::UserNotFound)?;
+
+    let file_rows = sqlx::query(
+        r#"
+        SELECT id, filename, size, is_public, uploaded_at, description, content_type,
+            download_count, last_downloaded_at
+        FROM files
+        WHERE owner_id = ?1
+        ORDER BY id
+        "#,
+    )
+    .bind(user_id as i64)
+    .fetch_all(&state.db)
+    .await?;
+
+    let share_rows = sqlx::query(
+        r#"
+        SELECT p.file_id, p.user_id, u.username
+        FROM permissions p
+        JOIN files f ON f.id = p.file_id
+        JOIN users u ON u.id = p.user_id
+        WHERE f.owner_id = ?1
+        ORDER BY p.id
+        "#,
+    )
+    .bind(user_id as i64)
+    .fetch_all(&state.db)
+    .await?;
+
+    let tag_rows = sqlx::query(
+        r#"
+        SELECT t.file_id, t.tag
+        FROM file_tags t
+        JOIN files f ON f.id = t.file_id
+        WHERE f.owner_id = ?1
+        ORDER BY t.tag
+        "#,
+    )
+    .bind(user_id as i64)
+    .fetch_all(&state.db)
+    .await?;
+
+    let metadata_rows = sqlx::query(
+        r#"
+        SELECT m.file_id, m.key, m.value
+        FROM file_metadata m
+        JOIN files f ON f.id = m.file_id
+        WHERE f.owner_id = ?1
+        "#,
+    )
+    .bind(user_id as i64)
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut files = Vec::with_capacity(file_rows.len());
+    for r in file_rows {
+        let id: i64 = r.get("id");
+        let filename: String = r.get("filename");
+
+        let shared_with = share_rows
+            .iter()
+            .filter(|s| s.get::<i64, _>("file_id") == id)
+            .map(|s| ExportShare {
+                user_id: s.get::<i64, _>("user_id") as u32,
+                username: s.get("username"),
+            })
+            .collect();
+
+        let tags = tag_rows
+            .iter()
+            .filter(|t| t.get::<i64, _>("file_id") == id)
+            .map(|t| t.get("tag"))
+            .collect();
+        let metadata = metadata_rows
+            .iter()
+            .filter(|m| m.get::<i64, _>("file_id") == id)
+            .map(|m| (m.get("key"), m.get("value")))
+            .collect();
+
+        let stored = tokio::fs::try_exists(final_upload_path(id as u64))
+            .await
+            .unwrap_or(false);
+        let archive_path = stored.then(|| format!("files/{id}-{}", safe_name(&filename)));
+
+        files.push(ExportFile {
+            file_id: id as u32,
+            size: r.get::<i64, _>("size") as u64,
+            is_public: r.get::<i64, _>("is_public") != 0,
+            uploaded_at: r.get("uploaded_at"),
+            description: r.get("description"),
+            content_type: r.get("content_type"),
+            download_count: r.get::<i64, _>("download_count") as u64,
+            last_downloaded_at: r.get("last_downloaded_at"),
+            tags,
+            metadata,
+            archive_path,
+            shared_with,
+            filename,
+        });
+    }
+
+    let identities = sqlx::query(
+        r#"
+        SELECT issuer, subject, email, created_at
+        FROM user_identities
+        WHERE user_id = ?1
+        ORDER BY id
+        "#,
+    )
+    .bind(user_id as i64)
+    .fetch_all(&state.db)
+    .await?
+    .into_iter()
+    .map(|r| ExportIdentity {
+        issuer: r.get("issuer"),
+        subject: r.get("subject"),
+        email: r.get("email"),
+        created_at: r.get("created_at"),
+    })
+    .collect();
+
+    let shared_with_me = sqlx::query(
+        r#"
+        SELECT f.id, f.filename, f.owner_id, u.username AS owner_username
+        FROM permissions p
+        JOIN files f ON f.id = p.file_id
+        JOIN users u ON u.id = f.owner_id
+        WHERE p.user_id = ?1
+        ORDER BY f.id
+        "#,
+    )
+    .bind(user_id as i64)
+    .fetch_all(&state.db)
+    .await?
+    .into_iter()
+    .map(|r| ExportSharedFile {
+        file_id: r.get::<i64, _>("id") as u32,
+        filename: r.get("filename"),
+        owner_id: r.get::<i64, _>("owner_id") as u32,
+        owner_username: r.get("owner_username"),
+    })
+    .collect();
+
+    Ok(ExportManifest {
+        exported_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| ApiError::Internal)?
+            .as_secs() as i64,
+        user: ExportUser {
+            user_id,
+            username: user.get("username"),
+            email: user.get("email"),
+            created_at: user.get("created_at"),
+            two_factor_enabled: user.get::<i64, _>("two_factor_enabled") != 0,
+            identities,
+        },
+        files,
+        shared_with_me,
+    })
+}
+
+/// Write the manifest and file blobs into a tar archive at `path`
+fn write_archive(path: &Path, manifest: ExportManifest) -> std::io::Result<()> {
+    let mut builder = tar::Builder::new(std::fs::File::create(path)?);
+
+    let json = serde_json::to_vec_pretty(&manifest).map_err(std::io::Error::other)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(manifest.exported_at as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, "manifest.json", json.as_slice())?;
+
+    for file in &manifest.files {
+        if let Some(name) = &file.archive_path {
+            builder.append_path_with_name(final_upload_path(file.file_id as u64), name)?;
+        }
+    }
+
+    builder.into_inner()?.sync_all()
+}
+
+/// Make a stored filename safe to use as a single archive path component
+fn safe_name(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || matches!(c, '/' | '\\' | '"') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    match cleaned.trim_start_matches('.') {
+        "" => "file".to_string(),
+        rest => rest.to_string(),
+    }
+}
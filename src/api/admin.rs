@@ -0,0 +1,125 @@
+use std::net::IpAddr;
+
+use axum::{
+    Json,
+    extract::{Extension, Path, State},
+    http::StatusCode,
+};
+
+use crate::api::AppState;
+use crate::api::audit::{RequestInfo, with_outcome};
+use crate::api::error::ApiError;
+use crate::audit::AuditAction;
+use crate::auth::service::AuthService;
+use crate::auth::throttle::{ip_key, user_key};
+use crate::auth::types::PasswordResetIssued;
+
+/// Reject callers without admin rights
+pub async fn require_admin(state: &AppState, user_id: u32) -> Result<(), ApiError> {
+    if state.auth.repo.is_admin(user_id).await? {
+        Ok(())
+    } else {
+        Err(ApiError::Forbidden("Admin rights required"))
+    }
+}
+
+/// Admin-only: lift a login lockout for a username
+pub async fn unlock_user_handler(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    info: RequestInfo,
+) -> Result<StatusCode, ApiError> {
+    let result = unlock_user(&state, user_id, &username).await;
+
+    let event = info
+        .event(AuditAction::AdminUnlockUser)
+        .target("username", &username);
+    state.audit.record(with_outcome(event, &result)).await;
+
+    result
+}
+
+async fn unlock_user(
+    state: &AppState,
+    user_id: u32,
+    username: &str,
+) -> Result<StatusCode, ApiError> {
+    require_admin(state, user_id).await?;
+
+    let cleared = state.throttle.clear(&user_key(username)).await?;
+
+    if cleared {
+        println!("[security] admin user_id={user_id} unlocked username {username}");
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound("No failed logins recorded"))
+    }
+}
+
+/// Admin-only: lift a login lockout for a client IP
+pub async fn unlock_ip_handler(
+    Path(ip): Path<IpAddr>,
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    info: RequestInfo,
+) -> Result<StatusCode, ApiError> {
+    let result = unlock_ip(&state, user_id, ip).await;
+
+    let event = info.event(AuditAction::AdminUnlockIp).target("ip", ip);
+    state.audit.record(with_outcome(event, &result)).await;
+
+    result
+}
+
+async fn unlock_ip(state: &AppState, user_id: u32, ip: IpAddr) -> Result<StatusCode, ApiError> {
+    require_admin(state, user_id).await?;
+
+    let cleared = state.throttle.clear(&ip_key(ip)).await?;
+
+    if cleared {
+        println!("[security] admin user_id={user_id} unlocked ip {ip}");
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound("No failed logins recorded"))
+    }
+}
+
+/// Admin-only: issue a password reset token for a user.
+/// The token is returned to the admin instead of being mailed.
+pub async fn issue_password_reset_handler(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    info: RequestInfo,
+) -> Result<Json<PasswordResetIssued>, ApiError> {
+    let result = issue_password_reset(&state, user_id, &username).await;
+
+    let event = info
+        .event(AuditAction::AdminPasswordReset)
+        .target("username", &username);
+    state.audit.record(with_outcome(event, &result)).await;
+
+    Ok(Json(result?))
+}
+
+async fn issue_password_reset(
+    state: &AppState,
+    user_id: u32,
+    username: &str,
+) -> Result<PasswordResetIssued, ApiError> {
+    require_admin(state, user_id).await?;
+
+    let token = state
+        .auth
+        .create_password_reset(username)
+        .await?
+        .ok_or(ApiError::NotFound("User not found"))?;
+
+    println!("[security] admin user_id={user_id} issued a password reset for {username}");
+
+    Ok(PasswordResetIssued {
+        reset_token: token,
+        expires_in: state.auth.config.password_reset_ttl_secs,
+    })
+}
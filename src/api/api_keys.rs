@@ -0,0 +1,81 @@
+use axum::{
+    Json,
+    extract::{Extension, Path, State},
+    http::StatusCode,
+};
+
+use crate::api::AppState;
+use crate::api::audit::{RequestInfo, with_outcome};
+use crate::api::error::ApiError;
+use crate::api::tags::normalize_tag;
+use crate::audit::AuditAction;
+use crate::auth::service::AuthService;
+use crate::auth::types::{ApiKeyCreated, ApiKeyInfo, CreateApiKeyRequest};
+
+/// POST /me/api-keys
+///
+/// Create a personal API key. The key is returned only in this response.
+pub async fn create_api_key_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    info: RequestInfo,
+    Json(mut req): Json<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<ApiKeyCreated>), ApiError> {
+    let result = match req.tag.as_deref().map(normalize_tag).transpose() {
+        Ok(tag) => {
+            req.tag = tag;
+            state
+                .auth
+                .create_api_key(user_id, req)
+                .await
+                .map_err(ApiError::from)
+        }
+        Err(e) => Err(e),
+    };
+
+    let mut event = info.event(AuditAction::ApiKeyCreate);
+    if let Ok(created) = &result {
+        event = event
+            .target("api_key", created.id)
+            .detail(created.scopes.join(" "));
+    }
+    state.audit.record(with_outcome(event, &result)).await;
+
+    Ok((StatusCode::CREATED, Json(result?)))
+}
+
+/// GET /me/api-keys
+///
+/// List your API keys, including revoked and expired ones
+pub async fn list_api_keys_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+) -> Result<Json<Vec<ApiKeyInfo>>, ApiError> {
+    Ok(Json(state.auth.repo.list_api_keys(user_id).await?))
+}
+
+/// DELETE /me/api-keys/:id
+///
+/// Revoke an API key; it stops working immediately
+pub async fn revoke_api_key_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    info: RequestInfo,
+    Path(key_id): Path<u32>,
+) -> Result<StatusCode, ApiError> {
+    let result = match state.auth.repo.revoke_api_key(user_id, key_id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(ApiError::NotFound("API key not found")),
+        Err(e) => Err(e.into()),
+    };
+
+    let event = info
+        .event(AuditAction::ApiKeyRevoke)
+        .target("api_key", key_id);
+    state.audit.record(with_outcome(event, &result)).await;
+
+    if result.is_ok() {
+        println!("[security] API key revoked: user_id={user_id} key_id={key_id}");
+    }
+    result
+}
@@ -0,0 +1,141 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use axum::{
+    Json, async_trait,
+    extract::{ConnectInfo, Extension, FromRequestParts, Query, State},
+    http::{StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+
+use crate::api::AppState;
+use crate::api::admin::require_admin;
+use crate::api::error::{ApiError, current_request_id};
+use crate::audit::{AuditAction, AuditEvent, AuditFilter, AuditRecord, AuditVerification};
+use crate::auth::scope::{Scope, Scopes};
+
+/// Longest `User-Agent` kept in an audit event
+const MAX_USER_AGENT_LEN: usize = 256;
+
+/// Who sent a request and from where, for audit events.
+///
+/// The actor is the user id set by `auth_middleware`, so it is `None` on public routes.
+pub struct RequestInfo {
+    pub actor_id: Option<u32>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for RequestInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(|ua| ua.chars().take(MAX_USER_AGENT_LEN).collect());
+
+        Ok(Self {
+            actor_id: parts.extensions.get::<u32>().copied(),
+            ip,
+            user_agent,
+        })
+    }
+}
+
+impl RequestInfo {
+    /// Start an audit event for this request
+    pub fn event(&self, action: AuditAction) -> AuditEvent {
+        let mut event = AuditEvent::new(action).actor(self.actor_id);
+        event.ip = self.ip.clone();
+        event.user_agent = self.user_agent.clone();
+        event.request_id = current_request_id();
+        event
+    }
+}
+
+/// Mark `event` as failed with the error code if `result` is an error
+pub fn with_outcome<T>(event: AuditEvent, result: &Result<T, ApiError>) -> AuditEvent {
+    match result {
+        Ok(_) => event,
+        Err(e) => event.failed(e.code()),
+    }
+}
+
+/// GET /audit
+///
+/// Your own events, newest first. Admins (with the `admin` scope) see everyone's
+/// and can filter by `actor_id`.
+pub async fn audit_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Extension(scopes): Extension<Scopes>,
+    Query(mut filter): Query<AuditFilter>,
+) -> Result<Json<Vec<AuditRecord>>, ApiError> {
+    let admin_view = scopes.contains(Scope::Admin) && state.auth.repo.is_admin(user_id).await?;
+
+    if !admin_view {
+        if filter.actor_id.is_some_and(|id| id != user_id) {
+            return Err(ApiError::Forbidden(
+                "Only admins can see other users' events",
+            ));
+        }
+        filter.actor_id = Some(user_id);
+    }
+
+    if let Some(outcome) = &filter.outcome
+        && !matches!(outcome.as_str(), "success" | "failure")
+    {
+        return Err(ApiError::BadRequest(
+            "outcome must be success or failure".into(),
+        ));
+    }
+
+    Ok(Json(state.audit.list(&filter).await?))
+}
+
+/// Admin-only: GET /admin/audit/verify
+///
+/// Walk the hash chain and check every signed checkpoint; reports the first broken link
+pub async fn audit_verify_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+) -> Result<Json<AuditVerification>, ApiError> {
+    require_admin(&state, user_id).await?;
+
+    let report = state.audit.verify().await?;
+    if let Some(broken) = &report.first_broken {
+        println!(
+            "[security] audit chain verification failed at event {:?}: {}",
+            broken.event_id, broken.reason
+        );
+    }
+
+    Ok(Json(report))
+}
+
+/// Admin-only: POST /admin/audit/checkpoint
+///
+/// Sign the current head now instead of waiting for the next periodic checkpoint.
+/// Returns 201 with the checkpoint, or 204 if the latest one already covers the head.
+pub async fn audit_checkpoint_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, user_id).await?;
+
+    match state.audit.checkpoint().await {
+        Ok(Some(cp)) => Ok((StatusCode::CREATED, Json(cp)).into_response()),
+        Ok(None) => Ok(StatusCode::NO_CONTENT.into_response()),
+        Err(e) => {
+            eprintln!("[security] audit checkpoint failed: {e}");
+            Err(ApiError::Internal)
+        }
+    }
+}
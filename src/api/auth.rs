@@ -1,64 +1,222 @@
+use std::net::SocketAddr;
+
 use axum::{
     Json,
-    extract::State,
+    extract::{ConnectInfo, Extension, Query, State},
     http::{HeaderMap, StatusCode, header},
 };
 
 use crate::api::AppState;
-use crate::auth::service::AuthService;
-use crate::auth::types::{AuthTokenResponse, LoginRequest, RegisterRequest};
+use crate::api::audit::{RequestInfo, with_outcome};
+use crate::api::error::ApiError;
+use crate::audit::AuditAction;
+use crate::auth::error::AuthError;
+use crate::auth::service::{AuthService, SimpleAuthService};
+use crate::auth::throttle::{ip_key, user_key};
+use crate::auth::token::verify_challenge_token;
+use crate::auth::types::{
+    AuthTokenResponse, Credential, LoginRequest, LoginResponse, RefreshQuery, RegisterRequest,
+    TwoFactorLoginRequest,
+};
 
 /// POST /register
 pub async fn register_handler(
     State(state): State<AppState>,
+    info: RequestInfo,
     Json(req): Json<RegisterRequest>,
-) -> Result<Json<AuthTokenResponse>, (StatusCode, String)> {
+) -> Result<Json<AuthTokenResponse>, ApiError> {
     let auth = state.auth.clone();
+    let event = info
+        .event(AuditAction::Register)
+        .target("username", &req.username);
 
-    match auth.register(req).await {
-        Ok(token) => Ok(Json(token)),
-        Err(msg) => Err((StatusCode::BAD_REQUEST, msg)),
-    }
+    let result = auth.register(req).await.map_err(ApiError::from);
+    let event = event.actor(result.as_ref().ok().map(|t| t.user_id));
+    state.audit.record(with_outcome(event, &result)).await;
+
+    Ok(Json(result?))
 }
 
 /// POST /login
+///
+/// Returns tokens, or a 2FA challenge if the account has two-factor enabled.
+/// An optional `scope` limits the session to fewer scopes.
+/// Repeated failures lock out the username and client IP with exponential backoff.
 pub async fn login_handler(
     State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    info: RequestInfo,
     Json(req): Json<LoginRequest>,
-) -> Result<Json<AuthTokenResponse>, (StatusCode, String)> {
+) -> Result<Json<LoginResponse>, ApiError> {
     let auth = state.auth.clone();
+    let user_key = user_key(&req.username);
+    let keys = [user_key.clone(), ip_key(addr.ip())];
+    let event = info
+        .event(AuditAction::Login)
+        .target("username", &req.username);
+
+    if let Err(e) = check_lockout(&state, &keys).await {
+        state.audit.record(event.failed(e.code())).await;
+        return Err(e);
+    }
 
     match auth.login(req).await {
-        Ok(token) => Ok(Json(token)),
-        Err(msg) => Err((StatusCode::UNAUTHORIZED, msg)),
+        Ok(res) => {
+            // With 2FA the login is not finished yet; keep counting until the code is checked
+            let event = match &res {
+                LoginResponse::Tokens(tokens) => {
+                    let _ = state.throttle.clear(&user_key).await;
+                    event.actor(Some(tokens.user_id))
+                }
+                LoginResponse::TwoFactorRequired(_) => event.detail("two_factor_required"),
+            };
+            state.audit.record(event).await;
+            Ok(Json(res))
+        }
+        Err(e) => {
+            if matches!(e, AuthError::InvalidCredentials) {
+                let _ = state.throttle.record_failure(&keys).await;
+            }
+            let e = ApiError::from(e);
+            state.audit.record(event.failed(e.code())).await;
+            Err(e)
+        }
+    }
+}
+
+/// POST /login/2fa
+pub async fn login_two_factor_handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    info: RequestInfo,
+    Json(req): Json<TwoFactorLoginRequest>,
+) -> Result<Json<AuthTokenResponse>, ApiError> {
+    let auth = state.auth.clone();
+    let event = info.event(AuditAction::LoginTwoFactor);
+
+    // Throttle per account too, so codes can't be guessed across many challenges
+    let claims = match verify_challenge_token(&auth.keys, &auth.config, &req.challenge_token) {
+        Ok(claims) => claims,
+        Err(_) => {
+            let e = ApiError::from(AuthError::InvalidChallenge);
+            state.audit.record(event.failed(e.code())).await;
+            return Err(e);
+        }
+    };
+    let user_key = user_key(&claims.username);
+    let keys = [user_key.clone(), ip_key(addr.ip())];
+    let event = event
+        .actor(Some(claims.sub))
+        .target("username", &claims.username);
+
+    if let Err(e) = check_lockout(&state, &keys).await {
+        state.audit.record(event.failed(e.code())).await;
+        return Err(e);
+    }
+
+    match auth.complete_two_factor(req).await {
+        Ok(token) => {
+            let _ = state.throttle.clear(&user_key).await;
+            state.audit.record(event).await;
+            Ok(Json(token))
+        }
+        Err(e) => {
+            if matches!(e, AuthError::InvalidTwoFactorCode) {
+                let _ = state.throttle.record_failure(&keys).await;
+            }
+            let e = ApiError::from(e);
+            state.audit.record(event.failed(e.code())).await;
+            Err(e)
+        }
+    }
+}
+
+/// POST /logout
+///
+/// End the session of the access token: its refresh token stops working and
+/// event streams opened with it close. The access token itself stays valid
+/// until it expires. Tokens from before session ids end every session of the user.
+pub async fn logout_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Extension(credential): Extension<Credential>,
+    info: RequestInfo,
+) -> Result<StatusCode, ApiError> {
+    let result = match credential {
+        Credential::Session { session_id, .. } if !session_id.is_empty() => state
+            .auth
+            .repo
+            .revoke_session(user_id, &session_id)
+            .await
+            .map(|_| StatusCode::NO_CONTENT)
+            .map_err(ApiError::from),
+        Credential::Session { .. } => state
+            .auth
+            .repo
+            .revoke_all_refresh_tokens_for_user(user_id)
+            .await
+            .map(|_| StatusCode::NO_CONTENT)
+            .map_err(ApiError::from),
+        Credential::ApiKey { .. } => Err(ApiError::BadRequest(
+            "API keys have no session; revoke the key instead".into(),
+        )),
+    };
+
+    let event = info.event(AuditAction::Logout).actor(Some(user_id));
+    state.audit.record(with_outcome(event, &result)).await;
+
+    result
+}
+
+/// Reject with 429 and `Retry-After` if any key is locked out
+pub async fn check_lockout(state: &AppState, keys: &[String]) -> Result<(), ApiError> {
+    match state.throttle.retry_after(keys).await? {
+        Some(retry_after) => Err(ApiError::LockedOut { retry_after }),
+        None => Ok(()),
     }
 }
 
 /// GET /token/refresh
+///
+/// `?scope=` narrows the new access token to a subset of the session's scopes;
+/// the rotated refresh token keeps them all.
 pub async fn refresh_handler(
     State(state): State<AppState>,
+    Query(query): Query<RefreshQuery>,
+    info: RequestInfo,
     headers: HeaderMap,
-) -> Result<Json<AuthTokenResponse>, (StatusCode, String)> {
+) -> Result<Json<AuthTokenResponse>, ApiError> {
     let auth = state.auth.clone();
+    let event = info.event(AuditAction::TokenRefresh);
+
+    let result = refresh(&auth, query, &headers).await;
+    let event = match &result {
+        Ok(tokens) => event.actor(Some(tokens.user_id)),
+        // Reuse revoked the owner's session, so the event goes on their record
+        Err(ApiError::Auth(AuthError::RefreshTokenReused { user_id })) => {
+            event.actor(Some(*user_id)).target("user", user_id)
+        }
+        Err(_) => event,
+    };
+    state.audit.record(with_outcome(event, &result)).await;
 
+    Ok(Json(result?))
+}
+
+async fn refresh(
+    auth: &SimpleAuthService,
+    query: RefreshQuery,
+    headers: &HeaderMap,
+) -> Result<AuthTokenResponse, ApiError> {
     let auth_header = headers
         .get(header::AUTHORIZATION)
         .and_then(|v| v.to_str().ok())
-        .ok_or((
-            StatusCode::UNAUTHORIZED,
-            "Missing Authorization header".into(),
-        ))?;
+        .ok_or(ApiError::Unauthorized("Missing Authorization header"))?;
 
     let refresh_token = auth_header
         .strip_prefix("Bearer ")
-        .ok_or((
-            StatusCode::UNAUTHORIZED,
-            "Invalid Authorization header".into(),
-        ))?
+        .ok_or(ApiError::Unauthorized("Invalid Authorization header"))?
         .to_string();
 
-    match auth.refresh(refresh_token).await {
-        Ok(token) => Ok(Json(token)),
-        Err(msg) => Err((StatusCode::UNAUTHORIZED, msg)),
-    }
+    Ok(auth.refresh(refresh_token, query.scope).await?)
 }
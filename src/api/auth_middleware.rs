@@ -1,14 +1,30 @@
+use std::net::SocketAddr;
+
 use axum::{
     body::Body,
-    http::{Request, StatusCode},
+    extract::{ConnectInfo, State},
+    http::Request,
     middleware::Next,
     response::Response,
 };
 
+use crate::api::AppState;
+use crate::api::error::ApiError;
+use crate::auth::scope::{Scope, Scopes, TagLimit};
+use crate::auth::service::API_KEY_PREFIX;
 use crate::auth::token::verify_token;
+use crate::auth::types::Credential;
 
-/// Authentication middleware
-pub async fn auth_middleware(mut req: Request<Body>, next: Next) -> Result<Response, StatusCode> {
+/// Authentication middleware.
+///
+/// Accepts a JWT access token or a personal API key as the bearer token, and
+/// makes the user id (`u32`), granted [`Scopes`], the key's [`TagLimit`] and
+/// the [`Credential`] itself available to handlers.
+pub async fn auth_middleware(
+    State(state): State<AppState>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Result<Response, ApiError> {
     let auth_header = req
         .headers()
         .get("Authorization")
@@ -16,13 +32,57 @@ pub async fn auth_middleware(mut req: Request<Body>, next: Next) -> Result<Respo
 
     let token = match auth_header {
         Some(h) if h.starts_with("Bearer ") => &h[7..],
-        _ => return Err(StatusCode::UNAUTHORIZED),
+        _ => return Err(ApiError::Unauthorized("Missing bearer token")),
+    };
+
+    let (user_id, scopes, limit, credential) = if token.starts_with(API_KEY_PREFIX) {
+        let ip = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+
+        let (user_id, scopes, limit, id) = state
+            .auth
+            .repo
+            .use_api_key(token, ip.as_deref())
+            .await?
+            .ok_or(ApiError::Unauthorized("Invalid or expired API key"))?;
+        (user_id, scopes, limit, Credential::ApiKey { id })
+    } else {
+        let claims = verify_token(&state.auth.keys, &state.auth.config, token)
+            .map_err(|_| ApiError::Unauthorized("Invalid or expired access token"))?;
+        let scopes = claims.scopes();
+        let credential = Credential::Session {
+            auth_time: claims.auth_time as u64,
+            session_id: claims.sid,
+        };
+        (claims.sub, scopes, TagLimit::default(), credential)
     };
 
-    let claims = verify_token(token).map_err(|_| StatusCode::UNAUTHORIZED)?;
+    // Make user_id, scopes, the tag limit and the credential available to handlers
+    req.extensions_mut().insert(user_id);
+    req.extensions_mut().insert(scopes);
+    req.extensions_mut().insert(limit);
+    req.extensions_mut().insert(credential);
+
+    Ok(next.run(req).await)
+}
+
+/// Per-route authorization: refuse with 403 unless the credential holds `scope`.
+/// Must run inside `auth_middleware`.
+pub async fn require_scope(
+    State(scope): State<Scope>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let allowed = req
+        .extensions()
+        .get::<Scopes>()
+        .is_some_and(|scopes| scopes.contains(scope));
 
-    // Make user_id available to handlers
-    req.extensions_mut().insert(claims.sub);
+    if !allowed {
+        return Err(ApiError::MissingScope(scope));
+    }
 
     Ok(next.run(req).await)
 }
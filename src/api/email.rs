@@ -0,0 +1,65 @@
+use axum::{
+    Json,
+    extract::{Extension, State},
+    http::StatusCode,
+};
+
+use crate::api::AppState;
+use crate::api::error::ApiError;
+use crate::auth::service::AuthService;
+use crate::auth::types::{SetEmailRequest, VerifyEmailRequest};
+
+/// PUT /me/email
+///
+/// Set, change or (with `null`) remove the account's email address.
+/// A new address starts unverified and a verification token is mailed to it.
+pub async fn set_email_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Json(req): Json<SetEmailRequest>,
+) -> Result<StatusCode, ApiError> {
+    state.auth.set_email(user_id, req.email).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// POST /me/email/resend
+///
+/// Mail a fresh verification token; earlier unused tokens stop working
+pub async fn resend_verification_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+) -> Result<StatusCode, ApiError> {
+    state.auth.resend_email_verification(user_id).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// POST /email/verify
+///
+/// Confirm an email address with the token from the verification mail
+pub async fn verify_email_handler(
+    State(state): State<AppState>,
+    Json(req): Json<VerifyEmailRequest>,
+) -> Result<StatusCode, ApiError> {
+    state.auth.verify_email(&req.token).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Refuse with 403 when `REQUIRE_VERIFIED_EMAIL` is set and the user has no verified address
+pub async fn require_verified_email(state: &AppState, user_id: u32) -> Result<(), ApiError> {
+    if !state.auth.config.require_verified_email {
+        return Ok(());
+    }
+
+    let verified = state
+        .auth
+        .repo
+        .get_email(user_id)
+        .await?
+        .is_some_and(|e| e.verified);
+
+    if verified {
+        Ok(())
+    } else {
+        Err(ApiError::EmailNotVerified)
+    }
+}
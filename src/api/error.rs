@@ -0,0 +1,318 @@
+use axum::{
+    Json,
+    body::{Body, to_bytes},
+    http::{HeaderName, HeaderValue, Request, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+use crate::auth::error::AuthError;
+use crate::auth::scope::Scope;
+
+/// Header carrying the request id, accepted from clients and always returned
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Largest plain-text error body that `request_context` rewrites as JSON
+const MAX_PLAIN_ERROR_BYTES: usize = 16 * 1024;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Error returned by handlers and middleware.
+///
+/// Every variant renders as the same JSON body (see [`ErrorBody`]), so clients
+/// can rely on `code` instead of parsing messages.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(&'static str),
+    Forbidden(&'static str),
+    /// The credential lacks the scope the route requires
+    MissingScope(Scope),
+    /// `REQUIRE_VERIFIED_EMAIL` is set and the user has no verified address
+    EmailNotVerified,
+    NotFound(&'static str),
+    Conflict(&'static str),
+    PayloadTooLarge,
+    /// Too many requests for a rate limit bucket
+    RateLimited {
+        retry_after: u64,
+    },
+    /// Too many failed logins for a username or client IP
+    LockedOut {
+        retry_after: u64,
+    },
+    Auth(AuthError),
+    /// Details are logged where the error happens, never sent to the client
+    Internal,
+}
+
+/// JSON body of every error response
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    /// Stable, machine-readable error code such as `invalid_credentials`
+    pub code: &'static str,
+    /// Human-readable description
+    pub message: String,
+    /// Same as the `X-Request-Id` response header; quote it when reporting a problem
+    pub request_id: Option<String>,
+    /// Extra structured information for some codes, otherwise `null`
+    pub details: Option<Value>,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) | ApiError::MissingScope(_) | ApiError::EmailNotVerified => {
+                StatusCode::FORBIDDEN
+            }
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::RateLimited { .. } | ApiError::LockedOut { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Auth(e) => match e {
+                AuthError::InvalidCredentials
+                | AuthError::InvalidTwoFactorCode
+                | AuthError::InvalidChallenge
+                | AuthError::RefreshTokenReused { .. }
+                | AuthError::RefreshTokenExpired
+                | AuthError::InvalidRefreshToken
+                | AuthError::UserNotFound => StatusCode::UNAUTHORIZED,
+                AuthError::UsernameTaken | AuthError::EmailTaken | AuthError::OidcIdentityTaken => {
+                    StatusCode::CONFLICT
+                }
+                AuthError::InvalidResetToken
+                | AuthError::InvalidVerificationToken
+                | AuthError::ScopeNotAvailable(_)
+                | AuthError::OidcLoginFailed
+                | AuthError::InvalidLinkCode
+                | AuthError::Invalid(_) => StatusCode::BAD_REQUEST,
+                AuthError::ReauthenticationRequired | AuthError::OidcNoAccount => {
+                    StatusCode::FORBIDDEN
+                }
+                AuthError::OidcNotConfigured => StatusCode::NOT_FOUND,
+                AuthError::OidcProviderError => StatusCode::BAD_GATEWAY,
+                AuthError::Database | AuthError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "invalid_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::MissingScope(_) => "insufficient_scope",
+            ApiError::EmailNotVerified => "email_not_verified",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::PayloadTooLarge => "payload_too_large",
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::LockedOut { .. } => "login_locked",
+            ApiError::Internal => "internal_error",
+            ApiError::Auth(e) => match e {
+                AuthError::InvalidCredentials => "invalid_credentials",
+                AuthError::InvalidTwoFactorCode => "invalid_two_factor_code",
+                AuthError::InvalidChallenge => "invalid_challenge",
+                AuthError::RefreshTokenReused { .. } => "refresh_token_reused",
+                AuthError::RefreshTokenExpired => "refresh_token_expired",
+                AuthError::InvalidRefreshToken => "invalid_refresh_token",
+                AuthError::UserNotFound => "user_not_found",
+                AuthError::UsernameTaken => "username_taken",
+                AuthError::EmailTaken => "email_taken",
+                AuthError::InvalidResetToken => "invalid_reset_token",
+                AuthError::InvalidVerificationToken => "invalid_verification_token",
+                AuthError::ReauthenticationRequired => "reauthentication_required",
+                AuthError::ScopeNotAvailable(_) => "scope_not_available",
+                AuthError::OidcNotConfigured => "oidc_not_configured",
+                AuthError::OidcProviderError => "oidc_provider_error",
+                AuthError::OidcLoginFailed => "oidc_login_failed",
+                AuthError::OidcNoAccount => "oidc_no_account",
+                AuthError::OidcIdentityTaken => "oidc_identity_taken",
+                AuthError::InvalidLinkCode => "invalid_link_code",
+                AuthError::Invalid(_) => "invalid_request",
+                AuthError::Database | AuthError::Internal => "internal_error",
+            },
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ApiError::BadRequest(msg) => msg.clone(),
+            ApiError::Unauthorized(msg)
+            | ApiError::Forbidden(msg)
+            | ApiError::NotFound(msg)
+            | ApiError::Conflict(msg) => msg.to_string(),
+            ApiError::MissingScope(scope) => format!("This credential lacks the {scope} scope"),
+            ApiError::EmailNotVerified => "A verified email address is required".into(),
+            ApiError::PayloadTooLarge => "Upload is too large".into(),
+            ApiError::RateLimited { .. } => "Rate limit exceeded".into(),
+            ApiError::LockedOut { .. } => "Too many failed login attempts; try again later".into(),
+            ApiError::Internal | ApiError::Auth(AuthError::Database | AuthError::Internal) => {
+                "Internal server error".into()
+            }
+            ApiError::Auth(e) => e.to_string(),
+        }
+    }
+
+    fn details(&self) -> Option<Value> {
+        match self {
+            ApiError::MissingScope(scope) => Some(json!({ "required_scope": scope.as_str() })),
+            ApiError::RateLimited { retry_after } | ApiError::LockedOut { retry_after } => {
+                Some(json!({ "retry_after": retry_after }))
+            }
+            ApiError::Auth(AuthError::ScopeNotAvailable(scope)) => {
+                Some(json!({ "scope": scope.as_str() }))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<AuthError> for ApiError {
+    fn from(e: AuthError) -> Self {
+        ApiError::Auth(e)
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        eprintln!("Database error: {e}");
+        ApiError::Internal
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let body = ErrorBody {
+            code: self.code(),
+            message: self.message(),
+            request_id: current_request_id(),
+            details: self.details(),
+        };
+
+        if status.is_server_error() {
+            eprintln!(
+                "request_id={} failed: {status} {}",
+                body.request_id.as_deref().unwrap_or("-"),
+                body.code
+            );
+        }
+
+        let mut res = (status, Json(body)).into_response();
+        if let ApiError::RateLimited { retry_after } | ApiError::LockedOut { retry_after } = self {
+            res.headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        res
+    }
+}
+
+/// Id of the request being handled, if called inside `request_context`
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Outermost middleware: gives every request an id (the client's `X-Request-Id`
+/// if it is sane, otherwise a new UUID), returns it in `X-Request-Id`, and turns
+/// plain-text or empty error responses (such as extractor rejections and unknown
+/// routes) into the JSON error body.
+pub async fn request_context(req: Request<Body>, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let res = REQUEST_ID.scope(request_id.clone(), next.run(req)).await;
+    let mut res = if is_plain_error(&res) {
+        REQUEST_ID
+            .scope(request_id.clone(), to_json_error(res))
+            .await
+    } else {
+        res
+    };
+
+    if let Ok(v) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, v);
+    }
+    res
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    (1..=64).contains(&id.len())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+fn is_plain_error(res: &Response) -> bool {
+    let status = res.status();
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("application/json"));
+
+    (status.is_client_error() || status.is_server_error()) && !is_json
+}
+
+/// Rewrite a plain-text error response as the JSON error body, keeping its
+/// status and headers such as `Allow` or `Retry-After`
+async fn to_json_error(res: Response) -> Response {
+    let (mut parts, body) = res.into_parts();
+    let text = to_bytes(body, MAX_PLAIN_ERROR_BYTES)
+        .await
+        .map(|b| String::from_utf8_lossy(&b).trim().to_string())
+        .unwrap_or_default();
+
+    let status = parts.status;
+    let message = if text.is_empty() {
+        status.canonical_reason().unwrap_or("Error").to_string()
+    } else {
+        text
+    };
+
+    let body = ErrorBody {
+        code: status_code_name(status),
+        message,
+        request_id: current_request_id(),
+        details: None,
+    };
+
+    parts.headers.remove(header::CONTENT_TYPE);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let mut res = (status, Json(body)).into_response();
+    res.headers_mut().extend(parts.headers);
+    res
+}
+
+/// Generic error code for a status, used when a response did not come from an `ApiError`
+fn status_code_name(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "invalid_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::UNPROCESSABLE_ENTITY => "invalid_body",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        s if s.is_server_error() => "internal_error",
+        _ => "error",
+    }
+}
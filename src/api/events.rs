@@ -0,0 +1,84 @@
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::{
+    extract::{Extension, State},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::stream::{self, Stream, StreamExt};
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+
+use crate::api::AppState;
+use crate::api::error::ApiError;
+use crate::api::file::require_no_limit;
+use crate::auth::scope::TagLimit;
+use crate::auth::types::Credential;
+
+/// How often an open stream checks that its credential still works
+const RECHECK_INTERVAL_SECS: u64 = 30;
+
+/// GET /events
+///
+/// Server-Sent Events stream of changes to your files. Starts with a `ready` event;
+/// a `lagged` event means some were dropped and the client should re-list.
+/// The stream ends after `ACCESS_TOKEN_TTL_SECS`, so it never outlives the token
+/// by more than one lifetime; clients reconnect with a fresh token. It also ends
+/// within `RECHECK_INTERVAL_SECS` once the credential stops working: the session
+/// logged out or was revoked, the API key revoked or expired, or the account deleted.
+/// Keys limited to a tag cannot subscribe, since events cover every file.
+pub async fn events_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Extension(limit): Extension<TagLimit>,
+    Extension(credential): Extension<Credential>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    require_no_limit(&limit)?;
+
+    let rx = state.events.subscribe();
+    let deadline = Instant::now() + Duration::from_secs(state.auth.config.access_token_ttl_secs);
+    let period = Duration::from_secs(RECHECK_INTERVAL_SECS);
+    let recheck = tokio::time::interval_at(Instant::now() + period, period);
+    let repo = state.auth.repo.clone();
+
+    let ready = Event::default()
+        .event("ready")
+        .data(json!({ "user_id": user_id }).to_string());
+
+    let updates = stream::unfold((rx, recheck), move |(mut rx, mut recheck)| {
+        let repo = repo.clone();
+        let credential = credential.clone();
+        async move {
+            loop {
+                let received = tokio::select! {
+                    _ = tokio::time::sleep_until(deadline) => return None,
+                    _ = recheck.tick() => {
+                        match repo.credential_is_active(user_id, &credential).await {
+                            Ok(true) => {}
+                            Ok(false) => return None,
+                            Err(e) => eprintln!("Event stream credential check failed: {e}"),
+                        }
+                        continue;
+                    }
+                    received = rx.recv() => received,
+                };
+
+                let event = match received {
+                    Ok(event) if event.recipients.contains(&user_id) => Event::default()
+                        .id(event.id.to_string())
+                        .event(event.kind.as_str())
+                        .data(event.data.to_string()),
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(missed)) => Event::default()
+                        .event("lagged")
+                        .data(json!({ "missed": missed }).to_string()),
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((Ok(event), (rx, recheck)));
+            }
+        }
+    });
+
+    Ok(Sse::new(stream::once(async { Ok(ready) }).chain(updates)).keep_alive(KeepAlive::default()))
+}
@@ -1,29 +1,74 @@
 use axum::{
     Json,
     body::Body,
-    extract::{Extension, Multipart, Path, State},
-    http::{HeaderValue, StatusCode, header},
+    extract::{Extension, Multipart, Path, Query, State},
+    http::StatusCode,
     response::Response,
 };
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use bytes::Bytes;
+use futures_util::Stream;
 use serde::{Deserialize, Serialize};
-use sqlx::Row;
+use serde_json::json;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{QueryBuilder, Row, Sqlite};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
 use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::fs::File as TokioFile;
 use tokio::io::AsyncWriteExt;
 use tokio_util::io::ReaderStream;
 
 use crate::api::AppState;
+use crate::api::audit::{RequestInfo, with_outcome};
+use crate::api::email::require_verified_email;
+use crate::api::error::ApiError;
+use crate::api::tags::normalize_tag;
+use crate::audit::AuditAction;
+use crate::auth::scope::TagLimit;
+use crate::events::LiveEventKind;
+use crate::storage::content_type::{self, OCTET_STREAM, SNIFF_LEN};
 use crate::storage::disk::{ensure_upload_dir, final_upload_path, temp_upload_path};
+use crate::webhooks::WebhookEvent;
 
 /// Maximum allowed upload size 10 MB
 const MAX_UPLOAD_SIZE: u64 = 10 * 1024 * 1024;
 
+/// Files per page when `GET /files` gets no `limit`
+const DEFAULT_LIST_PAGE: u32 = 100;
+
+/// Largest page `GET /files` returns
+const MAX_LIST_PAGE: u32 = 1000;
+
+/// Longest file description, in characters
+const MAX_DESCRIPTION_LEN: usize = 1000;
+
+/// Columns `FileListItem::from_row` reads, besides the computed `access`
+pub const FILE_LIST_COLUMNS: &str = "f.id, f.filename, f.size, f.is_public, f.uploaded_at, \
+                                     f.owner_id, f.description, f.download_count, f.last_downloaded_at, \
+                                     f.content_type, \
+                                     (SELECT group_concat(t.tag, ',') FROM file_tags t \
+                                      WHERE t.file_id = f.id) AS tags";
+
 #[derive(Serialize)]
 pub struct UploadResponse {
     pub file_id: u32,
     pub filename: String,
     pub size: u64,
     pub is_public: bool,
+    pub content_type: String,
+}
+
+#[derive(Deserialize)]
+pub struct DownloadQuery {
+    /// `1` or `true` to display the file in the browser; only honored for safe types
+    pub inline: Option<String>,
+}
+
+impl DownloadQuery {
+    fn wants_inline(&self) -> bool {
+        matches!(self.inline.as_deref(), Some("1" | "true"))
+    }
 }
 
 #[derive(Deserialize)]
@@ -45,58 +90,407 @@ pub struct FileListItem {
     pub size: u64,
     pub is_public: bool,
     pub uploaded_at: i64,
+    pub owner_id: u32,
+    pub description: Option<String>,
+    pub content_type: String,
+    pub tags: Vec<String>,
     pub access: String, // "owner" or "shared"
+    /// Owner only; `None` on files shared with you
+    pub download_count: Option<u64>,
+    pub last_downloaded_at: Option<i64>,
+}
+
+/// Whether a listed file is yours or shared with you
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileAccess {
+    Owner,
+    Shared,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileSort {
+    Name,
+    Size,
+    #[default]
+    Date,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Deserialize)]
+pub struct ListFilesQuery {
+    /// Page size
+    pub limit: Option<u32>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub sort: FileSort,
+    /// Defaults to ascending for `name`, descending otherwise
+    pub order: Option<SortOrder>,
+    pub access: Option<FileAccess>,
+    pub is_public: Option<bool>,
+    /// Comma-separated tags the file must all have
+    pub tag: Option<String>,
+}
+
+/// `GET /files` and `GET /files/search` response
+#[derive(Serialize)]
+pub struct FileListPage {
+    pub items: Vec<FileListItem>,
+    /// Files matching the filters, across all pages
+    pub total: u64,
+    /// Pass as `cursor` to get the next page; `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// Position after the last item of a page, sent to clients as base64url JSON
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListCursor {
+    sort: FileSort,
+    order: SortOrder,
+    /// Sort key of the last item
+    key: CursorKey,
+    id: i64,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum CursorKey {
+    Text(String),
+    Number(i64),
+}
+
+#[derive(Serialize)]
+pub struct FileStats {
+    pub file_id: u32,
+    pub filename: String,
+    pub download_count: u64,
+    pub last_downloaded_at: Option<i64>,
+    /// Downloads through the public link
+    pub public_downloads: u64,
+    /// Downloads by logged-in users, the owner included, most recent first
+    pub downloaders: Vec<DownloaderStats>,
+}
+
+#[derive(Serialize)]
+pub struct DownloaderStats {
+    pub user_id: u32,
+    /// `None` if the account has since been deleted
+    pub username: Option<String>,
+    pub downloads: u64,
+    pub last_downloaded_at: i64,
+}
+
+impl FileListItem {
+    /// Build from a row selected with [`FILE_LIST_COLUMNS`]
+    pub fn from_row(r: &SqliteRow) -> Self {
+        let access: String = r.get("access");
+        let is_owner = access == "owner";
+        let mut tags: Vec<String> = r
+            .get::<Option<String>, _>("tags")
+            .map(|t| t.split(',').map(str::to_string).collect())
+            .unwrap_or_default();
+        tags.sort();
+
+        Self {
+            file_id: r.get::<i64, _>("id") as u32,
+            filename: r.get("filename"),
+            size: r.get::<i64, _>("size") as u64,
+            is_public: r.get::<i64, _>("is_public") != 0,
+            uploaded_at: r.get("uploaded_at"),
+            owner_id: r.get::<i64, _>("owner_id") as u32,
+            description: r.get("description"),
+            content_type: r
+                .get::<Option<String>, _>("content_type")
+                .unwrap_or_else(|| OCTET_STREAM.to_string()),
+            tags,
+            access,
+            download_count: is_owner.then(|| r.get::<i64, _>("download_count") as u64),
+            last_downloaded_at: if is_owner {
+                r.get("last_downloaded_at")
+            } else {
+                None
+            },
+        }
+    }
 }
 
 /// Handle list files
+///
+/// One page of the files you own or that are shared with you, with the total
+/// across pages and a cursor for the next page
 pub async fn list_files_handler(
     State(state): State<AppState>,
     Extension(user_id): Extension<u32>,
-) -> Result<Json<Vec<FileListItem>>, StatusCode> {
-    let rows = sqlx::query(
+    Extension(tag_limit): Extension<TagLimit>,
+    Query(query): Query<ListFilesQuery>,
+) -> Result<Json<FileListPage>, ApiError> {
+    let uid = user_id as i64;
+    let order = query.order.unwrap_or(query.sort.default_order());
+    let after = match &query.cursor {
+        Some(cursor) => Some(ListCursor::decode(cursor, query.sort, order)?),
+        None => None,
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LIST_PAGE)
+        .clamp(1, MAX_LIST_PAGE);
+    let tags = parse_tag_filter(query.tag.as_deref())?;
+
+    let mut count: QueryBuilder<Sqlite> =
+        QueryBuilder::new("SELECT COUNT(*) AS n FROM files f WHERE ");
+    push_list_filters(&mut count, uid, &query, &tag_limit, &tags);
+    let total: i64 = count.build().fetch_one(&state.db).await?.get("n");
+
+    let mut sql: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+        "SELECT {FILE_LIST_COLUMNS}, CASE WHEN f.owner_id = "
+    ));
+    sql.push_bind(uid)
+        .push(" THEN 'owner' ELSE 'shared' END AS access FROM files f WHERE ");
+    push_list_filters(&mut sql, uid, &query, &tag_limit, &tags);
+    push_page(&mut sql, query.sort, order, after.as_ref(), limit);
+
+    let rows = sql.build().fetch_all(&state.db).await?;
+    let (items, next_cursor) = finish_page(&rows, query.sort, order, limit);
+
+    Ok(Json(FileListPage {
+        items,
+        total: total as u64,
+        next_cursor,
+    }))
+}
+
+/// Keyset condition for the page after `after`, then the `ORDER BY` and a `LIMIT`
+/// of one row more than `limit`, which tells whether there is a next page
+pub fn push_page(
+    sql: &mut QueryBuilder<'_, Sqlite>,
+    sort: FileSort,
+    order: SortOrder,
+    after: Option<&ListCursor>,
+    limit: u32,
+) {
+    let column = sort.column();
+    let (cmp, dir) = match order {
+        SortOrder::Asc => (">", "ASC"),
+        SortOrder::Desc => ("<", "DESC"),
+    };
+    if let Some(after) = after {
+        sql.push(format!(" AND ({column} {cmp} "));
+        after.key.push_bind(sql);
+        sql.push(format!(" OR ({column} = "));
+        after.key.push_bind(sql);
+        sql.push(format!(" AND f.id {cmp} "))
+            .push_bind(after.id)
+            .push("))");
+    }
+    sql.push(format!(" ORDER BY {column} {dir}, f.id {dir} LIMIT "))
+        .push_bind(limit as i64 + 1);
+}
+
+/// Items of a page selected with [`push_page`], and the cursor to the next page
+pub fn finish_page(
+    rows: &[SqliteRow],
+    sort: FileSort,
+    order: SortOrder,
+    limit: u32,
+) -> (Vec<FileListItem>, Option<String>) {
+    let mut items: Vec<FileListItem> = rows.iter().map(FileListItem::from_row).collect();
+    if items.len() <= limit as usize {
+        return (items, None);
+    }
+
+    items.truncate(limit as usize);
+    let next_cursor = items.last().map(|last| {
+        ListCursor {
+            sort,
+            order,
+            key: CursorKey::of(sort, last),
+            id: last.file_id as i64,
+        }
+        .encode()
+    });
+    (items, next_cursor)
+}
+
+/// Conditions of `GET /files` after `WHERE`; the cursor is not one of them
+fn push_list_filters(
+    sql: &mut QueryBuilder<'_, Sqlite>,
+    uid: i64,
+    query: &ListFilesQuery,
+    limit: &TagLimit,
+    tags: &[String],
+) {
+    push_visible(sql, uid);
+    push_within_limit(sql, uid, limit);
+    if let Some(access) = query.access {
+        push_access(sql, uid, access);
+    }
+    if let Some(is_public) = query.is_public {
+        sql.push(" AND f.is_public = ").push_bind(is_public as i64);
+    }
+    push_tags(sql, tags);
+}
+
+/// Condition matching files `uid` owns or that are shared with them
+pub fn push_visible(sql: &mut QueryBuilder<'_, Sqlite>, uid: i64) {
+    sql.push("(f.owner_id = ")
+        .push_bind(uid)
+        .push(" OR EXISTS (SELECT 1 FROM permissions p WHERE p.file_id = f.id AND p.user_id = ")
+        .push_bind(uid)
+        .push("))");
+}
+
+/// `AND` conditions keeping only files the credential's tag limit reaches:
+/// owned by `uid` and carrying the tag
+pub fn push_within_limit(sql: &mut QueryBuilder<'_, Sqlite>, uid: i64, limit: &TagLimit) {
+    if let Some(tag) = limit.tag() {
+        sql.push(" AND f.owner_id = ")
+            .push_bind(uid)
+            .push(" AND EXISTS (SELECT 1 FROM file_tags t WHERE t.file_id = f.id AND t.tag = ")
+            .push_bind(tag.to_string())
+            .push(")");
+    }
+}
+
+/// `AND` condition keeping only owned or only shared files
+pub fn push_access(sql: &mut QueryBuilder<'_, Sqlite>, uid: i64, access: FileAccess) {
+    match access {
+        FileAccess::Owner => sql.push(" AND f.owner_id = ").push_bind(uid),
+        FileAccess::Shared => sql.push(" AND f.owner_id != ").push_bind(uid),
+    };
+}
+
+/// Tags of a comma-separated `tag` filter, normalized
+pub fn parse_tag_filter(filter: Option<&str>) -> Result<Vec<String>, ApiError> {
+    let mut tags = Vec::new();
+    for tag in filter.unwrap_or_default().split(',') {
+        if tag.trim().is_empty() {
+            continue;
+        }
+        let tag = normalize_tag(tag)?;
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    Ok(tags)
+}
+
+/// `AND` conditions keeping files that have every tag in `tags`
+pub fn push_tags(sql: &mut QueryBuilder<'_, Sqlite>, tags: &[String]) {
+    for tag in tags {
+        sql.push(" AND EXISTS (SELECT 1 FROM file_tags t WHERE t.file_id = f.id AND t.tag = ")
+            .push_bind(tag.clone())
+            .push(")");
+    }
+}
+
+impl FileSort {
+    /// Sort expression; names compare case-insensitively
+    fn column(self) -> &'static str {
+        match self {
+            FileSort::Name => "f.filename COLLATE NOCASE",
+            FileSort::Size => "f.size",
+            FileSort::Date => "f.uploaded_at",
+        }
+    }
+
+    /// Names A-Z; sizes and dates largest or newest first
+    fn default_order(self) -> SortOrder {
+        match self {
+            FileSort::Name => SortOrder::Asc,
+            FileSort::Size | FileSort::Date => SortOrder::Desc,
+        }
+    }
+}
+
+impl ListCursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("cursor serializes"))
+    }
+
+    /// Parse a cursor, checking it belongs to a listing in this sort order
+    pub fn decode(cursor: &str, sort: FileSort, order: SortOrder) -> Result<Self, ApiError> {
+        let invalid = || ApiError::BadRequest("Invalid cursor".into());
+        let bytes = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+        let cursor: ListCursor = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+
+        let key_matches = matches!(
+            (cursor.sort, &cursor.key),
+            (FileSort::Name, CursorKey::Text(_))
+                | (FileSort::Size | FileSort::Date, CursorKey::Number(_))
+        );
+        if !key_matches {
+            return Err(invalid());
+        }
+        if cursor.sort != sort || cursor.order != order {
+            return Err(ApiError::BadRequest(
+                "Cursor belongs to a different sort order".into(),
+            ));
+        }
+        Ok(cursor)
+    }
+}
+
+impl CursorKey {
+    fn of(sort: FileSort, item: &FileListItem) -> Self {
+        match sort {
+            FileSort::Name => CursorKey::Text(item.filename.clone()),
+            FileSort::Size => CursorKey::Number(item.size as i64),
+            FileSort::Date => CursorKey::Number(item.uploaded_at),
+        }
+    }
+
+    fn push_bind(&self, sql: &mut QueryBuilder<'_, Sqlite>) {
+        match self {
+            CursorKey::Text(text) => sql.push_bind(text.clone()),
+            CursorKey::Number(n) => sql.push_bind(*n),
+        };
+    }
+}
+
+/// `NotFound` unless the credential's tag limit reaches the file: there is no
+/// limit, or `user_id` owns the file and it carries the tag
+pub async fn require_within_limit(
+    state: &AppState,
+    file_id: u32,
+    user_id: u32,
+    limit: &TagLimit,
+) -> Result<(), ApiError> {
+    let Some(tag) = limit.tag() else {
+        return Ok(());
+    };
+    sqlx::query(
         r#"
-        SELECT
-            f.id          AS id,
-            f.filename    AS filename,
-            f.size        AS size,
-            f.is_public   AS is_public,
-            f.uploaded_at AS uploaded_at,
-            CASE
-                WHEN f.owner_id = ?1 THEN 'owner'
-                ELSE 'shared'
-            END AS access
+        SELECT 1
         FROM files f
-        LEFT JOIN permissions p
-            ON p.file_id = f.id AND p.user_id = ?1
-        WHERE f.owner_id = ?1 OR p.user_id = ?1
-        ORDER BY f.uploaded_at DESC, f.id DESC
+        JOIN file_tags t ON t.file_id = f.id
+        WHERE f.id = ?1 AND f.owner_id = ?2 AND t.tag = ?3
         "#,
     )
+    .bind(file_id as i64)
     .bind(user_id as i64)
-    .fetch_all(&state.db)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let mut out = Vec::with_capacity(rows.len());
-    for r in rows {
-        let id: i64 = r.get("id");
-        let filename: String = r.get("filename");
-        let size: i64 = r.get("size");
-        let is_public: i64 = r.get("is_public");
-        let uploaded_at: i64 = r.get("uploaded_at");
-        let access: String = r.get("access");
+    .bind(tag)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(ApiError::NotFound("File not found"))?;
+    Ok(())
+}
 
-        out.push(FileListItem {
-            file_id: id as u32,
-            filename,
-            size: size as u64,
-            is_public: is_public != 0,
-            uploaded_at,
-            access,
-        });
+/// `Forbidden` for a credential limited to a tag, on routes that reach every file
+pub fn require_no_limit(limit: &TagLimit) -> Result<(), ApiError> {
+    match limit.tag() {
+        Some(_) => Err(ApiError::Forbidden(
+            "Not available to API keys limited to a tag",
+        )),
+        None => Ok(()),
     }
-
-    Ok(Json(out))
 }
 
 /// Handle authenticated file uploads
@@ -104,11 +498,30 @@ pub async fn list_files_handler(
 pub async fn upload_handler(
     State(state): State<AppState>,
     Extension(user_id): Extension<u32>,
+    Extension(limit): Extension<TagLimit>,
+    info: RequestInfo,
+    multipart: Multipart,
+) -> Result<Json<UploadResponse>, ApiError> {
+    let result = upload(&state, user_id, &limit, multipart).await;
+
+    let mut event = info.event(AuditAction::FileUpload);
+    if let Ok(uploaded) = &result {
+        event = event
+            .target("file", uploaded.file_id)
+            .detail(&uploaded.filename);
+    }
+    state.audit.record(with_outcome(event, &result)).await;
+
+    Ok(Json(result?))
+}
+
+async fn upload(
+    state: &AppState,
+    user_id: u32,
+    limit: &TagLimit,
     mut multipart: Multipart,
-) -> Result<Json<UploadResponse>, StatusCode> {
-    ensure_upload_dir()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+) -> Result<UploadResponse, ApiError> {
+    ensure_upload_dir().await.map_err(|_| ApiError::Internal)?;
 
     let temp_path = temp_upload_path();
 
@@ -116,6 +529,9 @@ pub async fn upload_handler(
     let mut wrote_file = false;
     let mut size: u64 = 0;
     let mut is_public: bool = false;
+    let mut description: Option<String> = None;
+    let mut declared_type: Option<String> = None;
+    let mut head: Vec<u8> = Vec::with_capacity(SNIFF_LEN);
 
     // Create temp file
     let mut temp_file: Option<TokioFile> = None;
@@ -123,39 +539,70 @@ pub async fn upload_handler(
     while let Some(field) = multipart
         .next_field()
         .await
-        .map_err(|_| StatusCode::BAD_REQUEST)?
+        .map_err(|e| ApiError::BadRequest(e.body_text()))?
     {
         match field.name() {
             Some("is_public") => {
-                let text = field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|e| ApiError::BadRequest(e.body_text()))?;
                 is_public = matches!(
                     text.trim().to_ascii_lowercase().as_str(),
                     "1" | "true" | "yes" | "on"
                 );
             }
 
+            Some("description") => {
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|e| ApiError::BadRequest(e.body_text()))?;
+                let text = text.trim();
+                if text.chars().count() > MAX_DESCRIPTION_LEN {
+                    return Err(ApiError::BadRequest(format!(
+                        "Description is longer than {MAX_DESCRIPTION_LEN} characters"
+                    )));
+                }
+                description = (!text.is_empty()).then(|| text.to_string());
+            }
+
             Some("file") => {
+                if temp_file.is_some() {
+                    let _ = tokio::fs::remove_file(&temp_path).await;
+                    return Err(ApiError::BadRequest(
+                        "Only one file may be uploaded per request".into(),
+                    ));
+                }
                 original_filename = field.file_name().map(|s| s.to_string());
+                declared_type = field.content_type().map(|s| s.to_string());
 
                 let file = TokioFile::create(&temp_path)
                     .await
-                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                    .map_err(|_| ApiError::Internal)?;
                 temp_file = Some(file);
 
                 let mut field = field;
-                while let Some(chunk) = field.chunk().await.map_err(|_| StatusCode::BAD_REQUEST)? {
+                while let Some(chunk) = field
+                    .chunk()
+                    .await
+                    .map_err(|e| ApiError::BadRequest(e.body_text()))?
+                {
                     size = size
                         .checked_add(chunk.len() as u64)
-                        .ok_or(StatusCode::PAYLOAD_TOO_LARGE)?;
+                        .ok_or(ApiError::PayloadTooLarge)?;
                     if size > MAX_UPLOAD_SIZE {
                         let _ = tokio::fs::remove_file(&temp_path).await;
-                        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+                        return Err(ApiError::PayloadTooLarge);
+                    }
+
+                    if head.len() < SNIFF_LEN {
+                        let take = chunk.len().min(SNIFF_LEN - head.len());
+                        head.extend_from_slice(&chunk[..take]);
                     }
 
                     if let Some(f) = temp_file.as_mut() {
-                        f.write_all(&chunk)
-                            .await
-                            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                        f.write_all(&chunk).await.map_err(|_| ApiError::Internal)?;
                     }
                 }
 
@@ -169,23 +616,32 @@ pub async fn upload_handler(
     }
 
     if !wrote_file {
-        return Err(StatusCode::BAD_REQUEST);
+        return Err(ApiError::BadRequest("Missing file field".into()));
     }
-    let filename = original_filename.ok_or(StatusCode::BAD_REQUEST)?;
+    let filename = original_filename
+        .ok_or_else(|| ApiError::BadRequest("The file field needs a filename".into()))?;
 
     // Close file before rename
     drop(temp_file);
 
+    let detected = content_type::detect(&head, &filename, declared_type.as_deref());
+
+    if is_public && let Err(e) = require_verified_email(state, user_id).await {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        return Err(e);
+    }
+
     // Insert into DB first to get a stable file_id
     let uploaded_at = SystemTime::now()
         .duration_since(UNIX_EPOCH)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| ApiError::Internal)?
         .as_secs() as i64;
 
+    let mut tx = state.db.begin().await?;
     let res = sqlx::query(
         r#"
-        INSERT INTO files (filename, size, owner_id, is_public, uploaded_at, description)
-        VALUES (?1, ?2, ?3, ?4, ?5, NULL)
+        INSERT INTO files (filename, size, owner_id, is_public, uploaded_at, description, content_type)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
         "#,
     )
     .bind(&filename)
@@ -193,12 +649,23 @@ pub async fn upload_handler(
     .bind(user_id as i64)
     .bind(if is_public { 1i64 } else { 0i64 })
     .bind(uploaded_at)
-    .execute(&state.db)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    .bind(&description)
+    .bind(detected.content_type)
+    .execute(&mut *tx)
+    .await?;
 
     let file_id = res.last_insert_rowid() as u32;
 
+    // Uploads through a key limited to a tag land in that tag, so the key can reach them
+    if let Some(tag) = limit.tag() {
+        sqlx::query("INSERT INTO file_tags (file_id, tag) VALUES (?1, ?2)")
+            .bind(file_id as i64)
+            .bind(tag)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+
     // Move temp file into final path using file_id
     let final_path = final_upload_path(file_id as u64);
     if tokio::fs::rename(&temp_path, &final_path).await.is_err() {
@@ -208,15 +675,48 @@ pub async fn upload_handler(
             .await;
         let _ = tokio::fs::remove_file(&temp_path).await;
 
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        return Err(ApiError::Internal);
+    }
+
+    if let Some(mismatch) = &detected.mismatch {
+        println!(
+            "[security] upload file_id={file_id} user_id={user_id} filename={filename:?}: {mismatch}"
+        );
     }
 
-    Ok(Json(UploadResponse {
+    state.events.publish(
+        LiveEventKind::Uploaded,
+        vec![user_id],
+        json!({
+            "file_id": file_id,
+            "filename": filename,
+            "size": size,
+            "is_public": is_public,
+            "content_type": detected.content_type,
+        }),
+    );
+    state
+        .webhooks
+        .emit(
+            user_id,
+            WebhookEvent::FileUploaded,
+            json!({
+                "file_id": file_id,
+                "filename": filename,
+                "size": size,
+                "is_public": is_public,
+                "content_type": detected.content_type,
+            }),
+        )
+        .await;
+
+    Ok(UploadResponse {
         file_id,
         filename,
         size,
         is_public,
-    }))
+        content_type: detected.content_type.to_string(),
+    })
 }
 
 /// Handle authenticated file downloads
@@ -224,10 +724,32 @@ pub async fn download_handler(
     Path(file_id): Path<u32>,
     State(state): State<AppState>,
     Extension(user_id): Extension<u32>,
-) -> Result<Response, StatusCode> {
+    Extension(limit): Extension<TagLimit>,
+    Query(query): Query<DownloadQuery>,
+    info: RequestInfo,
+) -> Result<Response, ApiError> {
+    let result = download(&state, file_id, user_id, &limit, query.wants_inline()).await;
+
+    let event = info
+        .event(AuditAction::FileDownload)
+        .target("file", file_id);
+    state.audit.record(with_outcome(event, &result)).await;
+
+    result
+}
+
+async fn download(
+    state: &AppState,
+    file_id: u32,
+    user_id: u32,
+    limit: &TagLimit,
+    inline: bool,
+) -> Result<Response, ApiError> {
+    require_within_limit(state, file_id, user_id, limit).await?;
+
     let row = sqlx::query(
         r#"
-        SELECT f.id, f.filename
+        SELECT f.id, f.filename, f.content_type
         FROM files f
         WHERE f.id = ?1
             AND (
@@ -244,38 +766,324 @@ pub async fn download_handler(
     .bind(file_id as i64)
     .bind(user_id as i64)
     .fetch_optional(&state.db)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    .await?;
 
-    let row = row.ok_or(StatusCode::NOT_FOUND)?;
+    let row = row.ok_or(ApiError::NotFound("File not found"))?;
     let stored_id: i64 = row.get("id");
     let filename_for_header: String = row.get("filename");
 
-    stream_file_response(stored_id as u32, filename_for_header).await
+    stream_file_response(
+        state,
+        stored_id as u32,
+        filename_for_header,
+        row.get("content_type"),
+        inline,
+        Some(user_id),
+    )
+    .await
 }
 
 /// Public download (no auth): only works if file.is_public == 1
 pub async fn download_public_handler(
     Path(file_id): Path<u32>,
     State(state): State<AppState>,
-) -> Result<Response, StatusCode> {
+    Query(query): Query<DownloadQuery>,
+    info: RequestInfo,
+) -> Result<Response, ApiError> {
+    let result = download_public(&state, file_id, query.wants_inline()).await;
+
+    let event = info
+        .event(AuditAction::FilePublicDownload)
+        .target("file", file_id);
+    state.audit.record(with_outcome(event, &result)).await;
+
+    result
+}
+
+async fn download_public(
+    state: &AppState,
+    file_id: u32,
+    inline: bool,
+) -> Result<Response, ApiError> {
     let row = sqlx::query(
         r#"
-        SELECT id, filename
+        SELECT id, filename, content_type
         FROM files
         WHERE id = ?1 AND is_public = 1
         "#,
     )
     .bind(file_id as i64)
     .fetch_optional(&state.db)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    .await?;
 
-    let row = row.ok_or(StatusCode::NOT_FOUND)?;
+    let row = row.ok_or(ApiError::NotFound("File not found"))?;
     let stored_id: i64 = row.get("id");
     let filename_for_header: String = row.get("filename");
 
-    stream_file_response(stored_id as u32, filename_for_header).await
+    stream_file_response(
+        state,
+        stored_id as u32,
+        filename_for_header,
+        row.get("content_type"),
+        inline,
+        None,
+    )
+    .await
+}
+
+/// Count a finished download, send the new count to the owner's live streams and, when
+/// someone other than the owner downloaded, queue `file.downloaded` for the owner's webhooks.
+/// Failures are logged; the file has already been sent.
+async fn record_download(state: &AppState, file_id: u32, downloader: Option<u32>) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    let row = match count_download(state, file_id, downloader, now).await {
+        Ok(row) => row,
+        Err(e) => {
+            eprintln!("Recording download of file {file_id} failed: {e}");
+            return;
+        }
+    };
+
+    let owner_id = row.get::<i64, _>("owner_id") as u32;
+    let filename: String = row.get("filename");
+    let download_count = row.get::<i64, _>("download_count") as u64;
+
+    state.events.publish(
+        LiveEventKind::Updated,
+        vec![owner_id],
+        json!({
+            "file_id": file_id,
+            "filename": filename,
+            "download_count": download_count,
+            "last_downloaded_at": now,
+        }),
+    );
+
+    if downloader == Some(owner_id) {
+        return;
+    }
+    let downloaded_by_username = match downloader {
+        Some(id) => state.auth.repo.get_username_by_id(id).await.ok().flatten(),
+        None => None,
+    };
+
+    state
+        .webhooks
+        .emit(
+            owner_id,
+            WebhookEvent::FileDownloaded,
+            json!({
+                "file_id": file_id,
+                "filename": filename,
+                "downloaded_by": downloader,
+                "downloaded_by_username": downloaded_by_username,
+                "public_link": downloader.is_none(),
+                "download_count": download_count,
+                "downloaded_at": now,
+            }),
+        )
+        .await;
+}
+
+/// Bump the counters and log the download; returns the file's owner, name and new count
+async fn count_download(
+    state: &AppState,
+    file_id: u32,
+    downloader: Option<u32>,
+    now: i64,
+) -> Result<sqlx::sqlite::SqliteRow, sqlx::Error> {
+    let mut tx = state.db.begin().await?;
+
+    sqlx::query("INSERT INTO file_downloads (file_id, user_id, downloaded_at) VALUES (?1, ?2, ?3)")
+        .bind(file_id as i64)
+        .bind(downloader.map(|id| id as i64))
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE files
+        SET download_count = download_count + 1, last_downloaded_at = ?2
+        WHERE id = ?1
+        "#,
+    )
+    .bind(file_id as i64)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+
+    let row = sqlx::query(
+        r#"
+        SELECT owner_id, filename, download_count
+        FROM files
+        WHERE id = ?1
+        "#,
+    )
+    .bind(file_id as i64)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(row)
+}
+
+/// Owner-only: delete a file, its shares and its download history
+pub async fn delete_file_handler(
+    Path(file_id): Path<u32>,
+    State(state): State<AppState>,
+    Extension(owner_id): Extension<u32>,
+    Extension(limit): Extension<TagLimit>,
+    info: RequestInfo,
+) -> Result<StatusCode, ApiError> {
+    let result = match require_within_limit(&state, file_id, owner_id, &limit).await {
+        Ok(()) => delete_file(&state, file_id, owner_id).await,
+        Err(e) => Err(e),
+    };
+
+    let event = info.event(AuditAction::FileDelete).target("file", file_id);
+    state.audit.record(with_outcome(event, &result)).await;
+
+    result
+}
+
+async fn delete_file(
+    state: &AppState,
+    file_id: u32,
+    owner_id: u32,
+) -> Result<StatusCode, ApiError> {
+    let mut tx = state.db.begin().await?;
+
+    let row = sqlx::query("SELECT filename FROM files WHERE id = ?1 AND owner_id = ?2")
+        .bind(file_id as i64)
+        .bind(owner_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(ApiError::NotFound("File not found"))?;
+    let filename: String = row.get("filename");
+
+    let shared_with: Vec<u32> = sqlx::query("SELECT user_id FROM permissions WHERE file_id = ?1")
+        .bind(file_id as i64)
+        .fetch_all(&mut *tx)
+        .await?
+        .iter()
+        .map(|r| r.get::<i64, _>("user_id") as u32)
+        .collect();
+
+    for table in [
+        "permissions",
+        "file_downloads",
+        "file_tags",
+        "file_metadata",
+    ] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE file_id = ?1"))
+            .bind(file_id as i64)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    sqlx::query("DELETE FROM files WHERE id = ?1")
+        .bind(file_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    // The blob goes only after the rows are gone, so a failed transaction loses nothing
+    let path = final_upload_path(file_id as u64);
+    if let Err(e) = tokio::fs::remove_file(&path).await {
+        eprintln!("Failed to remove {}: {e}", path.display());
+    }
+
+    let mut recipients = shared_with;
+    recipients.push(owner_id);
+    state.events.publish(
+        LiveEventKind::Deleted,
+        recipients,
+        json!({
+            "file_id": file_id,
+            "filename": filename,
+        }),
+    );
+    state
+        .webhooks
+        .emit(
+            owner_id,
+            WebhookEvent::FileDeleted,
+            json!({
+                "file_id": file_id,
+                "filename": filename,
+            }),
+        )
+        .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Owner-only: download counts, last access and who downloaded a file
+pub async fn file_stats_handler(
+    Path(file_id): Path<u32>,
+    State(state): State<AppState>,
+    Extension(owner_id): Extension<u32>,
+    Extension(limit): Extension<TagLimit>,
+) -> Result<Json<FileStats>, ApiError> {
+    require_within_limit(&state, file_id, owner_id, &limit).await?;
+
+    let row = sqlx::query(
+        r#"
+        SELECT filename, download_count, last_downloaded_at
+        FROM files
+        WHERE id = ?1 AND owner_id = ?2
+        "#,
+    )
+    .bind(file_id as i64)
+    .bind(owner_id as i64)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(ApiError::NotFound("File not found"))?;
+
+    let public_downloads: i64 = sqlx::query(
+        "SELECT COUNT(*) AS n FROM file_downloads WHERE file_id = ?1 AND user_id IS NULL",
+    )
+    .bind(file_id as i64)
+    .fetch_one(&state.db)
+    .await?
+    .get("n");
+
+    let downloaders = sqlx::query(
+        r#"
+        SELECT d.user_id, u.username, COUNT(*) AS downloads, MAX(d.downloaded_at) AS last_at
+        FROM file_downloads d
+        LEFT JOIN users u ON u.id = d.user_id
+        WHERE d.file_id = ?1 AND d.user_id IS NOT NULL
+        GROUP BY d.user_id
+        ORDER BY last_at DESC, d.user_id
+        "#,
+    )
+    .bind(file_id as i64)
+    .fetch_all(&state.db)
+    .await?
+    .into_iter()
+    .map(|r| DownloaderStats {
+        user_id: r.get::<i64, _>("user_id") as u32,
+        username: r.get("username"),
+        downloads: r.get::<i64, _>("downloads") as u64,
+        last_downloaded_at: r.get("last_at"),
+    })
+    .collect();
+
+    Ok(Json(FileStats {
+        file_id,
+        filename: row.get("filename"),
+        download_count: row.get::<i64, _>("download_count") as u64,
+        last_downloaded_at: row.get("last_downloaded_at"),
+        public_downloads: public_downloads as u64,
+        downloaders,
+    }))
 }
 
 /// Owner-only: grant another user access to a file
@@ -283,29 +1091,52 @@ pub async fn share_file_handler(
     Path(file_id): Path<u32>,
     State(state): State<AppState>,
     Extension(owner_id): Extension<u32>,
+    Extension(limit): Extension<TagLimit>,
+    info: RequestInfo,
     Json(req): Json<ShareRequest>,
-) -> Result<Json<ShareResponse>, StatusCode> {
+) -> Result<Json<ShareResponse>, ApiError> {
+    let result = match require_within_limit(&state, file_id, owner_id, &limit).await {
+        Ok(()) => share_file(&state, file_id, owner_id, req.user_id).await,
+        Err(e) => Err(e),
+    };
+
+    let event = info
+        .event(AuditAction::ShareCreate)
+        .target("file", file_id)
+        .detail(format!("user_id={}", req.user_id));
+    state.audit.record(with_outcome(event, &result)).await;
+
+    Ok(Json(result?))
+}
+
+async fn share_file(
+    state: &AppState,
+    file_id: u32,
+    owner_id: u32,
+    target_user_id: u32,
+) -> Result<ShareResponse, ApiError> {
     // Verify file exists and caller is owner
-    let row = sqlx::query("SELECT owner_id FROM files WHERE id = ?1")
+    let row = sqlx::query("SELECT owner_id, filename FROM files WHERE id = ?1")
         .bind(file_id as i64)
         .fetch_optional(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
 
-    let row = row.ok_or(StatusCode::NOT_FOUND)?;
+    let row = row.ok_or(ApiError::NotFound("File not found"))?;
     let db_owner_id: i64 = row.get("owner_id");
     if db_owner_id != owner_id as i64 {
-        return Err(StatusCode::NOT_FOUND);
+        return Err(ApiError::NotFound("File not found"));
     }
+    let filename: String = row.get("filename");
+
+    require_verified_email(state, owner_id).await?;
 
     // Return 404 if target user doesn't exist
     let target_exists = sqlx::query("SELECT 1 FROM users WHERE id = ?1 LIMIT 1")
-        .bind(req.user_id as i64)
+        .bind(target_user_id as i64)
         .fetch_optional(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
     if target_exists.is_none() {
-        return Err(StatusCode::NOT_FOUND);
+        return Err(ApiError::NotFound("User not found"));
     }
 
     // Insert permission
@@ -316,7 +1147,7 @@ pub async fn share_file_handler(
         "#,
     )
     .bind(file_id as i64)
-    .bind(req.user_id as i64)
+    .bind(target_user_id as i64)
     .execute(&state.db)
     .await;
 
@@ -326,20 +1157,43 @@ pub async fn share_file_handler(
             if let sqlx::Error::Database(db_err) = &e {
                 let msg = db_err.message().to_ascii_lowercase();
                 if msg.contains("unique") || msg.contains("constraint") {
-                    return Err(StatusCode::CONFLICT);
+                    return Err(ApiError::Conflict("File is already shared with this user"));
                 }
             }
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            return Err(ApiError::Internal);
         }
     };
 
     let permission_id = res.last_insert_rowid() as u32;
 
-    Ok(Json(ShareResponse {
+    state.events.publish(
+        LiveEventKind::Shared,
+        vec![target_user_id],
+        json!({
+            "file_id": file_id,
+            "filename": filename,
+            "owner_id": owner_id,
+        }),
+    );
+    state
+        .webhooks
+        .emit(
+            owner_id,
+            WebhookEvent::ShareCreated,
+            json!({
+                "file_id": file_id,
+                "filename": filename,
+                "permission_id": permission_id,
+                "user_id": target_user_id,
+            }),
+        )
+        .await;
+
+    Ok(ShareResponse {
         permission_id,
         file_id,
-        user_id: req.user_id,
-    }))
+        user_id: target_user_id,
+    })
 }
 
 /// Owner-only: revoke a specific permission by id
@@ -347,39 +1201,82 @@ pub async fn revoke_share_handler(
     Path((file_id, permission_id)): Path<(u32, u32)>,
     State(state): State<AppState>,
     Extension(owner_id): Extension<u32>,
-) -> Result<StatusCode, StatusCode> {
+    Extension(limit): Extension<TagLimit>,
+    info: RequestInfo,
+) -> Result<StatusCode, ApiError> {
+    let result = match require_within_limit(&state, file_id, owner_id, &limit).await {
+        Ok(()) => revoke_share(&state, file_id, permission_id, owner_id).await,
+        Err(e) => Err(e),
+    };
+
+    let event = info
+        .event(AuditAction::ShareRevoke)
+        .target("file", file_id)
+        .detail(format!("permission_id={permission_id}"));
+    state.audit.record(with_outcome(event, &result)).await;
+
+    result
+}
+
+async fn revoke_share(
+    state: &AppState,
+    file_id: u32,
+    permission_id: u32,
+    owner_id: u32,
+) -> Result<StatusCode, ApiError> {
     // Verify if file exists and caller owns it
-    let row = sqlx::query("SELECT owner_id FROM files WHERE id = ?1")
+    let row = sqlx::query("SELECT owner_id, filename FROM files WHERE id = ?1")
         .bind(file_id as i64)
         .fetch_optional(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .await?
+        .ok_or(ApiError::NotFound("File not found"))?;
 
     let db_owner_id: i64 = row.get("owner_id");
     if db_owner_id != owner_id as i64 {
-        return Err(StatusCode::NOT_FOUND);
+        return Err(ApiError::NotFound("File not found"));
     }
 
     // Verify permission belongs to this file
-    let prow = sqlx::query("SELECT file_id FROM permissions WHERE id = ?1")
+    let prow = sqlx::query("SELECT file_id, user_id FROM permissions WHERE id = ?1")
         .bind(permission_id as i64)
         .fetch_optional(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .await?
+        .ok_or(ApiError::NotFound("Share not found"))?;
 
     let p_file_id: i64 = prow.get("file_id");
     if p_file_id != file_id as i64 {
-        return Err(StatusCode::NOT_FOUND);
+        return Err(ApiError::NotFound("Share not found"));
     }
 
     // Delete
     sqlx::query("DELETE FROM permissions WHERE id = ?1")
         .bind(permission_id as i64)
         .execute(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
+
+    let filename: String = row.get("filename");
+    let target_user_id = prow.get::<i64, _>("user_id") as u32;
+    state.events.publish(
+        LiveEventKind::Unshared,
+        vec![target_user_id],
+        json!({
+            "file_id": file_id,
+            "filename": filename,
+        }),
+    );
+    state
+        .webhooks
+        .emit(
+            owner_id,
+            WebhookEvent::ShareRevoked,
+            json!({
+                "file_id": file_id,
+                "filename": filename,
+                "permission_id": permission_id,
+                "user_id": target_user_id,
+            }),
+        )
+        .await;
 
     Ok(StatusCode::NO_CONTENT)
 }
@@ -389,28 +1286,49 @@ pub async fn revoke_share_by_user_handler(
     Path((file_id, target_user_id)): Path<(u32, u32)>,
     State(state): State<AppState>,
     Extension(owner_id): Extension<u32>,
-) -> Result<StatusCode, StatusCode> {
+    Extension(limit): Extension<TagLimit>,
+    info: RequestInfo,
+) -> Result<StatusCode, ApiError> {
+    let result = match require_within_limit(&state, file_id, owner_id, &limit).await {
+        Ok(()) => revoke_share_by_user(&state, file_id, target_user_id, owner_id).await,
+        Err(e) => Err(e),
+    };
+
+    let event = info
+        .event(AuditAction::ShareRevoke)
+        .target("file", file_id)
+        .detail(format!("user_id={target_user_id}"));
+    state.audit.record(with_outcome(event, &result)).await;
+
+    result
+}
+
+async fn revoke_share_by_user(
+    state: &AppState,
+    file_id: u32,
+    target_user_id: u32,
+    owner_id: u32,
+) -> Result<StatusCode, ApiError> {
     // Verify file exists and caller owns it
-    let row = sqlx::query("SELECT owner_id FROM files WHERE id = ?1")
+    let row = sqlx::query("SELECT owner_id, filename FROM files WHERE id = ?1")
         .bind(file_id as i64)
         .fetch_optional(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .await?
+        .ok_or(ApiError::NotFound("File not found"))?;
 
     let db_owner_id: i64 = row.get("owner_id");
     if db_owner_id != owner_id as i64 {
-        return Err(StatusCode::NOT_FOUND);
+        return Err(ApiError::NotFound("File not found"));
     }
+    let filename: String = row.get("filename");
 
     // Find the permission id for (file_id, target_user_id)
     let row = sqlx::query("SELECT id FROM permissions WHERE file_id = ?1 AND user_id = ?2 LIMIT 1")
         .bind(file_id as i64)
         .bind(target_user_id as i64)
         .fetch_optional(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .await?
+        .ok_or(ApiError::NotFound("Share not found"))?;
 
     let permission_id: i64 = row.get("id");
 
@@ -418,40 +1336,436 @@ pub async fn revoke_share_by_user_handler(
     sqlx::query("DELETE FROM permissions WHERE id = ?1")
         .bind(permission_id)
         .execute(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
+
+    state.events.publish(
+        LiveEventKind::Unshared,
+        vec![target_user_id],
+        json!({
+            "file_id": file_id,
+            "filename": filename,
+        }),
+    );
+    state
+        .webhooks
+        .emit(
+            owner_id,
+            WebhookEvent::ShareRevoked,
+            json!({
+                "file_id": file_id,
+                "filename": filename,
+                "permission_id": permission_id,
+                "user_id": target_user_id,
+            }),
+        )
+        .await;
 
     Ok(StatusCode::NO_CONTENT)
 }
 
-/// Stream file with headers
+/// Stream a stored file with its detected type and the headers of
+/// [`content_type::serving_headers`]. Files from before types were detected are
+/// served as octet-stream.
+/// Stream a stored file. The download is recorded for `downloader` (`None` for the
+/// public link) once the whole file has been read into the response.
 async fn stream_file_response(
+    state: &AppState,
     file_id: u32,
     filename_for_header: String,
-) -> Result<Response, StatusCode> {
+    content_type: Option<String>,
+    inline: bool,
+    downloader: Option<u32>,
+) -> Result<Response, ApiError> {
     let path = final_upload_path(file_id as u64);
 
     let disk_file = TokioFile::open(&path)
         .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
+        .map_err(|_| ApiError::NotFound("File not found"))?;
 
-    let stream = ReaderStream::new(disk_file);
+    let state = state.clone();
+    let stream = CountedStream::new(ReaderStream::new(disk_file), move || {
+        tokio::spawn(async move { record_download(&state, file_id, downloader).await });
+    });
     let body = Body::from_stream(stream);
 
-    let mut response = Response::new(body);
-    response.headers_mut().insert(
-        header::CONTENT_TYPE,
-        HeaderValue::from_static("application/octet-stream"),
-    );
+    let content_type = content_type.as_deref().unwrap_or(OCTET_STREAM);
+    let headers = content_type::serving_headers(content_type, &filename_for_header, inline)
+        .map_err(|_| ApiError::Internal)?;
 
-    let safe_name = filename_for_header.replace('"', "_");
-    let disposition = format!("attachment; filename=\"{}\"", safe_name);
-    response.headers_mut().insert(
-        header::CONTENT_DISPOSITION,
-        disposition
-            .parse()
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
-    );
+    let mut response = Response::new(body);
+    response.headers_mut().extend(headers);
 
     Ok(response)
 }
+
+/// Body stream that calls `on_finish` after its last chunk. A read error or a
+/// client that disconnects first (dropping the body) means no call.
+struct CountedStream<S> {
+    inner: S,
+    on_finish: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl<S> CountedStream<S> {
+    fn new(inner: S, on_finish: impl FnOnce() + Send + 'static) -> Self {
+        Self {
+            inner,
+            on_finish: Some(Box::new(on_finish)),
+        }
+    }
+}
+
+impl<S> Stream for CountedStream<S>
+where
+    S: Stream<Item = std::io::Result<Bytes>> + Unpin,
+{
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = ready!(Pin::new(&mut self.inner).poll_next(cx));
+        match &item {
+            Some(Ok(_)) => {}
+            Some(Err(_)) => self.on_finish = None,
+            None => {
+                if let Some(on_finish) = self.on_finish.take() {
+                    on_finish();
+                }
+            }
+        }
+        Poll::Ready(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures_util::StreamExt;
+
+    use super::*;
+    use crate::api::test_state;
+
+    fn limit(tag: &str) -> TagLimit {
+        TagLimit(Some(tag.into()))
+    }
+
+    async fn add_user(state: &AppState, username: &str) -> u32 {
+        state
+            .auth
+            .repo
+            .create(username.into(), "hash".into(), None)
+            .await
+            .unwrap()
+            .id
+    }
+
+    async fn add_file(state: &AppState, owner_id: u32, tags: &[&str]) -> u32 {
+        let file_id =
+            sqlx::query("INSERT INTO files (filename, size, owner_id) VALUES ('a.txt', 1, ?1)")
+                .bind(owner_id as i64)
+                .execute(&state.db)
+                .await
+                .unwrap()
+                .last_insert_rowid() as u32;
+        for tag in tags {
+            sqlx::query("INSERT INTO file_tags (file_id, tag) VALUES (?1, ?2)")
+                .bind(file_id as i64)
+                .bind(tag)
+                .execute(&state.db)
+                .await
+                .unwrap();
+        }
+        file_id
+    }
+
+    #[tokio::test]
+    async fn no_limit_reaches_any_file() {
+        let state = test_state().await;
+        let alice = add_user(&state, "alice").await;
+        let file_id = add_file(&state, alice, &[]).await;
+
+        require_within_limit(&state, file_id, alice, &TagLimit::default())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn limit_reaches_own_files_with_the_tag() {
+        let state = test_state().await;
+        let alice = add_user(&state, "alice").await;
+        let tagged = add_file(&state, alice, &["reports", "2024"]).await;
+        let other_tag = add_file(&state, alice, &["photos"]).await;
+        let untagged = add_file(&state, alice, &[]).await;
+
+        require_within_limit(&state, tagged, alice, &limit("reports"))
+            .await
+            .unwrap();
+        for file_id in [other_tag, untagged] {
+            let err = require_within_limit(&state, file_id, alice, &limit("reports"))
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(err, ApiError::NotFound(_)),
+                "file {file_id}: {err:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn limit_never_reaches_files_shared_with_the_user() {
+        let state = test_state().await;
+        let alice = add_user(&state, "alice").await;
+        let bob = add_user(&state, "bob").await;
+        let file_id = add_file(&state, alice, &["reports"]).await;
+        sqlx::query(
+            "INSERT INTO permissions (file_id, user_id, permission_type) VALUES (?1, ?2, 'read')",
+        )
+        .bind(file_id as i64)
+        .bind(bob as i64)
+        .execute(&state.db)
+        .await
+        .unwrap();
+
+        let err = require_within_limit(&state, file_id, bob, &limit("reports"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ApiError::NotFound(_)), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn limit_hides_missing_files_the_same_way() {
+        let state = test_state().await;
+        let alice = add_user(&state, "alice").await;
+
+        let err = require_within_limit(&state, 999, alice, &limit("reports"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ApiError::NotFound(_)), "{err:?}");
+    }
+
+    const SORTS: [(FileSort, SortOrder); 6] = [
+        (FileSort::Name, SortOrder::Asc),
+        (FileSort::Name, SortOrder::Desc),
+        (FileSort::Size, SortOrder::Asc),
+        (FileSort::Size, SortOrder::Desc),
+        (FileSort::Date, SortOrder::Asc),
+        (FileSort::Date, SortOrder::Desc),
+    ];
+
+    fn list_query(
+        sort: FileSort,
+        order: SortOrder,
+        limit: u32,
+        cursor: Option<String>,
+    ) -> ListFilesQuery {
+        ListFilesQuery {
+            limit: Some(limit),
+            cursor,
+            sort,
+            order: Some(order),
+            access: None,
+            is_public: None,
+            tag: None,
+        }
+    }
+
+    /// Ids of every file, following cursors with `limit` files per page
+    async fn list_all(
+        state: &AppState,
+        user_id: u32,
+        sort: FileSort,
+        order: SortOrder,
+        limit: u32,
+    ) -> Vec<u32> {
+        let mut ids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let Json(page) = list_files_handler(
+                State(state.clone()),
+                Extension(user_id),
+                Extension(TagLimit::default()),
+                Query(list_query(sort, order, limit, cursor)),
+            )
+            .await
+            .unwrap();
+            assert_eq!(page.total, 7);
+            assert!(page.items.len() <= limit as usize);
+            ids.extend(page.items.iter().map(|f| f.file_id));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return ids,
+            }
+        }
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = ListCursor {
+            sort: FileSort::Name,
+            order: SortOrder::Asc,
+            key: CursorKey::Text("Report, final.pdf".into()),
+            id: 42,
+        };
+        let decoded = ListCursor::decode(&cursor.encode(), FileSort::Name, SortOrder::Asc).unwrap();
+        assert_eq!(decoded.key, cursor.key);
+        assert_eq!(decoded.id, 42);
+
+        let cursor = ListCursor {
+            sort: FileSort::Size,
+            order: SortOrder::Desc,
+            key: CursorKey::Number(1 << 40),
+            id: 7,
+        };
+        let decoded =
+            ListCursor::decode(&cursor.encode(), FileSort::Size, SortOrder::Desc).unwrap();
+        assert_eq!(decoded.key, CursorKey::Number(1 << 40));
+        assert_eq!(decoded.id, 7);
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        let json = |s: &str| URL_SAFE_NO_PAD.encode(s);
+        let malformed = [
+            "not base64!".to_string(),
+            URL_SAFE_NO_PAD.encode([0xff, 0xfe]),
+            json("[]"),
+            json(r#"{"sort":"date","order":"desc","key":5}"#),
+            json(r#"{"sort":"owner","order":"desc","key":5,"id":1}"#),
+            // The sort key does not fit the sort
+            json(r#"{"sort":"date","order":"desc","key":"x","id":1}"#),
+            json(r#"{"sort":"name","order":"asc","key":5,"id":1}"#),
+        ];
+        for cursor in malformed {
+            let err = ListCursor::decode(&cursor, FileSort::Date, SortOrder::Desc).unwrap_err();
+            assert!(
+                matches!(&err, ApiError::BadRequest(msg) if msg == "Invalid cursor"),
+                "{cursor}: {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn cursor_only_fits_its_own_sort_order() {
+        let cursor = ListCursor {
+            sort: FileSort::Date,
+            order: SortOrder::Desc,
+            key: CursorKey::Number(1_700_000_000),
+            id: 3,
+        }
+        .encode();
+        for (sort, order) in [
+            (FileSort::Date, SortOrder::Asc),
+            (FileSort::Size, SortOrder::Desc),
+        ] {
+            let err = ListCursor::decode(&cursor, sort, order).unwrap_err();
+            assert!(
+                matches!(&err, ApiError::BadRequest(msg) if msg.contains("different sort order")),
+                "{err:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn ties_break_the_same_way_on_every_page() {
+        let state = test_state().await;
+        let alice = add_user(&state, "alice").await;
+        // Names differing only in case, sizes and dates all tie between files
+        let mut files = Vec::new();
+        for i in 0..7i64 {
+            let name = ["a.txt", "A.TXT", "b.txt"][i as usize % 3];
+            let id = sqlx::query(
+                "INSERT INTO files (filename, size, owner_id, uploaded_at) VALUES (?1, ?2, ?3, ?4)",
+            )
+            .bind(name)
+            .bind(10 * (i % 2))
+            .bind(alice as i64)
+            .bind(1_700_000_000 + i % 3)
+            .execute(&state.db)
+            .await
+            .unwrap()
+            .last_insert_rowid();
+            files.push((
+                id as u32,
+                name.to_lowercase(),
+                10 * (i % 2),
+                1_700_000_000 + i % 3,
+            ));
+        }
+
+        for (sort, order) in SORTS {
+            let mut expected = files.clone();
+            expected.sort_by(|a, b| {
+                let key = match sort {
+                    FileSort::Name => a.1.cmp(&b.1),
+                    FileSort::Size => a.2.cmp(&b.2),
+                    FileSort::Date => a.3.cmp(&b.3),
+                };
+                key.then(a.0.cmp(&b.0))
+            });
+            if order == SortOrder::Desc {
+                expected.reverse();
+            }
+            let expected: Vec<u32> = expected.iter().map(|f| f.0).collect();
+
+            for limit in [1, 2, 3, 7] {
+                let ids = list_all(&state, alice, sort, order, limit).await;
+                assert_eq!(ids, expected, "{sort:?} {order:?}, {limit} per page");
+            }
+        }
+    }
+
+    /// Stream over `chunks` that counts how often it reported finishing
+    fn counted(
+        chunks: Vec<std::io::Result<Bytes>>,
+    ) -> (
+        CountedStream<impl Stream<Item = std::io::Result<Bytes>> + Unpin>,
+        Arc<AtomicUsize>,
+    ) {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let counter = finished.clone();
+        let stream = CountedStream::new(futures_util::stream::iter(chunks), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        (stream, finished)
+    }
+
+    #[tokio::test]
+    async fn download_counts_once_the_body_is_complete() {
+        let (mut stream, finished) = counted(vec![Ok(Bytes::from("a")), Ok(Bytes::from("b"))]);
+
+        assert!(stream.next().await.is_some());
+        assert!(stream.next().await.is_some());
+        assert_eq!(finished.load(Ordering::SeqCst), 0);
+        assert!(stream.next().await.is_none());
+        assert!(stream.next().await.is_none());
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn abandoned_download_is_not_counted() {
+        let (mut stream, finished) = counted(vec![Ok(Bytes::from("a")), Ok(Bytes::from("b"))]);
+
+        assert!(stream.next().await.is_some());
+        drop(stream);
+        assert_eq!(finished.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn failed_download_is_not_counted() {
+        let (stream, finished) = counted(vec![
+            Ok(Bytes::from("a")),
+            Err(std::io::Error::other("disk gone")),
+        ]);
+
+        let chunks: Vec<_> = stream.collect().await;
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(finished.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn routes_over_every_file_refuse_a_limit() {
+        require_no_limit(&TagLimit::default()).unwrap();
+        let err = require_no_limit(&limit("reports")).unwrap_err();
+        assert!(matches!(err, ApiError::Forbidden(_)), "{err:?}");
+    }
+}
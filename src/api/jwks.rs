@@ -0,0 +1,18 @@
+use axum::{Json, extract::State};
+
+use crate::api::AppState;
+use crate::auth::keys::Jwks;
+
+/// GET /.well-known/jwks.json
+///
+/// Public keys that other services can use to verify access tokens.
+pub async fn jwks_handler(State(state): State<AppState>) -> Json<Jwks> {
+    Json(state.auth.keys.jwks())
+}
+
+/// GET /.well-known/audit-keys.json
+///
+/// Public keys that verify audit checkpoints.
+pub async fn audit_keys_handler(State(state): State<AppState>) -> Json<Jwks> {
+    Json(state.audit.jwks())
+}
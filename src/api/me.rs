@@ -1,31 +1,41 @@
 use axum::{
     Json,
     extract::{Extension, State},
-    http::StatusCode,
 };
 use serde::Serialize;
 use sqlx::Row;
 
 use crate::api::AppState;
+use crate::api::error::ApiError;
+use crate::auth::error::AuthError;
 
 #[derive(Serialize)]
 pub struct MeResponse {
     pub user_id: u32,
     pub username: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
 }
 
 pub async fn me_handler(
     State(state): State<AppState>,
     Extension(user_id): Extension<u32>,
-) -> Result<Json<MeResponse>, StatusCode> {
-    let row = sqlx::query("SELECT username FROM users WHERE id = ?1")
+) -> Result<Json<MeResponse>, ApiError> {
+    let row = sqlx::query("SELECT username, email, email_verified_at FROM users WHERE id = ?1")
         .bind(user_id as i64)
         .fetch_optional(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
 
-    let row = row.ok_or(StatusCode::UNAUTHORIZED)?;
+    let row = row.ok_or(AuthError::UserNotFound)?;
     let username: String = row.get("username");
+    let email: Option<String> = row.get("email");
+    let email_verified =
+        email.is_some() && row.get::<Option<i64>, _>("email_verified_at").is_some();
 
-    Ok(Json(MeResponse { user_id, username }))
+    Ok(Json(MeResponse {
+        user_id,
+        username,
+        email,
+        email_verified,
+    }))
 }
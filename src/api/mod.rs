@@ -1,17 +1,119 @@
 use sqlx::SqlitePool;
 
+pub mod account;
+pub mod admin;
+pub mod api_keys;
+pub mod audit;
 pub mod auth;
 pub mod auth_middleware;
+pub mod email;
+pub mod error;
+pub mod events;
 pub mod file;
 pub mod health;
+pub mod jwks;
 pub mod me;
+pub mod oidc;
+pub mod password;
+pub mod rate_limit;
+pub mod search;
+pub mod tags;
+pub mod two_factor;
+pub mod webhooks;
 
 pub use health::health_check;
 
+use crate::audit::AuditLog;
 use crate::auth::service::SimpleAuthService;
+use crate::auth::throttle::LoginThrottle;
+use crate::events::EventBus;
+use crate::webhooks::Webhooks;
 
 #[derive(Clone)]
 pub struct AppState {
     pub auth: SimpleAuthService,
     pub db: SqlitePool,
+    pub throttle: LoginThrottle,
+    pub audit: AuditLog,
+    pub webhooks: Webhooks,
+    pub events: EventBus,
+}
+
+/// State over a fresh in-memory database, for tests
+#[cfg(test)]
+pub(crate) async fn test_state() -> AppState {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use crate::auth::keys::JwtKeys;
+    use crate::auth::passwords::PasswordHashing;
+    use crate::auth::policy::PasswordPolicy;
+    use crate::auth::repository::AuthUserRepository;
+    use crate::config::{AuthConfig, PasswordConfig, ThrottleConfig, WebhookConfig};
+    use crate::mail::FileMailer;
+
+    // One connection, since each in-memory connection is its own database
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    let auth_cfg = AuthConfig {
+        access_token_ttl_secs: 900,
+        refresh_token_idle_ttl_secs: 86_400,
+        refresh_token_absolute_ttl_secs: 86_400 * 30,
+        issuer: "sfs".into(),
+        audience: "sfs".into(),
+        leeway_secs: 0,
+        password_reset_ttl_secs: 3600,
+        email_verification_ttl_secs: 3600,
+        require_verified_email: false,
+    };
+    crate::db::create_schema(&pool, &auth_cfg).await.unwrap();
+
+    // Cheapest Argon2 parameters; strength is not under test
+    let password_cfg = PasswordConfig {
+        min_length: 8,
+        breached_list: None,
+        argon2_memory_kib: 8,
+        argon2_iterations: 1,
+        argon2_parallelism: 1,
+    };
+    let throttle_cfg = ThrottleConfig {
+        max_failures: 5,
+        ip_max_failures: 20,
+        window_secs: 900,
+        base_lockout_secs: 60,
+        max_lockout_secs: 3600,
+    };
+    let webhook_cfg = WebhookConfig {
+        max_attempts: 3,
+        retry_base_secs: 1,
+        retry_max_secs: 60,
+        timeout_secs: 5,
+        concurrency: 1,
+        allow_private_hosts: false,
+    };
+
+    let keys = Arc::new(JwtKeys::generate("test"));
+    let auth = SimpleAuthService::new(
+        AuthUserRepository::new(pool.clone()),
+        keys.clone(),
+        Arc::new(auth_cfg),
+        Arc::new(PasswordPolicy::load(&password_cfg).unwrap()),
+        PasswordHashing::new(&password_cfg).unwrap(),
+        Arc::new(FileMailer::new(PathBuf::from("target/test-outbox"))),
+        None,
+    );
+
+    AppState {
+        auth,
+        throttle: LoginThrottle::new(pool.clone(), throttle_cfg),
+        audit: AuditLog::new(pool.clone(), keys),
+        webhooks: Webhooks::new(pool.clone(), webhook_cfg).unwrap(),
+        events: EventBus::new(),
+        db: pool,
+    }
 }
@@ -0,0 +1,64 @@
+use axum::{
+    Json,
+    extract::{Extension, Query, State},
+    response::Redirect,
+};
+
+use crate::api::AppState;
+use crate::api::error::ApiError;
+use crate::auth::service::AuthService;
+use crate::auth::types::{
+    OidcCallbackOutcome, OidcCallbackQuery, OidcLinkConfirmRequest, OidcLinkStart, OidcLinked,
+};
+
+/// GET /oidc/login
+///
+/// Start a single sign-on login: redirects to the identity provider
+pub async fn oidc_login_handler(State(state): State<AppState>) -> Result<Redirect, ApiError> {
+    let url = state.auth.begin_oidc_login(None).await?;
+    Ok(Redirect::to(&url))
+}
+
+/// POST /me/oidc/link
+///
+/// Start linking an identity at the provider to the logged-in account.
+/// Returns the URL to open in a browser; the callback then returns a link code
+/// instead of logging in, to be sent to `POST /me/oidc/link/confirm`.
+pub async fn oidc_link_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+) -> Result<Json<OidcLinkStart>, ApiError> {
+    let authorization_url = state.auth.begin_oidc_login(Some(user_id)).await?;
+
+    Ok(Json(OidcLinkStart { authorization_url }))
+}
+
+/// POST /me/oidc/link/confirm
+///
+/// Finish a link with the code from the callback. Only the account that
+/// started the link can use its code.
+pub async fn oidc_link_confirm_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Json(req): Json<OidcLinkConfirmRequest>,
+) -> Result<Json<OidcLinked>, ApiError> {
+    let linked = state
+        .auth
+        .confirm_oidc_link(user_id, &req.link_code)
+        .await?;
+
+    Ok(Json(linked))
+}
+
+/// GET /oidc/callback
+///
+/// Where the provider sends the browser back. Returns the same body as
+/// `POST /login` (tokens or a 2FA challenge), or a link code for a link flow.
+pub async fn oidc_callback_handler(
+    State(state): State<AppState>,
+    Query(query): Query<OidcCallbackQuery>,
+) -> Result<Json<OidcCallbackOutcome>, ApiError> {
+    let outcome = state.auth.complete_oidc_login(query).await?;
+
+    Ok(Json(outcome))
+}
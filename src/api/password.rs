@@ -0,0 +1,96 @@
+use axum::{
+    Json,
+    extract::{Extension, State},
+    http::StatusCode,
+};
+
+use crate::api::AppState;
+use crate::api::audit::{RequestInfo, with_outcome};
+use crate::api::auth::check_lockout;
+use crate::api::error::ApiError;
+use crate::audit::AuditAction;
+use crate::auth::error::AuthError;
+use crate::auth::service::AuthService;
+use crate::auth::throttle::user_key;
+use crate::auth::types::{
+    AuthTokenResponse, ChangePasswordRequest, ForgotPasswordRequest, ResetPasswordRequest,
+};
+
+/// POST /me/password
+///
+/// Change the password after checking the current one. All other sessions are
+/// revoked and this session gets fresh tokens. Wrong current passwords count
+/// towards the login lockout for the account.
+pub async fn change_password_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    info: RequestInfo,
+    Json(req): Json<ChangePasswordRequest>,
+) -> Result<Json<AuthTokenResponse>, ApiError> {
+    let result = change_password(&state, user_id, req).await;
+
+    let event = info
+        .event(AuditAction::PasswordChange)
+        .target("user", user_id);
+    state.audit.record(with_outcome(event, &result)).await;
+
+    Ok(Json(result?))
+}
+
+async fn change_password(
+    state: &AppState,
+    user_id: u32,
+    req: ChangePasswordRequest,
+) -> Result<AuthTokenResponse, ApiError> {
+    let username = state
+        .auth
+        .repo
+        .get_username_by_id(user_id)
+        .await?
+        .ok_or(AuthError::UserNotFound)?;
+    let keys = [user_key(&username)];
+
+    check_lockout(state, &keys).await?;
+
+    match state.auth.change_password(user_id, req).await {
+        Ok(tokens) => Ok(tokens),
+        Err(AuthError::InvalidCredentials) => {
+            let _ = state.throttle.record_failure(&keys).await;
+            Err(AuthError::InvalidCredentials.into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// POST /password/forgot
+///
+/// Mail a reset token to the account's verified email address, if it has one.
+/// Always answers 202 so the response does not reveal whether the username exists.
+pub async fn forgot_password_handler(
+    State(state): State<AppState>,
+    Json(req): Json<ForgotPasswordRequest>,
+) -> Result<StatusCode, ApiError> {
+    state.auth.send_password_reset(&req.username).await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// POST /password/reset
+///
+/// Set a new password with a reset token; revokes all sessions
+pub async fn reset_password_handler(
+    State(state): State<AppState>,
+    info: RequestInfo,
+    Json(req): Json<ResetPasswordRequest>,
+) -> Result<StatusCode, ApiError> {
+    let result = state.auth.reset_password(req).await.map_err(ApiError::from);
+
+    let mut event = info.event(AuditAction::PasswordReset);
+    if let Ok(user_id) = result {
+        event = event.actor(Some(user_id)).target("user", user_id);
+    }
+    state.audit.record(with_outcome(event, &result)).await;
+
+    result?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use parking_lot::Mutex;
+
+use crate::api::error::ApiError;
+use crate::config::RatePolicy;
+
+const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+const RATELIMIT_POLICY: HeaderName = HeaderName::from_static("ratelimit-policy");
+
+/// In-memory token bucket rate limiter for one policy.
+///
+/// Requests are keyed by the user id set by `auth_middleware`, or by client IP
+/// when there is none: layered inside `auth_middleware` it limits each user,
+/// outside it each client IP.
+#[derive(Clone)]
+pub struct RateLimiter {
+    policy: RatePolicy,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Outcome of taking a token from a bucket
+struct Decision {
+    allowed: bool,
+    remaining: u32,
+    /// Seconds until the bucket is full again
+    reset_secs: u64,
+    /// Seconds until the next token is available
+    retry_after_secs: u64,
+}
+
+impl RateLimiter {
+    pub fn new(policy: RatePolicy) -> Self {
+        Self {
+            policy,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Tokens added per second
+    fn rate(&self) -> f64 {
+        self.policy.limit as f64 / self.policy.window_secs as f64
+    }
+
+    fn check(&self, key: String) -> Decision {
+        let limit = self.policy.limit as f64;
+        let rate = self.rate();
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock();
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: limit,
+            updated: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(limit);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        Decision {
+            allowed,
+            remaining: bucket.tokens.floor() as u32,
+            reset_secs: ((limit - bucket.tokens) / rate).ceil() as u64,
+            retry_after_secs: ((1.0 - bucket.tokens).max(0.0) / rate).ceil() as u64,
+        }
+    }
+
+    /// Drop buckets that have refilled completely; they are equivalent to a fresh bucket
+    pub fn prune(&self) -> usize {
+        let window = self.policy.window_secs as f64;
+        let mut buckets = self.buckets.lock();
+        let before = buckets.len();
+        buckets.retain(|_, b| b.updated.elapsed().as_secs_f64() < window);
+        before - buckets.len()
+    }
+
+    fn set_headers(&self, headers: &mut HeaderMap, decision: &Decision) {
+        // An inner, route-specific limiter has already reported; keep its numbers
+        if headers.contains_key(RATELIMIT_LIMIT) {
+            return;
+        }
+
+        headers.insert(RATELIMIT_LIMIT, HeaderValue::from(self.policy.limit));
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from(decision.remaining));
+        headers.insert(RATELIMIT_RESET, HeaderValue::from(decision.reset_secs));
+        if let Ok(v) = HeaderValue::from_str(&format!(
+            "{};w={}",
+            self.policy.limit, self.policy.window_secs
+        )) {
+            headers.insert(RATELIMIT_POLICY, v);
+        }
+    }
+}
+
+/// Bucket of the authenticated user, or of the client IP when there is none
+fn bucket_key(req: &Request<Body>) -> String {
+    match req.extensions().get::<u32>() {
+        Some(user_id) => format!("user:{user_id}"),
+        None => match req.extensions().get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+            None => "unknown".to_string(),
+        },
+    }
+}
+
+/// Rate limiting middleware; returns 429 with `Retry-After` once the bucket is empty
+pub async fn rate_limit(
+    State(limiter): State<RateLimiter>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let decision = limiter.check(bucket_key(&req));
+
+    let mut res = if decision.allowed {
+        next.run(req).await
+    } else {
+        ApiError::RateLimited {
+            retry_after: decision.retry_after_secs,
+        }
+        .into_response()
+    };
+
+    limiter.set_headers(res.headers_mut(), &decision);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// 2 requests per 10 seconds: one token every 5 seconds
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RatePolicy {
+            limit: 2,
+            window_secs: 10,
+        })
+    }
+
+    /// Pretend the bucket was last touched `secs` earlier than it was
+    fn rewind(limiter: &RateLimiter, key: &str, secs: u64) {
+        let mut buckets = limiter.buckets.lock();
+        let bucket = buckets.get_mut(key).unwrap();
+        bucket.updated -= Duration::from_secs(secs);
+    }
+
+    #[test]
+    fn full_bucket_allows_the_limit_then_refuses() {
+        let limiter = limiter();
+
+        let first = limiter.check("user:1".into());
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 1);
+        assert_eq!(first.reset_secs, 5);
+
+        let second = limiter.check("user:1".into());
+        assert!(second.allowed);
+        assert_eq!(second.remaining, 0);
+
+        let refused = limiter.check("user:1".into());
+        assert!(!refused.allowed);
+        assert_eq!(refused.remaining, 0);
+        assert_eq!(refused.retry_after_secs, 5);
+        assert_eq!(refused.reset_secs, 10);
+    }
+
+    #[test]
+    fn bucket_refills_over_the_window() {
+        let limiter = limiter();
+        for _ in 0..2 {
+            limiter.check("user:1".into());
+        }
+
+        rewind(&limiter, "user:1", 3);
+        let early = limiter.check("user:1".into());
+        assert!(!early.allowed);
+        assert_eq!(early.retry_after_secs, 2);
+
+        rewind(&limiter, "user:1", 2);
+        assert!(limiter.check("user:1".into()).allowed);
+        assert!(!limiter.check("user:1".into()).allowed);
+
+        // A long pause refills no more than the limit
+        rewind(&limiter, "user:1", 3600);
+        assert_eq!(limiter.check("user:1".into()).remaining, 1);
+    }
+
+    #[test]
+    fn keys_have_separate_buckets() {
+        let limiter = limiter();
+        for _ in 0..2 {
+            assert!(limiter.check("user:1".into()).allowed);
+        }
+        assert!(!limiter.check("user:1".into()).allowed);
+
+        assert!(limiter.check("user:2".into()).allowed);
+        assert!(limiter.check("ip:203.0.113.9".into()).allowed);
+    }
+
+    #[test]
+    fn requests_are_keyed_by_user_then_ip() {
+        let addr: SocketAddr = "203.0.113.9:4000".parse().unwrap();
+
+        let mut req = Request::new(Body::empty());
+        assert_eq!(bucket_key(&req), "unknown");
+
+        req.extensions_mut().insert(ConnectInfo(addr));
+        assert_eq!(bucket_key(&req), "ip:203.0.113.9");
+
+        req.extensions_mut().insert(7u32);
+        assert_eq!(bucket_key(&req), "user:7");
+    }
+
+    #[test]
+    fn prune_drops_refilled_buckets() {
+        let limiter = limiter();
+        limiter.check("user:1".into());
+        limiter.check("user:2".into());
+        rewind(&limiter, "user:1", 10);
+
+        assert_eq!(limiter.prune(), 1);
+        assert!(limiter.buckets.lock().contains_key("user:2"));
+    }
+}
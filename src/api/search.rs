@@ -0,0 +1,304 @@
+use axum::{
+    Json,
+    extract::{Extension, Query, State},
+};
+use serde::Deserialize;
+use sqlx::{QueryBuilder, Row, Sqlite};
+
+use crate::api::AppState;
+use crate::api::error::ApiError;
+use crate::api::file::{
+    FILE_LIST_COLUMNS, FileAccess, FileListPage, FileSort, ListCursor, SortOrder, finish_page,
+    parse_tag_filter, push_access, push_page, push_tags, push_visible, push_within_limit,
+};
+use crate::auth::scope::TagLimit;
+use crate::auth::username;
+
+/// Page size when no `limit` is given
+const DEFAULT_SEARCH_LIMIT: u32 = 100;
+
+/// Largest `limit` accepted
+const MAX_SEARCH_LIMIT: u32 = 1000;
+
+/// Results are newest first, paged like `GET /files?sort=date`
+const SEARCH_SORT: FileSort = FileSort::Date;
+const SEARCH_ORDER: SortOrder = SortOrder::Desc;
+
+/// Longest `name` or `q` accepted, in characters
+const MAX_TERM_LEN: usize = 256;
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    /// Filename substring, or a whole-name glob when it contains `*` or `?`
+    pub name: Option<String>,
+    /// Words in the description; each matches as a prefix
+    pub q: Option<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Uploaded at or after this Unix time
+    pub since: Option<i64>,
+    /// Uploaded before this Unix time
+    pub until: Option<i64>,
+    /// Owner's username
+    pub owner: Option<String>,
+    pub access: Option<FileAccess>,
+    pub is_public: Option<bool>,
+    /// Comma-separated tags the file must all have
+    pub tag: Option<String>,
+    /// Metadata key the file must have
+    pub meta_key: Option<String>,
+    /// Value `meta_key` must have; needs `meta_key`
+    pub meta_value: Option<String>,
+    /// Page size
+    pub limit: Option<u32>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+}
+
+/// GET /files/search
+///
+/// One page of the files you own or that are shared with you matching every given
+/// filter, newest first, with the total across pages and a cursor for the next page.
+/// Keys limited to a tag only search their owner's files carrying it.
+pub async fn search_files_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Extension(tag_limit): Extension<TagLimit>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<FileListPage>, ApiError> {
+    let uid = user_id as i64;
+    let after = match &query.cursor {
+        Some(cursor) => Some(ListCursor::decode(cursor, SEARCH_SORT, SEARCH_ORDER)?),
+        None => None,
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT);
+    let tags = parse_tag_filter(query.tag.as_deref())?;
+
+    let mut count: QueryBuilder<Sqlite> = QueryBuilder::new(
+        "SELECT COUNT(*) AS n FROM files f JOIN users u ON u.id = f.owner_id WHERE ",
+    );
+    push_search_filters(&mut count, uid, &query, &tag_limit, &tags)?;
+    let total: i64 = count.build().fetch_one(&state.db).await?.get("n");
+
+    let mut sql: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+        "SELECT {FILE_LIST_COLUMNS}, CASE WHEN f.owner_id = "
+    ));
+    sql.push_bind(uid).push(
+        " THEN 'owner' ELSE 'shared' END AS access \
+         FROM files f JOIN users u ON u.id = f.owner_id WHERE ",
+    );
+    push_search_filters(&mut sql, uid, &query, &tag_limit, &tags)?;
+    push_page(&mut sql, SEARCH_SORT, SEARCH_ORDER, after.as_ref(), limit);
+
+    let rows = sql.build().fetch_all(&state.db).await?;
+    let (items, next_cursor) = finish_page(&rows, SEARCH_SORT, SEARCH_ORDER, limit);
+
+    Ok(Json(FileListPage {
+        items,
+        total: total as u64,
+        next_cursor,
+    }))
+}
+
+/// Conditions of `GET /files/search` after `WHERE`; the cursor is not one of them
+fn push_search_filters(
+    sql: &mut QueryBuilder<'_, Sqlite>,
+    uid: i64,
+    query: &SearchQuery,
+    tag_limit: &TagLimit,
+    tags: &[String],
+) -> Result<(), ApiError> {
+    push_visible(sql, uid);
+    push_within_limit(sql, uid, tag_limit);
+
+    if let Some(name) = non_empty(&query.name, "name")? {
+        sql.push(" AND f.filename LIKE ")
+            .push_bind(like_pattern(name))
+            .push(" ESCAPE '\\'");
+    }
+    if let Some(q) = non_empty(&query.q, "q")? {
+        let Some(terms) = fts_query(q) else {
+            return Err(ApiError::BadRequest(
+                "q must contain at least one word".into(),
+            ));
+        };
+        sql.push(" AND f.id IN (SELECT rowid FROM files_fts WHERE files_fts MATCH ")
+            .push_bind(terms)
+            .push(")");
+    }
+    if let Some(min_size) = query.min_size {
+        sql.push(" AND f.size >= ").push_bind(min_size as i64);
+    }
+    if let Some(max_size) = query.max_size {
+        sql.push(" AND f.size <= ").push_bind(max_size as i64);
+    }
+    if let Some(since) = query.since {
+        sql.push(" AND f.uploaded_at >= ").push_bind(since);
+    }
+    if let Some(until) = query.until {
+        sql.push(" AND f.uploaded_at < ").push_bind(until);
+    }
+    if let Some(owner) = non_empty(&query.owner, "owner")? {
+        sql.push(" AND u.username = ")
+            .push_bind(username::canonicalize(owner));
+    }
+    if let Some(access) = query.access {
+        push_access(sql, uid, access);
+    }
+    if let Some(is_public) = query.is_public {
+        sql.push(" AND f.is_public = ").push_bind(is_public as i64);
+    }
+
+    push_tags(sql, tags);
+    match (&query.meta_key, &query.meta_value) {
+        (Some(key), value) => {
+            sql.push(
+                " AND EXISTS (SELECT 1 FROM file_metadata m WHERE m.file_id = f.id AND m.key = ",
+            )
+            .push_bind(key.clone());
+            if let Some(value) = value {
+                sql.push(" AND m.value = ").push_bind(value.clone());
+            }
+            sql.push(")");
+        }
+        (None, Some(_)) => {
+            return Err(ApiError::BadRequest("meta_value needs meta_key".into()));
+        }
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+/// Trimmed value of an optional text filter; blank counts as absent
+fn non_empty<'a>(value: &'a Option<String>, field: &str) -> Result<Option<&'a str>, ApiError> {
+    let Some(value) = value.as_deref().map(str::trim).filter(|v| !v.is_empty()) else {
+        return Ok(None);
+    };
+    if value.chars().count() > MAX_TERM_LEN {
+        return Err(ApiError::BadRequest(format!(
+            "{field} is longer than {MAX_TERM_LEN} characters"
+        )));
+    }
+    Ok(Some(value))
+}
+
+/// `LIKE` pattern (escaped with `\`) for a filename filter.
+///
+/// `*` and `?` make it a glob over the whole name; otherwise it matches anywhere.
+fn like_pattern(name: &str) -> String {
+    let is_glob = name.contains(['*', '?']);
+    let mut pattern = String::with_capacity(name.len() + 2);
+    if !is_glob {
+        pattern.push('%');
+    }
+    for c in name.chars() {
+        match c {
+            '*' => pattern.push('%'),
+            '?' => pattern.push('_'),
+            '%' | '_' | '\\' => {
+                pattern.push('\\');
+                pattern.push(c);
+            }
+            c => pattern.push(c),
+        }
+    }
+    if !is_glob {
+        pattern.push('%');
+    }
+    pattern
+}
+
+/// FTS5 query matching every word of `q` as a prefix, with FTS syntax neutralized
+fn fts_query(q: &str) -> Option<String> {
+    let terms: Vec<String> = q
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_state;
+
+    fn search(limit: u32, cursor: Option<String>) -> SearchQuery {
+        SearchQuery {
+            name: Some("report".into()),
+            q: None,
+            min_size: None,
+            max_size: None,
+            since: None,
+            until: None,
+            owner: None,
+            access: None,
+            is_public: None,
+            tag: None,
+            meta_key: None,
+            meta_value: None,
+            limit: Some(limit),
+            cursor,
+        }
+    }
+
+    #[tokio::test]
+    async fn search_pages_newest_first() {
+        let state = test_state().await;
+        let alice = state
+            .auth
+            .repo
+            .create("alice".into(), "hash".into(), None)
+            .await
+            .unwrap()
+            .id;
+        let mut reports = Vec::new();
+        for (name, uploaded_at) in [
+            ("report-1.pdf", 100),
+            ("photo.jpg", 200),
+            ("report-2.pdf", 200),
+            ("report-3.pdf", 200),
+            ("report-4.pdf", 300),
+        ] {
+            let id = sqlx::query(
+                "INSERT INTO files (filename, size, owner_id, uploaded_at) VALUES (?1, 1, ?2, ?3)",
+            )
+            .bind(name)
+            .bind(alice as i64)
+            .bind(uploaded_at)
+            .execute(&state.db)
+            .await
+            .unwrap()
+            .last_insert_rowid() as u32;
+            if name.starts_with("report") {
+                reports.push(id);
+            }
+        }
+        // Newest first, ties by id
+        let expected = vec![reports[3], reports[2], reports[1], reports[0]];
+
+        let mut ids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let Json(page) = search_files_handler(
+                State(state.clone()),
+                Extension(alice),
+                Extension(TagLimit::default()),
+                Query(search(3, cursor)),
+            )
+            .await
+            .unwrap();
+            assert_eq!(page.total, 4);
+            ids.extend(page.items.iter().map(|f| f.file_id));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(ids, expected);
+    }
+}
@@ -0,0 +1,751 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use axum::{
+    Json,
+    extract::{Extension, Path, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::Row;
+
+use crate::api::AppState;
+use crate::api::audit::{RequestInfo, with_outcome};
+use crate::api::error::ApiError;
+use crate::api::file::require_within_limit;
+use crate::audit::AuditAction;
+use crate::auth::scope::TagLimit;
+use crate::events::LiveEventKind;
+
+/// Most tags on one file
+const MAX_TAGS_PER_FILE: usize = 20;
+
+/// Longest tag, in characters
+const MAX_TAG_LEN: usize = 32;
+
+/// Most metadata keys on one file
+const MAX_METADATA_KEYS: usize = 50;
+
+/// Longest metadata key, in characters
+const MAX_METADATA_KEY_LEN: usize = 64;
+
+/// Longest metadata value, in characters
+const MAX_METADATA_VALUE_LEN: usize = 1024;
+
+#[derive(Deserialize)]
+pub struct AddTagsRequest {
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct FileTags {
+    pub file_id: u32,
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct FileMetadata {
+    pub file_id: u32,
+    pub metadata: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    /// Files visible to you with this tag
+    pub files: u64,
+}
+
+/// Canonical form of a tag: trimmed and lowercased, 1-32 characters from
+/// `a-z`, `0-9`, `.`, `_`, `-` and `:`
+pub fn normalize_tag(input: &str) -> Result<String, ApiError> {
+    let tag = input.trim().to_lowercase();
+    let len = tag.chars().count();
+    let valid_chars = tag.chars().all(|c| {
+        c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-' | ':')
+    });
+
+    if !(1..=MAX_TAG_LEN).contains(&len) || !valid_chars {
+        return Err(ApiError::BadRequest(format!(
+            "Invalid tag {input:?}: use 1-{MAX_TAG_LEN} characters from a-z, 0-9, '.', '_', '-' and ':'"
+        )));
+    }
+    Ok(tag)
+}
+
+fn validate_metadata_key(key: &str) -> Result<(), ApiError> {
+    let len = key.chars().count();
+    let valid_chars = key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+
+    if !(1..=MAX_METADATA_KEY_LEN).contains(&len) || !valid_chars {
+        return Err(ApiError::BadRequest(format!(
+            "Invalid metadata key {key:?}: use 1-{MAX_METADATA_KEY_LEN} characters from A-Z, a-z, 0-9, '.', '_' and '-'"
+        )));
+    }
+    Ok(())
+}
+
+/// GET /file/:id/tags
+///
+/// Tags of a file you own or that is shared with you
+pub async fn get_tags_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Extension(limit): Extension<TagLimit>,
+    Path(file_id): Path<u32>,
+) -> Result<Json<FileTags>, ApiError> {
+    require_visible(&state, file_id, user_id, &limit).await?;
+    Ok(Json(FileTags {
+        file_id,
+        tags: load_tags(&state, file_id).await?,
+    }))
+}
+
+/// POST /file/:id/tags
+///
+/// Owner-only: add tags; ones already on the file are kept
+pub async fn add_tags_handler(
+    State(state): State<AppState>,
+    Extension(owner_id): Extension<u32>,
+    Extension(limit): Extension<TagLimit>,
+    Path(file_id): Path<u32>,
+    info: RequestInfo,
+    Json(req): Json<AddTagsRequest>,
+) -> Result<Json<FileTags>, ApiError> {
+    let result = add_tags(&state, file_id, owner_id, &limit, &req).await;
+
+    let mut event = info.event(AuditAction::FileTagAdd).target("file", file_id);
+    if result.is_ok() {
+        // Every tag was valid; duplicates in the request are listed once
+        let added: BTreeSet<String> = req
+            .tags
+            .iter()
+            .filter_map(|t| normalize_tag(t).ok())
+            .collect();
+        event = event.detail(added.into_iter().collect::<Vec<_>>().join(","));
+    }
+    state.audit.record(with_outcome(event, &result)).await;
+
+    Ok(Json(result?))
+}
+
+async fn add_tags(
+    state: &AppState,
+    file_id: u32,
+    owner_id: u32,
+    limit: &TagLimit,
+    req: &AddTagsRequest,
+) -> Result<FileTags, ApiError> {
+    let filename = require_owner(state, file_id, owner_id, limit).await?;
+
+    let mut new_tags = Vec::with_capacity(req.tags.len());
+    for tag in &req.tags {
+        let tag = normalize_tag(tag)?;
+        if !new_tags.contains(&tag) {
+            new_tags.push(tag);
+        }
+    }
+    if new_tags.is_empty() {
+        return Err(ApiError::BadRequest("No tags given".into()));
+    }
+
+    let mut tx = state.db.begin().await?;
+    for tag in &new_tags {
+        sqlx::query("INSERT OR IGNORE INTO file_tags (file_id, tag) VALUES (?1, ?2)")
+            .bind(file_id as i64)
+            .bind(tag)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let count: i64 = sqlx::query("SELECT COUNT(*) AS n FROM file_tags WHERE file_id = ?1")
+        .bind(file_id as i64)
+        .fetch_one(&mut *tx)
+        .await?
+        .get("n");
+    if count as usize > MAX_TAGS_PER_FILE {
+        return Err(ApiError::BadRequest(format!(
+            "A file can have at most {MAX_TAGS_PER_FILE} tags"
+        )));
+    }
+    tx.commit().await?;
+
+    let tags = load_tags(state, file_id).await?;
+    publish_update(
+        state,
+        file_id,
+        owner_id,
+        json!({ "file_id": file_id, "filename": filename, "tags": tags }),
+    )
+    .await;
+
+    Ok(FileTags { file_id, tags })
+}
+
+/// DELETE /file/:id/tags/:tag
+///
+/// Owner-only: remove a tag
+pub async fn remove_tag_handler(
+    State(state): State<AppState>,
+    Extension(owner_id): Extension<u32>,
+    Extension(limit): Extension<TagLimit>,
+    Path((file_id, tag)): Path<(u32, String)>,
+    info: RequestInfo,
+) -> Result<StatusCode, ApiError> {
+    let result = remove_tag(&state, file_id, owner_id, &limit, &tag).await;
+
+    let event = info
+        .event(AuditAction::FileTagRemove)
+        .target("file", file_id)
+        .detail(&tag);
+    state.audit.record(with_outcome(event, &result)).await;
+
+    result?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn remove_tag(
+    state: &AppState,
+    file_id: u32,
+    owner_id: u32,
+    limit: &TagLimit,
+    tag: &str,
+) -> Result<(), ApiError> {
+    let filename = require_owner(state, file_id, owner_id, limit).await?;
+    let tag = normalize_tag(tag)?;
+    if limit.tag() == Some(tag.as_str()) {
+        return Err(ApiError::Forbidden(
+            "An API key cannot remove the tag it is limited to",
+        ));
+    }
+
+    let res = sqlx::query("DELETE FROM file_tags WHERE file_id = ?1 AND tag = ?2")
+        .bind(file_id as i64)
+        .bind(&tag)
+        .execute(&state.db)
+        .await?;
+    if res.rows_affected() == 0 {
+        return Err(ApiError::NotFound("Tag not found"));
+    }
+
+    let tags = load_tags(state, file_id).await?;
+    publish_update(
+        state,
+        file_id,
+        owner_id,
+        json!({ "file_id": file_id, "filename": filename, "tags": tags }),
+    )
+    .await;
+
+    Ok(())
+}
+
+/// GET /tags
+///
+/// Tags on files you own or that are shared with you, with how many files carry each.
+/// A key limited to a tag only sees its owner's files with that tag.
+pub async fn list_tags_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Extension(limit): Extension<TagLimit>,
+) -> Result<Json<Vec<TagCount>>, ApiError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT t.tag, COUNT(*) AS files
+        FROM file_tags t
+        JOIN files f ON f.id = t.file_id
+        WHERE (f.owner_id = ?1
+                OR EXISTS (SELECT 1 FROM permissions p WHERE p.file_id = f.id AND p.user_id = ?1))
+            AND (?2 IS NULL
+                OR (f.owner_id = ?1
+                    AND EXISTS (SELECT 1 FROM file_tags l WHERE l.file_id = f.id AND l.tag = ?2)))
+        GROUP BY t.tag
+        ORDER BY t.tag
+        "#,
+    )
+    .bind(user_id as i64)
+    .bind(limit.tag())
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(
+        rows.iter()
+            .map(|r| TagCount {
+                tag: r.get("tag"),
+                files: r.get::<i64, _>("files") as u64,
+            })
+            .collect(),
+    ))
+}
+
+/// GET /file/:id/metadata
+///
+/// Key/value metadata of a file you own or that is shared with you
+pub async fn get_metadata_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Extension(limit): Extension<TagLimit>,
+    Path(file_id): Path<u32>,
+) -> Result<Json<FileMetadata>, ApiError> {
+    require_visible(&state, file_id, user_id, &limit).await?;
+    Ok(Json(FileMetadata {
+        file_id,
+        metadata: load_metadata(&state, file_id).await?,
+    }))
+}
+
+/// PATCH /file/:id/metadata
+///
+/// Owner-only: set each key to its string value, or remove it when the value is `null`
+pub async fn patch_metadata_handler(
+    State(state): State<AppState>,
+    Extension(owner_id): Extension<u32>,
+    Extension(limit): Extension<TagLimit>,
+    Path(file_id): Path<u32>,
+    info: RequestInfo,
+    Json(changes): Json<BTreeMap<String, Option<String>>>,
+) -> Result<Json<FileMetadata>, ApiError> {
+    let result = patch_metadata(&state, file_id, owner_id, &limit, &changes).await;
+
+    // Keys only; values may hold anything the owner wrote
+    let keys: Vec<&str> = changes.keys().map(String::as_str).collect();
+    let event = info
+        .event(AuditAction::FileMetadataUpdate)
+        .target("file", file_id)
+        .detail(keys.join(","));
+    state.audit.record(with_outcome(event, &result)).await;
+
+    Ok(Json(result?))
+}
+
+async fn patch_metadata(
+    state: &AppState,
+    file_id: u32,
+    owner_id: u32,
+    limit: &TagLimit,
+    changes: &BTreeMap<String, Option<String>>,
+) -> Result<FileMetadata, ApiError> {
+    let filename = require_owner(state, file_id, owner_id, limit).await?;
+
+    if changes.is_empty() {
+        return Err(ApiError::BadRequest("No metadata keys given".into()));
+    }
+    for (key, value) in changes {
+        validate_metadata_key(key)?;
+        if let Some(value) = value
+            && value.chars().count() > MAX_METADATA_VALUE_LEN
+        {
+            return Err(ApiError::BadRequest(format!(
+                "Metadata value of {key:?} is longer than {MAX_METADATA_VALUE_LEN} characters"
+            )));
+        }
+    }
+
+    let mut tx = state.db.begin().await?;
+    for (key, value) in changes {
+        match value {
+            Some(value) => {
+                sqlx::query(
+                    r#"
+                    INSERT INTO file_metadata (file_id, key, value)
+                    VALUES (?1, ?2, ?3)
+                    ON CONFLICT(file_id, key) DO UPDATE SET
+                        value = excluded.value,
+                        updated_at = strftime('%s', 'now')
+                    "#,
+                )
+                .bind(file_id as i64)
+                .bind(key)
+                .bind(value)
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM file_metadata WHERE file_id = ?1 AND key = ?2")
+                    .bind(file_id as i64)
+                    .bind(key)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+    }
+
+    let count: i64 = sqlx::query("SELECT COUNT(*) AS n FROM file_metadata WHERE file_id = ?1")
+        .bind(file_id as i64)
+        .fetch_one(&mut *tx)
+        .await?
+        .get("n");
+    if count as usize > MAX_METADATA_KEYS {
+        return Err(ApiError::BadRequest(format!(
+            "A file can have at most {MAX_METADATA_KEYS} metadata keys"
+        )));
+    }
+    tx.commit().await?;
+
+    let metadata = load_metadata(state, file_id).await?;
+    publish_update(
+        state,
+        file_id,
+        owner_id,
+        json!({ "file_id": file_id, "filename": filename, "metadata": metadata }),
+    )
+    .await;
+
+    Ok(FileMetadata { file_id, metadata })
+}
+
+/// `NotFound` unless `user_id` owns the file or it is shared with them, and it is
+/// within the credential's tag limit
+async fn require_visible(
+    state: &AppState,
+    file_id: u32,
+    user_id: u32,
+    limit: &TagLimit,
+) -> Result<(), ApiError> {
+    sqlx::query(
+        r#"
+        SELECT 1 FROM files f
+        WHERE f.id = ?1
+            AND (
+                f.owner_id = ?2
+                OR EXISTS (SELECT 1 FROM permissions p WHERE p.file_id = f.id AND p.user_id = ?2)
+            )
+        "#,
+    )
+    .bind(file_id as i64)
+    .bind(user_id as i64)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(ApiError::NotFound("File not found"))?;
+    require_within_limit(state, file_id, user_id, limit).await
+}
+
+/// Filename of a file `owner_id` owns within the credential's tag limit, or `NotFound`
+async fn require_owner(
+    state: &AppState,
+    file_id: u32,
+    owner_id: u32,
+    limit: &TagLimit,
+) -> Result<String, ApiError> {
+    require_within_limit(state, file_id, owner_id, limit).await?;
+    let row = sqlx::query("SELECT filename FROM files WHERE id = ?1 AND owner_id = ?2")
+        .bind(file_id as i64)
+        .bind(owner_id as i64)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or(ApiError::NotFound("File not found"))?;
+    Ok(row.get("filename"))
+}
+
+async fn load_tags(state: &AppState, file_id: u32) -> Result<Vec<String>, ApiError> {
+    Ok(
+        sqlx::query("SELECT tag FROM file_tags WHERE file_id = ?1 ORDER BY tag")
+            .bind(file_id as i64)
+            .fetch_all(&state.db)
+            .await?
+            .iter()
+            .map(|r| r.get("tag"))
+            .collect(),
+    )
+}
+
+async fn load_metadata(
+    state: &AppState,
+    file_id: u32,
+) -> Result<BTreeMap<String, String>, ApiError> {
+    Ok(
+        sqlx::query("SELECT key, value FROM file_metadata WHERE file_id = ?1")
+            .bind(file_id as i64)
+            .fetch_all(&state.db)
+            .await?
+            .iter()
+            .map(|r| (r.get("key"), r.get("value")))
+            .collect(),
+    )
+}
+
+/// Send `file.updated` to the owner and everyone the file is shared with
+async fn publish_update(state: &AppState, file_id: u32, owner_id: u32, data: serde_json::Value) {
+    let shared_with = sqlx::query("SELECT user_id FROM permissions WHERE file_id = ?1")
+        .bind(file_id as i64)
+        .fetch_all(&state.db)
+        .await;
+
+    let mut recipients = match shared_with {
+        Ok(rows) => rows
+            .iter()
+            .map(|r| r.get::<i64, _>("user_id") as u32)
+            .collect(),
+        Err(e) => {
+            eprintln!("Failed to load shares of file {file_id}: {e}");
+            Vec::new()
+        }
+    };
+    recipients.push(owner_id);
+
+    state
+        .events
+        .publish(LiveEventKind::Updated, recipients, data);
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::Query;
+
+    use super::*;
+    use crate::api::file::{FileSort, ListFilesQuery, list_files_handler};
+    use crate::api::test_state;
+    use crate::audit::{AuditFilter, AuditRecord};
+
+    fn info(user_id: u32) -> RequestInfo {
+        RequestInfo {
+            actor_id: Some(user_id),
+            ip: None,
+            user_agent: None,
+        }
+    }
+
+    async fn add_user(state: &AppState, username: &str) -> u32 {
+        state
+            .auth
+            .repo
+            .create(username.into(), "hash".into(), None)
+            .await
+            .unwrap()
+            .id
+    }
+
+    async fn add_file(state: &AppState, owner_id: u32, tags: &[&str]) -> u32 {
+        let file_id =
+            sqlx::query("INSERT INTO files (filename, size, owner_id) VALUES ('a.txt', 1, ?1)")
+                .bind(owner_id as i64)
+                .execute(&state.db)
+                .await
+                .unwrap()
+                .last_insert_rowid() as u32;
+        for tag in tags {
+            sqlx::query("INSERT INTO file_tags (file_id, tag) VALUES (?1, ?2)")
+                .bind(file_id as i64)
+                .bind(tag)
+                .execute(&state.db)
+                .await
+                .unwrap();
+        }
+        file_id
+    }
+
+    async fn events(state: &AppState, action: AuditAction) -> Vec<AuditRecord> {
+        let filter = AuditFilter {
+            action: Some(action.as_str().into()),
+            ..Default::default()
+        };
+        state.audit.list(&filter).await.unwrap()
+    }
+
+    /// Ids of `user_id`'s files matching a `tag` filter, oldest first
+    async fn files_tagged(state: &AppState, user_id: u32, limit: TagLimit, tag: &str) -> Vec<u32> {
+        let query = ListFilesQuery {
+            limit: None,
+            cursor: None,
+            sort: FileSort::Date,
+            order: None,
+            access: None,
+            is_public: None,
+            tag: Some(tag.into()),
+        };
+        let Json(page) = list_files_handler(
+            State(state.clone()),
+            Extension(user_id),
+            Extension(limit),
+            Query(query),
+        )
+        .await
+        .unwrap();
+        let mut ids: Vec<u32> = page.items.iter().map(|f| f.file_id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn tags_are_normalized() {
+        assert_eq!(normalize_tag("  Reports ").unwrap(), "reports");
+        assert_eq!(
+            normalize_tag("q3:2024_v1.final-x").unwrap(),
+            "q3:2024_v1.final-x"
+        );
+        assert_eq!(
+            normalize_tag(&"a".repeat(MAX_TAG_LEN)).unwrap(),
+            "a".repeat(MAX_TAG_LEN)
+        );
+    }
+
+    #[test]
+    fn invalid_tags_are_rejected() {
+        let too_long = "a".repeat(MAX_TAG_LEN + 1);
+        for tag in [
+            "",
+            "   ",
+            too_long.as_str(),
+            "two words",
+            "a/b",
+            "a,b",
+            "é",
+            "tag!",
+        ] {
+            let err = normalize_tag(tag).unwrap_err();
+            assert!(matches!(err, ApiError::BadRequest(_)), "{tag:?}: {err:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn tag_filter_needs_every_tag() {
+        let state = test_state().await;
+        let alice = add_user(&state, "alice").await;
+        let both = add_file(&state, alice, &["reports", "2024"]).await;
+        let reports = add_file(&state, alice, &["reports"]).await;
+        add_file(&state, alice, &["2024"]).await;
+        add_file(&state, alice, &[]).await;
+
+        let none = TagLimit::default;
+        assert_eq!(
+            files_tagged(&state, alice, none(), "reports").await,
+            vec![both, reports]
+        );
+        assert_eq!(
+            files_tagged(&state, alice, none(), "Reports, 2024,").await,
+            vec![both]
+        );
+        assert_eq!(
+            files_tagged(&state, alice, none(), "missing").await,
+            Vec::<u32>::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn tag_filter_stays_within_the_key_limit() {
+        let state = test_state().await;
+        let alice = add_user(&state, "alice").await;
+        let both = add_file(&state, alice, &["reports", "2024"]).await;
+        add_file(&state, alice, &["2024"]).await;
+        let limit = || TagLimit(Some("reports".into()));
+
+        assert_eq!(
+            files_tagged(&state, alice, limit(), "2024").await,
+            vec![both]
+        );
+
+        let Json(counts) =
+            list_tags_handler(State(state.clone()), Extension(alice), Extension(limit()))
+                .await
+                .unwrap();
+        let counts: Vec<(String, u64)> = counts.into_iter().map(|c| (c.tag, c.files)).collect();
+        assert_eq!(counts, vec![("2024".into(), 1), ("reports".into(), 1)]);
+    }
+
+    #[tokio::test]
+    async fn tag_changes_are_audited() {
+        let state = test_state().await;
+        let alice = add_user(&state, "alice").await;
+        let bob = add_user(&state, "bob").await;
+        let file_id = add_file(&state, alice, &[]).await;
+
+        let req = AddTagsRequest {
+            tags: vec!["Reports".into(), "2024".into(), "reports".into()],
+        };
+        let Json(tags) = add_tags_handler(
+            State(state.clone()),
+            Extension(alice),
+            Extension(TagLimit::default()),
+            Path(file_id),
+            info(alice),
+            Json(req),
+        )
+        .await
+        .unwrap();
+        assert_eq!(tags.tags, vec!["2024", "reports"]);
+        remove_tag_handler(
+            State(state.clone()),
+            Extension(alice),
+            Extension(TagLimit::default()),
+            Path((file_id, "2024".into())),
+            info(alice),
+        )
+        .await
+        .unwrap();
+        // Someone else's file is not found, and that is recorded too
+        remove_tag_handler(
+            State(state.clone()),
+            Extension(bob),
+            Extension(TagLimit::default()),
+            Path((file_id, "reports".into())),
+            info(bob),
+        )
+        .await
+        .unwrap_err();
+
+        let added = events(&state, AuditAction::FileTagAdd).await;
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0].actor_id, Some(alice));
+        assert_eq!(
+            added[0].target_id.as_deref(),
+            Some(file_id.to_string().as_str())
+        );
+        assert_eq!(added[0].outcome, "success");
+        assert_eq!(added[0].detail.as_deref(), Some("2024,reports"));
+
+        // Newest first
+        let removed = events(&state, AuditAction::FileTagRemove).await;
+        assert_eq!(removed.len(), 2);
+        assert_eq!(removed[0].actor_id, Some(bob));
+        assert_eq!(removed[0].outcome, "failure");
+        assert_eq!(removed[0].detail.as_deref(), Some("not_found"));
+        assert_eq!(removed[1].actor_id, Some(alice));
+        assert_eq!(removed[1].outcome, "success");
+        assert_eq!(removed[1].detail.as_deref(), Some("2024"));
+    }
+
+    #[tokio::test]
+    async fn metadata_edits_are_audited_without_values() {
+        let state = test_state().await;
+        let alice = add_user(&state, "alice").await;
+        let file_id = add_file(&state, alice, &[]).await;
+
+        let changes = BTreeMap::from([
+            ("project".to_string(), Some("apollo".to_string())),
+            ("client".to_string(), None),
+        ]);
+        let Json(saved) = patch_metadata_handler(
+            State(state.clone()),
+            Extension(alice),
+            Extension(TagLimit::default()),
+            Path(file_id),
+            info(alice),
+            Json(changes),
+        )
+        .await
+        .unwrap();
+        assert_eq!(saved.metadata.len(), 1);
+        let invalid = BTreeMap::from([("bad key".to_string(), Some("x".to_string()))]);
+        patch_metadata_handler(
+            State(state.clone()),
+            Extension(alice),
+            Extension(TagLimit::default()),
+            Path(file_id),
+            info(alice),
+            Json(invalid),
+        )
+        .await
+        .err()
+        .unwrap();
+
+        let edits = events(&state, AuditAction::FileMetadataUpdate).await;
+        assert_eq!(edits.len(), 2);
+        assert_eq!(edits[0].outcome, "failure");
+        assert_eq!(edits[0].detail.as_deref(), Some("invalid_request"));
+        assert_eq!(edits[1].outcome, "success");
+        assert_eq!(edits[1].target_type.as_deref(), Some("file"));
+        assert_eq!(edits[1].detail.as_deref(), Some("client,project"));
+    }
+}
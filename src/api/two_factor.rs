@@ -0,0 +1,43 @@
+use axum::{
+    Json,
+    extract::{Extension, State},
+    http::StatusCode,
+};
+
+use crate::api::AppState;
+use crate::api::error::ApiError;
+use crate::auth::service::AuthService;
+use crate::auth::types::{
+    TotpConfirmRequest, TotpConfirmResponse, TotpDisableRequest, TotpEnrollResponse,
+};
+
+/// POST /me/2fa/enroll
+///
+/// Start TOTP enrollment; returns the secret and an otpauth URI
+pub async fn enroll_totp_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+) -> Result<Json<TotpEnrollResponse>, ApiError> {
+    Ok(Json(state.auth.begin_totp_enrollment(user_id).await?))
+}
+
+/// POST /me/2fa/confirm
+///
+/// Enable 2FA with a code from the authenticator; returns recovery codes once
+pub async fn confirm_totp_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Json(req): Json<TotpConfirmRequest>,
+) -> Result<Json<TotpConfirmResponse>, ApiError> {
+    Ok(Json(state.auth.confirm_totp(user_id, &req.code).await?))
+}
+
+/// POST /me/2fa/disable
+pub async fn disable_totp_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Json(req): Json<TotpDisableRequest>,
+) -> Result<StatusCode, ApiError> {
+    state.auth.disable_totp(user_id, req).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
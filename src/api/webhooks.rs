@@ -0,0 +1,273 @@
+use axum::{
+    Json,
+    extract::{Extension, Path, Query, State},
+    http::StatusCode,
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand::RngCore;
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+use crate::api::AppState;
+use crate::api::audit::{RequestInfo, with_outcome};
+use crate::api::error::ApiError;
+use crate::audit::AuditAction;
+use crate::webhooks::{MAX_WEBHOOKS_PER_USER, WebhookDelivery, WebhookEvent};
+
+/// Prefix of generated webhook secrets
+const SECRET_PREFIX: &str = "whsec_";
+
+/// Shortest secret a user may choose
+const MIN_SECRET_LEN: usize = 16;
+
+/// Longest secret a user may choose
+const MAX_SECRET_LEN: usize = 256;
+
+/// Deliveries returned when no `limit` is given
+const DEFAULT_DELIVERY_PAGE: u32 = 50;
+
+#[derive(Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    /// Signing secret; generated if omitted
+    pub secret: Option<String>,
+    /// Events to deliver; every event if omitted or empty
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+/// `POST /me/webhooks` response; the only time the secret is returned
+#[derive(Serialize)]
+pub struct WebhookCreated {
+    pub id: u32,
+    pub url: String,
+    pub events: Vec<String>,
+    pub secret: String,
+}
+
+#[derive(Serialize)]
+pub struct WebhookInfo {
+    pub id: u32,
+    pub url: String,
+    pub events: Vec<String>,
+    pub created_at: i64,
+    pub pending_deliveries: u32,
+    pub failed_deliveries: u32,
+}
+
+#[derive(Deserialize)]
+pub struct DeliveriesQuery {
+    pub limit: Option<u32>,
+}
+
+/// POST /me/webhooks
+///
+/// Register a webhook for events on your files
+pub async fn create_webhook_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    info: RequestInfo,
+    Json(req): Json<CreateWebhookRequest>,
+) -> Result<(StatusCode, Json<WebhookCreated>), ApiError> {
+    let result = create_webhook(&state, user_id, req).await;
+
+    let mut event = info.event(AuditAction::WebhookCreate);
+    if let Ok(created) = &result {
+        event = event
+            .target("webhook", created.id)
+            .detail(created.events.join(" "));
+    }
+    state.audit.record(with_outcome(event, &result)).await;
+
+    Ok((StatusCode::CREATED, Json(result?)))
+}
+
+async fn create_webhook(
+    state: &AppState,
+    user_id: u32,
+    req: CreateWebhookRequest,
+) -> Result<WebhookCreated, ApiError> {
+    let url = req.url.trim().to_string();
+    state
+        .webhooks
+        .check_url(&url)
+        .await
+        .map_err(|msg| ApiError::BadRequest(msg.into()))?;
+
+    let mut events = Vec::new();
+    for name in &req.events {
+        let event: WebhookEvent = name
+            .trim()
+            .parse()
+            .map_err(|_| ApiError::BadRequest(format!("Unknown webhook event: {name}")))?;
+        if !events.contains(&event) {
+            events.push(event);
+        }
+    }
+    if events.is_empty() {
+        events = WebhookEvent::ALL.to_vec();
+    }
+    let events: Vec<String> = events.iter().map(|e| e.as_str().to_string()).collect();
+
+    let secret = match req.secret {
+        Some(secret) if !(MIN_SECRET_LEN..=MAX_SECRET_LEN).contains(&secret.len()) => {
+            return Err(ApiError::BadRequest(format!(
+                "Webhook secret must be {MIN_SECRET_LEN}-{MAX_SECRET_LEN} bytes"
+            )));
+        }
+        Some(secret) => secret,
+        None => {
+            let mut bytes = [0u8; 32];
+            OsRng.fill_bytes(&mut bytes);
+            format!("{SECRET_PREFIX}{}", URL_SAFE_NO_PAD.encode(bytes))
+        }
+    };
+
+    let count: i64 = sqlx::query("SELECT COUNT(*) AS n FROM webhooks WHERE user_id = ?1")
+        .bind(user_id as i64)
+        .fetch_one(&state.db)
+        .await?
+        .get("n");
+    if count >= MAX_WEBHOOKS_PER_USER as i64 {
+        return Err(ApiError::Conflict("Webhook limit reached"));
+    }
+
+    let res =
+        sqlx::query("INSERT INTO webhooks (user_id, url, secret, events) VALUES (?1, ?2, ?3, ?4)")
+            .bind(user_id as i64)
+            .bind(&url)
+            .bind(&secret)
+            .bind(events.join(","))
+            .execute(&state.db)
+            .await?;
+
+    Ok(WebhookCreated {
+        id: res.last_insert_rowid() as u32,
+        url,
+        events,
+        secret,
+    })
+}
+
+/// GET /me/webhooks
+pub async fn list_webhooks_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+) -> Result<Json<Vec<WebhookInfo>>, ApiError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT w.id, w.url, w.events, w.created_at,
+            (SELECT COUNT(*) FROM webhook_deliveries d
+             WHERE d.webhook_id = w.id AND d.status = 'pending') AS pending,
+            (SELECT COUNT(*) FROM webhook_deliveries d
+             WHERE d.webhook_id = w.id AND d.status = 'failed') AS failed
+        FROM webhooks w
+        WHERE w.user_id = ?1
+        ORDER BY w.id
+        "#,
+    )
+    .bind(user_id as i64)
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(
+        rows.iter()
+            .map(|r| WebhookInfo {
+                id: r.get::<i64, _>("id") as u32,
+                url: r.get("url"),
+                events: r
+                    .get::<String, _>("events")
+                    .split(',')
+                    .map(str::to_string)
+                    .collect(),
+                created_at: r.get("created_at"),
+                pending_deliveries: r.get::<i64, _>("pending") as u32,
+                failed_deliveries: r.get::<i64, _>("failed") as u32,
+            })
+            .collect(),
+    ))
+}
+
+/// DELETE /me/webhooks/:id
+///
+/// Remove a webhook; its queued deliveries are dropped
+pub async fn delete_webhook_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    info: RequestInfo,
+    Path(webhook_id): Path<u32>,
+) -> Result<StatusCode, ApiError> {
+    let result = delete_webhook(&state, user_id, webhook_id).await;
+
+    let event = info
+        .event(AuditAction::WebhookDelete)
+        .target("webhook", webhook_id);
+    state.audit.record(with_outcome(event, &result)).await;
+
+    result
+}
+
+async fn delete_webhook(
+    state: &AppState,
+    user_id: u32,
+    webhook_id: u32,
+) -> Result<StatusCode, ApiError> {
+    let mut tx = state.db.begin().await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM webhook_deliveries
+        WHERE webhook_id = (SELECT id FROM webhooks WHERE id = ?1 AND user_id = ?2)
+        "#,
+    )
+    .bind(webhook_id as i64)
+    .bind(user_id as i64)
+    .execute(&mut *tx)
+    .await?;
+
+    let res = sqlx::query("DELETE FROM webhooks WHERE id = ?1 AND user_id = ?2")
+        .bind(webhook_id as i64)
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    if res.rows_affected() == 0 {
+        return Err(ApiError::NotFound("Webhook not found"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// POST /me/webhooks/:id/ping
+///
+/// Queue a signed `ping` delivery to test the receiver
+pub async fn ping_webhook_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Path(webhook_id): Path<u32>,
+) -> Result<StatusCode, ApiError> {
+    if !state.webhooks.ping(user_id, webhook_id).await? {
+        return Err(ApiError::NotFound("Webhook not found"));
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// GET /me/webhooks/:id/deliveries
+///
+/// Delivery log of a webhook, newest first
+pub async fn webhook_deliveries_handler(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<u32>,
+    Path(webhook_id): Path<u32>,
+    Query(query): Query<DeliveriesQuery>,
+) -> Result<Json<Vec<WebhookDelivery>>, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_DELIVERY_PAGE);
+    state
+        .webhooks
+        .deliveries(user_id, webhook_id, limit)
+        .await?
+        .map(Json)
+        .ok_or(ApiError::NotFound("Webhook not found"))
+}
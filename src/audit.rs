@@ -0,0 +1,879 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::Algorithm;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{QueryBuilder, Row, Sqlite, SqlitePool};
+use tokio::sync::Mutex;
+
+use crate::auth::keys::{Jwks, JwtKeys};
+
+/// Largest page `AuditLog::list` returns
+pub const MAX_AUDIT_PAGE: u32 = 1000;
+
+/// `prev_hash` of the first event in the chain
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Events read per query while verifying the chain
+const VERIFY_BATCH: i64 = 500;
+
+/// Columns of `audit_events`, in the order they are hashed
+const EVENT_COLUMNS: &str = "id, at, action, actor_id, target_type, target_id, ip, user_agent, \
+                             request_id, outcome, detail, prev_hash, hash";
+
+/// A security-relevant action recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Register,
+    Login,
+    LoginTwoFactor,
+    TokenRefresh,
+    Logout,
+    PasswordChange,
+    PasswordReset,
+    ApiKeyCreate,
+    ApiKeyRevoke,
+    AccountDelete,
+    FileUpload,
+    FileDelete,
+    FileDownload,
+    FileTagAdd,
+    FileTagRemove,
+    FileMetadataUpdate,
+    FilePublicDownload,
+    ShareCreate,
+    ShareRevoke,
+    WebhookCreate,
+    WebhookDelete,
+    AdminUnlockUser,
+    AdminUnlockIp,
+    AdminPasswordReset,
+}
+
+impl AuditAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::Register => "auth.register",
+            AuditAction::Login => "auth.login",
+            AuditAction::LoginTwoFactor => "auth.login_2fa",
+            AuditAction::TokenRefresh => "auth.refresh",
+            AuditAction::Logout => "auth.logout",
+            AuditAction::PasswordChange => "auth.password_change",
+            AuditAction::PasswordReset => "auth.password_reset",
+            AuditAction::ApiKeyCreate => "api_key.create",
+            AuditAction::ApiKeyRevoke => "api_key.revoke",
+            AuditAction::AccountDelete => "account.delete",
+            AuditAction::FileUpload => "file.upload",
+            AuditAction::FileDelete => "file.delete",
+            AuditAction::FileDownload => "file.download",
+            AuditAction::FileTagAdd => "file.tag_add",
+            AuditAction::FileTagRemove => "file.tag_remove",
+            AuditAction::FileMetadataUpdate => "file.metadata_update",
+            AuditAction::FilePublicDownload => "file.public_download",
+            AuditAction::ShareCreate => "share.create",
+            AuditAction::ShareRevoke => "share.revoke",
+            AuditAction::WebhookCreate => "webhook.create",
+            AuditAction::WebhookDelete => "webhook.delete",
+            AuditAction::AdminUnlockUser => "admin.unlock_user",
+            AuditAction::AdminUnlockIp => "admin.unlock_ip",
+            AuditAction::AdminPasswordReset => "admin.password_reset",
+        }
+    }
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One event to append to the audit log.
+///
+/// Built from the request (see `api::audit::RequestInfo::event`), then given a
+/// target and outcome before it is recorded.
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub action: AuditAction,
+    pub actor_id: Option<u32>,
+    pub target_type: Option<&'static str>,
+    pub target_id: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+    pub success: bool,
+    /// Error code of a failed action, or extra context of a successful one
+    pub detail: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction) -> Self {
+        Self {
+            action,
+            actor_id: None,
+            target_type: None,
+            target_id: None,
+            ip: None,
+            user_agent: None,
+            request_id: None,
+            success: true,
+            detail: None,
+        }
+    }
+
+    pub fn actor(mut self, actor_id: Option<u32>) -> Self {
+        self.actor_id = actor_id;
+        self
+    }
+
+    pub fn target(mut self, target_type: &'static str, target_id: impl ToString) -> Self {
+        self.target_type = Some(target_type);
+        self.target_id = Some(target_id.to_string());
+        self
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Mark the action as failed with an error code
+    pub fn failed(mut self, code: &str) -> Self {
+        self.success = false;
+        self.detail = Some(code.to_string());
+        self
+    }
+}
+
+/// A recorded audit event
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    pub id: i64,
+    pub at: i64,
+    pub action: String,
+    pub actor_id: Option<u32>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+    /// `success` or `failure`
+    pub outcome: String,
+    pub detail: Option<String>,
+    /// `hash` of the previous event, or [`GENESIS_HASH`] for the first one
+    pub prev_hash: Option<String>,
+    /// SHA-256 over `prev_hash` and every other column, hex-encoded
+    pub hash: Option<String>,
+}
+
+impl AuditRecord {
+    fn from_row(r: &SqliteRow) -> Self {
+        Self {
+            id: r.get("id"),
+            at: r.get("at"),
+            action: r.get("action"),
+            actor_id: r.get::<Option<i64>, _>("actor_id").map(|id| id as u32),
+            target_type: r.get("target_type"),
+            target_id: r.get("target_id"),
+            ip: r.get("ip"),
+            user_agent: r.get("user_agent"),
+            request_id: r.get("request_id"),
+            outcome: r.get("outcome"),
+            detail: r.get("detail"),
+            prev_hash: r.get("prev_hash"),
+            hash: r.get("hash"),
+        }
+    }
+
+    /// Hash of this event chained onto `prev_hash`.
+    ///
+    /// Every field is length-prefixed (and `None` is distinct from an empty
+    /// string), so no two different events encode to the same bytes.
+    pub fn compute_hash(&self, prev_hash: &str) -> String {
+        let id = self.id.to_string();
+        let at = self.at.to_string();
+        let actor_id = self.actor_id.map(|id| id.to_string());
+        let fields = [
+            Some(prev_hash),
+            Some(id.as_str()),
+            Some(at.as_str()),
+            Some(self.action.as_str()),
+            actor_id.as_deref(),
+            self.target_type.as_deref(),
+            self.target_id.as_deref(),
+            self.ip.as_deref(),
+            self.user_agent.as_deref(),
+            self.request_id.as_deref(),
+            Some(self.outcome.as_str()),
+            self.detail.as_deref(),
+        ];
+
+        let mut hasher = Sha256::new();
+        for field in fields {
+            match field {
+                None => hasher.update([0u8]),
+                Some(value) => {
+                    hasher.update([1u8]);
+                    hasher.update((value.len() as u64).to_be_bytes());
+                    hasher.update(value.as_bytes());
+                }
+            }
+        }
+        hex::encode(hasher.finalize())
+    }
+}
+
+/// A signed statement that the chain ended at `event_id` with `event_hash`.
+///
+/// Rewriting history means recomputing every later hash, which no longer
+/// matches the signed one; truncating the log drops events a checkpoint names.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditCheckpoint {
+    pub id: i64,
+    pub event_id: i64,
+    pub event_hash: String,
+    pub created_at: i64,
+    /// Audit signing key (`kid`) that made the signature, published at
+    /// `/.well-known/audit-keys.json`
+    pub key_id: String,
+    /// Ed25519 signature over [`AuditCheckpoint::message`], base64url
+    pub signature: String,
+}
+
+impl AuditCheckpoint {
+    /// The signed bytes
+    pub fn message(event_id: i64, event_hash: &str, created_at: i64) -> String {
+        format!("sfs-audit-checkpoint:v1:{event_id}:{event_hash}:{created_at}")
+    }
+}
+
+/// Result of walking the whole chain and every checkpoint
+#[derive(Debug, Serialize)]
+pub struct AuditVerification {
+    pub valid: bool,
+    pub events_checked: u64,
+    pub checkpoints_checked: u64,
+    /// Id and hash of the last event
+    pub head_id: Option<i64>,
+    pub head_hash: Option<String>,
+    pub latest_checkpoint: Option<AuditCheckpoint>,
+    /// Events after the latest checkpoint; they are chained but not yet signed
+    pub unsigned_events: u64,
+    pub first_broken: Option<BrokenLink>,
+}
+
+/// The first problem found while verifying
+#[derive(Debug, Serialize)]
+pub struct BrokenLink {
+    /// Event where the chain breaks, if the problem is in the events
+    pub event_id: Option<i64>,
+    /// Checkpoint that failed, if the problem is in a checkpoint
+    pub checkpoint_id: Option<i64>,
+    pub reason: String,
+}
+
+impl BrokenLink {
+    fn event(event_id: i64, reason: impl Into<String>) -> Self {
+        Self {
+            event_id: Some(event_id),
+            checkpoint_id: None,
+            reason: reason.into(),
+        }
+    }
+
+    fn checkpoint(cp: &AuditCheckpoint, reason: impl Into<String>) -> Self {
+        Self {
+            event_id: Some(cp.event_id),
+            checkpoint_id: Some(cp.id),
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Filters for `GET /audit`; all are optional and combine with AND
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditFilter {
+    pub action: Option<String>,
+    pub actor_id: Option<u32>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    /// `success` or `failure`
+    pub outcome: Option<String>,
+    /// Unix time, inclusive
+    pub since: Option<i64>,
+    /// Unix time, exclusive
+    pub until: Option<i64>,
+    /// Only events with a smaller id, for paging backwards
+    pub before_id: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+/// Append-only, hash-chained log of security-relevant events, stored in SQLite.
+///
+/// Each event stores the hash of the one before it, and checkpoints signed with
+/// a dedicated audit key pin the head of the chain from time to time, so edits made
+/// directly in the database are detected by [`AuditLog::verify`]. Triggers in
+/// `db::init_db` also refuse updates and deletes. Events outlive the accounts
+/// they mention, so actors are kept as plain ids.
+#[derive(Clone)]
+pub struct AuditLog {
+    pool: SqlitePool,
+    keys: Arc<JwtKeys>,
+    /// Appends read the head and insert after it, so they must not interleave
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl AuditLog {
+    pub fn new(pool: SqlitePool, keys: Arc<JwtKeys>) -> Self {
+        Self {
+            pool,
+            keys,
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Append an event. Failures are logged and otherwise ignored, so a broken
+    /// audit write never fails the request it describes.
+    pub async fn record(&self, event: AuditEvent) {
+        if let Err(e) = self.append(&event).await {
+            eprintln!("[security] audit write failed for {}: {e}", event.action);
+        }
+    }
+
+    async fn append(&self, event: &AuditEvent) -> Result<(), sqlx::Error> {
+        let _guard = self.write_lock.lock().await;
+        let (head_id, head_hash) = self.head().await?;
+
+        let mut record = AuditRecord {
+            id: head_id.unwrap_or(0) + 1,
+            at: now(),
+            action: event.action.as_str().to_string(),
+            actor_id: event.actor_id,
+            target_type: event.target_type.map(str::to_string),
+            target_id: event.target_id.clone(),
+            ip: event.ip.clone(),
+            user_agent: event.user_agent.clone(),
+            request_id: event.request_id.clone(),
+            outcome: if event.success { "success" } else { "failure" }.to_string(),
+            detail: event.detail.clone(),
+            prev_hash: None,
+            hash: None,
+        };
+        let prev_hash = head_hash.unwrap_or_else(|| GENESIS_HASH.to_string());
+        record.hash = Some(record.compute_hash(&prev_hash));
+        record.prev_hash = Some(prev_hash);
+
+        sqlx::query(
+            r#"
+            INSERT INTO audit_events
+                (id, at, action, actor_id, target_type, target_id, ip, user_agent,
+                 request_id, outcome, detail, prev_hash, hash)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+            "#,
+        )
+        .bind(record.id)
+        .bind(record.at)
+        .bind(&record.action)
+        .bind(record.actor_id.map(|id| id as i64))
+        .bind(&record.target_type)
+        .bind(&record.target_id)
+        .bind(&record.ip)
+        .bind(&record.user_agent)
+        .bind(&record.request_id)
+        .bind(&record.outcome)
+        .bind(&record.detail)
+        .bind(&record.prev_hash)
+        .bind(&record.hash)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Id and hash of the last event, if any
+    async fn head(&self) -> Result<(Option<i64>, Option<String>), sqlx::Error> {
+        let row = sqlx::query("SELECT id, hash FROM audit_events ORDER BY id DESC LIMIT 1")
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(match row {
+            Some(r) => (Some(r.get("id")), r.get("hash")),
+            None => (None, None),
+        })
+    }
+
+    /// Chain events written before hashing existed; returns how many were sealed.
+    /// The update trigger allows setting the hash of such events exactly once.
+    pub async fn seal_unhashed(&self) -> Result<u64, sqlx::Error> {
+        let _guard = self.write_lock.lock().await;
+
+        let rows = sqlx::query(&format!(
+            "SELECT {EVENT_COLUMNS} FROM audit_events WHERE hash IS NULL ORDER BY id"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        let Some(first) = rows.first() else {
+            return Ok(0);
+        };
+
+        let first_id: i64 = first.get("id");
+        let mut prev_hash: String =
+            sqlx::query("SELECT hash FROM audit_events WHERE id < ?1 ORDER BY id DESC LIMIT 1")
+                .bind(first_id)
+                .fetch_optional(&self.pool)
+                .await?
+                .and_then(|r| r.get("hash"))
+                .unwrap_or_else(|| GENESIS_HASH.to_string());
+
+        let mut tx = self.pool.begin().await?;
+        for row in &rows {
+            let record = AuditRecord::from_row(row);
+            let hash = record.compute_hash(&prev_hash);
+            sqlx::query("UPDATE audit_events SET prev_hash = ?1, hash = ?2 WHERE id = ?3")
+                .bind(&prev_hash)
+                .bind(&hash)
+                .bind(record.id)
+                .execute(&mut *tx)
+                .await?;
+            prev_hash = hash;
+        }
+        tx.commit().await?;
+
+        Ok(rows.len() as u64)
+    }
+
+    /// Sign the current head of the chain, unless the latest checkpoint already covers it
+    pub async fn checkpoint(&self) -> Result<Option<AuditCheckpoint>, String> {
+        let _guard = self.write_lock.lock().await;
+
+        let (Some(event_id), Some(event_hash)) = self.head().await.map_err(|e| e.to_string())?
+        else {
+            return Ok(None);
+        };
+        let latest = self.latest_checkpoint().await.map_err(|e| e.to_string())?;
+        if latest.is_some_and(|cp| cp.event_id == event_id) {
+            return Ok(None);
+        }
+
+        let created_at = now();
+        let message = AuditCheckpoint::message(event_id, &event_hash, created_at);
+        let signature = jsonwebtoken::crypto::sign(
+            message.as_bytes(),
+            self.keys.encoding_key(),
+            Algorithm::EdDSA,
+        )
+        .map_err(|e| format!("cannot sign audit checkpoint: {e}"))?;
+        let key_id = self.keys.active_kid().to_string();
+
+        let res = sqlx::query(
+            r#"
+            INSERT INTO audit_checkpoints (event_id, event_hash, created_at, key_id, signature)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+        )
+        .bind(event_id)
+        .bind(&event_hash)
+        .bind(created_at)
+        .bind(&key_id)
+        .bind(&signature)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        Ok(Some(AuditCheckpoint {
+            id: res.last_insert_rowid(),
+            event_id,
+            event_hash,
+            created_at,
+            key_id,
+            signature,
+        }))
+    }
+
+    /// Public keys that verify checkpoints
+    pub fn jwks(&self) -> Jwks {
+        self.keys.jwks()
+    }
+
+    async fn latest_checkpoint(&self) -> Result<Option<AuditCheckpoint>, sqlx::Error> {
+        Ok(self.checkpoints().await?.pop())
+    }
+
+    /// All checkpoints, oldest first
+    async fn checkpoints(&self) -> Result<Vec<AuditCheckpoint>, sqlx::Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, event_id, event_hash, created_at, key_id, signature
+            FROM audit_checkpoints
+            ORDER BY id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|r| AuditCheckpoint {
+                id: r.get("id"),
+                event_id: r.get("event_id"),
+                event_hash: r.get("event_hash"),
+                created_at: r.get("created_at"),
+                key_id: r.get("key_id"),
+                signature: r.get("signature"),
+            })
+            .collect())
+    }
+
+    /// Walk the chain from the first event, then check every checkpoint's
+    /// signature and that the event it names still has the signed hash.
+    /// Stops at the first broken link.
+    pub async fn verify(&self) -> Result<AuditVerification, sqlx::Error> {
+        let mut events_checked = 0u64;
+        let mut head_id = None;
+        let mut prev_hash = GENESIS_HASH.to_string();
+        let mut first_broken = None;
+
+        'walk: loop {
+            let rows = sqlx::query(&format!(
+                "SELECT {EVENT_COLUMNS} FROM audit_events WHERE id > ?1 ORDER BY id LIMIT ?2"
+            ))
+            .bind(head_id.unwrap_or(0))
+            .bind(VERIFY_BATCH)
+            .fetch_all(&self.pool)
+            .await?;
+            if rows.is_empty() {
+                break;
+            }
+
+            for row in &rows {
+                let record = AuditRecord::from_row(row);
+                head_id = Some(record.id);
+                events_checked += 1;
+
+                let broken = match (&record.prev_hash, &record.hash) {
+                    (_, None) => Some("event has no hash"),
+                    (Some(p), _) if *p != prev_hash => Some(
+                        "prev_hash does not match the previous event; an event was removed or reordered",
+                    ),
+                    (None, _) => Some("event has no prev_hash"),
+                    (_, Some(h)) if *h != record.compute_hash(&prev_hash) => {
+                        Some("event contents do not match its hash; the event was modified")
+                    }
+                    _ => None,
+                };
+                if let Some(reason) = broken {
+                    first_broken = Some(BrokenLink::event(record.id, reason));
+                    break 'walk;
+                }
+
+                prev_hash = record.hash.unwrap_or_default();
+            }
+        }
+
+        let checkpoints = self.checkpoints().await?;
+        let mut checkpoints_checked = 0u64;
+        if first_broken.is_none() {
+            for cp in &checkpoints {
+                checkpoints_checked += 1;
+                if let Some(reason) = self.check_checkpoint(cp, head_id).await? {
+                    first_broken = Some(BrokenLink::checkpoint(cp, reason));
+                    break;
+                }
+            }
+        }
+
+        let latest_checkpoint = checkpoints.last().cloned();
+        let unsigned_events = match (&latest_checkpoint, head_id) {
+            (Some(cp), Some(head)) => (head - cp.event_id).max(0) as u64,
+            (None, _) => events_checked,
+            (Some(_), None) => 0,
+        };
+
+        Ok(AuditVerification {
+            valid: first_broken.is_none(),
+            events_checked,
+            checkpoints_checked,
+            head_id,
+            head_hash: head_id.map(|_| prev_hash),
+            latest_checkpoint,
+            unsigned_events,
+            first_broken,
+        })
+    }
+
+    /// Why a checkpoint fails verification, or `None` if it holds
+    async fn check_checkpoint(
+        &self,
+        cp: &AuditCheckpoint,
+        head_id: Option<i64>,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let Some(key) = self.keys.decoding_key(&cp.key_id) else {
+            return Ok(Some(format!(
+                "checkpoint was signed by key {}, which is no longer trusted",
+                cp.key_id
+            )));
+        };
+        let message = AuditCheckpoint::message(cp.event_id, &cp.event_hash, cp.created_at);
+        let signed =
+            jsonwebtoken::crypto::verify(&cp.signature, message.as_bytes(), key, Algorithm::EdDSA)
+                .unwrap_or(false);
+        if !signed {
+            return Ok(Some("checkpoint signature is invalid".into()));
+        }
+
+        if head_id.is_none_or(|head| cp.event_id > head) {
+            return Ok(Some(
+                "checkpointed event is missing; the log was truncated".into(),
+            ));
+        }
+        let hash: Option<String> = sqlx::query("SELECT hash FROM audit_events WHERE id = ?1")
+            .bind(cp.event_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .and_then(|r| r.get("hash"));
+        if hash.as_deref() != Some(cp.event_hash.as_str()) {
+            return Ok(Some(
+                "event hash differs from the signed checkpoint; history was rewritten".into(),
+            ));
+        }
+
+        Ok(None)
+    }
+
+    /// Events matching `filter`, newest first
+    pub async fn list(&self, filter: &AuditFilter) -> Result<Vec<AuditRecord>, sqlx::Error> {
+        let limit = filter.limit.unwrap_or(100).clamp(1, MAX_AUDIT_PAGE);
+
+        let mut query: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+            "SELECT {EVENT_COLUMNS} FROM audit_events WHERE 1 = 1"
+        ));
+        if let Some(action) = &filter.action {
+            query.push(" AND action = ").push_bind(action);
+        }
+        if let Some(actor_id) = filter.actor_id {
+            query.push(" AND actor_id = ").push_bind(actor_id as i64);
+        }
+        if let Some(target_type) = &filter.target_type {
+            query.push(" AND target_type = ").push_bind(target_type);
+        }
+        if let Some(target_id) = &filter.target_id {
+            query.push(" AND target_id = ").push_bind(target_id);
+        }
+        if let Some(outcome) = &filter.outcome {
+            query.push(" AND outcome = ").push_bind(outcome);
+        }
+        if let Some(since) = filter.since {
+            query.push(" AND at >= ").push_bind(since);
+        }
+        if let Some(until) = filter.until {
+            query.push(" AND at < ").push_bind(until);
+        }
+        if let Some(before_id) = filter.before_id {
+            query.push(" AND id < ").push_bind(before_id);
+        }
+        query
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(limit as i64);
+
+        let rows = query.build().fetch_all(&self.pool).await?;
+
+        Ok(rows.iter().map(AuditRecord::from_row).collect())
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+    use crate::config::AuthConfig;
+
+    fn record(target_type: Option<&str>, target_id: Option<&str>) -> AuditRecord {
+        AuditRecord {
+            id: 1,
+            at: 1_700_000_000,
+            action: "file.delete".into(),
+            actor_id: Some(7),
+            target_type: target_type.map(str::to_string),
+            target_id: target_id.map(str::to_string),
+            ip: None,
+            user_agent: None,
+            request_id: None,
+            outcome: "success".into(),
+            detail: None,
+            prev_hash: None,
+            hash: None,
+        }
+    }
+
+    /// A log on a fresh in-memory database with `events` recorded
+    async fn log_with_events(events: usize) -> AuditLog {
+        // One connection, since each in-memory connection is its own database
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let auth_cfg = AuthConfig {
+            access_token_ttl_secs: 900,
+            refresh_token_idle_ttl_secs: 86_400,
+            refresh_token_absolute_ttl_secs: 86_400 * 30,
+            issuer: "sfs".into(),
+            audience: "sfs".into(),
+            leeway_secs: 0,
+            password_reset_ttl_secs: 3600,
+            email_verification_ttl_secs: 3600,
+            require_verified_email: false,
+        };
+        crate::db::create_schema(&pool, &auth_cfg).await.unwrap();
+
+        let log = AuditLog::new(pool, Arc::new(JwtKeys::generate("test")));
+        for i in 0..events {
+            log.record(AuditEvent::new(AuditAction::FileUpload).target("file", i))
+                .await;
+        }
+        log
+    }
+
+    /// Do what someone editing the database file directly could do
+    async fn tamper(log: &AuditLog, sql: &str) {
+        for trigger in ["audit_events_no_update", "audit_events_no_delete"] {
+            sqlx::query(&format!("DROP TRIGGER IF EXISTS {trigger}"))
+                .execute(&log.pool)
+                .await
+                .unwrap();
+        }
+        sqlx::query(sql).execute(&log.pool).await.unwrap();
+    }
+
+    async fn first_broken(log: &AuditLog) -> BrokenLink {
+        let verification = log.verify().await.unwrap();
+        assert!(!verification.valid);
+        verification.first_broken.unwrap()
+    }
+
+    #[test]
+    fn field_boundaries_change_the_hash() {
+        let ab_c = record(Some("ab"), Some("c")).compute_hash(GENESIS_HASH);
+        let a_bc = record(Some("a"), Some("bc")).compute_hash(GENESIS_HASH);
+        assert_ne!(ab_c, a_bc);
+
+        let none = record(Some("abc"), None).compute_hash(GENESIS_HASH);
+        let empty = record(Some("abc"), Some("")).compute_hash(GENESIS_HASH);
+        assert_ne!(none, empty);
+    }
+
+    #[test]
+    fn hash_depends_on_the_previous_hash() {
+        let r = record(Some("file"), Some("1"));
+        assert_eq!(r.compute_hash(GENESIS_HASH), r.compute_hash(GENESIS_HASH));
+        assert_ne!(
+            r.compute_hash(GENESIS_HASH),
+            r.compute_hash(&"1".repeat(64))
+        );
+    }
+
+    #[tokio::test]
+    async fn untouched_chain_verifies() {
+        let log = log_with_events(3).await;
+        assert!(log.checkpoint().await.unwrap().is_some());
+        log.record(AuditEvent::new(AuditAction::FileDelete)).await;
+
+        let verification = log.verify().await.unwrap();
+        assert!(verification.valid);
+        assert_eq!(verification.events_checked, 4);
+        assert_eq!(verification.checkpoints_checked, 1);
+        assert_eq!(verification.unsigned_events, 1);
+    }
+
+    #[tokio::test]
+    async fn triggers_refuse_updates_and_deletes() {
+        let log = log_with_events(2).await;
+
+        let update = sqlx::query("UPDATE audit_events SET detail = 'x' WHERE id = 1")
+            .execute(&log.pool)
+            .await;
+        let delete = sqlx::query("DELETE FROM audit_events WHERE id = 2")
+            .execute(&log.pool)
+            .await;
+        assert!(update.is_err());
+        assert!(delete.is_err());
+    }
+
+    #[tokio::test]
+    async fn changed_row_breaks_verification() {
+        let log = log_with_events(3).await;
+        tamper(&log, "UPDATE audit_events SET actor_id = 1 WHERE id = 2").await;
+
+        let broken = first_broken(&log).await;
+        assert_eq!(broken.event_id, Some(2));
+        assert!(broken.reason.contains("modified"), "{}", broken.reason);
+    }
+
+    #[tokio::test]
+    async fn deleted_row_breaks_verification() {
+        let log = log_with_events(3).await;
+        tamper(&log, "DELETE FROM audit_events WHERE id = 2").await;
+
+        let broken = first_broken(&log).await;
+        assert_eq!(broken.event_id, Some(3));
+        assert!(broken.reason.contains("removed"), "{}", broken.reason);
+    }
+
+    #[tokio::test]
+    async fn deleted_tail_breaks_the_checkpoint() {
+        let log = log_with_events(3).await;
+        log.checkpoint().await.unwrap();
+        tamper(&log, "DELETE FROM audit_events WHERE id = 3").await;
+
+        let broken = first_broken(&log).await;
+        assert_eq!(broken.checkpoint_id, Some(1));
+        assert!(broken.reason.contains("truncated"), "{}", broken.reason);
+    }
+
+    #[tokio::test]
+    async fn rehashed_history_breaks_the_checkpoint() {
+        let log = log_with_events(3).await;
+        log.checkpoint().await.unwrap();
+
+        // Change an event and recompute every hash after it, so the chain
+        // itself is consistent again
+        tamper(
+            &log,
+            "UPDATE audit_events SET detail = 'rewritten' WHERE id = 2",
+        )
+        .await;
+        let rows = sqlx::query(&format!(
+            "SELECT {EVENT_COLUMNS} FROM audit_events ORDER BY id"
+        ))
+        .fetch_all(&log.pool)
+        .await
+        .unwrap();
+        let mut prev_hash = GENESIS_HASH.to_string();
+        for row in &rows {
+            let record = AuditRecord::from_row(row);
+            let hash = record.compute_hash(&prev_hash);
+            sqlx::query("UPDATE audit_events SET prev_hash = ?1, hash = ?2 WHERE id = ?3")
+                .bind(&prev_hash)
+                .bind(&hash)
+                .bind(record.id)
+                .execute(&log.pool)
+                .await
+                .unwrap();
+            prev_hash = hash;
+        }
+
+        let broken = first_broken(&log).await;
+        assert_eq!(broken.checkpoint_id, Some(1));
+        assert!(broken.reason.contains("rewritten"), "{}", broken.reason);
+    }
+}
@@ -0,0 +1,30 @@
+/// Maximum length of an email address (RFC 5321 path limit)
+const MAX_LEN: usize = 254;
+
+/// Normalize and check an email address: trimmed and lowercased, one `@`,
+/// a non-empty local part and a dotted domain, no whitespace.
+///
+/// This is a sanity check only; ownership is proven by the verification mail.
+pub fn validate(input: &str) -> Result<String, String> {
+    let email = input.trim().to_lowercase();
+
+    if email.len() > MAX_LEN || email.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err("Invalid email address".into());
+    }
+
+    let Some((local, domain)) = email.split_once('@') else {
+        return Err("Invalid email address".into());
+    };
+
+    let domain_ok = domain.contains('.')
+        && !domain.contains('@')
+        && domain
+            .split('.')
+            .all(|label| !label.is_empty() && !label.starts_with('-') && !label.ends_with('-'));
+
+    if local.is_empty() || !domain_ok {
+        return Err("Invalid email address".into());
+    }
+
+    Ok(email)
+}
@@ -0,0 +1,108 @@
+use std::fmt;
+
+use crate::auth::scope::Scope;
+
+/// Errors returned by [`AuthService`](crate::auth::service::AuthService).
+///
+/// The HTTP status and the machine-readable code for each variant are chosen
+/// in `api::error`; the `Display` text is the message shown to clients.
+#[derive(Debug)]
+pub enum AuthError {
+    /// Wrong username or password
+    InvalidCredentials,
+    /// Wrong TOTP or recovery code
+    InvalidTwoFactorCode,
+    /// The 2FA challenge token is invalid or expired
+    InvalidChallenge,
+    /// An already-rotated refresh token was presented again and its whole
+    /// token family was revoked; clients must log in again
+    RefreshTokenReused {
+        user_id: u32,
+    },
+    RefreshTokenExpired,
+    InvalidRefreshToken,
+    /// The authenticated user no longer exists
+    UserNotFound,
+    UsernameTaken,
+    /// The email address belongs to another account
+    EmailTaken,
+    /// Unknown, used or expired password reset token
+    InvalidResetToken,
+    /// Unknown, used or expired email verification token
+    InvalidVerificationToken,
+    /// The action needs the account password or, for accounts without one,
+    /// a 2FA code or a recent login
+    ReauthenticationRequired,
+    /// A login or refresh asked for a scope the user or session cannot hold
+    ScopeNotAvailable(Scope),
+    /// `OIDC_ISSUER` is not set
+    OidcNotConfigured,
+    /// The identity provider cannot be reached or misbehaves; details are logged
+    OidcProviderError,
+    /// A failed or forged OIDC callback; details are logged
+    OidcLoginFailed,
+    /// An external identity has no local account and auto-creation is off
+    OidcNoAccount,
+    /// Linking an identity that belongs to another account
+    OidcIdentityTaken,
+    /// Unknown, used or expired link code, or one started by another account
+    InvalidLinkCode,
+    /// The request is invalid; the message says why
+    Invalid(String),
+    /// A database error; details are logged
+    Database,
+    /// Any other server-side failure; details are logged
+    Internal,
+}
+
+impl AuthError {
+    /// Log an unexpected failure and hide its details from the client
+    pub fn internal(context: &str, err: impl fmt::Display) -> Self {
+        eprintln!("{context}: {err}");
+        AuthError::Internal
+    }
+}
+
+impl From<sqlx::Error> for AuthError {
+    fn from(e: sqlx::Error) -> Self {
+        eprintln!("Database error: {e}");
+        AuthError::Database
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::InvalidCredentials => f.write_str("Invalid credentials"),
+            AuthError::InvalidTwoFactorCode => f.write_str("Invalid two-factor code"),
+            AuthError::InvalidChallenge => f.write_str("Invalid or expired challenge"),
+            AuthError::RefreshTokenReused { .. } => {
+                f.write_str("Refresh token reuse detected; please log in again")
+            }
+            AuthError::RefreshTokenExpired => f.write_str("Refresh token expired"),
+            AuthError::InvalidRefreshToken => f.write_str("Invalid refresh token"),
+            AuthError::UserNotFound => f.write_str("User not found"),
+            AuthError::UsernameTaken => f.write_str("Username already exists"),
+            AuthError::EmailTaken => f.write_str("Email address already in use"),
+            AuthError::InvalidResetToken => f.write_str("Invalid or expired reset token"),
+            AuthError::InvalidVerificationToken => {
+                f.write_str("Invalid or expired verification token")
+            }
+            AuthError::ReauthenticationRequired => f.write_str(
+                "Confirm with your password; accounts without one can use a 2FA code or log in again first",
+            ),
+            AuthError::ScopeNotAvailable(scope) => write!(f, "Scope not available: {scope}"),
+            AuthError::OidcNotConfigured => f.write_str("Single sign-on is not configured"),
+            AuthError::OidcProviderError => f.write_str("Identity provider request failed"),
+            AuthError::OidcLoginFailed => f.write_str("Single sign-on login failed"),
+            AuthError::OidcNoAccount => f.write_str("No account is linked to this identity"),
+            AuthError::OidcIdentityTaken => {
+                f.write_str("This identity is already linked to another account")
+            }
+            AuthError::InvalidLinkCode => f.write_str("Invalid or expired link code"),
+            AuthError::Invalid(msg) => f.write_str(msg),
+            AuthError::Database => f.write_str("Database error"),
+            AuthError::Internal => f.write_str("Internal server error"),
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{DecodingKey, EncodingKey};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::Serialize;
+
+/// Where one set of signing keys is configured
+struct KeySource {
+    /// Name used in log and error messages
+    label: &'static str,
+    dir_var: &'static str,
+    default_dir: &'static str,
+    active_var: &'static str,
+}
+
+/// Keys that sign access tokens
+const JWT_KEYS: KeySource = KeySource {
+    label: "JWT",
+    dir_var: "JWT_KEYS_DIR",
+    default_dir: "data/keys",
+    active_var: "JWT_ACTIVE_KID",
+};
+
+/// Keys that sign audit checkpoints, kept apart so a token key cannot vouch for the audit log
+const AUDIT_KEYS: KeySource = KeySource {
+    label: "audit",
+    dir_var: "AUDIT_KEYS_DIR",
+    default_dir: "data/audit_keys",
+    active_var: "AUDIT_ACTIVE_KID",
+};
+
+/// DER prefix of an Ed25519 SubjectPublicKeyInfo; the raw 32-byte key follows it
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// Ed25519 keys used to sign and verify JWTs.
+///
+/// Keys are loaded once at startup from `JWT_KEYS_DIR` (default `data/keys`):
+/// - `<kid>.pem`: PKCS#8 private key, usable for signing and verification
+/// - `<kid>.pub.pem`: public key only, kept around to verify tokens signed by a retired key
+///
+/// `JWT_ACTIVE_KID` selects the signing key. If unset, the private key with the
+/// lexicographically greatest kid is used, so date-based kids rotate naturally.
+///
+/// Audit checkpoint keys use the same layout in `AUDIT_KEYS_DIR` (default
+/// `data/audit_keys`), selected by `AUDIT_ACTIVE_KID`.
+pub struct JwtKeys {
+    active_kid: String,
+    encoding: EncodingKey,
+    verification: HashMap<String, VerificationKey>,
+}
+
+struct VerificationKey {
+    decoding: DecodingKey,
+    /// Base64url-encoded raw public key, as published in the JWKS
+    x: String,
+}
+
+/// JSON Web Key Set served at `/.well-known/jwks.json`
+#[derive(Debug, Serialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// Public half of one signing key
+#[derive(Debug, Serialize)]
+pub struct Jwk {
+    pub kty: &'static str,
+    pub crv: &'static str,
+    pub alg: &'static str,
+    #[serde(rename = "use")]
+    pub use_: &'static str,
+    pub kid: String,
+    pub x: String,
+}
+
+impl JwtKeys {
+    /// Load the access token keys from the environment.
+    ///
+    /// With `APP_ENV=dev` and no keys on disk, a development key is generated
+    /// and saved. In any other mode a missing key is an error.
+    pub fn load_from_env() -> Result<Self, String> {
+        Self::load(&JWT_KEYS)
+    }
+
+    /// Load the audit checkpoint keys from the environment, like [`JwtKeys::load_from_env`]
+    pub fn load_audit_from_env() -> Result<Self, String> {
+        Self::load(&AUDIT_KEYS)
+    }
+
+    fn load(source: &KeySource) -> Result<Self, String> {
+        let label = source.label;
+        let dir = std::env::var(source.dir_var)
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(source.default_dir));
+        let active_kid = std::env::var(source.active_var).ok();
+
+        let mut private_keys = read_private_keys(&dir)?;
+
+        if private_keys.is_empty() {
+            if !is_dev_mode() {
+                return Err(format!(
+                    "no {label} signing key found in {} (set APP_ENV=dev to generate one for local use)",
+                    dir.display()
+                ));
+            }
+
+            println!(
+                "WARNING: no {label} signing key found, generating a development key in {}",
+                dir.display()
+            );
+            private_keys.push(generate_dev_key(&dir)?);
+        }
+
+        let mut verification = HashMap::new();
+        for (kid, public_key) in read_public_keys(&dir)? {
+            verification.insert(kid, VerificationKey::from_raw(&public_key)?);
+        }
+
+        let mut encoding_keys = HashMap::new();
+        for (kid, der) in private_keys {
+            let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der)
+                .map_err(|_| format!("{label} key {kid} is not a valid Ed25519 PKCS#8 key"))?;
+            verification.insert(
+                kid.clone(),
+                VerificationKey::from_raw(pair.public_key().as_ref())?,
+            );
+            encoding_keys.insert(kid, EncodingKey::from_ed_der(&der));
+        }
+
+        let active_kid = match active_kid {
+            Some(kid) => kid,
+            None => encoding_keys
+                .keys()
+                .max()
+                .cloned()
+                .ok_or_else(|| format!("no {label} signing key available"))?,
+        };
+
+        let encoding = encoding_keys
+            .remove(&active_kid)
+            .ok_or_else(|| format!("{}={active_kid} has no private key", source.active_var))?;
+
+        println!(
+            "{label} signing key: {active_kid} ({} verification keys)",
+            verification.len()
+        );
+
+        Ok(Self {
+            active_kid,
+            encoding,
+            verification,
+        })
+    }
+
+    /// Kid of the key used to sign new tokens
+    pub fn active_kid(&self) -> &str {
+        &self.active_kid
+    }
+
+    /// Key used to sign new tokens
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.encoding
+    }
+
+    /// Verification key for a given kid, if it is still trusted
+    pub fn decoding_key(&self, kid: &str) -> Option<&DecodingKey> {
+        self.verification.get(kid).map(|k| &k.decoding)
+    }
+
+    /// Whether any key of `other` is also one of these, by public key
+    pub fn shares_key_with(&self, other: &JwtKeys) -> bool {
+        self.verification
+            .values()
+            .any(|k| other.verification.values().any(|o| o.x == k.x))
+    }
+
+    /// Public keys for all trusted kids
+    pub fn jwks(&self) -> Jwks {
+        let mut keys: Vec<Jwk> = self
+            .verification
+            .iter()
+            .map(|(kid, key)| Jwk {
+                kty: "OKP",
+                crv: "Ed25519",
+                alg: "EdDSA",
+                use_: "sig",
+                kid: kid.clone(),
+                x: key.x.clone(),
+            })
+            .collect();
+        keys.sort_by(|a, b| a.kid.cmp(&b.kid));
+
+        Jwks { keys }
+    }
+}
+
+impl VerificationKey {
+    fn from_raw(public_key: &[u8]) -> Result<Self, String> {
+        let x = URL_SAFE_NO_PAD.encode(public_key);
+        let decoding = DecodingKey::from_ed_components(&x).map_err(|e| e.to_string())?;
+        Ok(Self { decoding, x })
+    }
+}
+
+/// Dev mode is enabled with `APP_ENV=dev` (or `development`)
+fn is_dev_mode() -> bool {
+    matches!(
+        std::env::var("APP_ENV").as_deref(),
+        Ok("dev") | Ok("development")
+    )
+}
+
+/// Read all `<kid>.pem` private keys in `dir` as PKCS#8 DER
+fn read_private_keys(dir: &Path) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut out = Vec::new();
+    for (kid, path) in pem_files(dir, false)? {
+        let block = read_pem(&path)?;
+        if block.tag() != "PRIVATE KEY" {
+            return Err(format!("{} is not a PKCS#8 private key", path.display()));
+        }
+        out.push((kid, block.into_contents()));
+    }
+    Ok(out)
+}
+
+/// Read all `<kid>.pub.pem` public keys in `dir` as raw Ed25519 keys
+fn read_public_keys(dir: &Path) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut out = Vec::new();
+    for (kid, path) in pem_files(dir, true)? {
+        let block = read_pem(&path)?;
+        let der = block.contents();
+        if block.tag() != "PUBLIC KEY" || der.len() != 44 || der[..12] != ED25519_SPKI_PREFIX {
+            return Err(format!("{} is not an Ed25519 public key", path.display()));
+        }
+        out.push((kid, der[12..].to_vec()));
+    }
+    Ok(out)
+}
+
+/// List `(kid, path)` for key files in `dir`.
+/// `public` selects `*.pub.pem` files, otherwise plain `*.pem` files.
+fn pem_files(dir: &Path, public: bool) -> Result<Vec<(String, PathBuf)>, String> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("cannot read {}: {e}", dir.display())),
+    };
+
+    let mut out = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+
+        let kid = match (name.strip_suffix(".pub.pem"), name.strip_suffix(".pem")) {
+            (Some(kid), _) if public => kid,
+            (None, Some(kid)) if !public => kid,
+            _ => continue,
+        };
+        out.push((kid.to_string(), path.clone()));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+impl JwtKeys {
+    /// A fresh key that exists only in memory
+    pub(crate) fn generate(kid: &str) -> Self {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let verification = HashMap::from([(
+            kid.to_string(),
+            VerificationKey::from_raw(pair.public_key().as_ref()).unwrap(),
+        )]);
+
+        Self {
+            active_kid: kid.to_string(),
+            encoding: EncodingKey::from_ed_der(pkcs8.as_ref()),
+            verification,
+        }
+    }
+}
+
+fn read_pem(path: &Path) -> Result<pem::Pem, String> {
+    let text = std::fs::read(path).map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+    pem::parse(text).map_err(|e| format!("invalid PEM in {}: {e}", path.display()))
+}
+
+/// Generate a new Ed25519 key and save it as `<dir>/dev.pem`
+fn generate_dev_key(dir: &Path) -> Result<(String, Vec<u8>), String> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| "key generation failed")?;
+    let der = pkcs8.as_ref().to_vec();
+
+    std::fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {e}", dir.display()))?;
+    let path = dir.join("dev.pem");
+    let text = pem::encode(&pem::Pem::new("PRIVATE KEY", der.clone()));
+
+    // Created owner-only, so the key is never readable by others, not even briefly
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(&path)
+        .map_err(|e| format!("cannot create {}: {e}", path.display()))?;
+
+    // Some filesystems ignore the mode given at creation; fail rather than keep a readable key
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .map_err(|e| format!("cannot restrict permissions of {}: {e}", path.display()))?;
+    }
+
+    file.write_all(text.as_bytes())
+        .map_err(|e| format!("cannot write {}: {e}", path.display()))?;
+
+    Ok(("dev".to_string(), der))
+}
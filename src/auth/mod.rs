@@ -1,5 +1,14 @@
+pub mod email;
+pub mod error;
+pub mod keys;
+pub mod oidc;
 pub mod passwords;
+pub mod policy;
 pub mod repository;
+pub mod scope;
 pub mod service;
+pub mod throttle;
 pub mod token;
+pub mod totp;
 pub mod types;
+pub mod username;
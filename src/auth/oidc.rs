@@ -0,0 +1,318 @@
+use std::time::Duration;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use rand::RngCore;
+use rand_core::OsRng;
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+
+use crate::config::OidcConfig;
+
+/// Timeout for every request to the provider
+const HTTP_TIMEOUT_SECS: u64 = 10;
+
+/// Allowed clock skew when checking the ID token's `exp` and `iat`
+const ID_TOKEN_LEEWAY_SECS: u64 = 60;
+
+/// Signature algorithms accepted for ID tokens. Symmetric algorithms are
+/// refused, since they would let anyone holding the client secret mint tokens.
+const ALLOWED_ALGORITHMS: [Algorithm; 8] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::EdDSA,
+];
+
+/// OpenID Connect relying party: authorization code flow with PKCE.
+///
+/// Provider metadata and keys are discovered on first use and cached; the key
+/// set is fetched again when an ID token names an unknown `kid`, so provider
+/// key rotation needs no restart.
+pub struct OidcClient {
+    config: OidcConfig,
+    http: reqwest::Client,
+    provider: RwLock<Option<Provider>>,
+}
+
+#[derive(Clone)]
+struct Provider {
+    metadata: ProviderMetadata,
+    jwks: JwkSet,
+}
+
+/// The parts of `/.well-known/openid-configuration` the login flow needs
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Per-login secrets: `state` ties the callback to the login that started it,
+/// `nonce` ties the ID token to it, and `code_verifier` is the PKCE secret.
+pub struct LoginSecrets {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+impl LoginSecrets {
+    pub fn generate() -> Self {
+        Self {
+            state: random_string(),
+            nonce: random_string(),
+            code_verifier: random_string(),
+        }
+    }
+}
+
+/// Verified claims of an ID token
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    /// Some providers send this as a string
+    #[serde(default)]
+    pub email_verified: Option<serde_json::Value>,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+}
+
+impl IdTokenClaims {
+    /// The email claim, if the provider says it has verified it
+    pub fn verified_email(&self) -> Option<&str> {
+        let verified = match &self.email_verified {
+            Some(serde_json::Value::Bool(b)) => *b,
+            Some(serde_json::Value::String(s)) => s == "true",
+            _ => false,
+        };
+
+        self.email.as_deref().filter(|_| verified)
+    }
+}
+
+impl OidcClient {
+    pub fn new(config: OidcConfig) -> Result<Self, String> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(HTTP_TIMEOUT_SECS))
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| format!("cannot build OIDC HTTP client: {e}"))?;
+
+        println!("OIDC login enabled for issuer {}", config.issuer);
+
+        Ok(Self {
+            config,
+            http,
+            provider: RwLock::new(None),
+        })
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    /// Cached provider metadata and keys, discovered on first use
+    async fn provider(&self) -> Result<Provider, String> {
+        if let Some(provider) = self.provider.read().await.as_ref() {
+            return Ok(provider.clone());
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", self.config.issuer);
+        let metadata: ProviderMetadata = self.get_json(&url).await?;
+
+        // The discovery document must describe the configured issuer
+        if metadata.issuer.trim_end_matches('/') != self.config.issuer {
+            return Err(format!(
+                "OIDC discovery returned issuer {:?}, expected {:?}",
+                metadata.issuer, self.config.issuer
+            ));
+        }
+
+        let jwks: JwkSet = self.get_json(&metadata.jwks_uri).await?;
+        let provider = Provider { metadata, jwks };
+        *self.provider.write().await = Some(provider.clone());
+
+        Ok(provider)
+    }
+
+    /// Fetch the provider's key set again, after it named a key we do not have
+    async fn refresh_jwks(&self) -> Result<Provider, String> {
+        let mut provider = self.provider().await?;
+        provider.jwks = self.get_json(&provider.metadata.jwks_uri).await?;
+        *self.provider.write().await = Some(provider.clone());
+
+        Ok(provider)
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, String> {
+        let resp = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| format!("OIDC request to {url} failed: {e}"))?;
+
+        if !resp.status().is_success() {
+            return Err(format!("OIDC request to {url} returned {}", resp.status()));
+        }
+
+        resp.json()
+            .await
+            .map_err(|e| format!("invalid OIDC response from {url}: {e}"))
+    }
+
+    /// URL of the provider's login page for one login attempt
+    pub async fn authorization_url(&self, secrets: &LoginSecrets) -> Result<String, String> {
+        let provider = self.provider().await?;
+
+        let url = Url::parse_with_params(
+            &provider.metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", self.config.client_id.as_str()),
+                ("redirect_uri", self.config.redirect_uri.as_str()),
+                ("scope", self.config.scopes.as_str()),
+                ("state", secrets.state.as_str()),
+                ("nonce", secrets.nonce.as_str()),
+                ("code_challenge", &pkce_challenge(&secrets.code_verifier)),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| format!("invalid authorization_endpoint: {e}"))?;
+
+        Ok(url.into())
+    }
+
+    /// Redeem an authorization code at the token endpoint and validate the
+    /// returned ID token against the provider's keys, our client id and `nonce`.
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, String> {
+        let provider = self.provider().await?;
+
+        let mut req = self.http.post(&provider.metadata.token_endpoint).form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", code_verifier),
+        ]);
+        if let Some(secret) = &self.config.client_secret {
+            req = req.basic_auth(&self.config.client_id, Some(secret));
+        }
+
+        let resp = req
+            .send()
+            .await
+            .map_err(|e| format!("OIDC token request failed: {e}"))?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            return Err(match resp.json::<TokenErrorResponse>().await {
+                Ok(err) => format!(
+                    "OIDC token request rejected: {} {}",
+                    err.error,
+                    err.error_description.unwrap_or_default()
+                ),
+                Err(_) => format!("OIDC token request returned {status}"),
+            });
+        }
+
+        let tokens: TokenResponse = resp
+            .json()
+            .await
+            .map_err(|e| format!("invalid OIDC token response: {e}"))?;
+        let id_token = tokens
+            .id_token
+            .ok_or("OIDC token response has no id_token")?;
+
+        self.validate_id_token(&id_token, nonce).await
+    }
+
+    async fn validate_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, String> {
+        let header = decode_header(id_token).map_err(|e| format!("invalid ID token: {e}"))?;
+        if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+            return Err(format!(
+                "ID token algorithm {:?} is not allowed",
+                header.alg
+            ));
+        }
+
+        let mut provider = self.provider().await?;
+        if find_key(&provider.jwks, header.kid.as_deref()).is_none() {
+            provider = self.refresh_jwks().await?;
+        }
+        let jwk = find_key(&provider.jwks, header.kid.as_deref())
+            .ok_or("ID token is signed with an unknown key")?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|e| format!("unusable provider key: {e}"))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&provider.metadata.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.leeway = ID_TOKEN_LEEWAY_SECS;
+
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| format!("ID token rejected: {e}"))?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err("ID token nonce does not match".into());
+        }
+
+        Ok(claims)
+    }
+}
+
+/// Key for `kid`, or the only key if the token names none
+fn find_key<'a>(jwks: &'a JwkSet, kid: Option<&str>) -> Option<&'a jsonwebtoken::jwk::Jwk> {
+    match kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+}
+
+/// PKCE S256 challenge for a code verifier
+fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// 32 random bytes, base64url encoded (also a valid 43-character PKCE verifier)
+fn random_string() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
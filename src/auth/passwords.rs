@@ -1,30 +1,121 @@
 use argon2::{
-    Argon2,
-    password_hash::{
-        Error as PasswordHashError, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
-    },
+    Algorithm, Argon2, Params, Version,
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
 };
 use rand_core::OsRng;
 
-/// Hash a plaintext password using Argon2.
+use crate::config::PasswordConfig;
+
+/// Argon2id password hashing with parameters fixed at startup.
 ///
-/// Returns an encoded hash string that is safe to store.
-/// The raw password is never stored or returned.
-pub fn hash_password(password: &str) -> Result<String, PasswordHashError> {
-    let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-
-    let hash = argon2.hash_password(password.as_bytes(), &salt)?;
-    Ok(hash.to_string())
+/// Hashing and verification run on the blocking thread pool, since a single
+/// Argon2 call can take tens of milliseconds and would otherwise stall the runtime.
+#[derive(Clone)]
+pub struct PasswordHashing {
+    params: Params,
 }
 
-/// Verify a plaintext password against a stored Argon2 hash.
-///
-/// Returns `Ok(true)` if the password is valid, `Ok(false)` if not.
-pub fn verify_password(password: &str, password_hash: &str) -> Result<bool, PasswordHashError> {
-    let parsed_hash = PasswordHash::new(password_hash)?;
+/// Outcome of checking a password against a stored hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordCheck {
+    Invalid,
+    /// The password matches. `needs_rehash` is set when the stored hash uses
+    /// another algorithm, version or cost parameters than the current ones.
+    Valid {
+        needs_rehash: bool,
+    },
+}
+
+impl PasswordHashing {
+    /// Build from `ARGON2_*` settings; fails if the parameters are out of range
+    pub fn new(cfg: &PasswordConfig) -> Result<Self, String> {
+        let params = Params::new(
+            cfg.argon2_memory_kib,
+            cfg.argon2_iterations,
+            cfg.argon2_parallelism,
+            None,
+        )
+        .map_err(|e| format!("invalid Argon2 parameters: {e}"))?;
+
+        println!(
+            "Password hashing: argon2id m={} t={} p={}",
+            params.m_cost(),
+            params.t_cost(),
+            params.p_cost()
+        );
+
+        Ok(Self { params })
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    /// Hash a plaintext password using Argon2id and the current parameters.
+    ///
+    /// Returns an encoded hash string that is safe to store.
+    /// The raw password is never stored or returned.
+    pub async fn hash(&self, password: &str) -> Result<String, String> {
+        let argon2 = self.argon2();
+        let password = password.to_owned();
+
+        tokio::task::spawn_blocking(move || {
+            let salt = SaltString::generate(&mut OsRng);
+            argon2
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+        })
+        .await
+        .map_err(|_| "Password hashing failed")?
+        .map_err(|_| "Password hashing failed".into())
+    }
+
+    /// Verify a plaintext password against a stored Argon2 hash.
+    ///
+    /// The stored hash's own algorithm and parameters are used for verification,
+    /// so hashes created with older settings keep working.
+    pub async fn verify(
+        &self,
+        password: &str,
+        password_hash: &str,
+    ) -> Result<PasswordCheck, String> {
+        let current = self.params.clone();
+        let password = password.to_owned();
+        let password_hash = password_hash.to_owned();
+
+        tokio::task::spawn_blocking(move || {
+            let parsed =
+                PasswordHash::new(&password_hash).map_err(|_| "Password verification failed")?;
+
+            if Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_err()
+            {
+                return Ok(PasswordCheck::Invalid);
+            }
+
+            Ok(PasswordCheck::Valid {
+                needs_rehash: is_outdated(&parsed, &current),
+            })
+        })
+        .await
+        .map_err(|_| "Password verification failed")?
+    }
+}
+
+/// Whether a stored hash differs from what `hash` would produce today
+fn is_outdated(hash: &PasswordHash, current: &Params) -> bool {
+    if hash.algorithm != Algorithm::Argon2id.ident() || hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
 
-    Ok(Argon2::default()
-        .verify_password(password.as_bytes(), &parsed_hash)
-        .is_ok())
+    match Params::try_from(hash) {
+        Ok(p) => {
+            p.m_cost() != current.m_cost()
+                || p.t_cost() != current.t_cost()
+                || p.p_cost() != current.p_cost()
+        }
+        Err(_) => true,
+    }
 }
@@ -0,0 +1,74 @@
+use std::collections::HashSet;
+
+use crate::config::PasswordConfig;
+
+/// Upper bound on password length, to keep hashing cost bounded
+const MAX_LENGTH: usize = 1024;
+
+/// Usernames shorter than this are not checked for similarity
+const MIN_SIMILARITY_LEN: usize = 3;
+
+/// Password strength rules applied on registration, change and reset
+pub struct PasswordPolicy {
+    min_length: usize,
+    /// Lowercased known-breached passwords
+    breached: HashSet<String>,
+}
+
+impl PasswordPolicy {
+    /// Build the policy, loading the breached-password list if one is configured
+    pub fn load(cfg: &PasswordConfig) -> Result<Self, String> {
+        let breached = match &cfg.breached_list {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+                let set: HashSet<String> = text
+                    .lines()
+                    .map(|l| l.trim().to_lowercase())
+                    .filter(|l| !l.is_empty())
+                    .collect();
+                println!(
+                    "Loaded {} breached passwords from {}",
+                    set.len(),
+                    path.display()
+                );
+                set
+            }
+            None => HashSet::new(),
+        };
+
+        Ok(Self {
+            min_length: cfg.min_length,
+            breached,
+        })
+    }
+
+    /// Check a candidate password for `username`
+    pub fn check(&self, username: &str, password: &str) -> Result<(), String> {
+        let len = password.chars().count();
+        if len < self.min_length {
+            return Err(format!(
+                "Password must be at least {} characters",
+                self.min_length
+            ));
+        }
+        if len > MAX_LENGTH {
+            return Err(format!("Password must be at most {MAX_LENGTH} characters"));
+        }
+
+        let lower = password.to_lowercase();
+        if self.breached.contains(&lower) {
+            return Err("Password appears in a list of breached passwords".into());
+        }
+
+        let user = username.trim().to_lowercase();
+        if user.chars().count() >= MIN_SIMILARITY_LEN {
+            let reversed: String = user.chars().rev().collect();
+            if lower.contains(&user) || lower.contains(&reversed) || user.contains(&lower) {
+                return Err("Password must not contain the username".into());
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,6 +1,14 @@
 use sqlx::{Row, SqlitePool};
 
-use crate::auth::types::AuthUser;
+use crate::auth::scope::{Scopes, TagLimit};
+use crate::auth::token::hash_token;
+use crate::auth::types::{
+    ApiKeyInfo, AuthUser, Credential, NewApiKey, OidcLoginState, PendingLink, RefreshRotation,
+    TotpRecord, UserEmail,
+};
+
+/// Upper bound on how many successors are followed when revoking a token family
+const MAX_FAMILY_DEPTH: usize = 10_000;
 
 /// DB backed repository for authentication users
 #[derive(Clone)]
@@ -34,6 +42,26 @@ impl AuthUserRepository {
         }))
     }
 
+    /// Find a user by id
+    pub async fn find_by_id(&self, user_id: u32) -> Result<Option<AuthUser>, sqlx::Error> {
+        let row_opt = sqlx::query(
+            r#"
+            SELECT id, username, password_hash
+            FROM users
+            WHERE id = ?1
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row_opt.map(|row| AuthUser {
+            id: row.get::<i64, _>("id") as u32,
+            username: row.get::<String, _>("username"),
+            password_hash: row.get::<String, _>("password_hash"),
+        }))
+    }
+
     /// Get username by user id
     pub async fn get_username_by_id(&self, user_id: u32) -> Result<Option<String>, sqlx::Error> {
         let row_opt = sqlx::query(
@@ -50,20 +78,62 @@ impl AuthUserRepository {
         Ok(row_opt.map(|row| row.get::<String, _>("username")))
     }
 
+    /// Whether a user has chosen a password, `None` if there is no such user.
+    /// Accounts created by single sign-on have a random one until it is reset.
+    pub async fn password_is_set(&self, user_id: u32) -> Result<Option<bool>, sqlx::Error> {
+        let row_opt = sqlx::query("SELECT password_set FROM users WHERE id = ?1")
+            .bind(user_id as i64)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row_opt.map(|row| row.get::<i64, _>("password_set") != 0))
+    }
+
+    /// Whether a user has admin rights
+    pub async fn is_admin(&self, user_id: u32) -> Result<bool, sqlx::Error> {
+        let row_opt = sqlx::query("SELECT is_admin FROM users WHERE id = ?1")
+            .bind(user_id as i64)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row_opt.is_some_and(|row| row.get::<i64, _>("is_admin") != 0))
+    }
+
+    /// Make the given usernames the only admins: everyone else loses admin rights,
+    /// so removing a name from the list demotes that user. Unknown usernames are ignored.
+    pub async fn set_admins(&self, usernames: &[String]) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("UPDATE users SET is_admin = 0 WHERE is_admin != 0")
+            .execute(&mut *tx)
+            .await?;
+
+        for username in usernames {
+            sqlx::query("UPDATE users SET is_admin = 1 WHERE username = ?1")
+                .bind(username)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await
+    }
+
     /// Create and store a new user with a unique ID
     pub async fn create(
         &self,
         username: String,
         password_hash: String,
+        email: Option<&str>,
     ) -> Result<AuthUser, sqlx::Error> {
         let result = sqlx::query(
             r#"
-            INSERT INTO users (username, password_hash, active)
-            VALUES (?, ?, 1)
+            INSERT INTO users (username, password_hash, active, email)
+            VALUES (?, ?, 1, ?)
             "#,
         )
         .bind(&username)
         .bind(&password_hash)
+        .bind(email)
         .execute(&self.pool)
         .await?;
 
@@ -76,41 +146,85 @@ impl AuthUserRepository {
         })
     }
 
-    /// Store a refresh token for a user
-    pub async fn insert_refresh_token(&self, user_id: u32, token: &str) -> Result<(), sqlx::Error> {
+    /// Store a refresh token for a user, starting a new token family with the given scopes.
+    /// `auth_time` is when the user logged in and `session_id` names the session;
+    /// every token of the family keeps both.
+    ///
+    /// Only the token hash is stored. The token expires after `idle_ttl_secs` unless rotated,
+    /// and its family can never outlive `absolute_ttl_secs`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_refresh_token(
+        &self,
+        user_id: u32,
+        token: &str,
+        scopes: &Scopes,
+        auth_time: u64,
+        session_id: &str,
+        idle_ttl_secs: i64,
+        absolute_ttl_secs: i64,
+    ) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
-            INSERT INTO refresh_tokens (user_id, token)
-            VALUES (?1, ?2)
+            INSERT INTO refresh_tokens
+                (user_id, token_hash, expires_at, absolute_expires_at, scopes, auth_time,
+                 session_id)
+            VALUES (
+                ?1,
+                ?2,
+                strftime('%s', 'now') + MIN(?3, ?4),
+                strftime('%s', 'now') + ?4,
+                ?5,
+                ?6,
+                ?7
+            )
             "#,
         )
         .bind(user_id as i64)
-        .bind(token)
+        .bind(hash_token(token))
+        .bind(idle_ttl_secs)
+        .bind(absolute_ttl_secs)
+        .bind(scopes.to_db())
+        .bind(auth_time as i64)
+        .bind(session_id)
         .execute(&self.pool)
         .await?;
 
         Ok(())
     }
 
-    /// Rotates a refresh token in a single transaction: the old token must exist and be unrevoked,
-    /// it is marked revoked and linked via `replaced_by`, and a new token row is inserted.
-    /// Returns `Some(user_id)` on success, or `None` if the token is invalid or already used.
+    /// Rotates a refresh token in a single transaction: the old token must exist, be unrevoked
+    /// and unexpired, it is marked revoked and linked via `replaced_by`, and a new token row is
+    /// inserted. The new token gets a fresh idle timeout, capped by the family's absolute expiry.
+    ///
+    /// If the old token was already rotated (it has a `replaced_by` successor), the token has
+    /// been replayed. Every descendant in its family is revoked and `Reused` is returned.
+    ///
+    /// The new token keeps the family's scopes. `requested` must be a subset of them,
+    /// otherwise nothing changes and `ScopeNotGranted` is returned.
     pub async fn rotate_refresh_token(
         &self,
         old_token: &str,
         new_token: &str,
-    ) -> Result<Option<u32>, sqlx::Error> {
+        idle_ttl_secs: i64,
+        requested: Option<&Scopes>,
+    ) -> Result<RefreshRotation, sqlx::Error> {
+        let old_hash = hash_token(old_token);
+        let new_hash = hash_token(new_token);
+
         let mut tx = self.pool.begin().await?;
 
         // Look up old token
         let row_opt = sqlx::query(
             r#"
-            SELECT user_id, revoked_at
+            SELECT user_id, revoked_at, replaced_by, expires_at, absolute_expires_at, scopes,
+                   COALESCE(auth_time, 0) AS auth_time,
+                   COALESCE(session_id, token_hash) AS session_id,
+                   CAST(strftime('%s', 'now') AS INTEGER) AS now
             FROM refresh_tokens
-            WHERE token = ?1
+            WHERE token_hash = ?1
             "#,
         )
-        .bind(old_token)
+        .bind(&old_hash)
         .fetch_optional(&mut *tx)
         .await?;
 
@@ -118,17 +232,93 @@ impl AuthUserRepository {
             Some(r) => r,
             None => {
                 tx.rollback().await?;
-                return Ok(None);
+                return Ok(RefreshRotation::Invalid);
             }
         };
 
+        let user_id: i64 = row.get("user_id");
         let revoked_at: Option<i64> = row.get("revoked_at");
+        let replaced_by: Option<String> = row.get("replaced_by");
+        let expires_at: i64 = row.get("expires_at");
+        let absolute_expires_at: i64 = row.get("absolute_expires_at");
+        let scopes: Option<String> = row.get("scopes");
+        let auth_time: i64 = row.get("auth_time");
+        let session_id: String = row.get("session_id");
+        let now: i64 = row.get("now");
+
         if revoked_at.is_some() {
-            tx.rollback().await?;
-            return Ok(None);
+            let Some(successor) = replaced_by else {
+                tx.rollback().await?;
+                return Ok(RefreshRotation::Invalid);
+            };
+
+            // Reuse of a rotated token: revoke the whole chain after it
+            let mut next = Some(successor);
+            let mut hops = 0;
+            while let Some(token_hash) = next {
+                hops += 1;
+                if hops > MAX_FAMILY_DEPTH {
+                    break;
+                }
+
+                sqlx::query(
+                    r#"
+                    UPDATE refresh_tokens
+                    SET revoked_at = strftime('%s', 'now')
+                    WHERE token_hash = ?1 AND revoked_at IS NULL
+                    "#,
+                )
+                .bind(&token_hash)
+                .execute(&mut *tx)
+                .await?;
+
+                next = sqlx::query("SELECT replaced_by FROM refresh_tokens WHERE token_hash = ?1")
+                    .bind(&token_hash)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .and_then(|r| r.get::<Option<String>, _>("replaced_by"));
+            }
+
+            tx.commit().await?;
+            return Ok(RefreshRotation::Reused {
+                user_id: user_id as u32,
+            });
         }
 
-        let user_id: i64 = row.get("user_id");
+        if expires_at <= now || absolute_expires_at <= now {
+            sqlx::query(
+                r#"
+                UPDATE refresh_tokens
+                SET revoked_at = strftime('%s', 'now')
+                WHERE token_hash = ?1
+                "#,
+            )
+            .bind(&old_hash)
+            .execute(&mut *tx)
+            .await?;
+
+            tx.commit().await?;
+            return Ok(RefreshRotation::Expired);
+        }
+
+        // Sessions from before scoped tokens get what the user may hold today
+        let scopes = match scopes {
+            Some(s) => Scopes::from_db(&s),
+            None => {
+                let is_admin: i64 = sqlx::query("SELECT is_admin FROM users WHERE id = ?1")
+                    .bind(user_id)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .map_or(0, |r| r.get("is_admin"));
+                Scopes::session(is_admin != 0)
+            }
+        };
+
+        // Asking for more than the session holds leaves the token untouched
+        if let Some(missing) = requested.and_then(|r| r.first_missing_from(&scopes)) {
+            tx.rollback().await?;
+            return Ok(RefreshRotation::ScopeNotGranted(missing));
+        }
 
         // Revoke old token
         let upd = sqlx::query(
@@ -136,34 +326,66 @@ impl AuthUserRepository {
             UPDATE refresh_tokens
             SET revoked_at = strftime('%s', 'now'),
                 replaced_by = ?1
-            WHERE token = ?2
+            WHERE token_hash = ?2
                 AND revoked_at IS NULL
             "#,
         )
-        .bind(new_token)
-        .bind(old_token)
+        .bind(&new_hash)
+        .bind(&old_hash)
         .execute(&mut *tx)
         .await?;
 
         if upd.rows_affected() != 1 {
             tx.rollback().await?;
-            return Ok(None);
+            return Ok(RefreshRotation::Invalid);
         }
 
-        // Insert new token
+        // Insert new token, sliding the idle timeout but keeping the family's absolute expiry
         sqlx::query(
             r#"
-            INSERT INTO refresh_tokens (user_id, token)
-            VALUES (?1, ?2)
+            INSERT INTO refresh_tokens
+                (user_id, token_hash, expires_at, absolute_expires_at, scopes, auth_time,
+                 session_id)
+            VALUES (?1, ?2, MIN(?3 + ?4, ?5), ?5, ?6, ?7, ?8)
             "#,
         )
         .bind(user_id)
-        .bind(new_token)
+        .bind(&new_hash)
+        .bind(now)
+        .bind(idle_ttl_secs)
+        .bind(absolute_expires_at)
+        .bind(scopes.to_db())
+        .bind(auth_time)
+        .bind(&session_id)
         .execute(&mut *tx)
         .await?;
 
         tx.commit().await?;
-        Ok(Some(user_id as u32))
+        Ok(RefreshRotation::Rotated {
+            user_id: user_id as u32,
+            scopes,
+            auth_time: auth_time as u64,
+            session_id,
+        })
+    }
+
+    /// Delete refresh tokens that can no longer be used: rows past their family's absolute
+    /// expiry, and unrotated rows that are expired or revoked. Rotated rows are kept until the
+    /// family's absolute expiry so a replay is still detected as reuse for its whole lifetime.
+    /// Returns the number of deleted rows.
+    pub async fn delete_stale_refresh_tokens(&self) -> Result<u64, sqlx::Error> {
+        let res = sqlx::query(
+            r#"
+            DELETE FROM refresh_tokens
+            WHERE absolute_expires_at <= strftime('%s', 'now')
+                OR (replaced_by IS NULL
+                    AND (expires_at <= strftime('%s', 'now') OR revoked_at IS NOT NULL))
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected())
     }
 
     // Revoke all refresh tokens
@@ -183,4 +405,1145 @@ impl AuthUserRepository {
         .await?;
         Ok(())
     }
+
+    /// Revoke every token of one login session; returns whether any was still live
+    pub async fn revoke_session(
+        &self,
+        user_id: u32,
+        session_id: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = strftime('%s', 'now')
+            WHERE user_id = ?1 AND session_id = ?2 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(session_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Whether `credential` of `user_id` still works: its session has a live refresh
+    /// token, or its API key is neither revoked nor expired. Access tokens from before
+    /// session ids only need the user to still exist.
+    pub async fn credential_is_active(
+        &self,
+        user_id: u32,
+        credential: &Credential,
+    ) -> Result<bool, sqlx::Error> {
+        let query = match credential {
+            Credential::Session { session_id, .. } if !session_id.is_empty() => sqlx::query(
+                r#"
+                SELECT 1 FROM refresh_tokens
+                WHERE user_id = ?1 AND session_id = ?2
+                    AND revoked_at IS NULL AND expires_at > strftime('%s', 'now')
+                LIMIT 1
+                "#,
+            )
+            .bind(user_id as i64)
+            .bind(session_id.as_str()),
+            Credential::Session { .. } => {
+                sqlx::query("SELECT 1 FROM users WHERE id = ?1").bind(user_id as i64)
+            }
+            Credential::ApiKey { id } => sqlx::query(
+                r#"
+                SELECT 1 FROM api_keys
+                WHERE id = ?1 AND user_id = ?2
+                    AND revoked_at IS NULL
+                    AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+                "#,
+            )
+            .bind(*id as i64)
+            .bind(user_id as i64),
+        };
+
+        Ok(query.fetch_optional(&self.pool).await?.is_some())
+    }
+
+    /// Get the TOTP state for a user, if enrollment was started
+    pub async fn get_totp(&self, user_id: u32) -> Result<Option<TotpRecord>, sqlx::Error> {
+        let row_opt = sqlx::query(
+            r#"
+            SELECT secret, enabled_at, last_used_step
+            FROM user_totp
+            WHERE user_id = ?1
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row_opt.map(|row| TotpRecord {
+            secret: row.get("secret"),
+            enabled: row.get::<Option<i64>, _>("enabled_at").is_some(),
+            last_used_step: row
+                .get::<Option<i64>, _>("last_used_step")
+                .map(|s| s as u64),
+        }))
+    }
+
+    /// Store a new, not yet confirmed TOTP secret, replacing any pending one
+    pub async fn set_pending_totp(&self, user_id: u32, secret: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_totp (user_id, secret, enabled_at, last_used_step)
+            VALUES (?1, ?2, NULL, NULL)
+            ON CONFLICT(user_id) DO UPDATE
+                SET secret = excluded.secret,
+                    enabled_at = NULL,
+                    last_used_step = NULL
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(secret)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Enable TOTP for a user and replace their recovery codes, in one transaction
+    pub async fn enable_totp(
+        &self,
+        user_id: u32,
+        used_step: u64,
+        recovery_code_hashes: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            UPDATE user_totp
+            SET enabled_at = strftime('%s', 'now'),
+                last_used_step = ?2
+            WHERE user_id = ?1
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(used_step as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?1")
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        for hash in recovery_code_hashes {
+            sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) VALUES (?1, ?2)")
+                .bind(user_id as i64)
+                .bind(hash)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await
+    }
+
+    /// Record the time step of an accepted code.
+    /// Returns `false` if a code for this or a later step was already used.
+    pub async fn mark_totp_step_used(&self, user_id: u32, step: u64) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query(
+            r#"
+            UPDATE user_totp
+            SET last_used_step = ?2
+            WHERE user_id = ?1
+                AND (last_used_step IS NULL OR last_used_step < ?2)
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(step as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Mark a recovery code as used. Returns `false` if no unused code matches.
+    pub async fn consume_recovery_code(
+        &self,
+        user_id: u32,
+        code_hash: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query(
+            r#"
+            UPDATE recovery_codes
+            SET used_at = strftime('%s', 'now')
+            WHERE user_id = ?1 AND code_hash = ?2 AND used_at IS NULL
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(code_hash)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Remove TOTP and recovery codes for a user
+    pub async fn disable_totp(&self, user_id: u32) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM user_totp WHERE user_id = ?1")
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?1")
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await
+    }
+
+    /// Replace a user's password hash
+    pub async fn update_password(
+        &self,
+        user_id: u32,
+        password_hash: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE users SET password_hash = ?1, password_set = 1 WHERE id = ?2")
+            .bind(password_hash)
+            .bind(user_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Replace a password hash with an upgraded hash of the same password.
+    /// Does nothing if the password was changed in the meantime.
+    pub async fn upgrade_password_hash(
+        &self,
+        user_id: u32,
+        old_hash: &str,
+        new_hash: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let res =
+            sqlx::query("UPDATE users SET password_hash = ?1 WHERE id = ?2 AND password_hash = ?3")
+                .bind(new_hash)
+                .bind(user_id as i64)
+                .bind(old_hash)
+                .execute(&self.pool)
+                .await?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Store a password reset token for a user, replacing any unused one.
+    /// Only the token hash is stored.
+    pub async fn create_password_reset(
+        &self,
+        user_id: u32,
+        token: &str,
+        ttl_secs: i64,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM password_reset_tokens WHERE user_id = ?1 AND used_at IS NULL")
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO password_reset_tokens (token_hash, user_id, expires_at)
+            VALUES (?1, ?2, strftime('%s', 'now') + ?3)
+            "#,
+        )
+        .bind(hash_token(token))
+        .bind(user_id as i64)
+        .bind(ttl_secs)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await
+    }
+
+    /// User id for an unused, unexpired password reset token
+    pub async fn find_password_reset_user(&self, token: &str) -> Result<Option<u32>, sqlx::Error> {
+        let row_opt = sqlx::query(
+            r#"
+            SELECT user_id
+            FROM password_reset_tokens
+            WHERE token_hash = ?1
+                AND used_at IS NULL
+                AND expires_at > strftime('%s', 'now')
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row_opt.map(|row| row.get::<i64, _>("user_id") as u32))
+    }
+
+    /// Consume a reset token and set the new password hash in one transaction.
+    /// Returns `false` if the token was used or expired in the meantime.
+    pub async fn reset_password(
+        &self,
+        token: &str,
+        user_id: u32,
+        password_hash: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let res = sqlx::query(
+            r#"
+            UPDATE password_reset_tokens
+            SET used_at = strftime('%s', 'now')
+            WHERE token_hash = ?1
+                AND user_id = ?2
+                AND used_at IS NULL
+                AND expires_at > strftime('%s', 'now')
+            "#,
+        )
+        .bind(hash_token(token))
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        if res.rows_affected() != 1 {
+            return Ok(false);
+        }
+
+        sqlx::query("UPDATE users SET password_hash = ?1, password_set = 1 WHERE id = ?2")
+            .bind(password_hash)
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Delete used or expired password reset tokens
+    pub async fn delete_stale_password_resets(&self) -> Result<u64, sqlx::Error> {
+        let res = sqlx::query(
+            r#"
+            DELETE FROM password_reset_tokens
+            WHERE used_at IS NOT NULL OR expires_at <= strftime('%s', 'now')
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Email address of a user and whether it has been verified
+    pub async fn get_email(&self, user_id: u32) -> Result<Option<UserEmail>, sqlx::Error> {
+        let row_opt = sqlx::query(
+            r#"
+            SELECT email, email_verified_at
+            FROM users
+            WHERE id = ?1 AND email IS NOT NULL
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row_opt.map(|row| UserEmail {
+            address: row.get("email"),
+            verified: row.get::<Option<i64>, _>("email_verified_at").is_some(),
+        }))
+    }
+
+    /// Set or clear a user's email address. The new address starts unverified
+    /// and pending verification tokens for the old one are dropped.
+    pub async fn set_email(&self, user_id: u32, email: Option<&str>) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("UPDATE users SET email = ?1, email_verified_at = NULL WHERE id = ?2")
+            .bind(email)
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM email_verification_tokens WHERE user_id = ?1")
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await
+    }
+
+    /// Store an email verification token for `email`, replacing any unused one.
+    /// Only the token hash is stored.
+    pub async fn create_email_verification(
+        &self,
+        user_id: u32,
+        email: &str,
+        token: &str,
+        ttl_secs: i64,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM email_verification_tokens WHERE user_id = ?1 AND used_at IS NULL")
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO email_verification_tokens (token_hash, user_id, email, expires_at)
+            VALUES (?1, ?2, ?3, strftime('%s', 'now') + ?4)
+            "#,
+        )
+        .bind(hash_token(token))
+        .bind(user_id as i64)
+        .bind(email)
+        .bind(ttl_secs)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await
+    }
+
+    /// Consume a verification token and mark the address it was issued for as verified.
+    /// Returns `false` if the token is unknown, used, expired, or the user's address
+    /// has changed since it was issued.
+    pub async fn verify_email(&self, token: &str) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let row_opt = sqlx::query(
+            r#"
+            UPDATE email_verification_tokens
+            SET used_at = strftime('%s', 'now')
+            WHERE token_hash = ?1
+                AND used_at IS NULL
+                AND expires_at > strftime('%s', 'now')
+            RETURNING user_id, email
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(row) = row_opt else {
+            return Ok(false);
+        };
+
+        let res = sqlx::query(
+            r#"
+            UPDATE users
+            SET email_verified_at = strftime('%s', 'now')
+            WHERE id = ?1 AND email = ?2
+            "#,
+        )
+        .bind(row.get::<i64, _>("user_id"))
+        .bind(row.get::<String, _>("email"))
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Delete used or expired email verification tokens
+    pub async fn delete_stale_email_verifications(&self) -> Result<u64, sqlx::Error> {
+        let res = sqlx::query(
+            r#"
+            DELETE FROM email_verification_tokens
+            WHERE used_at IS NOT NULL OR expires_at <= strftime('%s', 'now')
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Remember a started OIDC login. Only a hash of `state` is stored.
+    pub async fn create_oidc_login(
+        &self,
+        state: &str,
+        login: &OidcLoginState,
+        ttl_secs: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO oidc_login_states (state_hash, code_verifier, nonce, link_user_id, expires_at)
+            VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now') + ?5)
+            "#,
+        )
+        .bind(hash_token(state))
+        .bind(&login.code_verifier)
+        .bind(&login.nonce)
+        .bind(login.link_user_id.map(|id| id as i64))
+        .bind(ttl_secs)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Consume a pending OIDC login; `None` if `state` is unknown, used or expired
+    pub async fn take_oidc_login(
+        &self,
+        state: &str,
+    ) -> Result<Option<OidcLoginState>, sqlx::Error> {
+        let row_opt = sqlx::query(
+            r#"
+            DELETE FROM oidc_login_states
+            WHERE state_hash = ?1 AND expires_at > strftime('%s', 'now')
+            RETURNING code_verifier, nonce, link_user_id
+            "#,
+        )
+        .bind(hash_token(state))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row_opt.map(|row| OidcLoginState {
+            code_verifier: row.get("code_verifier"),
+            nonce: row.get("nonce"),
+            link_user_id: row
+                .get::<Option<i64>, _>("link_user_id")
+                .map(|id| id as u32),
+        }))
+    }
+
+    /// Remember an identity verified by a link callback until `user_id` confirms it.
+    /// Only a hash of `code` is stored.
+    pub async fn create_pending_link(
+        &self,
+        code: &str,
+        user_id: u32,
+        issuer: &str,
+        subject: &str,
+        email: Option<&str>,
+        ttl_secs: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO oidc_pending_links (code_hash, user_id, issuer, subject, email, expires_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, strftime('%s', 'now') + ?6)
+            "#,
+        )
+        .bind(hash_token(code))
+        .bind(user_id as i64)
+        .bind(issuer)
+        .bind(subject)
+        .bind(email)
+        .bind(ttl_secs)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Consume a pending link of `user_id`; `None` if `code` is unknown, used,
+    /// expired or was started by another account
+    pub async fn take_pending_link(
+        &self,
+        code: &str,
+        user_id: u32,
+    ) -> Result<Option<PendingLink>, sqlx::Error> {
+        let row_opt = sqlx::query(
+            r#"
+            DELETE FROM oidc_pending_links
+            WHERE code_hash = ?1 AND user_id = ?2 AND expires_at > strftime('%s', 'now')
+            RETURNING issuer, subject, email
+            "#,
+        )
+        .bind(hash_token(code))
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row_opt.map(|row| PendingLink {
+            issuer: row.get("issuer"),
+            subject: row.get("subject"),
+            email: row.get("email"),
+        }))
+    }
+
+    /// Delete expired pending OIDC logins and links
+    pub async fn delete_stale_oidc_logins(&self) -> Result<u64, sqlx::Error> {
+        let logins =
+            sqlx::query("DELETE FROM oidc_login_states WHERE expires_at <= strftime('%s', 'now')")
+                .execute(&self.pool)
+                .await?;
+        let links =
+            sqlx::query("DELETE FROM oidc_pending_links WHERE expires_at <= strftime('%s', 'now')")
+                .execute(&self.pool)
+                .await?;
+
+        Ok(logins.rows_affected() + links.rows_affected())
+    }
+
+    /// Find the user an external identity is linked to, recording the login
+    pub async fn find_identity_user(
+        &self,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<Option<AuthUser>, sqlx::Error> {
+        let row_opt = sqlx::query(
+            r#"
+            UPDATE user_identities
+            SET last_login_at = strftime('%s', 'now')
+            WHERE issuer = ?1 AND subject = ?2
+            RETURNING user_id
+            "#,
+        )
+        .bind(issuer)
+        .bind(subject)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match row_opt {
+            Some(row) => self.find_by_id(row.get::<i64, _>("user_id") as u32).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Link an external identity to an existing user.
+    /// Fails with a unique violation if the identity is linked already.
+    pub async fn link_identity(
+        &self,
+        user_id: u32,
+        issuer: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_identities (issuer, subject, user_id, email, last_login_at)
+            VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now'))
+            "#,
+        )
+        .bind(issuer)
+        .bind(subject)
+        .bind(user_id as i64)
+        .bind(email)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Create a user for an external identity and link it, in one transaction.
+    /// `email` is stored as verified, since the provider vouches for it.
+    pub async fn create_identity_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        issuer: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<AuthUser, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let res = sqlx::query(
+            r#"
+            INSERT INTO users
+                (username, password_hash, password_set, active, email, email_verified_at)
+            VALUES (?1, ?2, 0, 1, ?3, CASE WHEN ?3 IS NULL THEN NULL ELSE strftime('%s', 'now') END)
+            "#,
+        )
+        .bind(username)
+        .bind(password_hash)
+        .bind(email)
+        .execute(&mut *tx)
+        .await?;
+        let user_id = res.last_insert_rowid();
+
+        sqlx::query(
+            r#"
+            INSERT INTO user_identities (issuer, subject, user_id, email, last_login_at)
+            VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now'))
+            "#,
+        )
+        .bind(issuer)
+        .bind(subject)
+        .bind(user_id)
+        .bind(email)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(AuthUser {
+            id: user_id as u32,
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+        })
+    }
+
+    /// Whether an email address is already used by any account (case-insensitive)
+    pub async fn email_in_use(&self, email: &str) -> Result<bool, sqlx::Error> {
+        let row = sqlx::query(
+            "SELECT EXISTS (SELECT 1 FROM users WHERE email = ?1 COLLATE NOCASE) AS used",
+        )
+        .bind(email)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.get::<i64, _>("used") != 0)
+    }
+
+    /// Number of API keys of a user that are neither revoked nor expired
+    pub async fn count_active_api_keys(&self, user_id: u32) -> Result<u32, sqlx::Error> {
+        let row = sqlx::query(
+            r#"
+            SELECT COUNT(*) AS n
+            FROM api_keys
+            WHERE user_id = ?1
+                AND revoked_at IS NULL
+                AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.get::<i64, _>("n") as u32)
+    }
+
+    /// Store a new API key; returns its id and expiry
+    pub async fn create_api_key(
+        &self,
+        user_id: u32,
+        new: &NewApiKey<'_>,
+    ) -> Result<(u32, Option<i64>), sqlx::Error> {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes, tag, expires_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, strftime('%s', 'now') + ?7)
+            RETURNING id, expires_at
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(new.name)
+        .bind(new.prefix)
+        .bind(hash_token(new.key))
+        .bind(new.scopes.to_db())
+        .bind(new.tag)
+        .bind(new.ttl_secs)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok((row.get::<i64, _>("id") as u32, row.get("expires_at")))
+    }
+
+    /// Look up a usable API key and record its use. Returns the owner, the key's scopes,
+    /// the tag it is limited to and the key id. `last_used_at` is written at most once a minute per key.
+    pub async fn use_api_key(
+        &self,
+        key: &str,
+        ip: Option<&str>,
+    ) -> Result<Option<(u32, Scopes, TagLimit, u32)>, sqlx::Error> {
+        let key_hash = hash_token(key);
+
+        let row_opt = sqlx::query(
+            r#"
+            SELECT id, user_id, scopes, tag, last_used_at
+            FROM api_keys
+            WHERE key_hash = ?1
+                AND revoked_at IS NULL
+                AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+            "#,
+        )
+        .bind(&key_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(row) = row_opt else {
+            return Ok(None);
+        };
+
+        sqlx::query(
+            r#"
+            UPDATE api_keys
+            SET last_used_at = strftime('%s', 'now'), last_used_ip = ?2
+            WHERE id = ?1
+                AND (last_used_at IS NULL OR last_used_at < strftime('%s', 'now') - 60
+                    OR last_used_ip IS NOT ?2)
+            "#,
+        )
+        .bind(row.get::<i64, _>("id"))
+        .bind(ip)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(Some((
+            row.get::<i64, _>("user_id") as u32,
+            Scopes::from_db(&row.get::<String, _>("scopes")),
+            TagLimit(row.get("tag")),
+            row.get::<i64, _>("id") as u32,
+        )))
+    }
+
+    /// All API keys of a user, newest first
+    pub async fn list_api_keys(&self, user_id: u32) -> Result<Vec<ApiKeyInfo>, sqlx::Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, name, prefix, scopes, tag, created_at, expires_at,
+                last_used_at, last_used_ip, revoked_at
+            FROM api_keys
+            WHERE user_id = ?1
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| ApiKeyInfo {
+                id: row.get::<i64, _>("id") as u32,
+                name: row.get("name"),
+                prefix: row.get("prefix"),
+                scopes: Scopes::from_db(&row.get::<String, _>("scopes")).names(),
+                tag: row.get("tag"),
+                created_at: row.get("created_at"),
+                expires_at: row.get("expires_at"),
+                last_used_at: row.get("last_used_at"),
+                last_used_ip: row.get("last_used_ip"),
+                revoked_at: row.get("revoked_at"),
+            })
+            .collect())
+    }
+
+    /// Revoke one of a user's API keys; `false` if there is no such active key
+    pub async fn revoke_api_key(&self, user_id: u32, key_id: u32) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query(
+            r#"
+            UPDATE api_keys
+            SET revoked_at = strftime('%s', 'now')
+            WHERE id = ?1 AND user_id = ?2 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(key_id as i64)
+        .bind(user_id as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected() == 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_state;
+    use crate::auth::scope::Scope;
+
+    async fn repo_with_user() -> (AuthUserRepository, u32) {
+        let repo = test_state().await.auth.repo;
+        let user = repo
+            .create("alice".into(), "hash".into(), None)
+            .await
+            .unwrap();
+        (repo, user.id)
+    }
+
+    async fn add_key(repo: &AuthUserRepository, user_id: u32, key: &str, tag: Option<&str>) {
+        let scopes = Scopes::from_db("files:read");
+        let new = NewApiKey {
+            name: key,
+            key,
+            prefix: &key[..8],
+            scopes: &scopes,
+            tag,
+            ttl_secs: None,
+        };
+        repo.create_api_key(user_id, &new).await.unwrap();
+    }
+
+    const IDLE: i64 = 3600;
+    const ABSOLUTE: i64 = 86_400;
+
+    fn scopes(s: &str) -> Scopes {
+        Scopes::from_db(s)
+    }
+
+    /// Start a session for `user_id` with refresh token `token`
+    async fn login(repo: &AuthUserRepository, user_id: u32, token: &str, granted: &str) {
+        repo.insert_refresh_token(
+            user_id,
+            token,
+            &scopes(granted),
+            1_700_000_000,
+            &format!("session-{token}"),
+            IDLE,
+            ABSOLUTE,
+        )
+        .await
+        .unwrap();
+    }
+
+    async fn rotate(repo: &AuthUserRepository, old: &str, new: &str) -> RefreshRotation {
+        repo.rotate_refresh_token(old, new, IDLE, None)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rotation_keeps_the_session() {
+        let (repo, alice) = repo_with_user().await;
+        login(&repo, alice, "t0", "files:read files:write").await;
+
+        let rotated = rotate(&repo, "t0", "t1").await;
+        assert_eq!(
+            rotated,
+            RefreshRotation::Rotated {
+                user_id: alice,
+                scopes: scopes("files:read files:write"),
+                auth_time: 1_700_000_000,
+                session_id: "session-t0".into(),
+            }
+        );
+        assert!(matches!(
+            rotate(&repo, "t1", "t2").await,
+            RefreshRotation::Rotated { .. }
+        ));
+        assert_eq!(
+            rotate(&repo, "unknown", "t3").await,
+            RefreshRotation::Invalid
+        );
+    }
+
+    #[tokio::test]
+    async fn reuse_revokes_the_whole_family() {
+        let (repo, alice) = repo_with_user().await;
+        login(&repo, alice, "a0", "files:read").await;
+        login(&repo, alice, "b0", "files:read").await;
+        for (old, new) in [("a0", "a1"), ("a1", "a2"), ("a2", "a3")] {
+            assert!(matches!(
+                rotate(&repo, old, new).await,
+                RefreshRotation::Rotated { .. }
+            ));
+        }
+
+        assert_eq!(
+            rotate(&repo, "a1", "x").await,
+            RefreshRotation::Reused { user_id: alice }
+        );
+        // The newest token of the family is revoked too, and a replay of it is no reuse
+        assert_eq!(rotate(&repo, "a3", "y").await, RefreshRotation::Invalid);
+        assert_eq!(
+            rotate(&repo, "a0", "z").await,
+            RefreshRotation::Reused { user_id: alice }
+        );
+        // Other sessions of the same user are left alone
+        assert!(matches!(
+            rotate(&repo, "b0", "b1").await,
+            RefreshRotation::Rotated { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn reuse_walk_stops_after_max_family_depth() {
+        let (repo, alice) = repo_with_user().await;
+        login(&repo, alice, "c0", "files:read").await;
+        assert!(matches!(
+            rotate(&repo, "c0", "c1").await,
+            RefreshRotation::Rotated { .. }
+        ));
+        // A successor pointing back at itself would be followed forever
+        sqlx::query("UPDATE refresh_tokens SET replaced_by = token_hash WHERE token_hash = ?1")
+            .bind(hash_token("c1"))
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            rotate(&repo, "c0", "x").await,
+            RefreshRotation::Reused { user_id: alice }
+        );
+        let revoked: Option<i64> =
+            sqlx::query_scalar("SELECT revoked_at FROM refresh_tokens WHERE token_hash = ?1")
+                .bind(hash_token("c1"))
+                .fetch_one(&repo.pool)
+                .await
+                .unwrap();
+        assert!(revoked.is_some());
+    }
+
+    #[tokio::test]
+    async fn rotation_narrows_only_to_a_subset() {
+        let (repo, alice) = repo_with_user().await;
+        login(&repo, alice, "t0", "files:read files:write").await;
+
+        let rotated = repo
+            .rotate_refresh_token("t0", "t1", IDLE, Some(&scopes("files:read")))
+            .await
+            .unwrap();
+        // The family keeps every scope; only the access token is narrowed
+        assert!(matches!(
+            rotated,
+            RefreshRotation::Rotated { scopes: s, .. } if s == scopes("files:read files:write")
+        ));
+
+        let refused = repo
+            .rotate_refresh_token("t1", "t2", IDLE, Some(&scopes("files:read shares:manage")))
+            .await
+            .unwrap();
+        assert_eq!(
+            refused,
+            RefreshRotation::ScopeNotGranted(Scope::SharesManage)
+        );
+        // A refused request leaves the token usable
+        assert!(matches!(
+            rotate(&repo, "t1", "t2").await,
+            RefreshRotation::Rotated { .. }
+        ));
+    }
+
+    /// Move a token's expiries to `idle` and `absolute` seconds from now
+    async fn set_expiry(repo: &AuthUserRepository, token: &str, idle: i64, absolute: i64) {
+        sqlx::query(
+            r#"
+            UPDATE refresh_tokens
+            SET expires_at = strftime('%s', 'now') + ?2,
+                absolute_expires_at = strftime('%s', 'now') + ?3
+            WHERE token_hash = ?1
+            "#,
+        )
+        .bind(hash_token(token))
+        .bind(idle)
+        .bind(absolute)
+        .execute(&repo.pool)
+        .await
+        .unwrap();
+    }
+
+    async fn expiry(repo: &AuthUserRepository, token: &str) -> (i64, i64, Option<i64>) {
+        let row = sqlx::query(
+            r#"
+            SELECT expires_at - strftime('%s', 'now') AS idle,
+                absolute_expires_at - strftime('%s', 'now') AS absolute,
+                revoked_at
+            FROM refresh_tokens
+            WHERE token_hash = ?1
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_one(&repo.pool)
+        .await
+        .unwrap();
+        (row.get("idle"), row.get("absolute"), row.get("revoked_at"))
+    }
+
+    #[tokio::test]
+    async fn refresh_just_before_the_idle_timeout_slides_it() {
+        let (repo, alice) = repo_with_user().await;
+        login(&repo, alice, "t0", "files:read").await;
+        set_expiry(&repo, "t0", 2, ABSOLUTE).await;
+
+        assert!(matches!(
+            rotate(&repo, "t0", "t1").await,
+            RefreshRotation::Rotated { .. }
+        ));
+        let (idle, absolute, revoked) = expiry(&repo, "t1").await;
+        assert!((IDLE - 1..=IDLE).contains(&idle), "idle {idle}");
+        assert!(
+            (ABSOLUTE - 1..=ABSOLUTE).contains(&absolute),
+            "absolute {absolute}"
+        );
+        assert_eq!(revoked, None);
+    }
+
+    #[tokio::test]
+    async fn refresh_after_the_idle_timeout_expires_the_token() {
+        let (repo, alice) = repo_with_user().await;
+        login(&repo, alice, "t0", "files:read").await;
+        set_expiry(&repo, "t0", 0, ABSOLUTE).await;
+
+        assert_eq!(rotate(&repo, "t0", "t1").await, RefreshRotation::Expired);
+        // The expired token is marked revoked, without a successor
+        let (_, _, revoked) = expiry(&repo, "t0").await;
+        assert!(revoked.is_some());
+        assert_eq!(rotate(&repo, "t0", "t1").await, RefreshRotation::Invalid);
+        assert_eq!(rotate(&repo, "t1", "t2").await, RefreshRotation::Invalid);
+    }
+
+    #[tokio::test]
+    async fn refresh_past_the_absolute_expiry_expires_the_token() {
+        let (repo, alice) = repo_with_user().await;
+        login(&repo, alice, "t0", "files:read").await;
+        set_expiry(&repo, "t0", IDLE, 0).await;
+
+        assert_eq!(rotate(&repo, "t0", "t1").await, RefreshRotation::Expired);
+        let (_, _, revoked) = expiry(&repo, "t0").await;
+        assert!(revoked.is_some());
+    }
+
+    #[tokio::test]
+    async fn idle_timeout_never_passes_the_absolute_expiry() {
+        let (repo, alice) = repo_with_user().await;
+        login(&repo, alice, "t0", "files:read").await;
+        set_expiry(&repo, "t0", 60, 120).await;
+
+        assert!(matches!(
+            rotate(&repo, "t0", "t1").await,
+            RefreshRotation::Rotated { .. }
+        ));
+        let (idle, absolute, _) = expiry(&repo, "t1").await;
+        assert_eq!(idle, absolute);
+        assert!((119..=120).contains(&absolute), "absolute {absolute}");
+    }
+
+    #[tokio::test]
+    async fn stale_refresh_tokens_are_deleted() {
+        let (repo, alice) = repo_with_user().await;
+        for token in ["live", "idle", "revoked", "rotated", "old"] {
+            login(&repo, alice, token, "files:read").await;
+        }
+        set_expiry(&repo, "idle", 0, ABSOLUTE).await;
+        repo.revoke_session(alice, "session-revoked").await.unwrap();
+        assert!(matches!(
+            rotate(&repo, "rotated", "next").await,
+            RefreshRotation::Rotated { .. }
+        ));
+        assert!(matches!(
+            rotate(&repo, "old", "old-next").await,
+            RefreshRotation::Rotated { .. }
+        ));
+        set_expiry(&repo, "old", IDLE, 0).await;
+
+        // "idle", "revoked" and "old" go; "old-next" keeps its own absolute expiry
+        assert_eq!(repo.delete_stale_refresh_tokens().await.unwrap(), 3);
+        let mut left: Vec<String> = sqlx::query_scalar("SELECT token_hash FROM refresh_tokens")
+            .fetch_all(&repo.pool)
+            .await
+            .unwrap();
+        let mut expected: Vec<String> = ["live", "rotated", "next", "old-next"]
+            .into_iter()
+            .map(hash_token)
+            .collect();
+        left.sort();
+        expected.sort();
+        assert_eq!(left, expected);
+
+        // The kept rotated token still reveals a replay
+        assert_eq!(
+            rotate(&repo, "rotated", "x").await,
+            RefreshRotation::Reused { user_id: alice }
+        );
+    }
+
+    #[tokio::test]
+    async fn api_key_carries_its_tag_limit() {
+        let (repo, alice) = repo_with_user().await;
+        add_key(&repo, alice, "sfs_tagged", Some("reports")).await;
+        add_key(&repo, alice, "sfs_whole", None).await;
+
+        let (user_id, _, limit, _) = repo.use_api_key("sfs_tagged", None).await.unwrap().unwrap();
+        assert_eq!(user_id, alice);
+        assert_eq!(limit, TagLimit(Some("reports".into())));
+        assert_eq!(limit.tag(), Some("reports"));
+
+        let (_, _, limit, _) = repo.use_api_key("sfs_whole", None).await.unwrap().unwrap();
+        assert_eq!(limit, TagLimit::default());
+        assert_eq!(limit.tag(), None);
+    }
 }
@@ -0,0 +1,134 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A permission required by a route and held by a credential
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// List and download files, read profile and export
+    FilesRead,
+    /// Upload files
+    FilesWrite,
+    /// Share files and revoke shares
+    SharesManage,
+    /// Admin routes; only granted to sessions of users with admin rights, never to API keys
+    Admin,
+    /// Manage the account itself (password, 2FA, email, API keys, deletion).
+    /// Only held by interactive sessions; API keys can never be granted it.
+    Account,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 5] = [
+        Scope::FilesRead,
+        Scope::FilesWrite,
+        Scope::SharesManage,
+        Scope::Admin,
+        Scope::Account,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::FilesRead => "files:read",
+            Scope::FilesWrite => "files:write",
+            Scope::SharesManage => "shares:manage",
+            Scope::Admin => "admin",
+            Scope::Account => "account",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Scope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| format!("Unknown scope: {s}"))
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Scopes granted to the current request, set by `auth_middleware`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scopes(Vec<Scope>);
+
+impl Scopes {
+    /// Scopes of a login session: everything, except `admin` for non-admins
+    pub fn session(is_admin: bool) -> Self {
+        Self(
+            Scope::ALL
+                .into_iter()
+                .filter(|s| is_admin || *s != Scope::Admin)
+                .collect(),
+        )
+    }
+
+    /// Parse a requested OAuth-style space-separated scope string; empty requests are refused
+    pub fn parse_request(requested: &str) -> Result<Self, String> {
+        let scopes = Self::parse(&requested.split_whitespace().collect::<Vec<_>>())?;
+        if scopes.is_empty() {
+            return Err("At least one scope must be requested".into());
+        }
+        Ok(scopes)
+    }
+
+    /// The first scope in `self` that `other` does not hold
+    pub fn first_missing_from(&self, other: &Scopes) -> Option<Scope> {
+        self.0.iter().copied().find(|s| !other.contains(*s))
+    }
+
+    pub fn contains(&self, scope: Scope) -> bool {
+        self.0.contains(&scope)
+    }
+
+    /// Parse a list of scope names, dropping duplicates
+    pub fn parse<S: AsRef<str>>(names: &[S]) -> Result<Self, String> {
+        let mut scopes = Vec::new();
+        for name in names {
+            let scope: Scope = name.as_ref().trim().parse()?;
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+        Ok(Self(scopes))
+    }
+
+    /// Space-separated form, as stored in the database
+    pub fn to_db(&self) -> String {
+        self.names().join(" ")
+    }
+
+    pub fn from_db(s: &str) -> Self {
+        Self(
+            s.split_whitespace()
+                .filter_map(|n| n.parse().ok())
+                .collect(),
+        )
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.0.iter().map(|s| s.as_str()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Tag the current request's credential is limited to, set by `auth_middleware`.
+/// API keys created with a `tag` only reach their owner's files carrying it, like
+/// a folder; sessions and other keys have no limit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagLimit(pub Option<String>);
+
+impl TagLimit {
+    pub fn tag(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+}
@@ -1,139 +1,1271 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand::RngCore;
+use rand_core::OsRng;
 use uuid::Uuid;
 
-use crate::auth::passwords::{hash_password, verify_password};
+use crate::auth::email;
+use crate::auth::error::AuthError;
+use crate::auth::keys::JwtKeys;
+use crate::auth::oidc::{IdTokenClaims, LoginSecrets, OidcClient};
+use crate::auth::passwords::{PasswordCheck, PasswordHashing};
+use crate::auth::policy::PasswordPolicy;
 use crate::auth::repository::AuthUserRepository;
-use crate::auth::token::create_token;
-use crate::auth::types::{AuthTokenResponse, LoginRequest, RegisterRequest};
+use crate::auth::scope::{Scope, Scopes};
+use crate::auth::token::{create_challenge_token, create_token, now_secs, verify_challenge_token};
+use crate::auth::totp;
+use crate::auth::types::{
+    ApiKeyCreated, AuthTokenResponse, AuthUser, ChangePasswordRequest, CreateApiKeyRequest,
+    Credential, LoginRequest, LoginResponse, NewApiKey, OidcCallbackOutcome, OidcCallbackQuery,
+    OidcLinkPending, OidcLinked, OidcLoginState, RefreshRotation, RegisterRequest,
+    ResetPasswordRequest, TotpConfirmResponse, TotpDisableRequest, TotpEnrollResponse,
+    TwoFactorChallenge, TwoFactorLoginRequest,
+};
+use crate::auth::username;
+use crate::config::AuthConfig;
+use crate::mail::Mailer;
 
 #[async_trait]
 pub trait AuthService {
-    async fn register(&self, req: RegisterRequest) -> Result<AuthTokenResponse, String>;
-    async fn login(&self, req: LoginRequest) -> Result<AuthTokenResponse, String>;
-    async fn refresh(&self, refresh_token: String) -> Result<AuthTokenResponse, String>;
+    async fn register(&self, req: RegisterRequest) -> Result<AuthTokenResponse, AuthError>;
+    async fn login(&self, req: LoginRequest) -> Result<LoginResponse, AuthError>;
+    async fn complete_two_factor(
+        &self,
+        req: TwoFactorLoginRequest,
+    ) -> Result<AuthTokenResponse, AuthError>;
+    async fn refresh(
+        &self,
+        refresh_token: String,
+        scope: Option<String>,
+    ) -> Result<AuthTokenResponse, AuthError>;
+    async fn begin_totp_enrollment(&self, user_id: u32) -> Result<TotpEnrollResponse, AuthError>;
+    async fn confirm_totp(
+        &self,
+        user_id: u32,
+        code: &str,
+    ) -> Result<TotpConfirmResponse, AuthError>;
+    async fn disable_totp(&self, user_id: u32, req: TotpDisableRequest) -> Result<(), AuthError>;
+    async fn change_password(
+        &self,
+        user_id: u32,
+        req: ChangePasswordRequest,
+    ) -> Result<AuthTokenResponse, AuthError>;
+    async fn create_password_reset(&self, username: &str) -> Result<Option<String>, AuthError>;
+    /// Returns the id of the user whose password was reset
+    async fn reset_password(&self, req: ResetPasswordRequest) -> Result<u32, AuthError>;
+    async fn confirm_password(&self, user_id: u32, password: &str) -> Result<(), AuthError>;
+    /// Check that the caller of a destructive action is the account holder.
+    /// Accounts with a password must give it; accounts without one (created by
+    /// single sign-on) give a 2FA code or logged in within `REAUTH_MAX_AGE_SECS`.
+    async fn confirm_account_holder(
+        &self,
+        user_id: u32,
+        password: Option<&str>,
+        code: Option<&str>,
+        credential: &Credential,
+    ) -> Result<(), AuthError>;
+    async fn send_password_reset(&self, username: &str) -> Result<(), AuthError>;
+    async fn set_email(&self, user_id: u32, email: Option<String>) -> Result<(), AuthError>;
+    async fn resend_email_verification(&self, user_id: u32) -> Result<(), AuthError>;
+    async fn verify_email(&self, token: &str) -> Result<(), AuthError>;
+    async fn begin_oidc_login(&self, link_user_id: Option<u32>) -> Result<String, AuthError>;
+    async fn complete_oidc_login(
+        &self,
+        query: OidcCallbackQuery,
+    ) -> Result<OidcCallbackOutcome, AuthError>;
+    /// Link the identity of a finished link callback to `user_id`, which must
+    /// be the account that started the link
+    async fn confirm_oidc_link(
+        &self,
+        user_id: u32,
+        link_code: &str,
+    ) -> Result<OidcLinked, AuthError>;
+    async fn create_api_key(
+        &self,
+        user_id: u32,
+        req: CreateApiKeyRequest,
+    ) -> Result<ApiKeyCreated, AuthError>;
 }
 
-const EXPIRES_IN: u64 = 3600;
+/// Every API key starts with this, so `auth_middleware` can tell keys from JWTs
+pub const API_KEY_PREFIX: &str = "sfs_";
+
+/// Maximum number of active (unrevoked, unexpired) API keys per user
+pub const MAX_API_KEYS: u32 = 20;
+
+/// How recently an account without a password must have logged in to confirm
+/// a destructive action without a 2FA code
+pub const REAUTH_MAX_AGE_SECS: u64 = 300;
 
 #[derive(Clone)]
 pub struct SimpleAuthService {
     pub repo: AuthUserRepository,
+    pub keys: Arc<JwtKeys>,
+    pub config: Arc<AuthConfig>,
+    pub policy: Arc<PasswordPolicy>,
+    pub passwords: PasswordHashing,
+    pub mailer: Arc<dyn Mailer>,
+    pub oidc: Option<Arc<OidcClient>>,
 }
 
 impl SimpleAuthService {
-    pub fn new(repo: AuthUserRepository) -> Self {
-        Self { repo }
+    pub fn new(
+        repo: AuthUserRepository,
+        keys: Arc<JwtKeys>,
+        config: Arc<AuthConfig>,
+        policy: Arc<PasswordPolicy>,
+        passwords: PasswordHashing,
+        mailer: Arc<dyn Mailer>,
+        oidc: Option<Arc<OidcClient>>,
+    ) -> Self {
+        Self {
+            repo,
+            keys,
+            config,
+            policy,
+            passwords,
+            mailer,
+            oidc,
+        }
     }
 
-    /// Issue access + refresh tokens and store refresh token in DB
-    async fn issue_tokens(
+    fn oidc(&self) -> Result<&OidcClient, AuthError> {
+        self.oidc.as_deref().ok_or(AuthError::OidcNotConfigured)
+    }
+
+    /// Tokens for a user who has proven who they are, or a 2FA challenge if
+    /// the account has 2FA enabled
+    async fn finish_login(
+        &self,
+        user: &AuthUser,
+        scope: Option<&str>,
+    ) -> Result<LoginResponse, AuthError> {
+        let scopes = self.grant_scopes(user.id, scope).await?;
+
+        let totp = self.repo.get_totp(user.id).await?;
+
+        if totp.is_some_and(|t| t.enabled) {
+            let challenge =
+                create_challenge_token(&self.keys, &self.config, user.id, &user.username, &scopes)
+                    .map_err(|e| AuthError::internal("Token creation failed", e))?;
+
+            return Ok(LoginResponse::TwoFactorRequired(TwoFactorChallenge {
+                two_factor_required: true,
+                challenge_token: challenge.token,
+                expires_in: challenge.expires_at.saturating_sub(now_secs()),
+            }));
+        }
+
+        let tokens = self.issue_tokens(user.id, &user.username, &scopes).await?;
+        Ok(LoginResponse::Tokens(tokens))
+    }
+
+    /// Scopes for a new session: the requested space-separated subset, or
+    /// everything the user may hold if nothing was requested
+    async fn grant_scopes(
         &self,
         user_id: u32,
-        username: &str,
-    ) -> Result<AuthTokenResponse, String> {
-        let access_token = create_token(user_id, username).map_err(|_| "Token creation failed")?;
+        requested: Option<&str>,
+    ) -> Result<Scopes, AuthError> {
+        let is_admin = self.repo.is_admin(user_id).await?;
+        let allowed = Scopes::session(is_admin);
 
-        let refresh_token = Uuid::new_v4().to_string();
+        let Some(requested) = requested else {
+            return Ok(allowed);
+        };
+        let scopes = Scopes::parse_request(requested).map_err(AuthError::Invalid)?;
+        if let Some(missing) = scopes.first_missing_from(&allowed) {
+            return Err(AuthError::ScopeNotAvailable(missing));
+        }
 
-        self.repo
-            .revoke_all_refresh_tokens_for_user(user_id)
+        Ok(scopes)
+    }
+
+    /// Create a local user for a new external identity. The username comes from
+    /// the provider's hints, with a numeric suffix if taken; the password is random
+    /// and the account counts as having none until the user resets it.
+    async fn create_identity_user(&self, claims: &IdTokenClaims) -> Result<AuthUser, AuthError> {
+        let base = [
+            claims.preferred_username.as_deref(),
+            claims.email.as_deref().and_then(|e| e.split('@').next()),
+        ]
+        .into_iter()
+        .flatten()
+        .find_map(username::derive)
+        .unwrap_or_else(|| "user".to_string());
+
+        let mut username = None;
+        for n in 1..=100 {
+            let candidate = if n == 1 {
+                base.clone()
+            } else {
+                format!("{base}-{n}")
+            };
+            let taken = self.repo.find_by_username(&candidate).await?.is_some();
+            if !taken {
+                username = Some(candidate);
+                break;
+            }
+        }
+        let username = username.ok_or(AuthError::OidcLoginFailed)?;
+
+        // A provider-verified address is kept only if no other account uses it
+        let email = match claims.verified_email().map(email::validate) {
+            Some(Ok(address)) => {
+                let in_use = self.repo.email_in_use(&address).await?;
+                (!in_use).then_some(address)
+            }
+            _ => None,
+        };
+
+        let password_hash = self
+            .passwords
+            .hash(&Self::random_token())
+            .await
+            .map_err(|e| AuthError::internal("Password hashing failed", e))?;
+
+        let user = self
+            .repo
+            .create_identity_user(
+                &username,
+                &password_hash,
+                &claims.iss,
+                &claims.sub,
+                email.as_deref(),
+            )
             .await
-            .map_err(|_| "Database error")?;
+            .map_err(|e| match e {
+                sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                    AuthError::UsernameTaken
+                }
+                e => e.into(),
+            })?;
+
+        println!(
+            "[security] user created from OIDC identity: user_id={} username={} iss={} sub={}",
+            user.id, user.username, claims.iss, claims.sub
+        );
+        Ok(user)
+    }
+
+    /// Generate a random single-use token (32 bytes, base64url)
+    fn random_token() -> String {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// Store a verification token for `address` and mail it.
+    /// A failed send is logged, not returned; the user can ask for a resend.
+    async fn send_verification(&self, user_id: u32, address: &str) -> Result<(), AuthError> {
+        let token = Self::random_token();
 
         self.repo
-            .insert_refresh_token(user_id, &refresh_token)
+            .create_email_verification(
+                user_id,
+                address,
+                &token,
+                self.config.email_verification_ttl_secs,
+            )
+            .await?;
+
+        let body = format!(
+            "Please confirm this email address for your Secure File Server account.\n\n\
+             Verification token: {token}\n\n\
+             Use it with POST /email/verify or `sfs email verify <token>`.\n\
+             It expires in {} hours. If you did not sign up, ignore this message.",
+            self.config.email_verification_ttl_secs / 3600
+        );
+
+        if let Err(e) = self
+            .mailer
+            .send(address, "Verify your email address", &body)
             .await
-            .map_err(|_| "Database error")?;
+        {
+            eprintln!("Failed to send verification mail for user_id={user_id}: {e}");
+        }
+
+        Ok(())
+    }
+
+    /// Check a user's password. On success, a hash made with outdated
+    /// parameters is transparently replaced by one made with the current ones.
+    async fn check_password(&self, user: &AuthUser, password: &str) -> Result<bool, AuthError> {
+        let needs_rehash = match self
+            .passwords
+            .verify(password, &user.password_hash)
+            .await
+            .map_err(|e| AuthError::internal("Password verification failed", e))?
+        {
+            PasswordCheck::Invalid => return Ok(false),
+            PasswordCheck::Valid { needs_rehash } => needs_rehash,
+        };
+
+        if needs_rehash {
+            // Best effort: the login still succeeds if the upgrade fails
+            match self.passwords.hash(password).await {
+                Ok(new_hash) => match self
+                    .repo
+                    .upgrade_password_hash(user.id, &user.password_hash, &new_hash)
+                    .await
+                {
+                    Ok(true) => println!("Upgraded password hash for user_id={}", user.id),
+                    Ok(false) => {}
+                    Err(e) => eprintln!("Password hash upgrade failed: {e}"),
+                },
+                Err(e) => eprintln!("Password hash upgrade failed: {e}"),
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Sign an access token and wrap it with its refresh token
+    fn token_response(
+        &self,
+        user_id: u32,
+        username: &str,
+        refresh_token: String,
+        scopes: &Scopes,
+        auth_time: u64,
+        session_id: &str,
+    ) -> Result<AuthTokenResponse, AuthError> {
+        let access = create_token(
+            &self.keys,
+            &self.config,
+            user_id,
+            username,
+            scopes,
+            auth_time,
+            session_id,
+        )
+        .map_err(|e| AuthError::internal("Token creation failed", e))?;
 
         Ok(AuthTokenResponse {
-            access_token,
+            user_id,
+            access_token: access.token,
             refresh_token,
-            expires_in: EXPIRES_IN,
+            expires_in: access.expires_at.saturating_sub(now_secs()),
+            scope: scopes.to_db(),
         })
     }
+
+    /// Issue access + refresh tokens and store refresh token in DB, starting a session
+    /// for a user who has just logged in
+    async fn issue_tokens(
+        &self,
+        user_id: u32,
+        username: &str,
+        scopes: &Scopes,
+    ) -> Result<AuthTokenResponse, AuthError> {
+        let refresh_token = Uuid::new_v4().to_string();
+        let auth_time = now_secs();
+        let session_id = Uuid::new_v4().to_string();
+
+        self.repo
+            .revoke_all_refresh_tokens_for_user(user_id)
+            .await?;
+
+        self.repo
+            .insert_refresh_token(
+                user_id,
+                &refresh_token,
+                scopes,
+                auth_time,
+                &session_id,
+                self.config.refresh_token_idle_ttl_secs,
+                self.config.refresh_token_absolute_ttl_secs,
+            )
+            .await?;
+
+        self.token_response(
+            user_id,
+            username,
+            refresh_token,
+            scopes,
+            auth_time,
+            &session_id,
+        )
+    }
+
+    /// Check a second factor: a TOTP code (not replayed) or an unused recovery code.
+    /// Returns `Ok(false)` if the code is wrong.
+    async fn check_second_factor(&self, user_id: u32, code: &str) -> Result<bool, AuthError> {
+        let record = self
+            .repo
+            .get_totp(user_id)
+            .await?
+            .filter(|r| r.enabled)
+            .ok_or_else(|| AuthError::Invalid("Two-factor authentication is not enabled".into()))?;
+
+        if totp::is_totp_code(code) {
+            let Some(step) =
+                totp::verify_code(&record.secret, code, now_secs(), record.last_used_step)
+            else {
+                return Ok(false);
+            };
+
+            return self
+                .repo
+                .mark_totp_step_used(user_id, step)
+                .await
+                .map_err(AuthError::from);
+        }
+
+        self.repo
+            .consume_recovery_code(user_id, &totp::hash_recovery_code(code))
+            .await
+            .map_err(AuthError::from)
+    }
 }
 
 #[async_trait]
 impl AuthService for SimpleAuthService {
-    async fn register(&self, req: RegisterRequest) -> Result<AuthTokenResponse, String> {
-        // Basic input validation
-        if req.username.trim().is_empty() {
-            return Err("Username cannot be empty".into());
-        }
+    async fn register(&self, req: RegisterRequest) -> Result<AuthTokenResponse, AuthError> {
+        // Canonicalize and validate the username
+        let username = username::validate(&req.username).map_err(AuthError::Invalid)?;
 
-        if req.password.len() < 8 {
-            return Err("Password must be at least 8 characters".into());
-        }
+        self.policy
+            .check(&username, &req.password)
+            .map_err(AuthError::Invalid)?;
+
+        let address = req
+            .email
+            .as_deref()
+            .map(email::validate)
+            .transpose()
+            .map_err(AuthError::Invalid)?;
 
         // Check if unique
-        let existing = self
-            .repo
-            .find_by_username(&req.username)
-            .await
-            .map_err(|_| "Database error")?;
+        let existing = self.repo.find_by_username(&username).await?;
 
         if existing.is_some() {
-            return Err("Username already exists".into());
+            return Err(AuthError::UsernameTaken);
         }
 
         // Hash password
-        let password_hash = hash_password(&req.password).map_err(|_| "Password hashing failed")?;
+        let password_hash = self
+            .passwords
+            .hash(&req.password)
+            .await
+            .map_err(|e| AuthError::internal("Password hashing failed", e))?;
 
-        // Create user
+        // Create user; the unique indexes still catch a concurrent registration
         let user = self
             .repo
-            .create(req.username, password_hash)
+            .create(username, password_hash, address.as_deref())
             .await
-            .map_err(|_| "Database error")?;
+            .map_err(|e| match e {
+                sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                    if db_err.message().contains("email") {
+                        AuthError::EmailTaken
+                    } else {
+                        AuthError::UsernameTaken
+                    }
+                }
+                e => e.into(),
+            })?;
+
+        if let Some(address) = &address {
+            self.send_verification(user.id, address).await?;
+        }
 
         // Issue access + refresh tokens
-        self.issue_tokens(user.id, &user.username).await
+        let scopes = self.grant_scopes(user.id, None).await?;
+        self.issue_tokens(user.id, &user.username, &scopes).await
     }
 
-    async fn login(&self, req: LoginRequest) -> Result<AuthTokenResponse, String> {
+    async fn login(&self, req: LoginRequest) -> Result<LoginResponse, AuthError> {
         // Find user
         let user = self
             .repo
-            .find_by_username(&req.username)
-            .await
-            .map_err(|_| "Database error")?
-            .ok_or("Invalid credentials")?;
+            .find_by_username(&username::canonicalize(&req.username))
+            .await?
+            .ok_or(AuthError::InvalidCredentials)?;
 
         // Verify password
-        let valid = verify_password(&req.password, &user.password_hash)
-            .map_err(|_| "Password verification failed")?;
+        if !self.check_password(&user, &req.password).await? {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        // Accounts with 2FA get a challenge instead of tokens
+        self.finish_login(&user, req.scope.as_deref()).await
+    }
+
+    async fn complete_two_factor(
+        &self,
+        req: TwoFactorLoginRequest,
+    ) -> Result<AuthTokenResponse, AuthError> {
+        let claims = verify_challenge_token(&self.keys, &self.config, &req.challenge_token)
+            .map_err(|_| AuthError::InvalidChallenge)?;
 
-        if !valid {
-            return Err("Invalid credentials".into());
+        if !self.check_second_factor(claims.sub, &req.code).await? {
+            return Err(AuthError::InvalidTwoFactorCode);
         }
 
-        // Issue access + refresh tokens
-        self.issue_tokens(user.id, &user.username).await
+        self.issue_tokens(claims.sub, &claims.username, &claims.scopes())
+            .await
     }
 
-    async fn refresh(&self, refresh_token: String) -> Result<AuthTokenResponse, String> {
+    async fn refresh(
+        &self,
+        refresh_token: String,
+        scope: Option<String>,
+    ) -> Result<AuthTokenResponse, AuthError> {
+        let requested = scope
+            .as_deref()
+            .map(Scopes::parse_request)
+            .transpose()
+            .map_err(AuthError::Invalid)?;
         let new_refresh = Uuid::new_v4().to_string();
 
-        let user_id = self
+        let rotation = self
+            .repo
+            .rotate_refresh_token(
+                &refresh_token,
+                &new_refresh,
+                self.config.refresh_token_idle_ttl_secs,
+                requested.as_ref(),
+            )
+            .await?;
+
+        let (user_id, session_scopes, auth_time, session_id) = match rotation {
+            RefreshRotation::Rotated {
+                user_id,
+                scopes,
+                auth_time,
+                session_id,
+            } => (user_id, scopes, auth_time, session_id),
+            RefreshRotation::Reused { user_id } => {
+                return Err(AuthError::RefreshTokenReused { user_id });
+            }
+            RefreshRotation::Expired => return Err(AuthError::RefreshTokenExpired),
+            RefreshRotation::Invalid => return Err(AuthError::InvalidRefreshToken),
+            RefreshRotation::ScopeNotGranted(scope) => {
+                return Err(AuthError::ScopeNotAvailable(scope));
+            }
+        };
+
+        let username = self
+            .repo
+            .get_username_by_id(user_id)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+
+        // The session keeps its scopes; a request only narrows this access token
+        let scopes = requested.unwrap_or(session_scopes);
+
+        self.token_response(
+            user_id,
+            &username,
+            new_refresh,
+            &scopes,
+            auth_time,
+            &session_id,
+        )
+    }
+
+    async fn begin_totp_enrollment(&self, user_id: u32) -> Result<TotpEnrollResponse, AuthError> {
+        let username = self
+            .repo
+            .get_username_by_id(user_id)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+
+        let existing = self.repo.get_totp(user_id).await?;
+        if existing.is_some_and(|t| t.enabled) {
+            return Err(AuthError::Invalid(
+                "Two-factor authentication is already enabled".into(),
+            ));
+        }
+
+        let secret = totp::generate_secret();
+        self.repo.set_pending_totp(user_id, &secret).await?;
+
+        Ok(TotpEnrollResponse {
+            otpauth_uri: totp::otpauth_uri(&self.config.issuer, &username, &secret),
+            secret,
+        })
+    }
+
+    async fn confirm_totp(
+        &self,
+        user_id: u32,
+        code: &str,
+    ) -> Result<TotpConfirmResponse, AuthError> {
+        let record = self
+            .repo
+            .get_totp(user_id)
+            .await?
+            .ok_or_else(|| AuthError::Invalid("No two-factor enrollment in progress".into()))?;
+
+        if record.enabled {
+            return Err(AuthError::Invalid(
+                "Two-factor authentication is already enabled".into(),
+            ));
+        }
+
+        let step = totp::verify_code(&record.secret, code, now_secs(), None)
+            .ok_or(AuthError::InvalidTwoFactorCode)?;
+
+        let recovery_codes = totp::generate_recovery_codes();
+        let hashes: Vec<String> = recovery_codes
+            .iter()
+            .map(|c| totp::hash_recovery_code(c))
+            .collect();
+
+        self.repo.enable_totp(user_id, step, &hashes).await?;
+
+        Ok(TotpConfirmResponse { recovery_codes })
+    }
+
+    async fn disable_totp(&self, user_id: u32, req: TotpDisableRequest) -> Result<(), AuthError> {
+        let user = self
+            .repo
+            .find_by_id(user_id)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+
+        if !self.check_password(&user, &req.password).await? {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        if !self.check_second_factor(user_id, &req.code).await? {
+            return Err(AuthError::InvalidTwoFactorCode);
+        }
+
+        self.repo
+            .disable_totp(user_id)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn change_password(
+        &self,
+        user_id: u32,
+        req: ChangePasswordRequest,
+    ) -> Result<AuthTokenResponse, AuthError> {
+        let user = self
             .repo
-            .rotate_refresh_token(&refresh_token, &new_refresh)
+            .find_by_id(user_id)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+
+        if !self.check_password(&user, &req.current_password).await? {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        if req.new_password == req.current_password {
+            return Err(AuthError::Invalid(
+                "New password must differ from the current password".into(),
+            ));
+        }
+        self.policy
+            .check(&user.username, &req.new_password)
+            .map_err(AuthError::Invalid)?;
+
+        let password_hash = self
+            .passwords
+            .hash(&req.new_password)
             .await
-            .map_err(|_| "Database error")?
-            .ok_or("Invalid refresh token")?;
+            .map_err(|e| AuthError::internal("Password hashing failed", e))?;
+        self.repo.update_password(user_id, &password_hash).await?;
+
+        println!("[security] password changed for user_id={user_id}");
+
+        // Revokes every other session's refresh token and hands this one new tokens
+        let scopes = self.grant_scopes(user_id, None).await?;
+        self.issue_tokens(user_id, &user.username, &scopes).await
+    }
+
+    async fn create_password_reset(&self, username: &str) -> Result<Option<String>, AuthError> {
+        let Some(user) = self
+            .repo
+            .find_by_username(&username::canonicalize(username))
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let token = Self::random_token();
+
+        self.repo
+            .create_password_reset(user.id, &token, self.config.password_reset_ttl_secs)
+            .await?;
+
+        Ok(Some(token))
+    }
+
+    async fn reset_password(&self, req: ResetPasswordRequest) -> Result<u32, AuthError> {
+        let user_id = self
+            .repo
+            .find_password_reset_user(&req.token)
+            .await?
+            .ok_or(AuthError::InvalidResetToken)?;
 
         let username = self
             .repo
             .get_username_by_id(user_id)
+            .await?
+            .ok_or(AuthError::InvalidResetToken)?;
+
+        self.policy
+            .check(&username, &req.new_password)
+            .map_err(AuthError::Invalid)?;
+
+        let password_hash = self
+            .passwords
+            .hash(&req.new_password)
             .await
-            .map_err(|_| "Database error")?
-            .ok_or("User not found")?;
+            .map_err(|e| AuthError::internal("Password hashing failed", e))?;
+        let reset = self
+            .repo
+            .reset_password(&req.token, user_id, &password_hash)
+            .await?;
+        if !reset {
+            return Err(AuthError::InvalidResetToken);
+        }
 
-        let access_token = create_token(user_id, &username).map_err(|_| "Token creation failed")?;
+        self.repo
+            .revoke_all_refresh_tokens_for_user(user_id)
+            .await?;
 
-        Ok(AuthTokenResponse {
-            access_token,
-            refresh_token: new_refresh,
-            expires_in: EXPIRES_IN,
+        println!("[security] password reset for user_id={user_id}; all sessions revoked");
+        Ok(user_id)
+    }
+
+    async fn confirm_password(&self, user_id: u32, password: &str) -> Result<(), AuthError> {
+        let user = self
+            .repo
+            .find_by_id(user_id)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+
+        if !self.check_password(&user, password).await? {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        Ok(())
+    }
+
+    async fn confirm_account_holder(
+        &self,
+        user_id: u32,
+        password: Option<&str>,
+        code: Option<&str>,
+        credential: &Credential,
+    ) -> Result<(), AuthError> {
+        let password_set = self
+            .repo
+            .password_is_set(user_id)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+
+        if password_set {
+            return match password {
+                Some(password) => self.confirm_password(user_id, password).await,
+                None => Err(AuthError::ReauthenticationRequired),
+            };
+        }
+
+        // The random password of an SSO account proves nothing, so it is ignored
+        match code {
+            Some(code) if self.check_second_factor(user_id, code).await? => Ok(()),
+            Some(_) => Err(AuthError::InvalidTwoFactorCode),
+            None if credential.logged_in_within(REAUTH_MAX_AGE_SECS) => Ok(()),
+            None => Err(AuthError::ReauthenticationRequired),
+        }
+    }
+
+    async fn send_password_reset(&self, username: &str) -> Result<(), AuthError> {
+        let Some(user) = self
+            .repo
+            .find_by_username(&username::canonicalize(username))
+            .await?
+        else {
+            return Ok(());
+        };
+
+        // Only a verified address may receive reset tokens
+        let Some(address) = self.repo.get_email(user.id).await?.filter(|e| e.verified) else {
+            println!(
+                "[security] password reset requested for user_id={} without a verified email; no mail sent",
+                user.id
+            );
+            return Ok(());
+        };
+
+        let Some(token) = self.create_password_reset(&user.username).await? else {
+            return Ok(());
+        };
+
+        let body = format!(
+            "A password reset was requested for your account.\n\n\
+             Reset token: {token}\n\n\
+             Use it with POST /password/reset or `sfs password reset <token> <new_password>`.\n\
+             It expires in {} minutes. If you did not ask for this, ignore this message.",
+            self.config.password_reset_ttl_secs / 60
+        );
+
+        if let Err(e) = self
+            .mailer
+            .send(&address.address, "Password reset", &body)
+            .await
+        {
+            eprintln!("Failed to send password reset mail: {e}");
+        }
+
+        Ok(())
+    }
+
+    async fn set_email(&self, user_id: u32, email: Option<String>) -> Result<(), AuthError> {
+        let address = email
+            .as_deref()
+            .map(email::validate)
+            .transpose()
+            .map_err(AuthError::Invalid)?;
+
+        self.repo
+            .set_email(user_id, address.as_deref())
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                    AuthError::EmailTaken
+                }
+                e => e.into(),
+            })?;
+
+        match &address {
+            Some(address) => self.send_verification(user_id, address).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn resend_email_verification(&self, user_id: u32) -> Result<(), AuthError> {
+        let address = self
+            .repo
+            .get_email(user_id)
+            .await?
+            .ok_or_else(|| AuthError::Invalid("No email address on file".into()))?;
+
+        if address.verified {
+            return Err(AuthError::Invalid(
+                "Email address is already verified".into(),
+            ));
+        }
+
+        self.send_verification(user_id, &address.address).await
+    }
+
+    async fn verify_email(&self, token: &str) -> Result<(), AuthError> {
+        let verified = self.repo.verify_email(token).await?;
+
+        if !verified {
+            return Err(AuthError::InvalidVerificationToken);
+        }
+
+        Ok(())
+    }
+
+    async fn begin_oidc_login(&self, link_user_id: Option<u32>) -> Result<String, AuthError> {
+        let oidc = self.oidc()?;
+        let secrets = LoginSecrets::generate();
+
+        let url = oidc.authorization_url(&secrets).await.map_err(|e| {
+            eprintln!("OIDC login could not start: {e}");
+            AuthError::OidcProviderError
+        })?;
+
+        let login = OidcLoginState {
+            code_verifier: secrets.code_verifier,
+            nonce: secrets.nonce,
+            link_user_id,
+        };
+        self.repo
+            .create_oidc_login(&secrets.state, &login, oidc.config().login_ttl_secs)
+            .await?;
+
+        Ok(url)
+    }
+
+    async fn complete_oidc_login(
+        &self,
+        query: OidcCallbackQuery,
+    ) -> Result<OidcCallbackOutcome, AuthError> {
+        let oidc = self.oidc()?;
+
+        // The state is consumed first, so a callback URL works once even if it fails
+        let login = match &query.state {
+            Some(state) => self.repo.take_oidc_login(state).await?,
+            None => None,
+        };
+        let Some(login) = login else {
+            println!("[security] OIDC callback with unknown or expired state");
+            return Err(AuthError::OidcLoginFailed);
+        };
+
+        if let Some(error) = &query.error {
+            println!(
+                "OIDC provider returned an error: {error} {}",
+                query.error_description.as_deref().unwrap_or_default()
+            );
+            return Err(AuthError::OidcLoginFailed);
+        }
+        let code = query.code.as_deref().ok_or(AuthError::OidcLoginFailed)?;
+
+        let claims = oidc
+            .exchange_code(code, &login.code_verifier, &login.nonce)
+            .await
+            .map_err(|e| {
+                println!("[security] OIDC login rejected: {e}");
+                AuthError::OidcLoginFailed
+            })?;
+
+        // A link is only recorded here: the browser that finished it need not
+        // belong to the account that started it, so that account must confirm
+        if let Some(user_id) = login.link_user_id {
+            let link_code = Self::random_token();
+            self.repo
+                .create_pending_link(
+                    &link_code,
+                    user_id,
+                    &claims.iss,
+                    &claims.sub,
+                    claims.email.as_deref(),
+                    oidc.config().login_ttl_secs,
+                )
+                .await?;
+
+            return Ok(OidcCallbackOutcome::LinkPending(OidcLinkPending {
+                link_code,
+                issuer: claims.iss,
+                subject: claims.sub,
+            }));
+        }
+
+        let user = match self
+            .repo
+            .find_identity_user(&claims.iss, &claims.sub)
+            .await?
+        {
+            Some(user) => user,
+            None if oidc.config().auto_create => self.create_identity_user(&claims).await?,
+            None => {
+                println!(
+                    "[security] OIDC login for unlinked identity iss={} sub={}",
+                    claims.iss, claims.sub
+                );
+                return Err(AuthError::OidcNoAccount);
+            }
+        };
+
+        println!("OIDC login: user_id={} sub={}", user.id, claims.sub);
+        Ok(OidcCallbackOutcome::Login(
+            self.finish_login(&user, None).await?,
+        ))
+    }
+
+    async fn confirm_oidc_link(
+        &self,
+        user_id: u32,
+        link_code: &str,
+    ) -> Result<OidcLinked, AuthError> {
+        let Some(link) = self.repo.take_pending_link(link_code, user_id).await? else {
+            println!("[security] OIDC link confirm with unknown code: user_id={user_id}");
+            return Err(AuthError::InvalidLinkCode);
+        };
+
+        let existing = self
+            .repo
+            .find_identity_user(&link.issuer, &link.subject)
+            .await?;
+
+        match existing {
+            Some(user) if user.id == user_id => {}
+            Some(_) => return Err(AuthError::OidcIdentityTaken),
+            None => self
+                .repo
+                .link_identity(user_id, &link.issuer, &link.subject, link.email.as_deref())
+                .await
+                .map_err(|e| match e {
+                    sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                        AuthError::OidcIdentityTaken
+                    }
+                    e => e.into(),
+                })?,
+        }
+
+        println!(
+            "[security] OIDC identity linked: user_id={user_id} iss={} sub={}",
+            link.issuer, link.subject
+        );
+        Ok(OidcLinked {
+            linked: true,
+            issuer: link.issuer,
+            subject: link.subject,
+        })
+    }
+
+    async fn create_api_key(
+        &self,
+        user_id: u32,
+        req: CreateApiKeyRequest,
+    ) -> Result<ApiKeyCreated, AuthError> {
+        let name = req.name.trim();
+        if name.is_empty() || name.chars().count() > 64 {
+            return Err(AuthError::Invalid(
+                "API key name must be 1 to 64 characters".into(),
+            ));
+        }
+
+        let scopes = Scopes::parse(&req.scopes).map_err(AuthError::Invalid)?;
+        if scopes.is_empty() {
+            return Err(AuthError::Invalid(
+                "An API key needs at least one scope".into(),
+            ));
+        }
+        if scopes.contains(Scope::Account) || scopes.contains(Scope::Admin) {
+            return Err(AuthError::Invalid(
+                "API keys cannot be granted the admin or account scope".into(),
+            ));
+        }
+
+        let ttl_secs = match req.expires_in_days {
+            Some(0) => {
+                return Err(AuthError::Invalid(
+                    "expires_in_days must be positive".into(),
+                ));
+            }
+            Some(days) => Some(days as i64 * 24 * 3600),
+            None => None,
+        };
+
+        let active = self.repo.count_active_api_keys(user_id).await?;
+        if active >= MAX_API_KEYS {
+            return Err(AuthError::Invalid(format!(
+                "Too many API keys (at most {MAX_API_KEYS}); revoke one first"
+            )));
+        }
+
+        let key = format!("{API_KEY_PREFIX}{}", Self::random_token());
+        let prefix: String = key.chars().take(API_KEY_PREFIX.len() + 8).collect();
+
+        let (id, expires_at) = self
+            .repo
+            .create_api_key(
+                user_id,
+                &NewApiKey {
+                    name,
+                    key: &key,
+                    prefix: &prefix,
+                    scopes: &scopes,
+                    tag: req.tag.as_deref(),
+                    ttl_secs,
+                },
+            )
+            .await?;
+
+        println!(
+            "[security] API key created: user_id={user_id} key_id={id} scopes={} tag={}",
+            scopes.to_db(),
+            req.tag.as_deref().unwrap_or("-")
+        );
+
+        Ok(ApiKeyCreated {
+            id,
+            name: name.to_string(),
+            key,
+            scopes: scopes.names(),
+            tag: req.tag,
+            expires_at,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::api::error::ApiError;
+    use crate::api::test_state;
+
+    const PASSWORD: &str = "correct horse battery";
+
+    fn fresh_login() -> Credential {
+        Credential::Session {
+            auth_time: now_secs() - 10,
+            session_id: "s".into(),
+        }
+    }
+
+    fn old_login() -> Credential {
+        Credential::Session {
+            auth_time: now_secs() - REAUTH_MAX_AGE_SECS - 10,
+            session_id: "s".into(),
+        }
+    }
+
+    /// A service with an account that has a password and one created by single
+    /// sign-on; both have 2FA with the returned recovery codes
+    async fn service_with_accounts() -> (SimpleAuthService, u32, u32, Vec<String>) {
+        let auth = test_state().await.auth;
+        let hash = auth.passwords.hash(PASSWORD).await.unwrap();
+        let with_password = auth.repo.create("alice".into(), hash, None).await.unwrap();
+        let sso = auth
+            .repo
+            .create_identity_user("bob", "random", "https://idp", "bob-sub", None)
+            .await
+            .unwrap();
+
+        let codes = totp::generate_recovery_codes();
+        for user_id in [with_password.id, sso.id] {
+            auth.repo
+                .set_pending_totp(user_id, &totp::generate_secret())
+                .await
+                .unwrap();
+            let hashes: Vec<String> = codes.iter().map(|c| totp::hash_recovery_code(c)).collect();
+            auth.repo.enable_totp(user_id, 0, &hashes).await.unwrap();
+        }
+        (auth, with_password.id, sso.id, codes)
+    }
+
+    #[tokio::test]
+    async fn password_account_confirms_with_its_password() {
+        let (auth, alice, _, _) = service_with_accounts().await;
+
+        auth.confirm_account_holder(alice, Some(PASSWORD), None, &old_login())
+            .await
+            .unwrap();
+        let err = auth
+            .confirm_account_holder(alice, Some("wrong password"), None, &fresh_login())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AuthError::InvalidCredentials), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn password_account_needs_the_password_despite_a_recent_login() {
+        let (auth, alice, _, _) = service_with_accounts().await;
+
+        let err = auth
+            .confirm_account_holder(alice, None, None, &fresh_login())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, AuthError::ReauthenticationRequired),
+            "{err:?}"
+        );
+        let err = ApiError::from(err);
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+        assert_eq!(err.code(), "reauthentication_required");
+    }
+
+    #[tokio::test]
+    async fn password_account_needs_the_password_despite_a_2fa_code() {
+        let (auth, alice, _, codes) = service_with_accounts().await;
+
+        let err = auth
+            .confirm_account_holder(alice, None, Some(&codes[0]), &fresh_login())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, AuthError::ReauthenticationRequired),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn sso_account_confirms_with_a_2fa_code() {
+        let (auth, _, bob, codes) = service_with_accounts().await;
+
+        auth.confirm_account_holder(bob, None, Some(&codes[0]), &old_login())
+            .await
+            .unwrap();
+        let err = auth
+            .confirm_account_holder(bob, None, Some("aaaaa-bbbbb"), &fresh_login())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AuthError::InvalidTwoFactorCode), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn sso_account_confirms_with_a_recent_login() {
+        let (auth, _, bob, _) = service_with_accounts().await;
+
+        auth.confirm_account_holder(bob, None, None, &fresh_login())
+            .await
+            .unwrap();
+        for credential in [old_login(), Credential::ApiKey { id: 1 }] {
+            let err = auth
+                .confirm_account_holder(bob, None, None, &credential)
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(err, AuthError::ReauthenticationRequired),
+                "{err:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn sso_account_password_proves_nothing() {
+        let (auth, _, bob, _) = service_with_accounts().await;
+
+        let err = auth
+            .confirm_account_holder(bob, Some("random"), None, &old_login())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, AuthError::ReauthenticationRequired),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn replayed_refresh_token_is_refused_as_reuse() {
+        let (auth, alice, _, _) = service_with_accounts().await;
+        let tokens = auth
+            .issue_tokens(alice, "alice", &Scopes::session(false))
+            .await
+            .unwrap();
+
+        let rotated = auth
+            .refresh(tokens.refresh_token.clone(), None)
+            .await
+            .unwrap();
+        let err = auth.refresh(tokens.refresh_token, None).await.unwrap_err();
+        assert!(
+            matches!(err, AuthError::RefreshTokenReused { user_id } if user_id == alice),
+            "{err:?}"
+        );
+        let err = ApiError::from(err);
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(err.code(), "refresh_token_reused");
+
+        // The token handed out by the first refresh went with its family
+        let err = auth.refresh(rotated.refresh_token, None).await.unwrap_err();
+        assert!(matches!(err, AuthError::InvalidRefreshToken), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn setting_a_password_makes_it_required() {
+        let (auth, _, bob, _) = service_with_accounts().await;
+        let hash = auth.passwords.hash(PASSWORD).await.unwrap();
+        auth.repo.update_password(bob, &hash).await.unwrap();
+
+        let err = auth
+            .confirm_account_holder(bob, None, None, &fresh_login())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, AuthError::ReauthenticationRequired),
+            "{err:?}"
+        );
+        auth.confirm_account_holder(bob, Some(PASSWORD), None, &old_login())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,298 @@
+use std::net::IpAddr;
+
+use sqlx::{Row, SqlitePool};
+
+use crate::auth::username::canonicalize;
+use crate::config::ThrottleConfig;
+
+/// Tracks failed login attempts per username and per client IP, stored in SQLite.
+///
+/// After `max_failures` failures within the window, the key is locked out. Each further
+/// failure doubles the lockout, up to `max_lockout_secs`. Lockouts are checked before the
+/// password is verified, so a locked account costs no Argon2 work.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    pool: SqlitePool,
+    cfg: ThrottleConfig,
+}
+
+/// Throttle key for a username
+pub fn user_key(username: &str) -> String {
+    format!("user:{}", canonicalize(username))
+}
+
+/// Throttle key for a client IP
+pub fn ip_key(ip: IpAddr) -> String {
+    format!("ip:{ip}")
+}
+
+impl LoginThrottle {
+    pub fn new(pool: SqlitePool, cfg: ThrottleConfig) -> Self {
+        Self { pool, cfg }
+    }
+
+    /// Seconds until the longest active lockout among `keys` ends, or `None` if none is locked
+    pub async fn retry_after(&self, keys: &[String]) -> Result<Option<u64>, sqlx::Error> {
+        let mut longest: Option<u64> = None;
+
+        for key in keys {
+            let row = sqlx::query(
+                r#"
+                SELECT locked_until - CAST(strftime('%s', 'now') AS INTEGER) AS remaining
+                FROM login_attempts
+                WHERE key = ?1 AND locked_until > strftime('%s', 'now')
+                "#,
+            )
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+
+            if let Some(row) = row {
+                let remaining = row.get::<i64, _>("remaining").max(1) as u64;
+                longest = Some(longest.map_or(remaining, |l| l.max(remaining)));
+            }
+        }
+
+        Ok(longest)
+    }
+
+    /// Record a failed attempt for each key, locking out keys over their threshold
+    pub async fn record_failure(&self, keys: &[String]) -> Result<(), sqlx::Error> {
+        for key in keys {
+            let threshold = if key.starts_with("ip:") {
+                self.cfg.ip_max_failures
+            } else {
+                self.cfg.max_failures
+            };
+
+            let mut tx = self.pool.begin().await?;
+
+            // Failures older than the window no longer count
+            let failures: i64 = sqlx::query(
+                r#"
+                INSERT INTO login_attempts (key, failures, last_failure_at, locked_until)
+                VALUES (?1, 1, strftime('%s', 'now'), NULL)
+                ON CONFLICT(key) DO UPDATE
+                    SET failures = CASE
+                            WHEN last_failure_at < strftime('%s', 'now') - ?2 THEN 1
+                            ELSE failures + 1
+                        END,
+                        last_failure_at = strftime('%s', 'now')
+                RETURNING failures
+                "#,
+            )
+            .bind(key)
+            .bind(self.cfg.window_secs as i64)
+            .fetch_one(&mut *tx)
+            .await?
+            .get("failures");
+
+            if failures >= threshold as i64 {
+                let lockout = self.lockout_secs((failures - threshold as i64) as u32);
+                sqlx::query(
+                    r#"
+                    UPDATE login_attempts
+                    SET locked_until = strftime('%s', 'now') + ?2
+                    WHERE key = ?1
+                    "#,
+                )
+                .bind(key)
+                .bind(lockout as i64)
+                .execute(&mut *tx)
+                .await?;
+
+                println!(
+                    "[security] login lockout for {key}: {lockout}s after {failures} failures"
+                );
+            }
+
+            tx.commit().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Forget failures for a key (after a successful login or an admin unlock).
+    /// Returns `true` if the key had any recorded failures.
+    pub async fn clear(&self, key: &str) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query("DELETE FROM login_attempts WHERE key = ?1")
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Delete rows whose failures fell out of the window and that are not locked
+    pub async fn delete_stale(&self) -> Result<u64, sqlx::Error> {
+        let res = sqlx::query(
+            r#"
+            DELETE FROM login_attempts
+            WHERE last_failure_at < strftime('%s', 'now') - ?1
+                AND (locked_until IS NULL OR locked_until <= strftime('%s', 'now'))
+            "#,
+        )
+        .bind(self.cfg.window_secs as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Exponential backoff: base * 2^excess, capped
+    fn lockout_secs(&self, excess: u32) -> u64 {
+        self.cfg
+            .base_lockout_secs
+            .saturating_mul(1u64 << excess.min(32))
+            .min(self.cfg.max_lockout_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_state;
+
+    const WINDOW: u64 = 900;
+
+    /// Throttle with 3 failures per user, 5 per IP, a 60 s first lockout capped at 200 s
+    async fn throttle() -> LoginThrottle {
+        let pool = test_state().await.db;
+        LoginThrottle::new(
+            pool,
+            ThrottleConfig {
+                max_failures: 3,
+                ip_max_failures: 5,
+                window_secs: WINDOW,
+                base_lockout_secs: 60,
+                max_lockout_secs: 200,
+            },
+        )
+    }
+
+    async fn fail(throttle: &LoginThrottle, keys: &[String], times: u32) {
+        for _ in 0..times {
+            throttle.record_failure(keys).await.unwrap();
+        }
+    }
+
+    /// Move a key's last failure `secs` into the past
+    async fn age(throttle: &LoginThrottle, key: &str, secs: i64) {
+        sqlx::query(
+            "UPDATE login_attempts SET last_failure_at = last_failure_at - ?2 WHERE key = ?1",
+        )
+        .bind(key)
+        .bind(secs)
+        .execute(&throttle.pool)
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn keys_are_canonical_and_distinct() {
+        assert_eq!(user_key(" Alice "), user_key("alice"));
+        assert_eq!(ip_key("10.0.0.1".parse().unwrap()), "ip:10.0.0.1");
+        assert_ne!(user_key("10.0.0.1"), ip_key("10.0.0.1".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn locks_out_at_the_threshold() {
+        let throttle = throttle().await;
+        let keys = [user_key("alice")];
+
+        fail(&throttle, &keys, 2).await;
+        assert_eq!(throttle.retry_after(&keys).await.unwrap(), None);
+
+        fail(&throttle, &keys, 1).await;
+        let retry = throttle.retry_after(&keys).await.unwrap().unwrap();
+        assert!((59..=60).contains(&retry), "retry after {retry}");
+    }
+
+    #[tokio::test]
+    async fn lockout_doubles_up_to_the_cap() {
+        let throttle = throttle().await;
+        let keys = [user_key("alice")];
+
+        fail(&throttle, &keys, 2).await;
+        for expected in [60, 120, 200, 200] {
+            fail(&throttle, &keys, 1).await;
+            let retry = throttle.retry_after(&keys).await.unwrap().unwrap();
+            assert!(
+                (expected - 1..=expected).contains(&retry),
+                "{retry} for {expected}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn user_and_ip_keys_count_separately() {
+        let ip = ip_key("203.0.113.9".parse().unwrap());
+        let alice = user_key("alice");
+        let bob = user_key("bob");
+        let throttle = throttle().await;
+        let locked = async |key: &String| {
+            throttle
+                .retry_after(std::slice::from_ref(key))
+                .await
+                .unwrap()
+                .is_some()
+        };
+
+        // Three failures lock alice but not the IP, which allows five
+        fail(&throttle, &[alice.clone(), ip.clone()], 3).await;
+        assert!(locked(&alice).await);
+        assert!(!locked(&ip).await);
+        assert!(!locked(&bob).await);
+
+        // Guessing other usernames from the same IP locks the IP
+        fail(&throttle, &[bob.clone(), ip.clone()], 2).await;
+        assert!(locked(&ip).await);
+        assert!(!locked(&bob).await);
+        assert_eq!(
+            throttle.retry_after(&[bob, ip.clone()]).await.unwrap(),
+            throttle.retry_after(&[ip]).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn failures_outside_the_window_are_forgotten() {
+        let throttle = throttle().await;
+        let keys = [user_key("alice")];
+
+        fail(&throttle, &keys, 2).await;
+        age(&throttle, &keys[0], WINDOW as i64 + 1).await;
+        fail(&throttle, &keys, 2).await;
+        assert_eq!(throttle.retry_after(&keys).await.unwrap(), None);
+
+        age(&throttle, &keys[0], WINDOW as i64 + 1).await;
+        assert_eq!(throttle.delete_stale().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn stale_rows_keep_an_active_lockout() {
+        let throttle = throttle().await;
+        let keys = [user_key("alice")];
+
+        fail(&throttle, &keys, 3).await;
+        age(&throttle, &keys[0], WINDOW as i64 + 1).await;
+        assert_eq!(throttle.delete_stale().await.unwrap(), 0);
+        assert!(throttle.retry_after(&keys).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn success_resets_the_count() {
+        let throttle = throttle().await;
+        let keys = [user_key("alice")];
+
+        fail(&throttle, &keys, 2).await;
+        assert!(throttle.clear(&keys[0]).await.unwrap());
+        fail(&throttle, &keys, 2).await;
+        assert_eq!(throttle.retry_after(&keys).await.unwrap(), None);
+
+        fail(&throttle, &keys, 1).await;
+        assert!(throttle.retry_after(&keys).await.unwrap().is_some());
+        assert!(throttle.clear(&keys[0]).await.unwrap());
+        assert_eq!(throttle.retry_after(&keys).await.unwrap(), None);
+        assert!(!throttle.clear(&keys[0]).await.unwrap());
+    }
+}
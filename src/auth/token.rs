@@ -1,12 +1,27 @@
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode, errors::Error};
+use jsonwebtoken::{
+    Algorithm, Header, Validation, decode, decode_header, encode,
+    errors::{Error, ErrorKind},
+};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
+use crate::auth::keys::JwtKeys;
+use crate::auth::scope::Scopes;
+use crate::config::AuthConfig;
+
 /// sub = user id
 /// exp = expiration timestamp
 /// iat = issued-at timestamp
 /// jti = unique token id
+/// iss = issuer, aud = audience (both checked on verify)
+/// scope = space-separated scopes granted to the token; required, so tokens
+///         without it fail verification
+/// auth_time = when the user logged in to start this session; refreshes keep it,
+///             and tokens from before it existed read as 0
+/// sid = id of the login session; refreshes keep it, so a revoked session can be
+///       told apart. Empty in challenge tokens and tokens from before it existed
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -15,29 +30,119 @@ pub struct Claims {
     pub exp: usize,
     pub iat: usize,
     pub jti: String,
+    pub iss: String,
+    pub aud: String,
+    pub scope: String,
+    #[serde(default)]
+    pub auth_time: usize,
+    #[serde(default)]
+    pub sid: String,
+}
+
+impl Claims {
+    /// Scopes granted by this token
+    pub fn scopes(&self) -> Scopes {
+        Scopes::from_db(&self.scope)
+    }
 }
 
-const ACCESS_TOKEN_TTL_SECS: u64 = 3600;
+/// A freshly signed access token
+pub struct AccessToken {
+    pub token: String,
+    /// The `exp` claim, as a unix timestamp
+    pub expires_at: u64,
+}
+
+/// Lifetime of a two-factor login challenge
+pub const CHALLENGE_TTL_SECS: u64 = 300;
 
-fn now_secs() -> u64 {
+pub fn now_secs() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("system time before unix epoch")
         .as_secs()
 }
 
-/// Load JWT signing secret from the `JWT_SECRET` environment variable
-/// Falls back to a dev default
-fn jwt_secret_bytes() -> Vec<u8> {
-    std::env::var("JWT_SECRET")
-        .unwrap_or_else(|_| "dev-secret-change-me".to_string())
-        .into_bytes()
+/// Create a JWT for a given user id and scopes, signed with the active key.
+/// `auth_time` is when the session's login happened, `session_id` which session it is.
+pub fn create_token(
+    keys: &JwtKeys,
+    cfg: &AuthConfig,
+    user_id: u32,
+    username: &str,
+    scopes: &Scopes,
+    auth_time: u64,
+    session_id: &str,
+) -> Result<AccessToken, Error> {
+    sign(
+        keys,
+        cfg,
+        &cfg.audience,
+        cfg.access_token_ttl_secs,
+        user_id,
+        username,
+        scopes,
+        auth_time,
+        session_id,
+    )
+}
+
+/// Verify a JWT against the key named by its `kid` header and return its claims.
+/// Issuer and audience must match the configuration.
+pub fn verify_token(keys: &JwtKeys, cfg: &AuthConfig, token: &str) -> Result<Claims, Error> {
+    verify(keys, cfg, &cfg.audience, token)
+}
+
+/// Create a short-lived token proving the password step of a 2FA login succeeded.
+/// It carries its own audience, so it is never accepted as an access token,
+/// and the scopes the finished login will be granted.
+pub fn create_challenge_token(
+    keys: &JwtKeys,
+    cfg: &AuthConfig,
+    user_id: u32,
+    username: &str,
+    scopes: &Scopes,
+) -> Result<AccessToken, Error> {
+    sign(
+        keys,
+        cfg,
+        &challenge_audience(cfg),
+        CHALLENGE_TTL_SECS,
+        user_id,
+        username,
+        scopes,
+        now_secs(),
+        "",
+    )
+}
+
+/// Verify a 2FA challenge token and return its claims
+pub fn verify_challenge_token(
+    keys: &JwtKeys,
+    cfg: &AuthConfig,
+    token: &str,
+) -> Result<Claims, Error> {
+    verify(keys, cfg, &challenge_audience(cfg), token)
+}
+
+fn challenge_audience(cfg: &AuthConfig) -> String {
+    format!("{}#2fa", cfg.audience)
 }
 
-/// Create a JWT for a given user id
-pub fn create_token(user_id: u32, username: &str) -> Result<String, Error> {
+#[allow(clippy::too_many_arguments)]
+fn sign(
+    keys: &JwtKeys,
+    cfg: &AuthConfig,
+    audience: &str,
+    ttl_secs: u64,
+    user_id: u32,
+    username: &str,
+    scopes: &Scopes,
+    auth_time: u64,
+    session_id: &str,
+) -> Result<AccessToken, Error> {
     let now = now_secs();
-    let expiration = now + ACCESS_TOKEN_TTL_SECS;
+    let expiration = now + ttl_secs;
 
     let claims = Claims {
         sub: user_id,
@@ -45,26 +150,47 @@ pub fn create_token(user_id: u32, username: &str) -> Result<String, Error> {
         exp: expiration as usize,
         iat: now as usize,
         jti: Uuid::new_v4().to_string(),
+        iss: cfg.issuer.clone(),
+        aud: audience.to_string(),
+        scope: scopes.to_db(),
+        auth_time: auth_time as usize,
+        sid: session_id.to_string(),
     };
 
-    let secret = jwt_secret_bytes();
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(keys.active_kid().to_string());
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(&secret),
-    )
+    let token = encode(&header, &claims, keys.encoding_key())?;
+
+    Ok(AccessToken {
+        token,
+        expires_at: expiration,
+    })
 }
 
-/// Verify a JWT and return its claims
-pub fn verify_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-    let secret = jwt_secret_bytes();
+fn verify(keys: &JwtKeys, cfg: &AuthConfig, audience: &str, token: &str) -> Result<Claims, Error> {
+    let header = decode_header(token)?;
+    let key = header
+        .kid
+        .as_deref()
+        .and_then(|kid| keys.decoding_key(kid))
+        .ok_or(ErrorKind::InvalidSignature)?;
 
-    let data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(&secret),
-        &Validation::default(),
-    )?;
+    let mut validation = Validation::new(Algorithm::EdDSA);
+    validation.set_issuer(&[&cfg.issuer]);
+    validation.set_audience(&[audience]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+    validation.leeway = cfg.leeway_secs;
+
+    let data = decode::<Claims>(token, key, &validation)?;
 
     Ok(data.claims)
 }
+
+/// Hash an opaque token (refresh or password reset) for storage.
+///
+/// Only the SHA-256 digest is persisted, so a leaked database
+/// does not hand out live sessions.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
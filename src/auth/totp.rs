@@ -0,0 +1,114 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rand_core::OsRng;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// TOTP time step in seconds (RFC 6238 default)
+const STEP_SECS: u64 = 30;
+
+/// Number of digits in a code
+const DIGITS: u32 = 6;
+
+/// Accept codes from this many steps before/after the current one (clock drift)
+const WINDOW: u64 = 1;
+
+/// Number of recovery codes issued when 2FA is confirmed
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Generate a new random shared secret, base32-encoded (160 bits, as recommended by RFC 4226)
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    OsRng.fill_bytes(&mut bytes);
+    BASE32_NOPAD.encode(&bytes)
+}
+
+/// Build the `otpauth://` URI that authenticator apps import (usually as a QR code)
+pub fn otpauth_uri(issuer: &str, username: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(username),
+        secret,
+        percent_encode(issuer),
+        DIGITS,
+        STEP_SECS
+    )
+}
+
+/// Check a 6-digit code against the secret at time `now`.
+///
+/// Returns the matched time step, which must be newer than `last_used_step`
+/// so a code cannot be replayed.
+pub fn verify_code(secret: &str, code: &str, now: u64, last_used_step: Option<u64>) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+
+    let current = now / STEP_SECS;
+    (current.saturating_sub(WINDOW)..=current + WINDOW)
+        .filter(|step| last_used_step.is_none_or(|last| *step > last))
+        .find(|step| hotp(&key, *step) == code)
+}
+
+/// Whether the input looks like a TOTP code rather than a recovery code
+pub fn is_totp_code(input: &str) -> bool {
+    let input = input.trim();
+    input.len() == DIGITS as usize && input.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Generate fresh single-use recovery codes, formatted as `xxxxx-xxxxx`
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 7];
+            OsRng.fill_bytes(&mut bytes);
+            let raw = BASE32_NOPAD.encode(&bytes).to_ascii_lowercase();
+            format!("{}-{}", &raw[..5], &raw[5..10])
+        })
+        .collect()
+}
+
+/// Hash a recovery code for storage. Dashes, spaces and case are ignored.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+/// HOTP (RFC 4226) value for a counter
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let bin = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    bin % 10u32.pow(DIGITS)
+}
+
+/// Percent-encode everything except RFC 3986 unreserved characters
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
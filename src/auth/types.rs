@@ -1,33 +1,294 @@
 use serde::{Deserialize, Serialize};
 
+use crate::auth::scope::{Scope, Scopes};
+use crate::auth::token::now_secs;
+
 /// Request body for `POST /register`.
 ///
 /// Contains the user's chosen username and plaintext password.
-/// The password will be hashed before storage.
+/// The password will be hashed before storage. If an email address is given,
+/// a verification token is mailed to it.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RegisterRequest {
     pub username: String,
     pub password: String,
+    #[serde(default)]
+    pub email: Option<String>,
 }
 
 /// Request body for `POST /login`.
 ///
-/// Contains the user's username and plaintext password.
+/// Contains the user's username and plaintext password, and optionally the
+/// space-separated scopes to limit the session to (default: all the user may hold).
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoginRequest {
     pub username: String,
     pub password: String,
+    #[serde(default)]
+    pub scope: Option<String>,
+}
+
+/// Query of `GET /token/refresh`: an optional subset of the session's scopes
+/// for the new access token
+#[derive(Debug, Deserialize)]
+pub struct RefreshQuery {
+    #[serde(default)]
+    pub scope: Option<String>,
 }
 
 /// Response body returned after successful authentication.
 ///
 /// The access token is used for authenticated requests.
-/// `expires_in` represents the token lifetime in seconds.
+/// `expires_in` represents the token lifetime in seconds, and `scope`
+/// the space-separated scopes of the access token.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthTokenResponse {
+    /// User the tokens were issued to; not part of the response body
+    #[serde(skip)]
+    pub user_id: u32,
     pub access_token: String,
     pub refresh_token: String,
     pub expires_in: u64,
+    pub scope: String,
+}
+
+/// Response body for `POST /login`.
+///
+/// Accounts without two-factor authentication get tokens straight away.
+/// Accounts with 2FA enabled get a short-lived challenge that must be
+/// completed at `POST /login/2fa`.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Tokens(AuthTokenResponse),
+    TwoFactorRequired(TwoFactorChallenge),
+}
+
+/// Second login step required for accounts with 2FA enabled.
+#[derive(Debug, Serialize)]
+pub struct TwoFactorChallenge {
+    pub two_factor_required: bool,
+    pub challenge_token: String,
+    pub expires_in: u64,
+}
+
+/// Request body for `POST /login/2fa`.
+///
+/// `code` is either the current TOTP code or an unused recovery code.
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorLoginRequest {
+    pub challenge_token: String,
+    pub code: String,
+}
+
+/// Response body for `POST /me/2fa/enroll`.
+///
+/// The secret is not active until confirmed with a valid code.
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+/// Request body for `POST /me/2fa/confirm`.
+#[derive(Debug, Deserialize)]
+pub struct TotpConfirmRequest {
+    pub code: String,
+}
+
+/// Response body for `POST /me/2fa/confirm`.
+///
+/// Recovery codes are shown only once; the server keeps only their hashes.
+#[derive(Debug, Serialize)]
+pub struct TotpConfirmResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Request body for `POST /me/2fa/disable`.
+///
+/// Requires both the account password and a current code.
+#[derive(Debug, Deserialize)]
+pub struct TotpDisableRequest {
+    pub password: String,
+    pub code: String,
+}
+
+/// Request body for `POST /me/password`.
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// Request body for `POST /password/forgot`.
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub username: String,
+}
+
+/// Request body for `POST /password/reset`.
+///
+/// `token` comes from the reset mail or from an admin.
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+/// Response body for `POST /admin/users/:username/password-reset`.
+///
+/// The admin hands the token to the user out of band.
+#[derive(Debug, Serialize)]
+pub struct PasswordResetIssued {
+    pub reset_token: String,
+    pub expires_in: i64,
+}
+
+/// Request body for `PUT /me/email`.
+///
+/// `null` removes the address.
+#[derive(Debug, Deserialize)]
+pub struct SetEmailRequest {
+    pub email: Option<String>,
+}
+
+/// Request body for `POST /email/verify`.
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+/// Query of `GET /oidc/callback`, as sent by the provider
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub state: Option<String>,
+    pub code: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// A pending OIDC login, between `/oidc/login` and the callback
+#[derive(Debug, Clone)]
+pub struct OidcLoginState {
+    pub code_verifier: String,
+    pub nonce: String,
+    /// Set when the flow links an identity to an already logged-in user
+    pub link_user_id: Option<u32>,
+}
+
+/// Response of `POST /me/oidc/link`
+#[derive(Debug, Serialize)]
+pub struct OidcLinkStart {
+    pub authorization_url: String,
+}
+
+/// Result of `GET /oidc/callback`: a login (tokens or 2FA challenge), or a
+/// verified identity waiting for `POST /me/oidc/link/confirm`
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum OidcCallbackOutcome {
+    Login(LoginResponse),
+    LinkPending(OidcLinkPending),
+}
+
+/// An identity verified by a link callback. Nothing is linked until the
+/// account that started the link sends `link_code` with its own token.
+#[derive(Debug, Serialize)]
+pub struct OidcLinkPending {
+    pub link_code: String,
+    pub issuer: String,
+    pub subject: String,
+}
+
+/// Request body for `POST /me/oidc/link/confirm`
+#[derive(Debug, Deserialize)]
+pub struct OidcLinkConfirmRequest {
+    pub link_code: String,
+}
+
+/// A verified identity between the link callback and its confirmation
+#[derive(Debug, Clone)]
+pub struct PendingLink {
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+}
+
+/// Response of `POST /me/oidc/link/confirm`
+#[derive(Debug, Serialize)]
+pub struct OidcLinked {
+    pub linked: bool,
+    pub issuer: String,
+    pub subject: String,
+}
+
+/// Request body for `POST /me/api-keys`
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    /// Scope names, e.g. `["files:read"]`
+    pub scopes: Vec<String>,
+    /// Omit for a key that does not expire
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
+    /// Limit the key to files with this tag; omit for all files
+    #[serde(default)]
+    pub tag: Option<String>,
+}
+
+/// Response of `POST /me/api-keys`. `key` is shown only here.
+#[derive(Debug, Serialize)]
+pub struct ApiKeyCreated {
+    pub id: u32,
+    pub name: String,
+    pub key: String,
+    pub scopes: Vec<&'static str>,
+    pub tag: Option<String>,
+    pub expires_at: Option<i64>,
+}
+
+/// An API key as listed by `GET /me/api-keys` (never includes the key itself)
+#[derive(Debug, Serialize)]
+pub struct ApiKeyInfo {
+    pub id: u32,
+    pub name: String,
+    /// First characters of the key, to tell keys apart
+    pub prefix: String,
+    pub scopes: Vec<&'static str>,
+    /// Tag the key is limited to
+    pub tag: Option<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+    pub last_used_ip: Option<String>,
+    pub revoked_at: Option<i64>,
+}
+
+/// A validated API key about to be stored
+#[derive(Debug)]
+pub struct NewApiKey<'a> {
+    pub name: &'a str,
+    pub key: &'a str,
+    pub prefix: &'a str,
+    pub scopes: &'a Scopes,
+    pub tag: Option<&'a str>,
+    /// `None` for a key that does not expire
+    pub ttl_secs: Option<i64>,
+}
+
+/// A user's email address and its verification state
+#[derive(Debug, Clone)]
+pub struct UserEmail {
+    pub address: String,
+    pub verified: bool,
+}
+
+/// Stored TOTP state for a user
+#[derive(Debug, Clone)]
+pub struct TotpRecord {
+    pub secret: String,
+    pub enabled: bool,
+    pub last_used_step: Option<u64>,
 }
 
 /// Internal representation of an authenticated user
@@ -40,3 +301,48 @@ pub struct AuthUser {
     pub username: String,
     pub password_hash: String,
 }
+
+/// Credential that authenticated the current request, set by `auth_middleware`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credential {
+    /// A JWT access token; `auth_time` is when its session's login happened (0 if unknown)
+    /// and `session_id` which session it belongs to (empty if unknown)
+    Session { auth_time: u64, session_id: String },
+    /// A personal API key
+    ApiKey { id: u32 },
+}
+
+impl Credential {
+    /// Whether the user logged in to get this credential within the last `max_age_secs`.
+    /// API keys never count.
+    pub fn logged_in_within(&self, max_age_secs: u64) -> bool {
+        match self {
+            Credential::Session { auth_time, .. } => {
+                *auth_time > 0 && now_secs().saturating_sub(*auth_time) <= max_age_secs
+            }
+            Credential::ApiKey { .. } => false,
+        }
+    }
+}
+
+/// Result of presenting a refresh token for rotation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefreshRotation {
+    /// Token was valid and has been replaced by the new token, which keeps its scopes,
+    /// its session id and the `auth_time` of the login that started the session
+    Rotated {
+        user_id: u32,
+        scopes: Scopes,
+        auth_time: u64,
+        session_id: String,
+    },
+    /// Token was already rotated and has been presented again.
+    /// The whole token family has been revoked.
+    Reused { user_id: u32 },
+    /// Token passed its idle timeout or its family's absolute lifetime
+    Expired,
+    /// Token is unknown or was revoked without a successor
+    Invalid,
+    /// A scope was requested that the session does not hold; the token is unchanged
+    ScopeNotGranted(Scope),
+}
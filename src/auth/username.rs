@@ -0,0 +1,165 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Minimum username length, in characters
+pub const MIN_LEN: usize = 3;
+
+/// Maximum username length, in characters
+pub const MAX_LEN: usize = 32;
+
+/// Canonical form of a username: NFKC-normalized, trimmed and lowercased.
+///
+/// Used for every lookup, so `"Alice "` and `"ａｌｉｃｅ"` (fullwidth) find the
+/// account `alice`. It does not check the charset; see [`validate`].
+pub fn canonicalize(input: &str) -> String {
+    input.nfkc().collect::<String>().trim().to_lowercase()
+}
+
+/// Canonicalize a new username and check it against the naming rules:
+/// 3-32 characters from `a-z`, `0-9`, `.`, `_` and `-`, starting with a letter or digit.
+pub fn validate(input: &str) -> Result<String, String> {
+    let name = canonicalize(input);
+
+    if name.is_empty() {
+        return Err("Username cannot be empty".into());
+    }
+
+    let len = name.chars().count();
+    if !(MIN_LEN..=MAX_LEN).contains(&len) {
+        return Err(format!(
+            "Username must be {MIN_LEN} to {MAX_LEN} characters"
+        ));
+    }
+
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-'))
+    {
+        return Err("Username may only contain letters, digits, '.', '_' and '-'".into());
+    }
+
+    if !name.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err("Username must start with a letter or digit".into());
+    }
+
+    Ok(name)
+}
+
+/// Turn an arbitrary hint (an SSO `preferred_username` or email local part)
+/// into a name that passes [`validate`], leaving room for a numeric suffix.
+/// Returns `None` if nothing usable is left.
+pub fn derive(hint: &str) -> Option<String> {
+    let cleaned: String = canonicalize(hint)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .skip_while(|c| !c.is_ascii_alphanumeric())
+        .take(MAX_LEN - 4)
+        .collect();
+
+    validate(&cleaned).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_and_surrounding_space_collide() {
+        for input in ["Alice", "ALICE", " alice", "alice\t", "\u{a0}Alice\u{3000}"] {
+            assert_eq!(canonicalize(input), "alice", "{input:?}");
+        }
+    }
+
+    #[test]
+    fn width_forms_and_compatibility_characters_collide() {
+        let cases = [
+            ("ａｌｉｃｅ", "alice"),    // fullwidth
+            ("ＡＬＩＣＥ", "alice"),    // fullwidth capitals
+            ("\u{1d41a}lice", "alice"), // mathematical bold a
+            ("\u{24d0}lice", "alice"),  // circled a
+            ("\u{fb01}sh", "fish"),     // fi ligature
+            ("\u{17f}am", "sam"),       // long s
+            ("\u{212a}im", "kim"),      // Kelvin sign
+            ("bob\u{2082}", "bob2"),    // subscript two
+            ("ｂｏｂ－１", "bob-1"),    // fullwidth hyphen-minus and digit
+        ];
+        for (input, expected) in cases {
+            assert_eq!(canonicalize(input), expected, "{input:?}");
+            assert_eq!(validate(input).as_deref(), Ok(expected), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn composed_and_decomposed_forms_collide() {
+        assert_eq!(canonicalize("e\u{301}"), canonicalize("\u{e9}"));
+    }
+
+    #[test]
+    fn validate_accepts_the_charset() {
+        for name in [
+            "abc",
+            "a.b",
+            "a_b",
+            "a-b",
+            "0day",
+            "x".repeat(MAX_LEN).as_str(),
+        ] {
+            assert_eq!(validate(name).as_deref(), Ok(name), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn validate_rejects_lookalikes_outside_ascii() {
+        // Cyrillic а and е, Greek ο: no compatibility mapping, so they must not pass
+        for input in [
+            "\u{430}lice",
+            "bob\u{435}",
+            "b\u{3bf}b",
+            "\u{130}van",
+            "café",
+        ] {
+            assert!(validate(input).is_err(), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn validate_rejects_bad_names() {
+        let long = "x".repeat(MAX_LEN + 1);
+        for input in [
+            "",
+            "   ",
+            "ab",
+            long.as_str(),
+            "a b",
+            "a/b",
+            "a@b",
+            "al\u{200b}ice",
+            ".alice",
+            "_alice",
+            "-alice",
+        ] {
+            assert!(validate(input).is_err(), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn length_counts_characters_after_normalization() {
+        // Two fullwidth letters are still too short once normalized
+        assert!(validate("ａｂ").is_err());
+        assert_eq!(validate("ａｂｃ").as_deref(), Ok("abc"));
+    }
+
+    #[test]
+    fn derive_cleans_hints() {
+        assert_eq!(derive("Jane Doe").as_deref(), Some("jane-doe"));
+        assert_eq!(derive("__jane").as_deref(), Some("jane"));
+        assert_eq!(derive("ｊａｎｅ").as_deref(), Some("jane"));
+        assert_eq!(derive("!!").as_deref(), None);
+        assert_eq!(derive(&"a".repeat(64)).map(|n| n.len()), Some(MAX_LEN - 4));
+    }
+}
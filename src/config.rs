@@ -0,0 +1,438 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Server configuration, read from the environment once at startup
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub auth: AuthConfig,
+    pub throttle: ThrottleConfig,
+    pub rate_limit: RateLimitConfig,
+    pub password: PasswordConfig,
+    pub mail: MailConfig,
+    /// OpenID Connect single sign-on; `None` unless `OIDC_ISSUER` is set
+    pub oidc: Option<OidcConfig>,
+    pub audit: AuditConfig,
+    pub webhooks: WebhookConfig,
+    /// `ADMIN_USERNAMES`: comma-separated usernames holding admin rights; set at startup
+    pub admin_usernames: Vec<String>,
+}
+
+/// Token lifetimes and JWT validation settings
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    /// `ACCESS_TOKEN_TTL_SECS`: access token lifetime
+    pub access_token_ttl_secs: u64,
+    /// `REFRESH_TOKEN_IDLE_TTL_SECS`: a refresh token expires if not used within this window
+    pub refresh_token_idle_ttl_secs: i64,
+    /// `REFRESH_TOKEN_ABSOLUTE_TTL_SECS`: a login session can never be refreshed past this
+    pub refresh_token_absolute_ttl_secs: i64,
+    /// `JWT_ISSUER`: `iss` claim emitted and required
+    pub issuer: String,
+    /// `JWT_AUDIENCE`: `aud` claim emitted and required
+    pub audience: String,
+    /// `JWT_LEEWAY_SECS`: allowed clock skew when checking `exp`
+    pub leeway_secs: u64,
+    /// `PASSWORD_RESET_TTL_SECS`: how long a password reset token stays valid
+    pub password_reset_ttl_secs: i64,
+    /// `EMAIL_VERIFICATION_TTL_SECS`: how long an email verification token stays valid
+    pub email_verification_ttl_secs: i64,
+    /// `REQUIRE_VERIFIED_EMAIL`: only users with a verified email may share files or make them public
+    pub require_verified_email: bool,
+}
+
+/// Outgoing mail settings
+#[derive(Debug, Clone)]
+pub struct MailConfig {
+    /// `MAIL_TRANSPORT`: `file` (default), `log` or `smtp`
+    pub transport: MailTransport,
+    /// `MAIL_FROM`: sender address
+    pub from: String,
+    /// `MAIL_OUTBOX_DIR`: where the `file` transport writes messages
+    pub outbox_dir: PathBuf,
+    /// `SMTP_HOST`
+    pub smtp_host: Option<String>,
+    /// `SMTP_PORT`: defaults to the standard port for `SMTP_TLS`
+    pub smtp_port: Option<u16>,
+    /// `SMTP_USERNAME`
+    pub smtp_username: Option<String>,
+    /// `SMTP_PASSWORD`
+    pub smtp_password: Option<String>,
+    /// `SMTP_TLS`: `starttls` (default), `tls` or `none`
+    pub smtp_tls: SmtpTls,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum MailTransport {
+    File,
+    Log,
+    Smtp,
+}
+
+impl FromStr for MailTransport {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "file" => Ok(Self::File),
+            "log" => Ok(Self::Log),
+            "smtp" => Ok(Self::Smtp),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SmtpTls {
+    Starttls,
+    Tls,
+    None,
+}
+
+impl FromStr for SmtpTls {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "starttls" => Ok(Self::Starttls),
+            "tls" => Ok(Self::Tls),
+            "none" => Ok(Self::None),
+            _ => Err(()),
+        }
+    }
+}
+
+/// OpenID Connect provider settings
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    /// `OIDC_ISSUER`: provider issuer URL; discovery is read from `<issuer>/.well-known/openid-configuration`
+    pub issuer: String,
+    /// `OIDC_CLIENT_ID`
+    pub client_id: String,
+    /// `OIDC_CLIENT_SECRET`: omit for public clients (PKCE only)
+    pub client_secret: Option<String>,
+    /// `OIDC_REDIRECT_URI`: must point at `/oidc/callback` and be registered with the provider
+    pub redirect_uri: String,
+    /// `OIDC_SCOPES`: space-separated, must include `openid`
+    pub scopes: String,
+    /// `OIDC_AUTO_CREATE`: create a local user on the first login of an unknown identity
+    pub auto_create: bool,
+    /// `OIDC_LOGIN_TTL_SECS`: how long a started login may take to come back to the callback
+    pub login_ttl_secs: i64,
+}
+
+/// Audit log settings
+#[derive(Debug, Clone)]
+pub struct AuditConfig {
+    /// `AUDIT_CHECKPOINT_INTERVAL_SECS`: how often the head of the audit chain is signed
+    pub checkpoint_interval_secs: u64,
+}
+
+/// Outbound webhook delivery settings
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    /// `WEBHOOK_MAX_ATTEMPTS`: a delivery is marked failed after this many attempts
+    pub max_attempts: u32,
+    /// `WEBHOOK_RETRY_BASE_SECS`: delay before the first retry; doubles with every attempt
+    pub retry_base_secs: u64,
+    /// `WEBHOOK_RETRY_MAX_SECS`: longest delay between two attempts
+    pub retry_max_secs: u64,
+    /// `WEBHOOK_TIMEOUT_SECS`: how long a receiver gets to answer
+    pub timeout_secs: u64,
+    /// `WEBHOOK_CONCURRENCY`: how many webhooks are sent to at once
+    pub concurrency: usize,
+    /// `WEBHOOK_ALLOW_PRIVATE_HOSTS`: also deliver to loopback, private and link-local addresses
+    pub allow_private_hosts: bool,
+}
+
+/// Password strength policy settings
+#[derive(Debug, Clone)]
+pub struct PasswordConfig {
+    /// `PASSWORD_MIN_LENGTH`: minimum number of characters
+    pub min_length: usize,
+    /// `PASSWORD_BREACHED_LIST`: optional file of known-breached passwords, one per line
+    pub breached_list: Option<PathBuf>,
+    /// `ARGON2_MEMORY_KIB`: Argon2id memory cost
+    pub argon2_memory_kib: u32,
+    /// `ARGON2_ITERATIONS`: Argon2id time cost
+    pub argon2_iterations: u32,
+    /// `ARGON2_PARALLELISM`: Argon2id lanes
+    pub argon2_parallelism: u32,
+}
+
+/// Failed-login tracking and lockout settings
+#[derive(Debug, Clone)]
+pub struct ThrottleConfig {
+    /// `LOGIN_MAX_FAILURES`: failures per username before a lockout
+    pub max_failures: u32,
+    /// `LOGIN_IP_MAX_FAILURES`: failures per client IP before a lockout
+    pub ip_max_failures: u32,
+    /// `LOGIN_FAILURE_WINDOW_SECS`: failures older than this are forgotten
+    pub window_secs: u64,
+    /// `LOGIN_BASE_LOCKOUT_SECS`: first lockout; doubles with each further failure
+    pub base_lockout_secs: u64,
+    /// `LOGIN_MAX_LOCKOUT_SECS`: lockout cap
+    pub max_lockout_secs: u64,
+}
+
+/// Request rate limits, each written as `<requests>/<seconds>` (e.g. `20/60`)
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// `RATE_LIMIT_DEFAULT`: every route, per user (or per IP on public routes)
+    pub default: RatePolicy,
+    /// `RATE_LIMIT_IP`: every protected route, per IP, checked before the credential
+    pub ip: RatePolicy,
+    /// `RATE_LIMIT_REGISTER`: `POST /register`, per IP
+    pub register: RatePolicy,
+    /// `RATE_LIMIT_UPLOAD`: `POST /file/upload`, per user
+    pub upload: RatePolicy,
+    /// `RATE_LIMIT_PUBLIC_DOWNLOAD`: `GET /file/public/:id`, per IP
+    pub public_download: RatePolicy,
+    /// `RATE_LIMIT_PASSWORD_RESET`: `POST /password/forgot` and `/password/reset`, per IP,
+    /// each route with its own buckets
+    pub password_reset: RatePolicy,
+    /// `RATE_LIMIT_EMAIL_VERIFY`: `POST /email/verify` per IP and `/me/email/resend` per user,
+    /// each route with its own buckets
+    pub email_verify: RatePolicy,
+}
+
+/// Token bucket policy: up to `limit` requests, refilled evenly over `window_secs`
+#[derive(Debug, Clone, Copy)]
+pub struct RatePolicy {
+    pub limit: u32,
+    pub window_secs: u64,
+}
+
+impl FromStr for RatePolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (limit, window) = s.split_once('/').ok_or(())?;
+        let policy = Self {
+            limit: limit.trim().parse().map_err(|_| ())?,
+            window_secs: window.trim().parse().map_err(|_| ())?,
+        };
+
+        if policy.limit == 0 || policy.window_secs == 0 {
+            return Err(());
+        }
+        Ok(policy)
+    }
+}
+
+impl Config {
+    /// Build the configuration from environment variables, falling back to defaults
+    pub fn from_env() -> Result<Self, String> {
+        let admin_usernames = std::env::var("ADMIN_USERNAMES")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        Ok(Self {
+            auth: AuthConfig::from_env()?,
+            throttle: ThrottleConfig::from_env()?,
+            rate_limit: RateLimitConfig::from_env()?,
+            password: PasswordConfig::from_env()?,
+            mail: MailConfig::from_env()?,
+            oidc: OidcConfig::from_env()?,
+            audit: AuditConfig::from_env()?,
+            webhooks: WebhookConfig::from_env()?,
+            admin_usernames,
+        })
+    }
+}
+
+impl ThrottleConfig {
+    fn from_env() -> Result<Self, String> {
+        let cfg = Self {
+            max_failures: env_or("LOGIN_MAX_FAILURES", 5)?,
+            ip_max_failures: env_or("LOGIN_IP_MAX_FAILURES", 20)?,
+            window_secs: env_or("LOGIN_FAILURE_WINDOW_SECS", 15 * 60)?,
+            base_lockout_secs: env_or("LOGIN_BASE_LOCKOUT_SECS", 30)?,
+            max_lockout_secs: env_or("LOGIN_MAX_LOCKOUT_SECS", 3600)?,
+        };
+
+        if cfg.max_failures == 0 || cfg.ip_max_failures == 0 {
+            return Err("login failure limits must be positive".into());
+        }
+
+        Ok(cfg)
+    }
+}
+
+impl AuditConfig {
+    fn from_env() -> Result<Self, String> {
+        let cfg = Self {
+            checkpoint_interval_secs: env_or("AUDIT_CHECKPOINT_INTERVAL_SECS", 3600)?,
+        };
+
+        if cfg.checkpoint_interval_secs == 0 {
+            return Err("AUDIT_CHECKPOINT_INTERVAL_SECS must be positive".into());
+        }
+
+        Ok(cfg)
+    }
+}
+
+impl WebhookConfig {
+    fn from_env() -> Result<Self, String> {
+        let cfg = Self {
+            max_attempts: env_or("WEBHOOK_MAX_ATTEMPTS", 8)?,
+            retry_base_secs: env_or("WEBHOOK_RETRY_BASE_SECS", 30)?,
+            retry_max_secs: env_or("WEBHOOK_RETRY_MAX_SECS", 3600)?,
+            timeout_secs: env_or("WEBHOOK_TIMEOUT_SECS", 10)?,
+            concurrency: env_or("WEBHOOK_CONCURRENCY", 8)?,
+            allow_private_hosts: env_or("WEBHOOK_ALLOW_PRIVATE_HOSTS", false)?,
+        };
+
+        if cfg.max_attempts == 0
+            || cfg.retry_base_secs == 0
+            || cfg.timeout_secs == 0
+            || cfg.concurrency == 0
+        {
+            return Err(
+                "webhook attempts, retry delay, timeout and concurrency must be positive".into(),
+            );
+        }
+        if cfg.retry_max_secs < cfg.retry_base_secs {
+            return Err("WEBHOOK_RETRY_MAX_SECS must be at least WEBHOOK_RETRY_BASE_SECS".into());
+        }
+
+        Ok(cfg)
+    }
+}
+
+impl PasswordConfig {
+    fn from_env() -> Result<Self, String> {
+        let cfg = Self {
+            min_length: env_or("PASSWORD_MIN_LENGTH", 8)?,
+            breached_list: env_opt("PASSWORD_BREACHED_LIST").map(PathBuf::from),
+            argon2_memory_kib: env_or("ARGON2_MEMORY_KIB", 19 * 1024)?,
+            argon2_iterations: env_or("ARGON2_ITERATIONS", 2)?,
+            argon2_parallelism: env_or("ARGON2_PARALLELISM", 1)?,
+        };
+
+        if cfg.min_length == 0 {
+            return Err("PASSWORD_MIN_LENGTH must be positive".into());
+        }
+
+        Ok(cfg)
+    }
+}
+
+impl MailConfig {
+    fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            transport: env_or("MAIL_TRANSPORT", MailTransport::File)?,
+            from: env_or(
+                "MAIL_FROM",
+                "Secure File Server <noreply@localhost>".to_string(),
+            )?,
+            outbox_dir: env_or("MAIL_OUTBOX_DIR", PathBuf::from("data/outbox"))?,
+            smtp_host: env_opt("SMTP_HOST"),
+            smtp_port: env_opt("SMTP_PORT")
+                .map(|v| {
+                    v.parse()
+                        .map_err(|_| format!("invalid value for SMTP_PORT: {v:?}"))
+                })
+                .transpose()?,
+            smtp_username: env_opt("SMTP_USERNAME"),
+            smtp_password: env_opt("SMTP_PASSWORD"),
+            smtp_tls: env_or("SMTP_TLS", SmtpTls::Starttls)?,
+        })
+    }
+}
+
+impl OidcConfig {
+    fn from_env() -> Result<Option<Self>, String> {
+        let Some(issuer) = env_opt("OIDC_ISSUER") else {
+            return Ok(None);
+        };
+
+        let cfg = Self {
+            issuer: issuer.trim().trim_end_matches('/').to_string(),
+            client_id: env_opt("OIDC_CLIENT_ID")
+                .ok_or("OIDC_CLIENT_ID is required when OIDC_ISSUER is set")?,
+            client_secret: env_opt("OIDC_CLIENT_SECRET"),
+            redirect_uri: env_or(
+                "OIDC_REDIRECT_URI",
+                "http://localhost:8080/oidc/callback".to_string(),
+            )?,
+            scopes: env_or("OIDC_SCOPES", "openid email profile".to_string())?,
+            auto_create: env_or("OIDC_AUTO_CREATE", true)?,
+            login_ttl_secs: env_or("OIDC_LOGIN_TTL_SECS", 600)?,
+        };
+
+        if !cfg.scopes.split_whitespace().any(|s| s == "openid") {
+            return Err("OIDC_SCOPES must include openid".into());
+        }
+        if cfg.login_ttl_secs <= 0 {
+            return Err("OIDC_LOGIN_TTL_SECS must be positive".into());
+        }
+
+        Ok(Some(cfg))
+    }
+}
+
+impl RateLimitConfig {
+    fn from_env() -> Result<Self, String> {
+        let policy = |limit, window_secs| RatePolicy { limit, window_secs };
+
+        Ok(Self {
+            default: env_or("RATE_LIMIT_DEFAULT", policy(300, 60))?,
+            ip: env_or("RATE_LIMIT_IP", policy(600, 60))?,
+            register: env_or("RATE_LIMIT_REGISTER", policy(10, 3600))?,
+            upload: env_or("RATE_LIMIT_UPLOAD", policy(20, 60))?,
+            public_download: env_or("RATE_LIMIT_PUBLIC_DOWNLOAD", policy(60, 60))?,
+            password_reset: env_or("RATE_LIMIT_PASSWORD_RESET", policy(5, 3600))?,
+            email_verify: env_or("RATE_LIMIT_EMAIL_VERIFY", policy(10, 3600))?,
+        })
+    }
+}
+
+impl AuthConfig {
+    fn from_env() -> Result<Self, String> {
+        let cfg = Self {
+            access_token_ttl_secs: env_or("ACCESS_TOKEN_TTL_SECS", 3600)?,
+            refresh_token_idle_ttl_secs: env_or("REFRESH_TOKEN_IDLE_TTL_SECS", 14 * 24 * 3600)?,
+            refresh_token_absolute_ttl_secs: env_or(
+                "REFRESH_TOKEN_ABSOLUTE_TTL_SECS",
+                30 * 24 * 3600,
+            )?,
+            issuer: env_or("JWT_ISSUER", "secure-file-server".to_string())?,
+            audience: env_or("JWT_AUDIENCE", "secure-file-server".to_string())?,
+            leeway_secs: env_or("JWT_LEEWAY_SECS", 30)?,
+            password_reset_ttl_secs: env_or("PASSWORD_RESET_TTL_SECS", 3600)?,
+            email_verification_ttl_secs: env_or("EMAIL_VERIFICATION_TTL_SECS", 24 * 3600)?,
+            require_verified_email: env_or("REQUIRE_VERIFIED_EMAIL", false)?,
+        };
+
+        if cfg.access_token_ttl_secs == 0
+            || cfg.refresh_token_idle_ttl_secs <= 0
+            || cfg.refresh_token_absolute_ttl_secs <= 0
+            || cfg.password_reset_ttl_secs <= 0
+            || cfg.email_verification_ttl_secs <= 0
+        {
+            return Err("token lifetimes must be positive".into());
+        }
+
+        Ok(cfg)
+    }
+}
+
+/// Read and parse an environment variable, using `default` if it is unset
+fn env_or<T: FromStr>(name: &str, default: T) -> Result<T, String> {
+    match std::env::var(name) {
+        Ok(v) => v
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid value for {name}: {v:?}")),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Read an environment variable, treating unset and blank values as `None`
+fn env_opt(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
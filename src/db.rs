@@ -1,11 +1,17 @@
+use sqlx::Row;
 use sqlx::SqlitePool;
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use tokio::fs;
 
+use crate::auth::token::hash_token;
+use crate::auth::username::canonicalize;
+use crate::config::AuthConfig;
+
 /// Initialize SQLite database and create tables if missing
 /// DB file will be created in data/app.db
-pub async fn init_db() -> Result<SqlitePool, sqlx::Error> {
+pub async fn init_db(auth_cfg: &AuthConfig) -> Result<SqlitePool, sqlx::Error> {
     // ensure ./data exists
     let _ = fs::create_dir_all("data").await;
 
@@ -26,6 +32,16 @@ pub async fn init_db() -> Result<SqlitePool, sqlx::Error> {
         .connect_with(opts)
         .await?;
 
+    create_schema(&pool, auth_cfg).await?;
+
+    Ok(pool)
+}
+
+/// Create missing tables, indexes and triggers, and upgrade older databases
+pub(crate) async fn create_schema(
+    pool: &SqlitePool,
+    auth_cfg: &AuthConfig,
+) -> Result<(), sqlx::Error> {
     // Create tables
     sqlx::query(
         r#"
@@ -35,11 +51,36 @@ pub async fn init_db() -> Result<SqlitePool, sqlx::Error> {
             password_hash TEXT NOT NULL,
             created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
             active INTEGER NOT NULL DEFAULT 1,
-            email TEXT
+            email TEXT,
+            is_admin INTEGER NOT NULL DEFAULT 0,
+            email_verified_at INTEGER,
+            password_set INTEGER NOT NULL DEFAULT 1
         );
         "#,
     )
-    .execute(&pool)
+    .execute(pool)
+    .await?;
+
+    if !column_exists(pool, "users", "is_admin").await? {
+        sqlx::query("ALTER TABLE users ADD COLUMN is_admin INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await?;
+    }
+
+    if !column_exists(pool, "users", "email_verified_at").await? {
+        sqlx::query("ALTER TABLE users ADD COLUMN email_verified_at INTEGER")
+            .execute(pool)
+            .await?;
+    }
+
+    sqlx::query(
+        r#"
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_users_email_nocase
+        ON users(email COLLATE NOCASE)
+        WHERE email IS NOT NULL;
+        "#,
+    )
+    .execute(pool)
     .await?;
 
     sqlx::query(
@@ -52,13 +93,70 @@ pub async fn init_db() -> Result<SqlitePool, sqlx::Error> {
             is_public INTEGER NOT NULL DEFAULT 0,
             uploaded_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
             description TEXT,
+            download_count INTEGER NOT NULL DEFAULT 0,
+            last_downloaded_at INTEGER,
+            content_type TEXT,
             FOREIGN KEY(owner_id) REFERENCES users(id)
         );
         "#,
     )
-    .execute(&pool)
+    .execute(pool)
     .await?;
 
+    if !column_exists(pool, "files", "download_count").await? {
+        sqlx::query("ALTER TABLE files ADD COLUMN download_count INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await?;
+        sqlx::query("ALTER TABLE files ADD COLUMN last_downloaded_at INTEGER")
+            .execute(pool)
+            .await?;
+    }
+
+    // Filled in for existing files by `content_type::backfill` at startup
+    if !column_exists(pool, "files", "content_type").await? {
+        sqlx::query("ALTER TABLE files ADD COLUMN content_type TEXT")
+            .execute(pool)
+            .await?;
+    }
+
+    // Full-text index over file descriptions, kept in sync by triggers
+    let fts_exists =
+        sqlx::query("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'files_fts'")
+            .fetch_optional(pool)
+            .await?
+            .is_some();
+
+    sqlx::query(
+        r#"
+        CREATE VIRTUAL TABLE IF NOT EXISTS files_fts
+        USING fts5(description, content='files', content_rowid='id');
+
+        CREATE TRIGGER IF NOT EXISTS files_fts_insert AFTER INSERT ON files BEGIN
+            INSERT INTO files_fts(rowid, description) VALUES (new.id, new.description);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS files_fts_delete AFTER DELETE ON files BEGIN
+            INSERT INTO files_fts(files_fts, rowid, description)
+            VALUES ('delete', old.id, old.description);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS files_fts_update AFTER UPDATE OF description ON files BEGIN
+            INSERT INTO files_fts(files_fts, rowid, description)
+            VALUES ('delete', old.id, old.description);
+            INSERT INTO files_fts(rowid, description) VALUES (new.id, new.description);
+        END;
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Index descriptions written before the table existed
+    if !fts_exists {
+        sqlx::query("INSERT INTO files_fts(files_fts) VALUES ('rebuild')")
+            .execute(pool)
+            .await?;
+    }
+
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS permissions (
@@ -72,32 +170,679 @@ pub async fn init_db() -> Result<SqlitePool, sqlx::Error> {
         );
         "#,
     )
-    .execute(&pool)
+    .execute(pool)
+    .await?;
+
+    // Labels on files; an API key limited to a tag only reaches files carrying it
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS file_tags (
+            file_id INTEGER NOT NULL,
+            tag TEXT NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            PRIMARY KEY(file_id, tag),
+            FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(pool)
     .await?;
 
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_file_tags_tag ON file_tags(tag);")
+        .execute(pool)
+        .await?;
+
+    // One row per successful download; user_id is NULL for public link downloads
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS file_downloads (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            file_id INTEGER NOT NULL,
+            user_id INTEGER,
+            downloaded_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_file_downloads_file_id
+        ON file_downloads(file_id, downloaded_at);
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Owner-defined key/value pairs on files
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS file_metadata (
+            file_id INTEGER NOT NULL,
+            key TEXT NOT NULL,
+            value TEXT NOT NULL,
+            updated_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            PRIMARY KEY(file_id, key),
+            FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Older databases stored raw refresh tokens; hash them before creating the new table
+    if column_exists(pool, "refresh_tokens", "token").await? {
+        migrate_refresh_tokens_to_hashes(pool, auth_cfg).await?;
+    }
+
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS refresh_tokens (
-            token TEXT PRIMARY KEY,
+            token_hash TEXT PRIMARY KEY,
             user_id INTEGER NOT NULL,
             created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            expires_at INTEGER NOT NULL,
+            absolute_expires_at INTEGER NOT NULL,
             revoked_at INTEGER,
             replaced_by TEXT,
+            scopes TEXT,
+            auth_time INTEGER,
+            session_id TEXT,
             FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
         );
         "#,
     )
-    .execute(&pool)
+    .execute(pool)
     .await?;
 
+    // Sessions from before scoped tokens have no scopes column; NULL means a full session
+    if !column_exists(pool, "refresh_tokens", "scopes").await? {
+        sqlx::query("ALTER TABLE refresh_tokens ADD COLUMN scopes TEXT")
+            .execute(pool)
+            .await?;
+    }
+
+    // When the session's login happened; NULL on older sessions, which never count as recent
+    if !column_exists(pool, "refresh_tokens", "auth_time").await? {
+        sqlx::query("ALTER TABLE refresh_tokens ADD COLUMN auth_time INTEGER")
+            .execute(pool)
+            .await?;
+    }
+
+    // Shared by every token of one login; NULL on older sessions until their next rotation
+    if !column_exists(pool, "refresh_tokens", "session_id").await? {
+        sqlx::query("ALTER TABLE refresh_tokens ADD COLUMN session_id TEXT")
+            .execute(pool)
+            .await?;
+    }
+
     sqlx::query(
         r#"
         CREATE INDEX IF NOT EXISTS idx_refresh_tokens_user_id
         ON refresh_tokens(user_id);
         "#,
     )
-    .execute(&pool)
+    .execute(pool)
     .await?;
 
-    Ok(pool)
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_refresh_tokens_session_id
+        ON refresh_tokens(session_id);
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_totp (
+            user_id INTEGER PRIMARY KEY,
+            secret TEXT NOT NULL,
+            enabled_at INTEGER,
+            last_used_step INTEGER,
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS recovery_codes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            code_hash TEXT NOT NULL,
+            used_at INTEGER,
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_recovery_codes_user_id
+        ON recovery_codes(user_id);
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS login_attempts (
+            key TEXT PRIMARY KEY,
+            failures INTEGER NOT NULL,
+            last_failure_at INTEGER NOT NULL,
+            locked_until INTEGER
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS email_verification_tokens (
+            token_hash TEXT PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            email TEXT NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            expires_at INTEGER NOT NULL,
+            used_at INTEGER,
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS password_reset_tokens (
+            token_hash TEXT PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            expires_at INTEGER NOT NULL,
+            used_at INTEGER,
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // External (OIDC) identities linked to local users
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_identities (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            issuer TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            user_id INTEGER NOT NULL,
+            email TEXT,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            last_login_at INTEGER,
+            UNIQUE(issuer, subject),
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Accounts created by single sign-on got a random password along with their
+    // identity, in the same second; they have no password unless they reset it since
+    if !column_exists(pool, "users", "password_set").await? {
+        sqlx::query("ALTER TABLE users ADD COLUMN password_set INTEGER NOT NULL DEFAULT 1")
+            .execute(pool)
+            .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE users SET password_set = 0
+            WHERE EXISTS (
+                SELECT 1 FROM user_identities i
+                WHERE i.user_id = users.id AND i.created_at = users.created_at
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM password_reset_tokens t
+                WHERE t.user_id = users.id AND t.used_at IS NOT NULL
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    // Pending OIDC logins, keyed by the hash of the `state` parameter
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS oidc_login_states (
+            state_hash TEXT PRIMARY KEY,
+            code_verifier TEXT NOT NULL,
+            nonce TEXT NOT NULL,
+            link_user_id INTEGER,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            expires_at INTEGER NOT NULL,
+            FOREIGN KEY(link_user_id) REFERENCES users(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Identities verified by a link callback, waiting for the account that
+    // started the link to confirm them; keyed by the hash of the link code
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS oidc_pending_links (
+            code_hash TEXT PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            issuer TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            email TEXT,
+            expires_at INTEGER NOT NULL,
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Personal API keys; only a hash of each key is stored
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_keys (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            prefix TEXT NOT NULL,
+            key_hash TEXT NOT NULL UNIQUE,
+            scopes TEXT NOT NULL,
+            tag TEXT,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            expires_at INTEGER,
+            last_used_at INTEGER,
+            last_used_ip TEXT,
+            revoked_at INTEGER,
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Keys created before tag limits existed reach all of their owner's files
+    if !column_exists(pool, "api_keys", "tag").await? {
+        sqlx::query("ALTER TABLE api_keys ADD COLUMN tag TEXT")
+            .execute(pool)
+            .await?;
+    }
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_api_keys_user_id
+        ON api_keys(user_id);
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Outbound webhooks; the secret is kept in clear because it signs every payload
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS webhooks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            url TEXT NOT NULL,
+            secret TEXT NOT NULL,
+            events TEXT NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_webhooks_user_id
+        ON webhooks(user_id);
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Delivery queue and log: rows stay `pending` until delivered or out of attempts
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS webhook_deliveries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            webhook_id INTEGER NOT NULL,
+            event TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            attempts INTEGER NOT NULL DEFAULT 0,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            next_attempt_at INTEGER NOT NULL,
+            last_attempt_at INTEGER,
+            last_status_code INTEGER,
+            last_error TEXT,
+            delivered_at INTEGER,
+            FOREIGN KEY(webhook_id) REFERENCES webhooks(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_pending
+        ON webhook_deliveries(status, next_attempt_at);
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_webhook_id
+        ON webhook_deliveries(webhook_id, id);
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Append-only, hash-chained audit log; no foreign keys so events outlive
+    // deleted accounts and files
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS audit_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            action TEXT NOT NULL,
+            actor_id INTEGER,
+            target_type TEXT,
+            target_id TEXT,
+            ip TEXT,
+            user_agent TEXT,
+            request_id TEXT,
+            outcome TEXT NOT NULL CHECK (outcome IN ('success', 'failure')),
+            detail TEXT,
+            prev_hash TEXT,
+            hash TEXT
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Events from before hash chaining get their hashes from `AuditLog::seal_unhashed`
+    if !column_exists(pool, "audit_events", "hash").await? {
+        sqlx::query("ALTER TABLE audit_events ADD COLUMN prev_hash TEXT")
+            .execute(pool)
+            .await?;
+        sqlx::query("ALTER TABLE audit_events ADD COLUMN hash TEXT")
+            .execute(pool)
+            .await?;
+    }
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_audit_events_actor_id
+        ON audit_events(actor_id, id);
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Signed heads of the audit chain
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS audit_checkpoints (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            event_id INTEGER NOT NULL,
+            event_hash TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            key_id TEXT NOT NULL,
+            signature TEXT NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Updates are refused, except giving an unhashed event its hash once. Databases
+    // from before hash chaining have a stricter trigger, which is replaced; both
+    // statements run on one connection so its schema cache sees the drop.
+    let mut tx = pool.begin().await?;
+    let update_trigger: Option<String> = sqlx::query(
+        "SELECT sql FROM sqlite_master WHERE type = 'trigger' AND name = 'audit_events_no_update'",
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .map(|r| r.get("sql"));
+    if update_trigger.is_some_and(|sql| !sql.contains("OLD.hash")) {
+        sqlx::query("DROP TRIGGER audit_events_no_update")
+            .execute(&mut *tx)
+            .await?;
+    }
+    sqlx::query(
+        r#"
+        CREATE TRIGGER IF NOT EXISTS audit_events_no_update
+        BEFORE UPDATE ON audit_events
+        WHEN OLD.hash IS NOT NULL
+            OR NEW.id IS NOT OLD.id
+            OR NEW.at IS NOT OLD.at
+            OR NEW.action IS NOT OLD.action
+            OR NEW.actor_id IS NOT OLD.actor_id
+            OR NEW.target_type IS NOT OLD.target_type
+            OR NEW.target_id IS NOT OLD.target_id
+            OR NEW.ip IS NOT OLD.ip
+            OR NEW.user_agent IS NOT OLD.user_agent
+            OR NEW.request_id IS NOT OLD.request_id
+            OR NEW.outcome IS NOT OLD.outcome
+            OR NEW.detail IS NOT OLD.detail
+        BEGIN
+            SELECT RAISE(ABORT, 'audit_events is append-only');
+        END;
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    for table in ["audit_events", "audit_checkpoints"] {
+        sqlx::query(&format!(
+            r#"
+            CREATE TRIGGER IF NOT EXISTS {table}_no_delete
+            BEFORE DELETE ON {table}
+            BEGIN
+                SELECT RAISE(ABORT, '{table} is append-only');
+            END;
+            "#
+        ))
+        .execute(pool)
+        .await?;
+    }
+
+    sqlx::query(
+        r#"
+        CREATE TRIGGER IF NOT EXISTS audit_checkpoints_no_update
+        BEFORE UPDATE ON audit_checkpoints
+        BEGIN
+            SELECT RAISE(ABORT, 'audit_checkpoints is append-only');
+        END;
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Rewrite stored usernames to their canonical form and add a case-insensitive
+/// unique index. Runs once; fails, listing the accounts involved, if two existing
+/// usernames share a canonical form, since merging accounts needs a human decision.
+pub async fn migrate_usernames(pool: &SqlitePool) -> Result<(), String> {
+    let db_err = |e: sqlx::Error| format!("username migration failed: {e}");
+
+    let done = sqlx::query(
+        "SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = 'idx_users_username_nocase'",
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(db_err)?;
+    if done.is_some() {
+        return Ok(());
+    }
+
+    let rows = sqlx::query("SELECT id, username FROM users ORDER BY id")
+        .fetch_all(pool)
+        .await
+        .map_err(db_err)?;
+
+    let mut by_canonical: BTreeMap<String, Vec<(i64, String)>> = BTreeMap::new();
+    for row in rows {
+        let username: String = row.get("username");
+        by_canonical
+            .entry(canonicalize(&username))
+            .or_default()
+            .push((row.get("id"), username));
+    }
+
+    let collisions: Vec<String> = by_canonical
+        .iter()
+        .filter(|(_, users)| users.len() > 1)
+        .map(|(canonical, users)| {
+            let list: Vec<String> = users
+                .iter()
+                .map(|(id, name)| format!("id={id} {name:?}"))
+                .collect();
+            format!("{canonical:?}: {}", list.join(", "))
+        })
+        .collect();
+
+    if !collisions.is_empty() {
+        return Err(format!(
+            "usernames that differ only in case, spacing or unicode form must be renamed first: {}",
+            collisions.join("; ")
+        ));
+    }
+
+    let mut tx = pool.begin().await.map_err(db_err)?;
+    let mut renamed = 0;
+    for (canonical, users) in &by_canonical {
+        let (id, username) = &users[0];
+        if username != canonical {
+            sqlx::query("UPDATE users SET username = ?1 WHERE id = ?2")
+                .bind(canonical)
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_err)?;
+            renamed += 1;
+        }
+    }
+
+    sqlx::query("CREATE UNIQUE INDEX idx_users_username_nocase ON users(username COLLATE NOCASE)")
+        .execute(&mut *tx)
+        .await
+        .map_err(db_err)?;
+
+    tx.commit().await.map_err(db_err)?;
+
+    if renamed > 0 {
+        println!("Canonicalized {renamed} usernames");
+    }
+    Ok(())
+}
+
+/// Check whether `table` has a column named `column`
+async fn column_exists(pool: &SqlitePool, table: &str, column: &str) -> Result<bool, sqlx::Error> {
+    let rows = sqlx::query(&format!("PRAGMA table_info({table})"))
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.iter().any(|r| r.get::<String, _>("name") == column))
+}
+
+/// Replace the legacy `refresh_tokens` table (raw tokens, no expiry) with the hashed layout.
+/// Existing tokens keep working: they are hashed and given expiries based on `created_at`.
+async fn migrate_refresh_tokens_to_hashes(
+    pool: &SqlitePool,
+    auth_cfg: &AuthConfig,
+) -> Result<(), sqlx::Error> {
+    println!("Migrating refresh_tokens to hashed storage");
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("ALTER TABLE refresh_tokens RENAME TO refresh_tokens_legacy")
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE refresh_tokens (
+            token_hash TEXT PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            expires_at INTEGER NOT NULL,
+            absolute_expires_at INTEGER NOT NULL,
+            revoked_at INTEGER,
+            replaced_by TEXT,
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let rows = sqlx::query(
+        "SELECT token, user_id, created_at, revoked_at, replaced_by FROM refresh_tokens_legacy",
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for row in rows {
+        let token: String = row.get("token");
+        let created_at: i64 = row.get("created_at");
+        let replaced_by: Option<String> = row.get("replaced_by");
+
+        sqlx::query(
+            r#"
+            INSERT INTO refresh_tokens
+                (token_hash, user_id, created_at, expires_at, absolute_expires_at, revoked_at, replaced_by)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            "#,
+        )
+        .bind(hash_token(&token))
+        .bind(row.get::<i64, _>("user_id"))
+        .bind(created_at)
+        .bind(
+            created_at
+                + auth_cfg
+                    .refresh_token_idle_ttl_secs
+                    .min(auth_cfg.refresh_token_absolute_ttl_secs),
+        )
+        .bind(created_at + auth_cfg.refresh_token_absolute_ttl_secs)
+        .bind(row.get::<Option<i64>, _>("revoked_at"))
+        .bind(replaced_by.as_deref().map(hash_token))
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    sqlx::query("DROP TABLE refresh_tokens_legacy")
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await
 }
@@ -0,0 +1,86 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::sync::broadcast;
+
+/// Events buffered per subscriber before a slow one starts missing them
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// A change pushed to connected `GET /events` streams
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiveEventKind {
+    /// You uploaded a file
+    Uploaded,
+    /// A file you can see changed, e.g. its download count or tags
+    Updated,
+    /// A file you owned or that was shared with you was deleted
+    Deleted,
+    /// A file was shared with you
+    Shared,
+    /// Your access to a shared file was revoked
+    Unshared,
+}
+
+impl LiveEventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LiveEventKind::Uploaded => "file.uploaded",
+            LiveEventKind::Updated => "file.updated",
+            LiveEventKind::Deleted => "file.deleted",
+            LiveEventKind::Shared => "file.shared",
+            LiveEventKind::Unshared => "file.unshared",
+        }
+    }
+}
+
+impl fmt::Display for LiveEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub struct LiveEvent {
+    /// Increases with every event since the server started
+    pub id: u64,
+    pub kind: LiveEventKind,
+    /// Users whose streams receive the event
+    pub recipients: Vec<u32>,
+    pub data: serde_json::Value,
+}
+
+/// In-process fan-out of live events to every open stream.
+///
+/// Nothing is stored: events published while a user has no stream open are not replayed.
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<Arc<LiveEvent>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            tx,
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    /// Send `kind` to the streams of `recipients`
+    pub fn publish(&self, kind: LiveEventKind, recipients: Vec<u32>, data: serde_json::Value) {
+        let event = LiveEvent {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            kind,
+            recipients,
+            data,
+        };
+        // An error only means nobody is listening
+        let _ = self.tx.send(Arc::new(event));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<LiveEvent>> {
+        self.tx.subscribe()
+    }
+}
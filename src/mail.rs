@@ -0,0 +1,140 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use lettre::message::{Mailbox, header::ContentType};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use uuid::Uuid;
+
+use crate::config::{MailConfig, MailTransport, SmtpTls};
+
+/// Sends plain-text mail (verification links, password resets)
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String>;
+}
+
+/// Build the mailer selected by `MAIL_TRANSPORT`
+pub fn from_config(cfg: &MailConfig) -> Result<Arc<dyn Mailer>, String> {
+    let mailer: Arc<dyn Mailer> = match cfg.transport {
+        MailTransport::File => Arc::new(FileMailer::new(cfg.outbox_dir.clone())),
+        MailTransport::Log => Arc::new(LogMailer),
+        MailTransport::Smtp => Arc::new(SmtpMailer::new(cfg)?),
+    };
+
+    println!("Mail transport: {:?}", cfg.transport);
+    Ok(mailer)
+}
+
+/// Development mailer: each message is written as a plain-text file in the outbox
+/// directory, where it can be read by hand or picked up by another process.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| format!("cannot create {}: {e}", self.dir.display()))?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = self.dir.join(format!("{now}-{}.eml", Uuid::new_v4()));
+
+        let message = format!("To: {to}\nSubject: {subject}\n\n{body}\n");
+        tokio::fs::write(&path, message)
+            .await
+            .map_err(|e| format!("cannot write {}: {e}", path.display()))?;
+
+        println!("Mail to {to} written to {}", path.display());
+        Ok(())
+    }
+}
+
+/// Development mailer that prints each message to stdout
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        println!("--- mail to {to} ---\nSubject: {subject}\n\n{body}\n--- end of mail ---");
+        Ok(())
+    }
+}
+
+/// Sends mail through an SMTP relay
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(cfg: &MailConfig) -> Result<Self, String> {
+        let host = cfg
+            .smtp_host
+            .as_deref()
+            .ok_or("SMTP_HOST is required when MAIL_TRANSPORT=smtp")?;
+
+        let mut builder = match cfg.smtp_tls {
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .map_err(|e| format!("invalid SMTP_HOST: {e}"))?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+                .map_err(|e| format!("invalid SMTP_HOST: {e}"))?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        };
+
+        if let Some(port) = cfg.smtp_port {
+            builder = builder.port(port);
+        }
+
+        if let (Some(user), Some(pass)) = (&cfg.smtp_username, &cfg.smtp_password) {
+            builder = builder.credentials(Credentials::new(user.clone(), pass.clone()));
+        }
+
+        let from = cfg
+            .from
+            .parse()
+            .map_err(|e| format!("invalid MAIL_FROM {:?}: {e}", cfg.from))?;
+
+        Ok(Self {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        let to: Mailbox = to
+            .parse()
+            .map_err(|e| format!("invalid recipient {to:?}: {e}"))?;
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body.to_string())
+            .map_err(|e| format!("cannot build mail: {e}"))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| format!("SMTP send failed: {e}"))?;
+
+        Ok(())
+    }
+}
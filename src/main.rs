@@ -1,27 +1,75 @@
 mod api;
+mod audit;
 mod auth;
+mod config;
 mod db;
+mod events;
+mod mail;
 mod storage;
+mod webhooks;
 
 use axum::routing::delete;
 use axum::{
     Router, middleware,
-    routing::{get, post},
+    routing::{get, patch, post, put},
 };
 
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
 
-use api::auth::{login_handler, refresh_handler, register_handler};
-use api::auth_middleware::auth_middleware;
+use api::account::{delete_account_handler, export_handler};
+use api::admin::{issue_password_reset_handler, unlock_ip_handler, unlock_user_handler};
+use api::api_keys::{create_api_key_handler, list_api_keys_handler, revoke_api_key_handler};
+use api::audit::{audit_checkpoint_handler, audit_handler, audit_verify_handler};
+use api::auth::{
+    login_handler, login_two_factor_handler, logout_handler, refresh_handler, register_handler,
+};
+use api::auth_middleware::{auth_middleware, require_scope};
+use api::email::{resend_verification_handler, set_email_handler, verify_email_handler};
+use api::error::request_context;
+use api::events::events_handler;
 use api::file::{
-    download_handler, download_public_handler, list_files_handler, revoke_share_by_user_handler,
-    revoke_share_handler, share_file_handler, upload_handler,
+    delete_file_handler, download_handler, download_public_handler, file_stats_handler,
+    list_files_handler, revoke_share_by_user_handler, revoke_share_handler, share_file_handler,
+    upload_handler,
 };
+use api::jwks::{audit_keys_handler, jwks_handler};
 use api::me::me_handler;
+use api::oidc::{
+    oidc_callback_handler, oidc_link_confirm_handler, oidc_link_handler, oidc_login_handler,
+};
+use api::password::{change_password_handler, forgot_password_handler, reset_password_handler};
+use api::rate_limit::{RateLimiter, rate_limit};
+use api::search::search_files_handler;
+use api::tags::{
+    add_tags_handler, get_metadata_handler, get_tags_handler, list_tags_handler,
+    patch_metadata_handler, remove_tag_handler,
+};
+use api::two_factor::{confirm_totp_handler, disable_totp_handler, enroll_totp_handler};
+use api::webhooks::{
+    create_webhook_handler, delete_webhook_handler, list_webhooks_handler, ping_webhook_handler,
+    webhook_deliveries_handler,
+};
 use api::{AppState, health_check};
+use audit::AuditLog;
 
+use auth::keys::JwtKeys;
+use auth::oidc::OidcClient;
+use auth::passwords::PasswordHashing;
+use auth::policy::PasswordPolicy;
 use auth::repository::AuthUserRepository;
+use auth::scope::Scope;
 use auth::service::SimpleAuthService;
+use auth::throttle::LoginThrottle;
+use auth::username::canonicalize;
+use config::Config;
+use events::EventBus;
+use webhooks::Webhooks;
+
+/// How often expired tokens, stale login failures and idle rate limit buckets are purged
+const CLEANUP_INTERVAL_SECS: u64 = 3600;
 
 #[tokio::main]
 async fn main() {
@@ -30,32 +78,285 @@ async fn main() {
     // Load .env
     dotenvy::dotenv().ok();
 
+    // Read configuration
+    let config = match Config::from_env() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Refusing to start: {e}");
+            std::process::exit(1);
+        }
+    };
+
     // Initialize SQLite DB
-    let db_pool = db::init_db().await.expect("DB init failed");
+    let db_pool = db::init_db(&config.auth).await.expect("DB init failed");
+    if let Err(e) = db::migrate_usernames(&db_pool).await {
+        eprintln!("Refusing to start: {e}");
+        std::process::exit(1);
+    }
+    if let Err(e) = storage::content_type::backfill(&db_pool).await {
+        eprintln!("Failed to detect content types of existing files: {e}");
+    }
+
+    // Load JWT signing keys
+    let jwt_keys = match JwtKeys::load_from_env() {
+        Ok(keys) => Arc::new(keys),
+        Err(e) => {
+            eprintln!("Refusing to start: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    // Load the audit checkpoint keys, which must not double as token keys
+    let audit_keys = match JwtKeys::load_audit_from_env() {
+        Ok(keys) if keys.shares_key_with(&jwt_keys) => {
+            eprintln!("Refusing to start: an audit signing key is also a JWT key");
+            std::process::exit(1);
+        }
+        Ok(keys) => Arc::new(keys),
+        Err(e) => {
+            eprintln!("Refusing to start: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    // Load the password policy and hashing parameters
+    let password_policy = match PasswordPolicy::load(&config.password) {
+        Ok(policy) => Arc::new(policy),
+        Err(e) => {
+            eprintln!("Refusing to start: {e}");
+            std::process::exit(1);
+        }
+    };
+    let password_hashing = match PasswordHashing::new(&config.password) {
+        Ok(hashing) => hashing,
+        Err(e) => {
+            eprintln!("Refusing to start: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    // Mail transport for verification and reset mails
+    let mailer = match mail::from_config(&config.mail) {
+        Ok(mailer) => mailer,
+        Err(e) => {
+            eprintln!("Refusing to start: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    // Single sign-on, if an identity provider is configured
+    let oidc = match config.oidc.clone().map(OidcClient::new).transpose() {
+        Ok(oidc) => oidc.map(Arc::new),
+        Err(e) => {
+            eprintln!("Refusing to start: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    // Outbound webhooks; the worker sends queued deliveries, including those left from before a restart
+    let webhooks = match Webhooks::new(db_pool.clone(), config.webhooks) {
+        Ok(webhooks) => webhooks,
+        Err(e) => {
+            eprintln!("Refusing to start: {e}");
+            std::process::exit(1);
+        }
+    };
+    tokio::spawn(webhooks.clone().run_worker());
 
     // Build auth service
     let auth_repo = AuthUserRepository::new(db_pool.clone());
-    let auth_service = SimpleAuthService::new(auth_repo);
+    let admin_usernames: Vec<String> = config
+        .admin_usernames
+        .iter()
+        .map(|name| canonicalize(name))
+        .collect();
+    auth_repo
+        .set_admins(&admin_usernames)
+        .await
+        .expect("Admin setup failed");
+    let audit = AuditLog::new(db_pool.clone(), audit_keys);
+    match audit.seal_unhashed().await {
+        Ok(0) => {}
+        Ok(n) => println!("Added {n} earlier audit events to the hash chain"),
+        Err(e) => {
+            eprintln!("Refusing to start: cannot seal audit events: {e}");
+            std::process::exit(1);
+        }
+    }
+    let auth_service = SimpleAuthService::new(
+        auth_repo.clone(),
+        jwt_keys,
+        Arc::new(config.auth),
+        password_policy,
+        password_hashing,
+        mailer,
+        oidc,
+    );
+    let throttle = LoginThrottle::new(db_pool.clone(), config.throttle);
+
+    // Rate limiters; routes sharing a policy still get their own buckets
+    let default_limit = RateLimiter::new(config.rate_limit.default);
+    let ip_limit = RateLimiter::new(config.rate_limit.ip);
+    let register_limit = RateLimiter::new(config.rate_limit.register);
+    let upload_limit = RateLimiter::new(config.rate_limit.upload);
+    let public_download_limit = RateLimiter::new(config.rate_limit.public_download);
+    let forgot_password_limit = RateLimiter::new(config.rate_limit.password_reset);
+    let reset_password_limit = RateLimiter::new(config.rate_limit.password_reset);
+    let verify_email_limit = RateLimiter::new(config.rate_limit.email_verify);
+    let resend_verification_limit = RateLimiter::new(config.rate_limit.email_verify);
+
+    // Periodically purge expired and revoked tokens, old login failures and idle buckets
+    let cleanup_throttle = throttle.clone();
+    let cleanup_webhooks = webhooks.clone();
+    let cleanup_limiters = [
+        default_limit.clone(),
+        ip_limit.clone(),
+        register_limit.clone(),
+        upload_limit.clone(),
+        public_download_limit.clone(),
+        forgot_password_limit.clone(),
+        reset_password_limit.clone(),
+        verify_email_limit.clone(),
+        resend_verification_limit.clone(),
+    ];
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            match auth_repo.delete_stale_refresh_tokens().await {
+                Ok(0) => {}
+                Ok(n) => println!("Removed {n} stale refresh tokens"),
+                Err(e) => eprintln!("Refresh token cleanup failed: {e}"),
+            }
+            if let Err(e) = auth_repo.delete_stale_password_resets().await {
+                eprintln!("Password reset cleanup failed: {e}");
+            }
+            if let Err(e) = auth_repo.delete_stale_email_verifications().await {
+                eprintln!("Email verification cleanup failed: {e}");
+            }
+            if let Err(e) = auth_repo.delete_stale_oidc_logins().await {
+                eprintln!("OIDC login cleanup failed: {e}");
+            }
+            if let Err(e) = cleanup_throttle.delete_stale().await {
+                eprintln!("Login attempt cleanup failed: {e}");
+            }
+            if let Err(e) = cleanup_webhooks.delete_stale().await {
+                eprintln!("Webhook delivery cleanup failed: {e}");
+            }
+            for limiter in &cleanup_limiters {
+                limiter.prune();
+            }
+        }
+    });
+
+    // Periodically sign the head of the audit chain
+    let checkpoint_audit = audit.clone();
+    let checkpoint_interval = Duration::from_secs(config.audit.checkpoint_interval_secs);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(checkpoint_interval);
+        loop {
+            interval.tick().await;
+            match checkpoint_audit.checkpoint().await {
+                Ok(Some(cp)) => println!(
+                    "[security] audit checkpoint {} signed at event {}",
+                    cp.id, cp.event_id
+                ),
+                Ok(None) => {}
+                Err(e) => eprintln!("[security] audit checkpoint failed: {e}"),
+            }
+        }
+    });
 
     // Build application state
     let state = AppState {
         auth: auth_service,
         db: db_pool,
+        throttle,
+        audit,
+        webhooks,
+        events: EventBus::new(),
     };
 
     // Public routes
     let public_routes = Router::new()
         .route("/health", get(health_check))
-        .route("/register", post(register_handler))
+        .route("/.well-known/jwks.json", get(jwks_handler))
+        .route("/.well-known/audit-keys.json", get(audit_keys_handler))
+        .route(
+            "/register",
+            post(register_handler)
+                .layer(middleware::from_fn_with_state(register_limit, rate_limit)),
+        )
         .route("/login", post(login_handler))
+        .route("/login/2fa", post(login_two_factor_handler))
         .route("/token/refresh", get(refresh_handler))
-        .route("/file/public/:id", get(download_public_handler));
+        .route("/oidc/login", get(oidc_login_handler))
+        .route("/oidc/callback", get(oidc_callback_handler))
+        .route(
+            "/password/forgot",
+            post(forgot_password_handler).layer(middleware::from_fn_with_state(
+                forgot_password_limit,
+                rate_limit,
+            )),
+        )
+        .route(
+            "/password/reset",
+            post(reset_password_handler).layer(middleware::from_fn_with_state(
+                reset_password_limit,
+                rate_limit,
+            )),
+        )
+        .route(
+            "/email/verify",
+            post(verify_email_handler).layer(middleware::from_fn_with_state(
+                verify_email_limit,
+                rate_limit,
+            )),
+        )
+        .route(
+            "/file/public/:id",
+            get(download_public_handler).layer(middleware::from_fn_with_state(
+                public_download_limit,
+                rate_limit,
+            )),
+        )
+        .layer(middleware::from_fn_with_state(
+            default_limit.clone(),
+            rate_limit,
+        ));
 
-    // Protected routes
-    let protected_routes = Router::new()
+    // Protected routes, grouped by the scope they require
+    let read_routes = Router::new()
         .route("/me", get(me_handler))
-        .route("/file/upload", post(upload_handler))
+        .route("/me/export", get(export_handler))
         .route("/file/:id", get(download_handler))
+        .route("/file/:id/stats", get(file_stats_handler))
+        .route("/files", get(list_files_handler))
+        .route("/files/search", get(search_files_handler))
+        .route("/file/:id/tags", get(get_tags_handler))
+        .route("/file/:id/metadata", get(get_metadata_handler))
+        .route("/tags", get(list_tags_handler))
+        .route("/events", get(events_handler))
+        .route_layer(middleware::from_fn_with_state(
+            Scope::FilesRead,
+            require_scope,
+        ));
+
+    let write_routes = Router::new()
+        .route(
+            "/file/upload",
+            post(upload_handler).layer(middleware::from_fn_with_state(upload_limit, rate_limit)),
+        )
+        .route("/file/:id", delete(delete_file_handler))
+        .route("/file/:id/tags", post(add_tags_handler))
+        .route("/file/:id/tags/:tag", delete(remove_tag_handler))
+        .route("/file/:id/metadata", patch(patch_metadata_handler))
+        .route_layer(middleware::from_fn_with_state(
+            Scope::FilesWrite,
+            require_scope,
+        ));
+
+    let share_routes = Router::new()
         .route("/file/:id/share", post(share_file_handler))
         .route(
             "/file/:id/share/:permission_id",
@@ -65,19 +366,97 @@ async fn main() {
             "/file/:id/share/user/:user_id",
             delete(revoke_share_by_user_handler),
         )
-        .route("/files", get(list_files_handler))
+        .route_layer(middleware::from_fn_with_state(
+            Scope::SharesManage,
+            require_scope,
+        ));
+
+    // Account management is never available to API keys
+    let account_routes = Router::new()
+        .route("/me", delete(delete_account_handler))
+        .route("/me/2fa/enroll", post(enroll_totp_handler))
+        .route("/me/2fa/confirm", post(confirm_totp_handler))
+        .route("/me/2fa/disable", post(disable_totp_handler))
+        .route("/me/password", post(change_password_handler))
+        .route("/me/email", put(set_email_handler))
+        .route("/me/oidc/link", post(oidc_link_handler))
+        .route("/me/oidc/link/confirm", post(oidc_link_confirm_handler))
+        .route(
+            "/me/email/resend",
+            post(resend_verification_handler).layer(middleware::from_fn_with_state(
+                resend_verification_limit,
+                rate_limit,
+            )),
+        )
+        .route(
+            "/me/api-keys",
+            get(list_api_keys_handler).post(create_api_key_handler),
+        )
+        .route("/me/api-keys/:id", delete(revoke_api_key_handler))
+        .route(
+            "/me/webhooks",
+            get(list_webhooks_handler).post(create_webhook_handler),
+        )
+        .route("/me/webhooks/:id", delete(delete_webhook_handler))
+        .route("/me/webhooks/:id/ping", post(ping_webhook_handler))
+        .route(
+            "/me/webhooks/:id/deliveries",
+            get(webhook_deliveries_handler),
+        )
+        .route("/audit", get(audit_handler))
+        .route_layer(middleware::from_fn_with_state(
+            Scope::Account,
+            require_scope,
+        ));
+
+    // Admin handlers also check the user's admin flag
+    let admin_routes = Router::new()
+        .route(
+            "/admin/lockouts/user/:username",
+            delete(unlock_user_handler),
+        )
+        .route("/admin/lockouts/ip/:ip", delete(unlock_ip_handler))
+        .route(
+            "/admin/users/:username/password-reset",
+            post(issue_password_reset_handler),
+        )
+        .route("/admin/audit/verify", get(audit_verify_handler))
+        .route("/admin/audit/checkpoint", post(audit_checkpoint_handler))
+        .route_layer(middleware::from_fn_with_state(Scope::Admin, require_scope));
+
+    // Any access token may end its own session, whatever its scopes
+    let session_routes = Router::new().route("/logout", post(logout_handler));
+
+    let protected_routes = read_routes
+        .merge(session_routes)
+        .merge(write_routes)
+        .merge(share_routes)
+        .merge(account_routes)
+        .merge(admin_routes)
+        // Inside auth so requests are limited per user
+        .layer(middleware::from_fn_with_state(default_limit, rate_limit))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
-        ));
+        ))
+        // Outside auth so bad or missing credentials are limited per IP before any lookup
+        .layer(middleware::from_fn_with_state(ip_limit, rate_limit));
 
-    // Combine routers
-    let app = public_routes.merge(protected_routes).with_state(state);
+    // Combine routers; request ids and JSON error bodies cover every route, including unknown ones
+    let app = public_routes
+        .merge(protected_routes)
+        .with_state(state)
+        .layer(middleware::from_fn(request_context));
 
     // Listener
     let listener = TcpListener::bind("0.0.0.0:8080").await.unwrap();
     println!("Server running at http://0.0.0.0:8080/");
 
     // Start server
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
@@ -0,0 +1,628 @@
+use axum::http::header::{self, HeaderMap, HeaderValue, InvalidHeaderValue};
+use sqlx::{Row, SqlitePool};
+use tokio::io::AsyncReadExt;
+
+use crate::storage::disk::final_upload_path;
+
+/// Leading bytes of an upload inspected to detect its type
+pub const SNIFF_LEN: usize = 8192;
+
+/// Served for anything not recognized
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Signatures at a fixed offset: (offset, magic bytes, type)
+const MAGIC: &[(usize, &[u8], &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (0, b"\x00\x00\x01\x00", "image/x-icon"),
+    (0, b"II*\x00", "image/tiff"),
+    (0, b"MM\x00*", "image/tiff"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"PK\x05\x06", "application/zip"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"BZh", "application/x-bzip2"),
+    (0, b"\xfd7zXZ\x00", "application/x-xz"),
+    (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (0, b"Rar!\x1a\x07", "application/vnd.rar"),
+    (257, b"ustar", "application/x-tar"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"\xff\xfb", "audio/mpeg"),
+    (0, b"\xff\xf3", "audio/mpeg"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"fLaC", "audio/flac"),
+    (0, b"\x1a\x45\xdf\xa3", "video/webm"),
+    (4, b"ftyp", "video/mp4"),
+    (0, b"\x00asm", "application/wasm"),
+    (0, b"\x7fELF", "application/x-executable"),
+    (0, b"SQLite format 3\x00", "application/vnd.sqlite3"),
+];
+
+/// Short printable signatures, only trusted when the bytes are not text
+/// (so a text file starting with "BMW" is not a bitmap)
+const WEAK_MAGIC: &[(&[u8], &str)] = &[(b"BM", "image/bmp"), (b"MZ", "application/x-msdownload")];
+
+/// Types by filename extension
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("bmp", "image/bmp"),
+    ("ico", "image/x-icon"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("svg", "image/svg+xml"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    (
+        "pptx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    ),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("epub", "application/epub+zip"),
+    ("jar", "application/java-archive"),
+    ("apk", "application/vnd.android.package-archive"),
+    ("gz", "application/gzip"),
+    ("tgz", "application/gzip"),
+    ("bz2", "application/x-bzip2"),
+    ("xz", "application/x-xz"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),
+    ("tar", "application/x-tar"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("flac", "audio/flac"),
+    ("wav", "audio/wav"),
+    ("m4a", "audio/mp4"),
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("mov", "video/quicktime"),
+    ("webm", "video/webm"),
+    ("mkv", "video/x-matroska"),
+    ("avi", "video/x-msvideo"),
+    ("wasm", "application/wasm"),
+    ("exe", "application/x-msdownload"),
+    ("dll", "application/x-msdownload"),
+    ("sqlite", "application/vnd.sqlite3"),
+    ("db", "application/vnd.sqlite3"),
+    ("txt", "text/plain"),
+    ("log", "text/plain"),
+    ("csv", "text/csv"),
+    ("md", "text/markdown"),
+    ("json", "application/json"),
+    ("xml", "application/xml"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+];
+
+/// Types a browser may render inline. Anything that can run script (HTML, SVG,
+/// XML, JavaScript) is left out and always downloaded as an attachment.
+const INLINE_SAFE: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/bmp",
+    "image/x-icon",
+    "application/pdf",
+    "text/plain",
+    "text/csv",
+    "text/markdown",
+    "application/json",
+    "audio/mpeg",
+    "audio/ogg",
+    "audio/flac",
+    "audio/wav",
+    "audio/mp4",
+    "video/mp4",
+    "video/quicktime",
+    "video/webm",
+];
+
+/// Leading tags that make text HTML, checked case-insensitively
+const HTML_TAGS: &[&str] = &[
+    "<!doctype html",
+    "<html",
+    "<head",
+    "<body",
+    "<script",
+    "<iframe",
+    "<style",
+    "<title",
+    "<div",
+    "<a ",
+    "<img",
+    "<svg",
+    "<?xml",
+];
+
+/// Result of checking an upload's bytes against its name and declared type
+pub struct Detected {
+    pub content_type: &'static str,
+    /// What disagreed with the bytes, if anything
+    pub mismatch: Option<String>,
+}
+
+/// Detect the type of a file from its leading bytes.
+///
+/// The bytes decide: the extension and the type the client declared are only used
+/// to pick a more specific type the bytes agree with (a `.docx` is a zip, a `.csv`
+/// is text), and disagreements are reported in `mismatch`. The result is always one
+/// of this module's known types, never a string taken from the client.
+pub fn detect(head: &[u8], filename: &str, declared: Option<&str>) -> Detected {
+    let by_ext = from_extension(filename);
+    let declared = declared
+        .map(|d| {
+            d.split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase()
+        })
+        .filter(|d| !d.is_empty() && d != OCTET_STREAM);
+    let declared_known = declared.as_deref().and_then(known);
+
+    let content_type = match sniff(head) {
+        Sniffed::Binary(sniffed) => match by_ext {
+            Some(ext) if refines(sniffed, ext) => ext,
+            _ => sniffed,
+        },
+        Sniffed::Markup(sniffed) => sniffed,
+        Sniffed::Text => by_ext
+            .filter(|t| is_plain_text(t))
+            .or(declared_known.filter(|t| is_plain_text(t)))
+            .unwrap_or("text/plain"),
+        Sniffed::Unknown => OCTET_STREAM,
+    };
+
+    // Text saved under another text type (a .log declared text/x-log) is not worth reporting
+    let disagrees = |other: &str| {
+        other != content_type && !(is_plain_text(other) && is_plain_text(content_type))
+    };
+    let mut problems = Vec::new();
+    if let Some(ext) = by_ext
+        && disagrees(ext)
+    {
+        problems.push(format!("extension says {ext}"));
+    }
+    if let Some(declared) = &declared
+        && disagrees(declared)
+    {
+        problems.push(format!("client declared {declared}"));
+    }
+
+    Detected {
+        content_type,
+        mismatch: (!problems.is_empty())
+            .then(|| format!("content is {content_type} but {}", problems.join(" and "))),
+    }
+}
+
+/// Whether a browser may be asked to display this type instead of downloading it
+pub fn is_inline_safe(content_type: &str) -> bool {
+    INLINE_SAFE.contains(&content_type)
+}
+
+/// Headers a stored file is served with.
+///
+/// `nosniff` stops browsers from second-guessing the type, and the file is only
+/// shown inline when asked for and the type cannot run script; otherwise it is an
+/// attachment.
+pub fn serving_headers(
+    content_type: &str,
+    filename: &str,
+    inline: bool,
+) -> Result<HeaderMap, InvalidHeaderValue> {
+    let inline = inline && is_inline_safe(content_type);
+    let header_type = if is_text(content_type) {
+        format!("{content_type}; charset=utf-8")
+    } else {
+        content_type.to_string()
+    };
+    let disposition = format!(
+        "{}; filename=\"{}\"",
+        if inline { "inline" } else { "attachment" },
+        filename.replace('"', "_")
+    );
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, header_type.parse()?);
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    headers.insert(header::CONTENT_DISPOSITION, disposition.parse()?);
+    Ok(headers)
+}
+
+/// Whether the stored bytes of this type are UTF-8 text
+pub fn is_text(content_type: &str) -> bool {
+    content_type.starts_with("text/")
+        || matches!(
+            content_type,
+            "application/json" | "application/xml" | "image/svg+xml"
+        )
+}
+
+enum Sniffed {
+    Binary(&'static str),
+    /// HTML, SVG or XML
+    Markup(&'static str),
+    /// UTF-8 without control characters other than whitespace
+    Text,
+    Unknown,
+}
+
+fn sniff(head: &[u8]) -> Sniffed {
+    for (offset, magic, content_type) in MAGIC {
+        if head.len() >= offset + magic.len() && &head[*offset..offset + magic.len()] == *magic {
+            return Sniffed::Binary(content_type);
+        }
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" {
+        match &head[8..12] {
+            b"WEBP" => return Sniffed::Binary("image/webp"),
+            b"WAVE" => return Sniffed::Binary("audio/wav"),
+            b"AVI " => return Sniffed::Binary("video/x-msvideo"),
+            _ => {}
+        }
+    }
+
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // A character cut off at the end of the sniffed bytes is fine
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return sniff_weak(head),
+    };
+    if text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t' | '\x0c'))
+    {
+        return sniff_weak(head);
+    }
+
+    let start = text
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .chars()
+        .take(256)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if HTML_TAGS.iter().any(|tag| start.starts_with(tag)) {
+        let lower = text.to_ascii_lowercase();
+        return Sniffed::Markup(if lower.contains("<svg") {
+            "image/svg+xml"
+        } else if start.starts_with("<?xml") && !lower.contains("<html") {
+            "application/xml"
+        } else {
+            "text/html"
+        });
+    }
+    Sniffed::Text
+}
+
+fn sniff_weak(head: &[u8]) -> Sniffed {
+    WEAK_MAGIC
+        .iter()
+        .find(|(magic, _)| head.starts_with(magic))
+        .map_or(Sniffed::Unknown, |(_, content_type)| {
+            Sniffed::Binary(content_type)
+        })
+}
+
+/// Type for a filename's extension, if it is one we know
+fn from_extension(filename: &str) -> Option<&'static str> {
+    let (_, ext) = filename.rsplit_once('.')?;
+    let ext = ext.to_ascii_lowercase();
+    EXTENSIONS.iter().find(|(e, _)| *e == ext).map(|(_, t)| *t)
+}
+
+/// The static copy of a known type
+fn known(content_type: &str) -> Option<&'static str> {
+    EXTENSIONS
+        .iter()
+        .map(|(_, t)| *t)
+        .find(|t| *t == content_type)
+}
+
+/// Whether `specific` is a format built on the container the bytes show
+fn refines(sniffed: &str, specific: &str) -> bool {
+    match sniffed {
+        "application/zip" => {
+            specific.starts_with("application/vnd.openxmlformats")
+                || specific.starts_with("application/vnd.oasis")
+                || matches!(
+                    specific,
+                    "application/epub+zip"
+                        | "application/java-archive"
+                        | "application/vnd.android.package-archive"
+                )
+        }
+        "video/mp4" => matches!(specific, "audio/mp4" | "video/quicktime"),
+        "video/webm" => specific == "video/x-matroska",
+        _ => false,
+    }
+}
+
+/// Text types an extension or client may choose for plain-looking text.
+/// Markup is excluded so a `.html` name cannot turn harmless text into HTML.
+fn is_plain_text(content_type: &str) -> bool {
+    is_text(content_type) && !matches!(content_type, "text/html" | "image/svg+xml")
+}
+
+/// Detect and store the type of files uploaded before types were recorded
+pub async fn backfill(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let rows = sqlx::query("SELECT id, filename FROM files WHERE content_type IS NULL")
+        .fetch_all(pool)
+        .await?;
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    for r in &rows {
+        let id: i64 = r.get("id");
+        let filename: String = r.get("filename");
+
+        let content_type = match read_head(id as u64).await {
+            Ok(head) => detect(&head, &filename, None).content_type,
+            Err(e) => {
+                eprintln!("Cannot read file {id} to detect its type: {e}");
+                OCTET_STREAM
+            }
+        };
+
+        sqlx::query("UPDATE files SET content_type = ?1 WHERE id = ?2")
+            .bind(content_type)
+            .bind(id)
+            .execute(pool)
+            .await?;
+    }
+
+    println!("Detected content types of {} existing files", rows.len());
+    Ok(())
+}
+
+async fn read_head(file_id: u64) -> std::io::Result<Vec<u8>> {
+    let file = tokio::fs::File::open(final_upload_path(file_id)).await?;
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    file.take(SNIFF_LEN as u64).read_to_end(&mut head).await?;
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+    const JPEG: &[u8] = b"\xff\xd8\xff\xe0\0\x10JFIF\0";
+    const ZIP: &[u8] = b"PK\x03\x04\x14\0\0\0\x08\0";
+
+    fn served_inline(content_type: &str) -> bool {
+        serving_headers(content_type, "f", true).unwrap()[header::CONTENT_DISPOSITION]
+            .to_str()
+            .unwrap()
+            .starts_with("inline")
+    }
+
+    #[test]
+    fn markup_disguised_as_an_image_is_not_inline() {
+        let cases: &[(&[u8], &str)] = &[
+            (b"<html><script>alert(1)</script></html>", "text/html"),
+            (b"<!DOCTYPE html><p>hi", "text/html"),
+            (b"\xef\xbb\xbf  \n<script>alert(1)</script>", "text/html"),
+            (
+                b"<svg xmlns=\"http://www.w3.org/2000/svg\" onload=\"alert(1)\"/>",
+                "image/svg+xml",
+            ),
+            (
+                b"<?xml version=\"1.0\"?><svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+                "image/svg+xml",
+            ),
+            (b"<?xml version=\"1.0\"?><html><body/></html>", "text/html"),
+            (b"<?xml version=\"1.0\"?><note/>", "application/xml"),
+        ];
+        for (head, expected) in cases {
+            let detected = detect(head, "cat.png", Some("image/png"));
+            assert_eq!(detected.content_type, *expected);
+            assert!(detected.mismatch.is_some());
+            assert!(!is_inline_safe(detected.content_type));
+            assert!(!served_inline(detected.content_type));
+        }
+    }
+
+    #[test]
+    fn markup_names_do_not_turn_text_into_markup() {
+        for name in ["page.html", "icon.svg"] {
+            let detected = detect(b"just some words", name, Some("text/html"));
+            assert_eq!(detected.content_type, "text/plain");
+            assert!(detected.mismatch.is_some());
+        }
+    }
+
+    #[test]
+    fn magic_bytes_win_over_name_and_declared_type() {
+        let detected = detect(PNG, "report.pdf", Some("application/pdf"));
+        assert_eq!(detected.content_type, "image/png");
+        let mismatch = detected.mismatch.unwrap();
+        assert!(
+            mismatch.contains("extension says application/pdf"),
+            "{mismatch}"
+        );
+        assert!(
+            mismatch.contains("client declared application/pdf"),
+            "{mismatch}"
+        );
+
+        assert_eq!(detect(JPEG, "notes.txt", None).content_type, "image/jpeg");
+        assert_eq!(
+            detect(ZIP, "setup.exe", None).content_type,
+            "application/zip"
+        );
+        assert_eq!(
+            detect(b"\x7fELF\x02\x01\x01", "photo.jpg", Some("image/jpeg")).content_type,
+            "application/x-executable"
+        );
+    }
+
+    #[test]
+    fn matching_names_refine_without_a_mismatch() {
+        let docx = detect(ZIP, "letter.docx", None);
+        assert_eq!(
+            docx.content_type,
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+        );
+        assert!(docx.mismatch.is_none());
+
+        let png = detect(PNG, "cat.PNG", Some("image/png; charset=binary"));
+        assert_eq!(png.content_type, "image/png");
+        assert!(png.mismatch.is_none());
+
+        let csv = detect(b"a,b\n1,2\n", "data.csv", Some("text/plain"));
+        assert_eq!(csv.content_type, "text/csv");
+        assert!(csv.mismatch.is_none());
+    }
+
+    #[test]
+    fn declared_types_are_never_echoed() {
+        let detected = detect(b"hello", "a", Some("text/html\r\nX-Evil: 1"));
+        assert_eq!(detected.content_type, "text/plain");
+
+        let detected = detect(b"\x00\x01\x02\x03", "a.bin", Some("image/x-evil"));
+        assert_eq!(detected.content_type, OCTET_STREAM);
+    }
+
+    #[test]
+    fn empty_and_short_buffers() {
+        assert_eq!(detect(b"", "a.png", None).content_type, "text/plain");
+        assert_eq!(detect(b"", "a", None).mismatch, None);
+
+        // Truncated signatures are not trusted
+        assert_eq!(detect(&PNG[..3], "a.png", None).content_type, OCTET_STREAM);
+        assert_eq!(detect(&JPEG[..2], "a.jpg", None).content_type, OCTET_STREAM);
+        assert_eq!(
+            detect(b"RIFF\0\0\0\0WEB", "a.webp", None).content_type,
+            OCTET_STREAM
+        );
+        assert_eq!(detect(b"PK", "a.zip", None).content_type, "text/plain");
+
+        // An offset past the end of a short buffer is skipped, not read
+        assert_eq!(
+            detect(&[0xff; 10], "a.tar", None).content_type,
+            OCTET_STREAM
+        );
+        assert_eq!(
+            detect(b"\0\0\0\0ftyp", "a.mp4", None).content_type,
+            "video/mp4"
+        );
+        assert_eq!(
+            detect(b"\0\0\0\0fty", "a.mp4", None).content_type,
+            OCTET_STREAM
+        );
+    }
+
+    #[test]
+    fn text_cut_inside_a_character_is_still_text() {
+        let head = "café".as_bytes();
+        assert_eq!(
+            detect(&head[..head.len() - 1], "a.txt", None).content_type,
+            "text/plain"
+        );
+    }
+
+    #[test]
+    fn weak_magic_only_applies_to_binary() {
+        assert_eq!(
+            detect(b"BMW owners club", "a.bmp", None).content_type,
+            "text/plain"
+        );
+        assert_eq!(
+            detect(b"BM\x00\x00\x01\x02", "a", None).content_type,
+            "image/bmp"
+        );
+        assert_eq!(
+            detect(b"MZ\x90\x00\x03\x00", "a.txt", None).content_type,
+            "application/x-msdownload"
+        );
+    }
+
+    #[test]
+    fn inline_safe_types_cannot_run_script() {
+        for content_type in INLINE_SAFE {
+            assert!(
+                known(content_type).is_some(),
+                "{content_type} is not a known type"
+            );
+            assert!(
+                !matches!(
+                    *content_type,
+                    "text/html" | "image/svg+xml" | "application/xml" | "text/javascript"
+                ),
+                "{content_type} can run script"
+            );
+        }
+        for content_type in [
+            "text/html",
+            "image/svg+xml",
+            "application/xml",
+            "text/javascript",
+            "text/css",
+            OCTET_STREAM,
+        ] {
+            assert!(!is_inline_safe(content_type), "{content_type}");
+            assert!(!served_inline(content_type), "{content_type}");
+        }
+    }
+
+    #[test]
+    fn nosniff_is_always_set() {
+        let types = EXTENSIONS.iter().map(|(_, t)| *t).chain([OCTET_STREAM]);
+        for content_type in types {
+            for inline in [true, false] {
+                let headers = serving_headers(content_type, "file", inline).unwrap();
+                assert_eq!(
+                    headers[header::X_CONTENT_TYPE_OPTIONS],
+                    "nosniff",
+                    "{content_type}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn serving_headers_describe_the_file() {
+        let headers = serving_headers("text/csv", "a \"b\".csv", true).unwrap();
+        assert_eq!(headers[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+        assert_eq!(
+            headers[header::CONTENT_DISPOSITION],
+            "inline; filename=\"a _b_.csv\""
+        );
+
+        let headers = serving_headers("image/png", "a.png", false).unwrap();
+        assert_eq!(headers[header::CONTENT_TYPE], "image/png");
+        assert_eq!(
+            headers[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"a.png\""
+        );
+
+        assert!(serving_headers("text/plain", "a\nb", false).is_err());
+    }
+}
@@ -1 +1,2 @@
+pub mod content_type;
 pub mod disk;
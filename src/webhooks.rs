@@ -0,0 +1,817 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures_util::stream::{self, StreamExt};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::{Row, SqlitePool};
+use tokio::sync::Notify;
+
+use crate::config::WebhookConfig;
+
+/// Most webhooks one user may register
+pub const MAX_WEBHOOKS_PER_USER: u32 = 10;
+
+/// Largest page of deliveries returned for one webhook
+pub const MAX_DELIVERY_PAGE: u32 = 200;
+
+/// Deliveries picked up per pass of the worker
+const DELIVERY_BATCH: i64 = 50;
+
+/// The worker also wakes up this often to pick up retries that came due
+const POLL_INTERVAL_SECS: u64 = 5;
+
+/// Delivered and failed deliveries are purged after this long
+const DELIVERY_RETENTION_SECS: i64 = 7 * 24 * 3600;
+
+/// Longest receiver error kept in `last_error`
+const MAX_ERROR_LEN: usize = 512;
+
+/// Longest webhook URL a user may register
+pub const MAX_WEBHOOK_URL_LEN: usize = 2048;
+
+/// `last_error` of a delivery whose host resolved to an address that is not public
+const BLOCKED_ADDRESS_ERROR: &str = "Webhook host resolves to a non-public address";
+
+/// Something that happened to a file, delivered to its owner's webhooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEvent {
+    FileUploaded,
+    FileDeleted,
+    FileDownloaded,
+    ShareCreated,
+    ShareRevoked,
+    /// Sent on request to test a receiver; never filtered out
+    Ping,
+}
+
+impl WebhookEvent {
+    /// Events a webhook can subscribe to
+    pub const ALL: [WebhookEvent; 5] = [
+        WebhookEvent::FileUploaded,
+        WebhookEvent::FileDeleted,
+        WebhookEvent::FileDownloaded,
+        WebhookEvent::ShareCreated,
+        WebhookEvent::ShareRevoked,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::FileUploaded => "file.uploaded",
+            WebhookEvent::FileDeleted => "file.deleted",
+            WebhookEvent::FileDownloaded => "file.downloaded",
+            WebhookEvent::ShareCreated => "share.created",
+            WebhookEvent::ShareRevoked => "share.revoked",
+            WebhookEvent::Ping => "ping",
+        }
+    }
+}
+
+impl fmt::Display for WebhookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Self::ALL.into_iter().find(|e| e.as_str() == s).ok_or(())
+    }
+}
+
+/// JSON body of every delivery
+#[derive(Serialize)]
+struct Payload<'a> {
+    event: &'a str,
+    webhook_id: u32,
+    created_at: i64,
+    data: &'a serde_json::Value,
+}
+
+/// A queued or finished delivery, as shown to the webhook's owner
+#[derive(Debug, Serialize)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub event: String,
+    /// `pending`, `delivered` or `failed`
+    pub status: String,
+    pub attempts: u32,
+    pub created_at: i64,
+    pub next_attempt_at: Option<i64>,
+    pub last_attempt_at: Option<i64>,
+    pub last_status_code: Option<u16>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<i64>,
+}
+
+/// A pending delivery picked up by the worker
+struct DueDelivery {
+    id: i64,
+    event: String,
+    payload: String,
+    url: String,
+    secret: String,
+    /// Number of this attempt, counting from 1
+    attempts: u32,
+}
+
+/// Queues webhook deliveries in SQLite and sends them from a background worker.
+///
+/// Each delivery is retried with exponential backoff until the receiver answers
+/// 2xx or `WEBHOOK_MAX_ATTEMPTS` is reached. Queued deliveries survive a restart.
+#[derive(Clone)]
+pub struct Webhooks {
+    db: SqlitePool,
+    http: reqwest::Client,
+    config: WebhookConfig,
+    wake: Arc<Notify>,
+}
+
+impl Webhooks {
+    pub fn new(db: SqlitePool, config: WebhookConfig) -> Result<Self, String> {
+        let mut builder = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .redirect(reqwest::redirect::Policy::none());
+        if !config.allow_private_hosts {
+            // Addresses are checked as the connection is made, so a host cannot pass
+            // at registration and then be re-pointed at an internal service
+            builder = builder.no_proxy().dns_resolver(Arc::new(PublicResolver));
+        }
+        let http = builder
+            .build()
+            .map_err(|e| format!("cannot build webhook client: {e}"))?;
+
+        Ok(Self {
+            db,
+            http,
+            config,
+            wake: Arc::new(Notify::new()),
+        })
+    }
+
+    /// Check a webhook URL before it is registered: well formed, and unless
+    /// `WEBHOOK_ALLOW_PRIVATE_HOSTS` is set, resolving only to public addresses
+    pub async fn check_url(&self, url: &str) -> Result<(), &'static str> {
+        validate_webhook_url(url)?;
+        if self.config.allow_private_hosts {
+            return Ok(());
+        }
+        let parsed = reqwest::Url::parse(url).map_err(|_| "Webhook URL is not a valid URL")?;
+        let addrs: Vec<SocketAddr> = match (literal_ip(&parsed), parsed.host_str()) {
+            (Some(ip), _) => vec![SocketAddr::new(ip, 0)],
+            (None, Some(host)) => tokio::net::lookup_host((host, 0))
+                .await
+                .map_err(|_| "Webhook URL host does not resolve")?
+                .collect(),
+            (None, None) => return Err("Webhook URL needs a host"),
+        };
+        if addrs.is_empty() {
+            return Err("Webhook URL host does not resolve");
+        }
+        if !addrs.iter().all(|a| is_public(a.ip())) {
+            return Err("Webhook URL must point to a public address");
+        }
+        Ok(())
+    }
+
+    /// Queue `event` for every webhook of `user_id` subscribed to it.
+    /// Failures are logged; they never fail the action that caused the event.
+    pub async fn emit(&self, user_id: u32, event: WebhookEvent, data: serde_json::Value) {
+        match self.enqueue(user_id, None, event, &data).await {
+            Ok(0) => {}
+            Ok(_) => self.wake.notify_one(),
+            Err(e) => eprintln!("Queueing {event} webhooks for user_id={user_id} failed: {e}"),
+        }
+    }
+
+    /// Queue a `ping` to one webhook; `false` if it does not belong to `user_id`
+    pub async fn ping(&self, user_id: u32, webhook_id: u32) -> Result<bool, sqlx::Error> {
+        let data = serde_json::json!({ "message": "Webhook test from the file server" });
+        let queued = self
+            .enqueue(user_id, Some(webhook_id), WebhookEvent::Ping, &data)
+            .await?;
+        if queued > 0 {
+            self.wake.notify_one();
+        }
+        Ok(queued > 0)
+    }
+
+    async fn enqueue(
+        &self,
+        user_id: u32,
+        only_webhook: Option<u32>,
+        event: WebhookEvent,
+        data: &serde_json::Value,
+    ) -> Result<usize, sqlx::Error> {
+        let hooks = sqlx::query(
+            r#"
+            SELECT id
+            FROM webhooks
+            WHERE user_id = ?1
+                AND (?2 IS NULL OR id = ?2)
+                AND (?3 = 'ping' OR instr(',' || events || ',', ',' || ?3 || ',') > 0)
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(only_webhook.map(|id| id as i64))
+        .bind(event.as_str())
+        .fetch_all(&self.db)
+        .await?;
+
+        let created_at = now();
+        for hook in &hooks {
+            let webhook_id: i64 = hook.get("id");
+            let payload = serde_json::to_string(&Payload {
+                event: event.as_str(),
+                webhook_id: webhook_id as u32,
+                created_at,
+                data,
+            })
+            .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+
+            sqlx::query(
+                r#"
+                INSERT INTO webhook_deliveries (webhook_id, event, payload, created_at, next_attempt_at)
+                VALUES (?1, ?2, ?3, ?4, ?4)
+                "#,
+            )
+            .bind(webhook_id)
+            .bind(event.as_str())
+            .bind(payload)
+            .bind(created_at)
+            .execute(&self.db)
+            .await?;
+        }
+
+        Ok(hooks.len())
+    }
+
+    /// Send due deliveries until the server stops; run this once, in its own task
+    pub async fn run_worker(self) {
+        loop {
+            loop {
+                match self.deliver_due().await {
+                    Ok(n) if n == DELIVERY_BATCH as usize => continue,
+                    Ok(_) => break,
+                    Err(e) => {
+                        eprintln!("Webhook delivery pass failed: {e}");
+                        break;
+                    }
+                }
+            }
+
+            tokio::select! {
+                _ = self.wake.notified() => {}
+                _ = tokio::time::sleep(Duration::from_secs(POLL_INTERVAL_SECS)) => {}
+            }
+        }
+    }
+
+    /// Attempt every pending delivery that is due; returns how many were attempted.
+    ///
+    /// Up to `WEBHOOK_CONCURRENCY` webhooks are sent to at once, so a slow receiver
+    /// does not hold up the others. Deliveries to one webhook go one at a time, in order.
+    async fn deliver_due(&self) -> Result<usize, sqlx::Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT d.id, d.webhook_id, d.event, d.payload, d.attempts, w.url, w.secret
+            FROM webhook_deliveries d
+            JOIN webhooks w ON w.id = d.webhook_id
+            WHERE d.status = 'pending' AND d.next_attempt_at <= ?1
+            ORDER BY d.next_attempt_at, d.id
+            LIMIT ?2
+            "#,
+        )
+        .bind(now())
+        .bind(DELIVERY_BATCH)
+        .fetch_all(&self.db)
+        .await?;
+        let attempted = rows.len();
+
+        let mut by_webhook: Vec<(i64, Vec<DueDelivery>)> = Vec::new();
+        for row in &rows {
+            let delivery = DueDelivery {
+                id: row.get("id"),
+                event: row.get("event"),
+                payload: row.get("payload"),
+                url: row.get("url"),
+                secret: row.get("secret"),
+                attempts: row.get::<i64, _>("attempts") as u32 + 1,
+            };
+            let webhook_id: i64 = row.get("webhook_id");
+            match by_webhook.iter_mut().find(|(id, _)| *id == webhook_id) {
+                Some((_, queue)) => queue.push(delivery),
+                None => by_webhook.push((webhook_id, vec![delivery])),
+            }
+        }
+
+        let results: Vec<Result<(), sqlx::Error>> = stream::iter(by_webhook)
+            .map(|(_, queue)| async move {
+                for delivery in queue {
+                    self.attempt(delivery).await?;
+                }
+                Ok(())
+            })
+            .buffer_unordered(self.config.concurrency)
+            .collect()
+            .await;
+        results.into_iter().collect::<Result<(), _>>()?;
+
+        Ok(attempted)
+    }
+
+    /// Send one delivery and record the outcome, scheduling a retry if it failed
+    async fn attempt(&self, delivery: DueDelivery) -> Result<(), sqlx::Error> {
+        let DueDelivery {
+            id,
+            event,
+            payload,
+            url,
+            secret,
+            attempts,
+        } = delivery;
+
+        let (status_code, error) = self.send(id, &event, &url, &secret, payload).await;
+        let at = now();
+
+        if error.is_none() {
+            sqlx::query(
+                r#"
+                UPDATE webhook_deliveries
+                SET status = 'delivered', attempts = ?2, last_attempt_at = ?3,
+                    last_status_code = ?4, last_error = NULL, delivered_at = ?3
+                WHERE id = ?1
+                "#,
+            )
+            .bind(id)
+            .bind(attempts as i64)
+            .bind(at)
+            .bind(status_code.map(i64::from))
+            .execute(&self.db)
+            .await?;
+            return Ok(());
+        }
+
+        let gave_up = attempts >= self.config.max_attempts;
+        if gave_up {
+            eprintln!("Webhook delivery {id} to {url} failed after {attempts} attempts");
+        }
+        sqlx::query(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = ?2, attempts = ?3, last_attempt_at = ?4, next_attempt_at = ?5,
+                last_status_code = ?6, last_error = ?7
+            WHERE id = ?1
+            "#,
+        )
+        .bind(id)
+        .bind(if gave_up { "failed" } else { "pending" })
+        .bind(attempts as i64)
+        .bind(at)
+        .bind(at + self.backoff_secs(attempts) as i64)
+        .bind(status_code.map(i64::from))
+        .bind(error)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// POST one signed payload; returns the status code and an error unless it was 2xx
+    async fn send(
+        &self,
+        delivery_id: i64,
+        event: &str,
+        url: &str,
+        secret: &str,
+        payload: String,
+    ) -> (Option<u16>, Option<String>) {
+        // IP literals never reach the resolver, so check them here
+        if !self.config.allow_private_hosts && !literal_host_is_public(url) {
+            eprintln!("[security] Webhook delivery {delivery_id} blocked: non-public address");
+            return (None, Some(BLOCKED_ADDRESS_ERROR.to_string()));
+        }
+
+        let timestamp = now();
+        let signature = sign(secret, timestamp, &payload);
+
+        let result = self
+            .http
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-SFS-Event", event)
+            .header("X-SFS-Delivery", delivery_id.to_string())
+            .header("X-SFS-Timestamp", timestamp.to_string())
+            .header("X-SFS-Signature", format!("sha256={signature}"))
+            .body(payload)
+            .send()
+            .await;
+
+        match result {
+            Ok(resp) if resp.status().is_success() => (Some(resp.status().as_u16()), None),
+            Ok(resp) => {
+                let status = resp.status();
+                (Some(status.as_u16()), Some(format!("HTTP {status}")))
+            }
+            Err(e) if is_blocked(&e) => {
+                eprintln!("[security] Webhook delivery {delivery_id} blocked: non-public address");
+                (None, Some(BLOCKED_ADDRESS_ERROR.to_string()))
+            }
+            Err(e) => {
+                let mut msg = e.to_string();
+                if msg.len() > MAX_ERROR_LEN {
+                    let mut end = MAX_ERROR_LEN;
+                    while !msg.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    msg.truncate(end);
+                }
+                (None, Some(msg))
+            }
+        }
+    }
+
+    /// Delay after the `attempts`-th failed attempt: base, 2x base, 4x base, ... up to the max
+    fn backoff_secs(&self, attempts: u32) -> u64 {
+        let factor = 1u64 << attempts.saturating_sub(1).min(32);
+        self.config
+            .retry_base_secs
+            .saturating_mul(factor)
+            .min(self.config.retry_max_secs)
+    }
+
+    /// Deliveries of one webhook, newest first; `None` if it does not belong to `user_id`
+    pub async fn deliveries(
+        &self,
+        user_id: u32,
+        webhook_id: u32,
+        limit: u32,
+    ) -> Result<Option<Vec<WebhookDelivery>>, sqlx::Error> {
+        let owned = sqlx::query("SELECT 1 FROM webhooks WHERE id = ?1 AND user_id = ?2")
+            .bind(webhook_id as i64)
+            .bind(user_id as i64)
+            .fetch_optional(&self.db)
+            .await?;
+        if owned.is_none() {
+            return Ok(None);
+        }
+
+        let rows = sqlx::query(
+            r#"
+            SELECT id, event, status, attempts, created_at, next_attempt_at, last_attempt_at,
+                last_status_code, last_error, delivered_at
+            FROM webhook_deliveries
+            WHERE webhook_id = ?1
+            ORDER BY id DESC
+            LIMIT ?2
+            "#,
+        )
+        .bind(webhook_id as i64)
+        .bind(limit.min(MAX_DELIVERY_PAGE) as i64)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(Some(
+            rows.iter()
+                .map(|r| {
+                    let status: String = r.get("status");
+                    WebhookDelivery {
+                        id: r.get("id"),
+                        event: r.get("event"),
+                        next_attempt_at: (status == "pending").then(|| r.get("next_attempt_at")),
+                        status,
+                        attempts: r.get::<i64, _>("attempts") as u32,
+                        created_at: r.get("created_at"),
+                        last_attempt_at: r.get("last_attempt_at"),
+                        last_status_code: r
+                            .get::<Option<i64>, _>("last_status_code")
+                            .map(|c| c as u16),
+                        last_error: r.get("last_error"),
+                        delivered_at: r.get("delivered_at"),
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    /// Purge finished deliveries older than the retention period
+    pub async fn delete_stale(&self) -> Result<u64, sqlx::Error> {
+        let res = sqlx::query(
+            "DELETE FROM webhook_deliveries WHERE status != 'pending' AND created_at < ?1",
+        )
+        .bind(now() - DELIVERY_RETENTION_SECS)
+        .execute(&self.db)
+        .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+/// Hex HMAC-SHA256 of `<timestamp>.<body>` keyed with the webhook secret
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+/// Check a webhook URL supplied by a user: absolute http(s) with a host
+fn validate_webhook_url(url: &str) -> Result<(), &'static str> {
+    if url.len() > MAX_WEBHOOK_URL_LEN {
+        return Err("Webhook URL is too long");
+    }
+    let parsed = reqwest::Url::parse(url).map_err(|_| "Webhook URL is not a valid URL")?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err("Webhook URL must use http or https");
+    }
+    if parsed.host_str().is_none_or(str::is_empty) {
+        return Err("Webhook URL needs a host");
+    }
+    if !parsed.username().is_empty() || parsed.password().is_some() {
+        return Err("Webhook URL must not contain credentials");
+    }
+    Ok(())
+}
+
+/// Resolves webhook hosts and refuses any that have a non-public address
+struct PublicResolver;
+
+/// Error of [`PublicResolver`], found again in the request error's source chain
+#[derive(Debug)]
+struct BlockedAddress;
+
+impl fmt::Display for BlockedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(BLOCKED_ADDRESS_ERROR)
+    }
+}
+
+impl std::error::Error for BlockedAddress {}
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if !addrs.iter().all(|a| is_public(a.ip())) {
+                return Err(Box::new(BlockedAddress) as Box<dyn std::error::Error + Send + Sync>);
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// Whether a request failed because [`PublicResolver`] refused the host
+fn is_blocked(err: &reqwest::Error) -> bool {
+    let mut source = std::error::Error::source(err);
+    while let Some(e) = source {
+        if e.is::<BlockedAddress>() {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// Host of `url` if it is an IP address rather than a name
+fn literal_ip(url: &reqwest::Url) -> Option<IpAddr> {
+    let host = url.host_str()?;
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+/// `false` only for a URL whose host is a non-public IP literal
+fn literal_host_is_public(url: &str) -> bool {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|u| literal_ip(&u))
+        .is_none_or(is_public)
+}
+
+/// Whether `ip` is reachable on the public internet: not loopback, private,
+/// link-local (cloud metadata), shared, documentation, multicast or reserved
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(a == 0
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_multicast()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        // 100.64.0.0/10 shared address space (carrier-grade NAT)
+        || (a == 100 && (64..128).contains(&b))
+        // 192.0.0.0/24 protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // 198.18.0.0/15 benchmarking
+        || (a == 198 && (b == 18 || b == 19))
+        // 240.0.0.0/4 reserved
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_public_v4(v4);
+    }
+    let segments = ip.segments();
+    let embedded_v4 =
+        |hi: u16, lo: u16| Ipv4Addr::new((hi >> 8) as u8, hi as u8, (lo >> 8) as u8, lo as u8);
+    match segments {
+        // 64:ff9b::/96 NAT64
+        [0x64, 0xff9b, 0, 0, 0, 0, hi, lo] => return is_public_v4(embedded_v4(hi, lo)),
+        // 2002::/16 6to4
+        [0x2002, hi, lo, ..] => return is_public_v4(embedded_v4(hi, lo)),
+        _ => {}
+    }
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // fc00::/7 unique local
+        || (segments[0] & 0xfe00) == 0xfc00
+        // fe80::/10 link-local, fec0::/10 site-local
+        || (segments[0] & 0xffc0) == 0xfe80
+        || (segments[0] & 0xffc0) == 0xfec0
+        // 2001:db8::/32 documentation, 2001::/32 Teredo
+        || (segments[0] == 0x2001 && (segments[1] == 0x0db8 || segments[1] == 0))
+        // ::/96 IPv4-compatible, deprecated
+        || segments[..6] == [0; 6])
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::dns::Resolve;
+
+    use super::*;
+
+    const BLOCKED_V4: &[&str] = &[
+        "0.0.0.0",
+        "0.1.2.3",
+        "127.0.0.1",
+        "127.255.255.254",
+        "10.0.0.1",
+        "172.16.0.1",
+        "172.31.255.255",
+        "192.168.1.1",
+        "169.254.169.254",
+        "169.254.0.1",
+        "100.64.0.1",
+        "100.127.255.255",
+        "192.0.0.8",
+        "192.0.2.1",
+        "198.18.0.1",
+        "198.51.100.7",
+        "203.0.113.9",
+        "224.0.0.1",
+        "240.0.0.1",
+        "255.255.255.255",
+    ];
+
+    const PUBLIC_V4: &[&str] = &[
+        "1.1.1.1",
+        "8.8.8.8",
+        "93.184.216.34",
+        "100.63.255.255",
+        "100.128.0.1",
+        "172.15.255.255",
+        "172.32.0.1",
+        "192.169.0.1",
+        "198.20.0.1",
+    ];
+
+    const BLOCKED_V6: &[&str] = &[
+        "::",
+        "::1",
+        "::ffff:127.0.0.1",
+        "::ffff:10.0.0.1",
+        "::ffff:169.254.169.254",
+        "::127.0.0.1",
+        "64:ff9b::7f00:1",
+        "64:ff9b::a9fe:a9fe",
+        "64:ff9b::a00:1",
+        "2002:7f00:1::",
+        "2002:a9fe:a9fe::1",
+        "fc00::1",
+        "fd12:3456::1",
+        "fe80::1",
+        "fec0::1",
+        "ff02::1",
+        "2001:db8::1",
+        "2001::1",
+    ];
+
+    const PUBLIC_V6: &[&str] = &[
+        "2606:4700:4700::1111",
+        "2001:4860:4860::8888",
+        "::ffff:8.8.8.8",
+        "64:ff9b::808:808",
+        "2002:808:808::1",
+    ];
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn non_public_ipv4_is_blocked() {
+        for addr in BLOCKED_V4 {
+            assert!(!is_public(ip(addr)), "{addr} should be blocked");
+        }
+    }
+
+    #[test]
+    fn public_ipv4_is_allowed() {
+        for addr in PUBLIC_V4 {
+            assert!(is_public(ip(addr)), "{addr} should be allowed");
+        }
+    }
+
+    #[test]
+    fn non_public_ipv6_is_blocked() {
+        for addr in BLOCKED_V6 {
+            assert!(!is_public(ip(addr)), "{addr} should be blocked");
+        }
+    }
+
+    #[test]
+    fn public_ipv6_is_allowed() {
+        for addr in PUBLIC_V6 {
+            assert!(is_public(ip(addr)), "{addr} should be allowed");
+        }
+    }
+
+    #[test]
+    fn literal_hosts_are_checked_without_resolving() {
+        assert!(!literal_host_is_public("http://127.0.0.1:8080/hook"));
+        assert!(!literal_host_is_public("http://[::1]/hook"));
+        assert!(!literal_host_is_public("http://[::ffff:a9fe:a9fe]/"));
+        assert!(!literal_host_is_public(
+            "http://169.254.169.254/latest/meta-data"
+        ));
+        assert!(literal_host_is_public("https://8.8.8.8/hook"));
+        // Names are left to the resolver
+        assert!(literal_host_is_public("https://localhost/hook"));
+    }
+
+    async fn resolve(
+        host: &str,
+    ) -> Result<Vec<SocketAddr>, Box<dyn std::error::Error + Send + Sync>> {
+        let name = host.parse().unwrap();
+        Ok(PublicResolver.resolve(name).await?.collect())
+    }
+
+    #[tokio::test]
+    async fn resolver_refuses_non_public_addresses() {
+        for addr in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "169.254.169.254",
+            "100.64.0.1",
+            "::1",
+            "fd00::1",
+            "::ffff:192.168.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "::",
+        ] {
+            let err = resolve(addr).await.expect_err(addr);
+            assert!(err.is::<BlockedAddress>(), "{addr}: {err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn resolver_refuses_names_of_local_addresses() {
+        let err = resolve("localhost").await.unwrap_err();
+        assert!(err.is::<BlockedAddress>(), "{err}");
+    }
+
+    #[tokio::test]
+    async fn resolver_passes_public_addresses() {
+        let addrs = resolve("8.8.8.8").await.unwrap();
+        assert_eq!(
+            addrs.iter().map(|a| a.ip()).collect::<Vec<_>>(),
+            [ip("8.8.8.8")]
+        );
+    }
+}